@@ -0,0 +1,124 @@
+//! Mask boolean ops and applying masks to heightmaps, on a 4000 x 4000 grid (16 million cells).
+//! Every op is timed as a plain loop (what it used to be) and as the library call, which splits the grid between threads.
+//!
+//! `cargo bench --bench mask_ops`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use las_kml_to_stl::height_map::HeightMap;
+use las_kml_to_stl::mask::Mask;
+use las_kml_to_stl::utm_bounds::UtmBoundingBox;
+use las_kml_to_stl::utm_point::UtmZone;
+
+const RESOLUTION: usize = 4000;
+
+fn bounds() -> UtmBoundingBox {
+    UtmBoundingBox::new(500000.0, 504000.0, 4100000.0, 4104000.0, 0.0, 100.0)
+}
+
+/// a mask with every `period`th cell set
+fn striped_mask(period: usize) -> Mask {
+    let mut mask = Mask::new_with_dims(RESOLUTION, RESOLUTION, bounds(), UtmZone::new(10, true)).unwrap();
+    for (index, state) in mask.data.iter_mut().enumerate() {
+        *state = index % period == 0;
+    }
+    mask
+}
+
+/// heights going 0 to 99 over and over
+fn ramp_height_map() -> HeightMap {
+    HeightMap {
+        data: (0..RESOLUTION * RESOLUTION).map(|index| (index % 100) as f64).collect(),
+        x_res: RESOLUTION,
+        y_res: RESOLUTION,
+        bounds: bounds(),
+        utm_zone: Some(UtmZone::new(10, true)),
+        geoid_correction: None,
+        crs: None,
+    }
+}
+
+fn mask_boolean_ops(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mask_bitor");
+    group.sample_size(20);
+    let other_mask = striped_mask(3);
+
+    let mut mask = striped_mask(2);
+    group.bench_function("sequential", |b| b.iter(|| {
+        for (own_state, other_state) in mask.data.iter_mut().zip(other_mask.data.iter()) {
+            *own_state |= *other_state;
+        }
+        black_box(&mask);
+    }));
+
+    let mut mask = striped_mask(2);
+    group.bench_function("checked_bitor_assign", |b| b.iter(|| {
+        mask.checked_bitor_assign(&other_mask).unwrap();
+        black_box(&mask);
+    }));
+    group.finish();
+
+    let mut group = c.benchmark_group("mask_sub");
+    group.sample_size(20);
+
+    let mut mask = striped_mask(2);
+    group.bench_function("sequential", |b| b.iter(|| {
+        for (own_state, other_state) in mask.data.iter_mut().zip(other_mask.data.iter()) {
+            *own_state = *own_state && !*other_state;
+        }
+        black_box(&mask);
+    }));
+
+    let mut mask = striped_mask(2);
+    group.bench_function("checked_sub_assign", |b| b.iter(|| {
+        mask.checked_sub_assign(&other_mask).unwrap();
+        black_box(&mask);
+    }));
+    group.finish();
+}
+
+fn height_map_mask_ops(c: &mut Criterion) {
+    let mask = striped_mask(2);
+
+    let mut group = c.benchmark_group("offset_by_mask");
+    group.sample_size(20);
+
+    let mut height_map = ramp_height_map();
+    group.bench_function("sequential", |b| b.iter(|| {
+        for (height, mask_state) in height_map.data.iter_mut().zip(mask.data.iter()) {
+            if *mask_state {
+                *height += 0.5;
+            }
+        }
+        black_box(&height_map);
+    }));
+
+    let mut height_map = ramp_height_map();
+    group.bench_function("offset_by_mask", |b| b.iter(|| {
+        height_map.offset_by_mask(&mask, 0.5).unwrap();
+        black_box(&height_map);
+    }));
+    group.finish();
+
+    let mut group = c.benchmark_group("set_by_mask");
+    group.sample_size(20);
+
+    let mut height_map = ramp_height_map();
+    group.bench_function("sequential", |b| b.iter(|| {
+        for (height, mask_state) in height_map.data.iter_mut().zip(mask.data.iter()) {
+            if *mask_state {
+                *height = 10.0;
+            }
+        }
+        black_box(&height_map);
+    }));
+
+    let mut height_map = ramp_height_map();
+    group.bench_function("set_by_mask", |b| b.iter(|| {
+        height_map.set_by_mask(&mask, 10.0).unwrap();
+        black_box(&height_map);
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, mask_boolean_ops, height_map_mask_ops);
+criterion_main!(benches);
@@ -29,13 +29,13 @@ pub fn save_data(){
 
         The saved data is specific to this library and is not a standard.
     */
-    hm.save("example data.json").unwrap();
+    hm.save("example data.heightmap").unwrap();
 }
 
 
 pub fn load_and_manipulate(){
      // load the height map that was previously saved
-    let hm = HeightMap::load("example data.json").unwrap();
+    let hm = HeightMap::load("example data.heightmap").unwrap();
 
     // save the height map as an stl
     hm.save_as_stl("stl file out.stl", 2.0, 10.0).unwrap();
@@ -0,0 +1,19 @@
+use simple_logger::SimpleLogger;
+use las_kml_to_stl::terrain_model::TerrainModelBuilder;
+
+
+fn main() {
+    // start a logger to print info from las_kml_to_stl
+    SimpleLogger::new().env().init().unwrap();
+
+    // does the same as region_and_trail_example, without saving the heightmap in between
+    TerrainModelBuilder::new()
+        .las_glob("test_laz/*.laz") // look for files ending in '.laz' in folder 'test_laz'
+        .resolution(1000) // use 1000 sample points across the x axis
+        .clip_to_kml("test_perimeters/property_line.kml") // only load and keep what's inside the property line
+        .carve_trails("test_perimeters/trails.kml", 4.0, 10.0) // lower the trails by 10 meters, 4 meters wide
+        .z_scale(2.0) // exaggerate the heights
+        .base_thickness(10.0) // make the printed part a little stronger
+        .save_stl("my property.stl")
+        .unwrap();
+}
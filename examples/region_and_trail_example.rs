@@ -1,7 +1,7 @@
 use geo::{GeometryCollection, LineString, Polygon};
 use simple_logger::SimpleLogger;
 use las_kml_to_stl::height_map::HeightMap;
-use las_kml_to_stl::kml_utils::{get_regions, get_trails, load_kml_file};
+use las_kml_to_stl::kml_utils::{get_regions, get_trails, infer_utm_zone, load_kml_file};
 use las_kml_to_stl::mask::Mask;
 
 
@@ -32,20 +32,23 @@ pub fn save_data(){
 
         The saved data is specific to this library and is not a standard.
     */
-    hm.save("example data.json").unwrap();
+    hm.save("example data.heightmap").unwrap();
 }
 
 pub fn load_and_manipulate(){
 
-    //UTM zone 10. You can find your UTM Zone online, and it doesn't have to be perfect, just keep it constant
-    let utm_zone: u8 = 10;
-
     // load the height map that was previously saved
-    let mut hm = HeightMap::load("example data.json").unwrap();
+    let mut hm = HeightMap::load("example data.heightmap").unwrap();
 
     // load a file that contains a boundary
     let kml_file_containing_property_line: GeometryCollection = load_kml_file("test_perimeters/property_line.kml").unwrap();
 
+    // the heightmap knows its UTM zone if the LAS files said what it was.
+    // If they didn't (or the heightmap was saved by an older version), guess it from the KML instead
+    if hm.utm_zone.is_none(){
+        hm.utm_zone = infer_utm_zone(&kml_file_containing_property_line);
+    }
+
     // get a list (vec) of all polygons in the file.
     let all_polygons_in_file: Vec<Polygon> = get_regions(kml_file_containing_property_line);
 
@@ -53,8 +56,8 @@ pub fn load_and_manipulate(){
     // get a reference to the first polygon in the file
     let property_line_polygon = &all_polygons_in_file[0];
 
-    // create a mask with the same resolution and bounds as our heightmap,
-    let mut property_mask: Mask = Mask::new_with_dims(hm.x_res, hm.y_res, hm.bounds, utm_zone);
+    // create a mask with the same resolution, bounds and UTM zone as our heightmap,
+    let mut property_mask: Mask = Mask::new_for_height_map(&hm).unwrap();
 
     // add the filled in polygon to the mask.
     // The KML file must be in decimal GPS coordinates. I have never seen a KML in a different format,
@@ -69,13 +72,13 @@ pub fn load_and_manipulate(){
     let all_trails_in_file: Vec<LineString> = get_trails(kml_file_with_trails);
 
     // create another mask for the trails
-    let mut trail_mask: Mask = Mask::new_with_dims(hm.x_res, hm.y_res, hm.bounds, utm_zone);
+    let mut trail_mask: Mask = Mask::new_for_height_map(&hm).unwrap();
 
     let trail_width_in_pixels: u16 = 16;
 
     for trail in all_trails_in_file{
 
-        // for each trail, add it to the mask with sample points every ~ `trail_width_in_pixels / 4` pixels
+        // for each trail, draw it onto the mask as a continuous line `trail_width_in_pixels` wide
         trail_mask.add_lat_lon_trail_auto_sample
         (
             &trail,
@@ -0,0 +1,164 @@
+use geo::{BoundingRect, Polygon};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::kml_utils::polygon_to_utm_polygon;
+use crate::mask::Mask;
+use crate::utils::par_zip_for_each;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// how many rows each pixel row is split into when finding coverage. Coverage along x is exact
+const SUB_ROWS: usize = 8;
+
+/// Like a `Mask`, but every cell has how much of it (0 to 1) is covered by polygons instead of just on or off.
+/// Using it to offset or set heights blends region edges in, instead of leaving a jagged pixel staircase in the STL.
+pub struct AlphaMask{
+    pub data: Vec<f32>,
+    pub x_res: usize,
+    pub y_res: usize,
+    /// meters per pixel in x axis, same as `Mask::x_tick`
+    pub x_tick: f64,
+    /// meters per pixel in y axis, same as `Mask::y_tick`
+    pub y_tick: f64,
+    pub bounds: UtmBoundingBox,
+    pub utm_zone: UtmZone,
+}
+
+impl AlphaMask{
+    /// creates an alpha mask with nothing covered
+    pub fn new_with_dims(x_res: usize, y_res: usize, bounds: UtmBoundingBox, utm_zone: UtmZone) -> AlphaMask{
+        AlphaMask{
+            data: vec![0f32; x_res*y_res],
+            x_res,
+            y_res,
+            x_tick: bounds.x_range() / (x_res - 1) as f64,
+            y_tick: bounds.y_range() / (y_res - 1) as f64,
+            bounds,
+            utm_zone,
+        }
+    }
+
+    /// Creates an empty alpha mask with the same resolution, bounds and UTM zone as `height_map`.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone, like `Mask::new_for_height_map`
+    pub fn new_for_height_map(height_map: &HeightMap) -> Result<AlphaMask, LasToStlError>{
+        let utm_zone = height_map.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+        Ok(AlphaMask::new_with_dims(height_map.x_res, height_map.y_res, height_map.bounds, utm_zone))
+    }
+
+    /// creates an alpha mask that is fully covered wherever `mask` is true and not at all anywhere else
+    pub fn from_mask(mask: &Mask) -> AlphaMask{
+        AlphaMask{
+            data: mask.data.iter().map(|state| if *state { 1f32 } else { 0f32 }).collect(),
+            x_res: mask.x_res,
+            y_res: mask.y_res,
+            x_tick: mask.x_tick,
+            y_tick: mask.y_tick,
+            bounds: mask.bounds,
+            utm_zone: mask.utm_zone,
+        }
+    }
+
+    /// adds the part of every pixel that is inside the lat/lon polygon (except its holes). See `add_filled_utm_polygon`
+    pub fn add_filled_lat_lon_polygon(&mut self, lat_lon_region: &Polygon) -> Result<(), LasToStlError>{
+        self.add_filled_utm_polygon(&polygon_to_utm_polygon(lat_lon_region, self.utm_zone))
+    }
+
+    /// Adds the part of every pixel that is inside the polygon (except its holes) to its coverage, up to 1.
+    /// Every pixel is the square (or rectangle) centered on its UTM coordinate, so pixels on the edge get partly covered.
+    /// Two polygons that share an edge add up to fully covered along it. Parts outside of the mask are ignored
+    pub fn add_filled_utm_polygon(&mut self, utm_region: &Polygon) -> Result<(), LasToStlError>{
+        let bounding_rectangle = utm_region.bounding_rect().ok_or(LasToStlError::NoBoundingRectError)?;
+
+        // pixel rows the polygon touches
+        let first_y = ((bounding_rectangle.min().y - self.bounds.min_y) / self.y_tick - 0.5).floor().max(0f64) as usize;
+        let last_y = ((bounding_rectangle.max().y - self.bounds.min_y) / self.y_tick + 0.5).ceil().min((self.y_res - 1) as f64);
+        if last_y < 0f64 {
+            return Ok(())
+        }
+
+        let mut row_coverage: Vec<f64> = vec![0f64; self.x_res];
+        for y in first_y..=(last_y as usize){
+            for sub_row in 0..SUB_ROWS{
+                let utm_y = self.bounds.min_y + (y as f64 - 0.5 + (sub_row as f64 + 0.5) / SUB_ROWS as f64) * self.y_tick;
+
+                // crossings of every ring, so the even-odd pairs leave the holes out
+                let mut crossings: Vec<f64> = std::iter::once(utm_region.exterior())
+                    .chain(utm_region.interiors())
+                    .flat_map(|ring| ring.lines())
+                    .filter(|line| (line.start.y <= utm_y) != (line.end.y <= utm_y))
+                    .map(|line| line.start.x + (utm_y - line.start.y) / (line.end.y - line.start.y) * (line.end.x - line.start.x))
+                    .collect();
+                crossings.sort_unstable_by(|a, b| a.total_cmp(b));
+
+                for pair in crossings.chunks_exact(2){
+                    // in pixels, where pixel x covers x to x + 1
+                    let start = (pair[0] - self.bounds.min_x) / self.x_tick + 0.5;
+                    let end = (pair[1] - self.bounds.min_x) / self.x_tick + 0.5;
+                    let first_x = start.floor().max(0f64) as usize;
+                    let last_x = end.floor().min((self.x_res - 1) as f64);
+                    if last_x < 0f64 {
+                        continue
+                    }
+                    for (x, coverage) in row_coverage.iter_mut().enumerate().take(last_x as usize + 1).skip(first_x){
+                        let overlap = end.min((x + 1) as f64) - start.max(x as f64);
+                        *coverage += overlap.max(0f64) / SUB_ROWS as f64;
+                    }
+                }
+            }
+
+            for (alpha, coverage) in self.data[y*self.x_res..(y + 1)*self.x_res].iter_mut().zip(row_coverage.iter_mut()){
+                *alpha = (*alpha + *coverage as f32).min(1f32);
+                *coverage = 0f64;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// gets a mask of every pixel that is covered at least `threshold` (0 to 1)
+    pub fn to_mask(&self, threshold: f32) -> Mask{
+        let mut mask = Mask::new_with_dims_unchecked(self.x_res, self.y_res, self.bounds, self.utm_zone);
+        mask.data = self.data.iter().map(|alpha| *alpha >= threshold).collect();
+        mask
+    }
+
+    fn check_matches(&self, height_map: &HeightMap) -> Result<(), LasToStlError>{
+        if let Some(utm_zone) = height_map.utm_zone {
+            utm_zone.check_matches(&self.utm_zone)?;
+        }
+        if height_map.x_res == self.x_res && height_map.y_res == self.y_res && height_map.bounds.eq_xy(&self.bounds){
+            Ok(())
+        } else {
+            Err(LasToStlError::MaskBoundMismatchError{
+                other_x_res: height_map.x_res,
+                other_y_res: height_map.y_res,
+                mask_x_res: self.x_res,
+                mask_y_res: self.y_res,
+                other_bounds: Box::new(height_map.bounds),
+                mask_bounds: Box::new(self.bounds),
+            })
+        }
+    }
+}
+
+impl HeightMap{
+    /// Same as `offset_by_mask`, but every height is offset by `offset` times its coverage,
+    /// so partly covered edge cells only get part of it. Alpha mask must have the same resolution and x/y bounds as self.
+    pub fn offset_by_alpha_mask(&mut self, alpha_mask: &AlphaMask, offset: f64) -> Result<(), LasToStlError>{
+        alpha_mask.check_matches(self)?;
+        par_zip_for_each(&mut self.data, &alpha_mask.data, |height, alpha| *height += *alpha as f64 * offset);
+        Ok(())
+    }
+
+    /// Same as `set_by_mask`, but every height is blended towards `value_to_set` by its coverage,
+    /// so fully covered cells are set and partly covered edge cells end up in between.
+    /// Alpha mask must have the same resolution and x/y bounds as self.
+    pub fn set_by_alpha_mask(&mut self, alpha_mask: &AlphaMask, value_to_set: f64) -> Result<(), LasToStlError>{
+        alpha_mask.check_matches(self)?;
+        par_zip_for_each(&mut self.data, &alpha_mask.data, |height, alpha| {
+            let alpha = *alpha as f64;
+            *height = *height * (1f64 - alpha) + value_to_set * alpha;
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use std::path::Path;
+use geo::Coord;
+#[cfg(feature = "kml")]
+use kml::types::Element;
+#[cfg(feature = "kml")]
+use kml::{Kml, KmlReader};
+#[cfg(feature = "kml")]
+use log::warn;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::utm_point::UtmCoord;
+
+/// A trail with an altitude at every point, like a GPS track, a flight path or a planned road with its grade
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AltitudeTrail {
+    /// lat/lon points (x = lon, y = lat)
+    pub points: Vec<Coord>,
+    /// the altitude of every point, in meters
+    pub altitudes: Vec<f64>,
+}
+
+/// How the altitudes of the trail are applied to the terrain by `HeightMap::emboss_altitude_trail`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum AltitudeTrailMode {
+    /// the terrain under the trail is set to the trail's altitude, up or down
+    #[default]
+    Replace,
+    /// the terrain is only raised up to the trail, like a flight path or a bridge
+    RaiseOnly,
+    /// the terrain is only carved down to the trail, like a tunnel or a road cut
+    CarveOnly,
+}
+
+/// Settings for `HeightMap::emboss_altitude_trail`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AltitudeTrailOptions {
+    /// how wide the trail is, in meters
+    pub width_m: f64,
+    /// added to every altitude, in meters. Handy if the track was recorded with a different vertical datum than the LAS files
+    pub altitude_offset_m: f64,
+    /// altitudes are above the terrain (like KML `relativeToGround`), instead of above sea level
+    pub relative_to_ground: bool,
+    /// see `AltitudeTrailMode`
+    pub mode: AltitudeTrailMode,
+}
+
+impl Default for AltitudeTrailOptions {
+    fn default() -> Self {
+        AltitudeTrailOptions {
+            width_m: 5f64,
+            altitude_offset_m: 0f64,
+            relative_to_ground: false,
+            mode: AltitudeTrailMode::default(),
+        }
+    }
+}
+
+/// Loads every line string and Google Earth track in a KML file that has an altitude on every point.
+/// Lines without altitudes are skipped (load those with `kml_utils::load_kml_file`)
+#[cfg(feature = "kml")]
+pub fn load_kml_altitude_trails<P: AsRef<Path>>(path: P) -> Result<Vec<AltitudeTrail>, LasToStlError>{
+    let mut kml_reader = KmlReader::<_, f64>::from_path(path)?;
+    let kml_data: Kml<f64> = kml_reader.read()?;
+
+    let mut trails: Vec<AltitudeTrail> = Vec::new();
+    collect_kml_altitude_trails(kml_data, &mut trails);
+    Ok(trails)
+}
+
+#[cfg(feature = "kml")]
+fn collect_kml_altitude_trails(kml: Kml<f64>, trails: &mut Vec<AltitudeTrail>){
+    match kml {
+        Kml::KmlDocument(document) => {
+            document.elements.into_iter().for_each(|element| collect_kml_altitude_trails(element, trails))
+        }
+        Kml::Document { elements, .. } | Kml::Folder { elements, .. } => {
+            elements.into_iter().for_each(|element| collect_kml_altitude_trails(element, trails))
+        }
+        Kml::Placemark(placemark) => {
+            if let Some(geometry) = placemark.geometry {
+                collect_kml_geometry_altitude_trails(geometry, trails);
+            }
+            placemark.children.iter().for_each(|element| collect_element_altitude_trails(element, trails));
+        }
+        Kml::LineString(line_string) => collect_kml_geometry_altitude_trails(kml::types::Geometry::LineString(line_string), trails),
+        Kml::MultiGeometry(multi_geometry) => collect_kml_geometry_altitude_trails(kml::types::Geometry::MultiGeometry(multi_geometry), trails),
+        Kml::Element(element) => collect_element_altitude_trails(&element, trails),
+        _ => {}
+    }
+}
+
+#[cfg(feature = "kml")]
+fn collect_kml_geometry_altitude_trails(geometry: kml::types::Geometry<f64>, trails: &mut Vec<AltitudeTrail>){
+    match geometry {
+        kml::types::Geometry::LineString(line_string) => {
+            let altitudes: Option<Vec<f64>> = line_string.coords.iter().map(|coord| coord.z).collect();
+            match altitudes {
+                Some(altitudes) => trails.push(AltitudeTrail {
+                    points: line_string.coords.iter().map(|coord| Coord { x: coord.x, y: coord.y }).collect(),
+                    altitudes,
+                }),
+                None => warn!("skipping a line string without an altitude on every point"),
+            }
+        }
+        kml::types::Geometry::MultiGeometry(multi_geometry) => {
+            multi_geometry.geometries.into_iter().for_each(|geometry| collect_kml_geometry_altitude_trails(geometry, trails))
+        }
+        kml::types::Geometry::Element(element) => collect_element_altitude_trails(&element, trails),
+        _ => {}
+    }
+}
+
+/// finds `gx:Track`s, which the kml library keeps as plain elements
+#[cfg(feature = "kml")]
+fn collect_element_altitude_trails(element: &Element, trails: &mut Vec<AltitudeTrail>){
+    if element.name != "Track" {
+        element.children.iter().for_each(|child| collect_element_altitude_trails(child, trails));
+        return
+    }
+    // gx:coord is "lon lat alt"
+    let mut trail = AltitudeTrail::default();
+    for coord in element.children.iter().filter(|child| child.name == "coord") {
+        let values: Vec<f64> = coord.content.as_deref().unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if let [lon, lat, altitude] = values[..] {
+            trail.points.push(Coord { x: lon, y: lat });
+            trail.altitudes.push(altitude);
+        }
+    }
+    if trail.altitudes.len() >= 2 {
+        trails.push(trail);
+    }
+}
+
+/// Loads every track segment and route of a GPX file, with the elevation (`ele`) of every point.
+/// Points without an elevation are skipped
+pub fn load_gpx_trails<P: AsRef<Path>>(path: P) -> Result<Vec<AltitudeTrail>, LasToStlError>{
+    let mut reader = Reader::from_file(path)?;
+    reader.config_mut().trim_text(true);
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let mut trails: Vec<AltitudeTrail> = Vec::new();
+    let mut current_trail: Option<AltitudeTrail> = None;
+    let mut current_point: Option<Coord> = None;
+    let mut in_elevation = false;
+
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(start) => match start.local_name().as_ref() {
+                b"trkseg" | b"rte" => current_trail = Some(AltitudeTrail::default()),
+                b"trkpt" | b"rtept" => {
+                    let mut point = Coord { x: f64::NAN, y: f64::NAN };
+                    for attribute in start.attributes().flatten() {
+                        let value = attribute.unescape_value()?.parse::<f64>().unwrap_or(f64::NAN);
+                        match attribute.key.local_name().as_ref() {
+                            b"lon" => point.x = value,
+                            b"lat" => point.y = value,
+                            _ => {}
+                        }
+                    }
+                    current_point = Some(point);
+                }
+                b"ele" => in_elevation = true,
+                _ => {}
+            },
+            Event::Text(text) if in_elevation => {
+                if let (Some(trail), Some(point), Ok(altitude)) = (&mut current_trail, current_point.take(), text.unescape()?.trim().parse::<f64>()) {
+                    if point.x.is_finite() && point.y.is_finite() {
+                        trail.points.push(point);
+                        trail.altitudes.push(altitude);
+                    }
+                }
+            }
+            Event::End(end) => match end.local_name().as_ref() {
+                b"trkseg" | b"rte" => {
+                    if let Some(trail) = current_trail.take() {
+                        if trail.altitudes.len() >= 2 {
+                            trails.push(trail);
+                        }
+                    }
+                }
+                b"ele" => in_elevation = false,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    Ok(trails)
+}
+
+impl HeightMap {
+    /// Sets the terrain under `trail` (within half of `options.width_m`) to the trail's own altitude,
+    /// interpolated between its points, instead of offsetting the terrain like `offset_by_mask` does.
+    /// See `AltitudeTrailOptions` to only raise or only carve.
+    ///
+    /// `bounds` aren't changed, so a trail above `bounds.max_z` is still fine in an STL, but will be clipped in images.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn emboss_altitude_trail(&mut self, trail: &AltitudeTrail, options: &AltitudeTrailOptions) -> Result<(), LasToStlError>{
+        let utm_zone = self.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+        let radius = options.width_m / 2f64;
+
+        let utm_points: Vec<Coord> = trail.points.iter()
+            .map(|point| Coord::from(&UtmCoord::from_gps_coord_in_zone(point, &utm_zone)))
+            .collect();
+
+        // (distance to the trail, altitude there) of every cell near it
+        let mut nearest: Vec<Option<(f64, f64)>> = vec![None; self.data.len()];
+        for (index, segment) in utm_points.windows(2).enumerate() {
+            let (start, end) = (segment[0], segment[1]);
+            let (start_altitude, end_altitude) = (trail.altitudes[index], trail.altitudes[index + 1]);
+            let (dx, dy) = (end.x - start.x, end.y - start.y);
+            let length_squared = dx * dx + dy * dy;
+
+            let first_x = ((start.x.min(end.x) - radius - self.bounds.min_x) / x_tick).ceil().max(0f64) as usize;
+            let last_x = ((start.x.max(end.x) + radius - self.bounds.min_x) / x_tick).floor().min((self.x_res - 1) as f64);
+            let first_y = ((start.y.min(end.y) - radius - self.bounds.min_y) / y_tick).ceil().max(0f64) as usize;
+            let last_y = ((start.y.max(end.y) + radius - self.bounds.min_y) / y_tick).floor().min((self.y_res - 1) as f64);
+            if last_x < 0f64 || last_y < 0f64 {
+                continue
+            }
+
+            for y in first_y..=(last_y as usize) {
+                for x in first_x..=(last_x as usize) {
+                    let cell = Coord { x: self.bounds.min_x + x as f64 * x_tick, y: self.bounds.min_y + y as f64 * y_tick };
+                    // how far along the segment the closest point to the cell is, 0 to 1
+                    let t = match length_squared > 0f64 {
+                        true => (((cell.x - start.x) * dx + (cell.y - start.y) * dy) / length_squared).clamp(0f64, 1f64),
+                        false => 0f64,
+                    };
+                    let distance = (cell.x - (start.x + t * dx)).hypot(cell.y - (start.y + t * dy));
+                    let cell_nearest = &mut nearest[y * self.x_res + x];
+                    if distance <= radius && cell_nearest.is_none_or(|(nearest_distance, _)| distance < nearest_distance) {
+                        *cell_nearest = Some((distance, start_altitude + t * (end_altitude - start_altitude)));
+                    }
+                }
+            }
+        }
+
+        for (height, cell_nearest) in self.data.iter_mut().zip(nearest) {
+            let Some((_, altitude)) = cell_nearest else { continue };
+            let mut target = altitude + options.altitude_offset_m;
+            if options.relative_to_ground {
+                target += *height;
+            }
+            *height = match options.mode {
+                AltitudeTrailMode::Replace => target,
+                AltitudeTrailMode::RaiseOnly => height.max(target),
+                AltitudeTrailMode::CarveOnly => height.min(target),
+            };
+        }
+        Ok(())
+    }
+}
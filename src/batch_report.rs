@@ -0,0 +1,87 @@
+use std::fmt::Display;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+
+/// One item of a batch that was left out, and why
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SkippedItem {
+    /// position of the item in the list it was given in
+    pub index: usize,
+    /// what the item was, like a file path or a coordinate
+    pub item: String,
+    /// the error that made it get skipped
+    pub reason: String,
+}
+
+/// What a function that works through a list of points or files did with each of them.
+/// Bad items are skipped and listed here instead of stopping the whole batch at the first one,
+/// so it's up to the caller whether a few missing points are fine (see `require_complete`)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchReport {
+    /// number of items that were used
+    pub num_succeeded: usize,
+    /// items that were left out, in the order they were skipped
+    pub skipped: Vec<SkippedItem>,
+}
+
+impl BatchReport {
+    pub fn new() -> BatchReport {
+        BatchReport::default()
+    }
+
+    /// counts one item as used
+    pub fn add_success(&mut self) {
+        self.num_succeeded += 1;
+    }
+
+    /// records an item as skipped because of `reason`
+    pub fn add_skipped<I: Display, R: Display>(&mut self, index: usize, item: I, reason: R) {
+        self.skipped.push(SkippedItem { index, item: item.to_string(), reason: reason.to_string() });
+    }
+
+    /// adds a success if `result` is `Ok`, otherwise skips the item with the error as the reason
+    pub fn record<T, I: Display>(&mut self, index: usize, item: I, result: Result<T, LasToStlError>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.add_success();
+                Some(value)
+            }
+            Err(e) => {
+                self.add_skipped(index, item, e);
+                None
+            }
+        }
+    }
+
+    /// number of items in the batch, used or not
+    pub fn num_items(&self) -> usize {
+        self.num_succeeded + self.skipped.len()
+    }
+
+    /// true if nothing was skipped
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    /// adds the counts and skipped items of another report (of a later part of the same batch) to this one
+    pub fn merge(&mut self, other: BatchReport) {
+        self.num_succeeded += other.num_succeeded;
+        self.skipped.extend(other.skipped);
+    }
+
+    /// logs a warning for every skipped item, `what` being what the items are (like "waypoint")
+    pub fn log_skipped(&self, what: &str) {
+        for skipped in &self.skipped {
+            warn!("skipped {what} {} ({}): {}", skipped.index, skipped.item, skipped.reason);
+        }
+    }
+
+    /// returns an `IncompleteBatchError` if anything was skipped, for callers that want all or nothing
+    pub fn require_complete(self) -> Result<BatchReport, LasToStlError> {
+        match self.is_complete() {
+            true => Ok(self),
+            false => Err(LasToStlError::IncompleteBatchError(self)),
+        }
+    }
+}
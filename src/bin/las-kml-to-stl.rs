@@ -0,0 +1,235 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::error;
+use simple_logger::SimpleLogger;
+use las_kml_to_stl::color_relief::Colormap;
+use las_kml_to_stl::errors::LasToStlError;
+use las_kml_to_stl::height_map::{HeightMap, HeightMapFormat};
+use las_kml_to_stl::kml_utils::{get_regions, get_trails, infer_utm_zone, load_kml_file};
+use las_kml_to_stl::las_resampler::LasLoadOptions;
+use las_kml_to_stl::mask::Mask;
+use las_kml_to_stl::point_filter::PointFilter;
+use las_kml_to_stl::project::run_project_file;
+use las_kml_to_stl::terrain_model::TerrainModelBuilder;
+
+/// Turns lidar (LAS/LAZ files) and KML regions and trails into 3D printable STL models of the terrain
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Load LAS/LAZ files into a heightmap and save it, so it doesn't have to be loaded again
+    Heightmap {
+        #[command(flatten)]
+        load: LoadArgs,
+        /// save it as (much bigger) JSON instead of the compressed binary format, to read it with other programs
+        #[arg(long)]
+        json: bool,
+        /// where to save the heightmap
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Draw the regions or trails of a KML file onto a mask for a saved heightmap, and save it as JSON
+    Mask {
+        /// heightmap made with the `heightmap` command
+        #[arg(long)]
+        height_map: PathBuf,
+        /// KML file with the regions or trails
+        #[arg(long)]
+        kml: PathBuf,
+        /// draw the trails this many meters wide instead of filling the regions
+        #[arg(long)]
+        trail_width: Option<f64>,
+        /// where to save the mask JSON
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Make an STL, from LAS/LAZ files or a saved heightmap
+    Stl {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// only keep the cells that are true in this mask JSON (made with the `mask` command)
+        #[arg(long, conflicts_with = "clip_kml")]
+        mask: Option<PathBuf>,
+        /// exaggerate the heights
+        #[arg(long, default_value_t = 1.0)]
+        z_scale: f64,
+        /// added to every height so the printed part is a little stronger
+        #[arg(long, default_value_t = 5.0)]
+        base_thickness: f32,
+        /// where to save the STL
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Run a project file (TOML or JSON) that describes the inputs, masks, operations and outputs of a whole build
+    Project {
+        /// the project file. Paths in it are relative to the folder it's in
+        path: PathBuf,
+    },
+    /// Save a heightmap as an image
+    Image {
+        #[command(flatten)]
+        source: SourceArgs,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// how heights are colored
+        #[arg(long, value_enum, default_value_t = ImageStyle::Grayscale)]
+        style: ImageStyle,
+        /// where to save the PNG
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+}
+
+/// where the LAS files are and how to load them
+#[derive(Args)]
+struct LoadArgs {
+    /// glob pattern of the LAS/LAZ files, like "lidar/*.laz" (quote it so the shell doesn't expand it)
+    #[arg(long)]
+    las: Option<String>,
+    /// number of cells across the x axis. The y axis keeps the aspect ratio
+    #[arg(short, long, default_value_t = 1000)]
+    resolution: usize,
+    /// only use ground points, leaving out buildings and trees
+    #[arg(long)]
+    bare_earth: bool,
+    /// number of files to read at the same time. Defaults to one per core
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+/// LAS files or a saved heightmap
+#[derive(Args)]
+struct SourceArgs {
+    #[command(flatten)]
+    load: LoadArgs,
+    /// heightmap made with the `heightmap` command, instead of `--las`
+    #[arg(long, conflicts_with = "las")]
+    height_map: Option<PathBuf>,
+}
+
+/// what's done to the terrain
+#[derive(Args)]
+struct ModelArgs {
+    /// only keep what's inside the polygons of this KML file
+    #[arg(long)]
+    clip_kml: Option<PathBuf>,
+    /// carve the trails in this KML file into the terrain
+    #[arg(long)]
+    carve_trails: Option<PathBuf>,
+    /// width of the carved trails, in meters
+    #[arg(long, default_value_t = 4.0)]
+    trail_width: f64,
+    /// how deep the trails are carved, in meters
+    #[arg(long, default_value_t = 2.0)]
+    trail_depth: f64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ImageStyle {
+    /// black to white
+    Grayscale,
+    /// the colors of paper maps, with hillshading
+    Relief,
+}
+
+impl LoadArgs {
+    fn to_load_options(&self) -> LasLoadOptions {
+        LasLoadOptions {
+            filter: if self.bare_earth { PointFilter::bare_earth() } else { PointFilter::default() },
+            num_threads: self.threads,
+            ..LasLoadOptions::default()
+        }
+    }
+}
+
+impl SourceArgs {
+    /// a builder with the heightmap or LAS files and the changes to the terrain
+    fn to_builder(&self, model: &ModelArgs) -> Result<TerrainModelBuilder, LasToStlError> {
+        let mut builder = TerrainModelBuilder::new()
+            .resolution(self.load.resolution)
+            .load_options(self.load.to_load_options());
+        builder = match (&self.height_map, &self.load.las) {
+            (Some(height_map), _) => builder.height_map(HeightMap::load(height_map)?),
+            (None, Some(las)) => builder.las_glob(las),
+            (None, None) => return Err(LasToStlError::NoHeightMapSourceError),
+        };
+        if let Some(clip_kml) = &model.clip_kml {
+            builder = builder.clip_to_kml(clip_kml);
+        }
+        if let Some(carve_trails) = &model.carve_trails {
+            builder = builder.carve_trails(carve_trails, model.trail_width, model.trail_depth);
+        }
+        Ok(builder)
+    }
+}
+
+fn main() -> ExitCode {
+    SimpleLogger::new().env().with_level(log::LevelFilter::Info).init().unwrap();
+
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), LasToStlError> {
+    match command {
+        Command::Heightmap { load, json, out } => {
+            let las = load.las.as_deref().ok_or(LasToStlError::NoHeightMapSourceError)?;
+            let height_map = HeightMap::glob_get_height_map_with_options(las, Some(load.resolution), None, &load.to_load_options())?;
+            height_map.save_with_format(out, if json { HeightMapFormat::Json } else { HeightMapFormat::Binary })
+        }
+        Command::Mask { height_map, kml, trail_width, out } => {
+            let mut height_map = HeightMap::load(height_map)?;
+            let geometry_collection = load_kml_file(kml)?;
+            if height_map.utm_zone.is_none() {
+                height_map.set_utm_zone(infer_utm_zone(&geometry_collection));
+            }
+            let mut mask = Mask::new_for_height_map(&height_map)?;
+            match trail_width {
+                Some(trail_width) => {
+                    for trail in get_trails(geometry_collection) {
+                        mask.add_lat_lon_trail_meters(&trail, trail_width / 2f64)?;
+                    }
+                }
+                None => {
+                    for region in get_regions(geometry_collection) {
+                        mask.add_filled_lat_lon_polygon(&region)?;
+                    }
+                }
+            }
+            mask.save(out)
+        }
+        Command::Stl { source, model, mask, z_scale, base_thickness, out } => {
+            let out = out.to_string_lossy();
+            let builder = source.to_builder(&model)?.z_scale(z_scale).base_thickness(base_thickness);
+            match mask {
+                Some(mask) => {
+                    let stl_options = builder.stl_options.clone();
+                    let terrain_model = builder.build()?;
+                    terrain_model.height_map.save_as_stl_masked_with_options(&out, &Mask::load(mask)?, &stl_options)
+                }
+                None => builder.save_stl(&out).map(|_| ()),
+            }
+        }
+        Command::Project { path } => run_project_file(path),
+        Command::Image { source, model, style, out } => {
+            let terrain_model = source.to_builder(&model)?.build()?;
+            match style {
+                ImageStyle::Grayscale => terrain_model.height_map.save_to_image(out),
+                ImageStyle::Relief => terrain_model.height_map.save_color_relief(out, &Colormap::Terrain, 0.5),
+            }
+        }
+    }
+}
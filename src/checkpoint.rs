@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use log::info;
+use crate::color_map::ColorAggregate;
+use crate::errors::LasToStlError;
+use crate::height_map::{BinningStrategy, HeightMapIntermediate, PointAggregate};
+use crate::utm_bounds::UtmBoundingBox;
+
+/// first bytes of every checkpoint file, to catch loading something that isn't a checkpoint (or an old version)
+const CHECKPOINT_MAGIC: &[u8; 8] = b"LKSCKPT2";
+
+/// A partially loaded `HeightMapIntermediate` and the LAS files that are already in it.
+///
+/// Unlike a saved `HeightMap`, this keeps the sums and counters of every cell, so loading can pick up
+/// where it left off after a crash or Ctrl-C. See `LasLoadOptions::checkpoint_path`
+pub struct Checkpoint {
+    pub intermediate: HeightMapIntermediate,
+
+    /// files that have already been read into `intermediate`. Files that failed to load aren't in here
+    pub done_files: Vec<PathBuf>,
+}
+
+impl Checkpoint {
+    /// Saves the checkpoint as a binary file.
+    /// The file is written next to `path` and then renamed, so a crash while saving doesn't destroy the last checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(&temp_path, path)?;
+        info!("saved checkpoint with {} files to {:?}", self.done_files.len(), path.display());
+        Ok(())
+    }
+
+    /// Loads a checkpoint saved with `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, LasToStlError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Checkpoint::read_from(&mut reader)
+    }
+
+    /// returns true if the checkpoint's grid can be continued with a new grid of this size, bounds and binning
+    pub fn matches(&self, other: &HeightMapIntermediate) -> bool {
+        self.intermediate.x_res == other.x_res &&
+            self.intermediate.y_res == other.y_res &&
+            self.intermediate.bounds == other.bounds &&
+            self.intermediate.binning == other.binning &&
+            self.intermediate.data.first().map(|aggregate| aggregate.keeps_samples()) == Some(other.keeps_samples()) &&
+            self.intermediate.intensity.is_some() == other.intensity.is_some() &&
+            self.intermediate.color.is_some() == other.color.is_some()
+    }
+
+    /// writes the checkpoint as little endian binary
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError> {
+        let intermediate = &self.intermediate;
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&(intermediate.x_res as u64).to_le_bytes())?;
+        writer.write_all(&(intermediate.y_res as u64).to_le_bytes())?;
+
+        let bounds = intermediate.bounds;
+        for value in [bounds.min_x, bounds.max_x, bounds.min_y, bounds.max_y, bounds.min_z, bounds.max_z] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        let (binning_tag, percentile): (u8, f32) = match intermediate.binning {
+            BinningStrategy::Mean => (0, 0f32),
+            BinningStrategy::Min => (1, 0f32),
+            BinningStrategy::Max => (2, 0f32),
+            BinningStrategy::Median => (3, 0f32),
+            BinningStrategy::Percentile(percentile) => (4, percentile),
+        };
+        writer.write_all(&[binning_tag])?;
+        writer.write_all(&percentile.to_le_bytes())?;
+
+        writer.write_all(&[intermediate.intensity.is_some() as u8, intermediate.color.is_some() as u8])?;
+
+        for aggregate in &intermediate.data {
+            aggregate.write_to(writer)?;
+        }
+        if let Some(intensity) = &intermediate.intensity {
+            for aggregate in intensity {
+                aggregate.write_to(writer)?;
+            }
+        }
+        if let Some(color) = &intermediate.color {
+            for aggregate in color {
+                aggregate.write_to(writer)?;
+            }
+        }
+
+        writer.write_all(&(self.done_files.len() as u64).to_le_bytes())?;
+        for done_file in &self.done_files {
+            let path_bytes = done_file.to_string_lossy().into_owned().into_bytes();
+            writer.write_all(&(path_bytes.len() as u64).to_le_bytes())?;
+            writer.write_all(&path_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// reads a checkpoint written by `write_to`
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Checkpoint, LasToStlError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(LasToStlError::BadCheckpointError)
+        }
+
+        let x_res = read_u64(reader)? as usize;
+        let y_res = read_u64(reader)? as usize;
+
+        let mut bound_values = [0f64; 6];
+        for value in bound_values.iter_mut() {
+            *value = read_f64(reader)?;
+        }
+        let [min_x, max_x, min_y, max_y, min_z, max_z] = bound_values;
+        let bounds = UtmBoundingBox::new(min_x, max_x, min_y, max_y, min_z, max_z);
+
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let mut percentile_bytes = [0u8; 4];
+        reader.read_exact(&mut percentile_bytes)?;
+        let binning = match tag[0] {
+            0 => BinningStrategy::Mean,
+            1 => BinningStrategy::Min,
+            2 => BinningStrategy::Max,
+            3 => BinningStrategy::Median,
+            4 => BinningStrategy::Percentile(f32::from_le_bytes(percentile_bytes)),
+            _ => return Err(LasToStlError::BadCheckpointError),
+        };
+
+        let mut layer_flags = [0u8; 2];
+        reader.read_exact(&mut layer_flags)?;
+
+        let mut intermediate = HeightMapIntermediate::new_with_binning(x_res, y_res, bounds, binning)?;
+
+        for aggregate in intermediate.data.iter_mut() {
+            *aggregate = PointAggregate::read_from(reader)?;
+        }
+        if layer_flags[0] == 1 {
+            intermediate.intensity = Some((0..x_res * y_res)
+                .map(|_| PointAggregate::read_from(reader))
+                .collect::<Result<Vec<PointAggregate>, LasToStlError>>()?);
+        }
+        if layer_flags[1] == 1 {
+            intermediate.color = Some((0..x_res * y_res)
+                .map(|_| ColorAggregate::read_from(reader))
+                .collect::<Result<Vec<ColorAggregate>, LasToStlError>>()?);
+        }
+
+        let num_done_files = read_u64(reader)?;
+        let mut done_files = Vec::with_capacity(num_done_files as usize);
+        for _ in 0..num_done_files {
+            let mut path_bytes = vec![0u8; read_u64(reader)? as usize];
+            reader.read_exact(&mut path_bytes)?;
+            done_files.push(PathBuf::from(String::from_utf8_lossy(&path_bytes).into_owned()));
+        }
+
+        Ok(Checkpoint {
+            intermediate,
+            done_files,
+        })
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, LasToStlError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64, LasToStlError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use log::trace;
+use tiff::decoder::{Decoder, Limits};
+use crate::errors::LasToStlError;
+use crate::geotiff::read_geotiff_window;
+use crate::height_map::HeightMap;
+use crate::utm_bounds::UtmBoundingBox;
+
+/// how many bytes are downloaded at once. Reads are rounded out to whole blocks, which are kept
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A file on a web server that can be read and seeked in like a local file, using HTTP range requests.
+/// Only the blocks that are read are downloaded (once), so a tiff decoder can read the header and a few tiles
+/// of a huge Cloud-Optimized GeoTIFF without downloading the rest
+pub struct HttpRangeReader {
+    url: String,
+    length: u64,
+    position: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl HttpRangeReader {
+    /// Gets the size of the file with a HEAD request.
+    /// Returns `LasToStlError::HttpError` if the server doesn't say how big it is or doesn't do range requests
+    pub fn open(url: &str) -> Result<HttpRangeReader, LasToStlError> {
+        let response = ureq::head(url)
+            .call()
+            .map_err(|e| LasToStlError::HttpError(e.to_string()))?;
+        if response.header("Accept-Ranges") == Some("none") {
+            return Err(LasToStlError::HttpError(format!("{url} doesn't support range requests")))
+        }
+        let length = response.header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .ok_or_else(|| LasToStlError::HttpError(format!("{url} didn't say how big it is")))?;
+
+        Ok(HttpRangeReader { url: url.to_string(), length, position: 0, blocks: HashMap::new() })
+    }
+
+    /// downloads the blocks from `first` to `last` that aren't downloaded yet, in one request
+    fn download_blocks(&mut self, first: u64, last: u64) -> std::io::Result<()> {
+        let Some(first_missing) = (first..=last).find(|block| !self.blocks.contains_key(block)) else {
+            return Ok(())
+        };
+        let last_missing = (first_missing..=last).rev().find(|block| !self.blocks.contains_key(block)).unwrap_or(first_missing);
+
+        let start = first_missing * BLOCK_SIZE;
+        let end = ((last_missing + 1) * BLOCK_SIZE).min(self.length) - 1;
+        trace!("downloading bytes {start} to {end} of {}", self.url);
+        let response = ureq::get(&self.url)
+            .set("Range", &format!("bytes={start}-{end}"))
+            .call()
+            .map_err(|e| Error::other(e.to_string()))?;
+        if response.status() != 206 {
+            return Err(Error::new(ErrorKind::Unsupported, format!("{} ignored the range request", self.url)))
+        }
+        let mut bytes: Vec<u8> = Vec::with_capacity((end - start + 1) as usize);
+        response.into_reader().read_to_end(&mut bytes)?;
+
+        for (block, chunk) in (first_missing..=last_missing).zip(bytes.chunks(BLOCK_SIZE as usize)) {
+            self.blocks.entry(block).or_insert_with(|| chunk.to_vec());
+        }
+        Ok(())
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.length || buffer.is_empty() {
+            return Ok(0)
+        }
+        let end = (self.position + buffer.len() as u64).min(self.length);
+        let (first, last) = (self.position / BLOCK_SIZE, (end - 1) / BLOCK_SIZE);
+        self.download_blocks(first, last)?;
+
+        let mut read = 0;
+        while self.position < end {
+            let block = &self.blocks[&(self.position / BLOCK_SIZE)];
+            let offset = (self.position % BLOCK_SIZE) as usize;
+            let count = (block.len() - offset).min((end - self.position) as usize);
+            if count == 0 {
+                break
+            }
+            buffer[read..read + count].copy_from_slice(&block[offset..offset + count]);
+            read += count;
+            self.position += count as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let new_position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = new_position.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seeked before the start of the file"))?;
+        Ok(self.position)
+    }
+}
+
+impl HeightMap {
+    /// Same as `from_geotiff_window`, but for a Cloud-Optimized GeoTIFF on a web server.
+    /// Only the header and the tiles inside `bounds` (of the overview that fits `target_resolution`) are downloaded,
+    /// so building a small model never downloads a whole state's DEM
+    pub fn from_cog_url(url: &str, bounds: &UtmBoundingBox, target_resolution: Option<(usize, usize)>) -> Result<HeightMap, LasToStlError> {
+        let decoder = Decoder::new(HttpRangeReader::open(url)?)?.with_limits(Limits::unlimited());
+        read_geotiff_window(decoder, Path::new(url), bounds, target_resolution)
+    }
+}
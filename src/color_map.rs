@@ -0,0 +1,132 @@
+use std::io::{Read, Write};
+#[cfg(feature = "image")]
+use std::path::Path;
+#[cfg(feature = "image")]
+use image::{ImageBuffer, Rgb};
+use las::Color;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::utils::x_y_to_index;
+use crate::utm_bounds::UtmBoundingBox;
+
+/// Sums of the color channels of every point in a cell and a counter.
+/// This should only be used in the context of loading LAS files(s) into a `ColorMap`
+/// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+#[derive(Copy, Clone, Default)]
+pub struct ColorAggregate{
+    red_sum: u64,
+    green_sum: u64,
+    blue_sum: u64,
+    num_points: u32
+}
+
+impl ColorAggregate{
+    pub fn add_sample(&mut self, color: Color){
+        self.red_sum += color.red as u64;
+        self.green_sum += color.green as u64;
+        self.blue_sum += color.blue as u64;
+        self.num_points += 1;
+    }
+
+    /// adds all samples from `other` to self, as if they had been added with `add_sample`
+    pub fn merge(&mut self, other: &ColorAggregate){
+        self.red_sum += other.red_sum;
+        self.green_sum += other.green_sum;
+        self.blue_sum += other.blue_sum;
+        self.num_points += other.num_points;
+    }
+
+    /// returns the average color of all added values, or black if there were no samples.
+    pub fn get_average_or_black(&self) -> [u16; 3]{
+        if self.num_points == 0{
+            [0, 0, 0]
+        } else {
+            let num_points = self.num_points as u64;
+            [
+                (self.red_sum / num_points) as u16,
+                (self.green_sum / num_points) as u16,
+                (self.blue_sum / num_points) as u16,
+            ]
+        }
+    }
+
+    /// writes this aggregate as a little endian binary record.
+    /// Used to spill partial grids to disk when loading LAS files in streaming mode.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        writer.write_all(&self.red_sum.to_le_bytes())?;
+        writer.write_all(&self.green_sum.to_le_bytes())?;
+        writer.write_all(&self.blue_sum.to_le_bytes())?;
+        writer.write_all(&self.num_points.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// reads a record written by `write_to`
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<ColorAggregate, LasToStlError>{
+        let mut u64_bytes = [0u8; 8];
+        let mut u32_bytes = [0u8; 4];
+
+        reader.read_exact(&mut u64_bytes)?;
+        let red_sum = u64::from_le_bytes(u64_bytes);
+        reader.read_exact(&mut u64_bytes)?;
+        let green_sum = u64::from_le_bytes(u64_bytes);
+        reader.read_exact(&mut u64_bytes)?;
+        let blue_sum = u64::from_le_bytes(u64_bytes);
+        reader.read_exact(&mut u32_bytes)?;
+        let num_points = u32::from_le_bytes(u32_bytes);
+
+        Ok(ColorAggregate{
+            red_sum,
+            green_sum,
+            blue_sum,
+            num_points,
+        })
+    }
+}
+
+/// A grid of average point colors spanning `bounds` (in utm), with the same layout as a `HeightMap`.
+/// Colors are kept as the 16 bit [red, green, blue] values from the LAS file.
+///
+/// Get one from `HeightMap::glob_get_maps` (or `HeightMapIntermediate::get_color_map`) by loading with `LasLoadOptions::aggregate_color`.
+/// Cells without any colored points are black.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ColorMap{
+    pub data: Vec<[u16; 3]>,
+    pub x_res: usize,
+    pub y_res: usize,
+    pub bounds: UtmBoundingBox
+}
+
+impl ColorMap{
+
+    /// Get the [red, green, blue] color at x, y. The coordinates are unit-less but evenly spaced.
+    pub fn get_color(&self, x: usize, y: usize) -> Result<[u16; 3], LasToStlError>{
+        Ok(self.data[x_y_to_index(self.x_res, self.y_res, x, y)?])
+    }
+
+    /// saves as a true color png.
+    ///
+    /// The LAS spec says colors should be scaled to 16 bits, but plenty of files just store 8 bit values.
+    /// If no channel in the map is above 255, the colors are assumed to be 8 bit and are not scaled down.
+    ///
+    /// Like `HeightMap::save_to_image`, the image is vertically flipped.
+    #[cfg(feature = "image")]
+    pub fn save_to_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        self.to_image()?.save(path)?;
+        Ok(())
+    }
+
+    /// gets the image `save_to_image` saves, with 8 bit colors
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, LasToStlError>{
+        let is_8_bit = self.data.iter().flatten().all(|channel| *channel <= 255);
+        let shift = if is_8_bit { 0 } else { 8 };
+
+        ImageBuffer::from_vec(
+            self.x_res as u32,
+            self.y_res as u32,
+            self.data.iter().flatten().map(|channel| {
+                (channel >> shift) as u8
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+use image::{ImageBuffer, RgbImage};
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+
+/// sun direction of the hillshade under a color relief, the usual one for maps
+const RELIEF_AZIMUTH_DEGREES: f64 = 315f64;
+const RELIEF_ALTITUDE_DEGREES: f64 = 45f64;
+
+/// matplotlib's viridis, sampled every eighth
+const VIRIDIS: [(f64, [u8; 3]); 9] = [
+    (0f64, [68, 1, 84]),
+    (0.125, [71, 44, 122]),
+    (0.25, [59, 81, 139]),
+    (0.375, [44, 113, 142]),
+    (0.5, [33, 144, 141]),
+    (0.625, [39, 173, 129]),
+    (0.75, [92, 200, 99]),
+    (0.875, [170, 220, 50]),
+    (1f64, [253, 231, 37]),
+];
+
+/// green valleys, tan hills, brown mountains and snowy peaks
+const TERRAIN: [(f64, [u8; 3]); 6] = [
+    (0f64, [16, 100, 60]),
+    (0.25, [120, 170, 80]),
+    (0.5, [230, 220, 140]),
+    (0.75, [160, 110, 60]),
+    (0.9, [140, 130, 120]),
+    (1f64, [255, 255, 255]),
+];
+
+/// The colors of the heights in a color relief image (see `HeightMap::save_color_relief`)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Colormap {
+    /// dark purple to yellow, readable for color blind people and in grayscale
+    Viridis,
+    /// the classic hypsometric tint of paper maps
+    #[default]
+    Terrain,
+    /// black to white, like `save_to_image`
+    Grayscale,
+    /// (height in meters, color) stops, sorted by height. Heights between stops are blended,
+    /// heights below the first or above the last stop get its color. e.g. a snow line at 3000m
+    Custom(Vec<(f64, [u8; 3])>),
+}
+
+impl Colormap {
+    /// the color of `height`, where `min_z` and `max_z` are the bottom and top of the built in colormaps
+    pub fn get_color(&self, height: f64, min_z: f64, max_z: f64) -> [u8; 3]{
+        let fraction = match max_z > min_z {
+            true => ((height - min_z) / (max_z - min_z)).clamp(0f64, 1f64),
+            false => 0f64,
+        };
+        match self {
+            Colormap::Viridis => interpolate_stops(&VIRIDIS, fraction),
+            Colormap::Terrain => interpolate_stops(&TERRAIN, fraction),
+            Colormap::Grayscale => [(fraction * 255f64).round() as u8; 3],
+            Colormap::Custom(stops) => interpolate_stops(stops, height),
+        }
+    }
+}
+
+/// blends between the two stops around `value`
+fn interpolate_stops(stops: &[(f64, [u8; 3])], value: f64) -> [u8; 3]{
+    let Some(upper) = stops.iter().position(|(stop, _)| *stop >= value) else {
+        return stops.last().map_or([0u8; 3], |(_, color)| *color)
+    };
+    if upper == 0 {
+        return stops[0].1
+    }
+    let ((low, low_color), (high, high_color)) = (stops[upper - 1], stops[upper]);
+    let t = (value - low) / (high - low);
+    let mut color = [0u8; 3];
+    for channel in 0..3 {
+        color[channel] = (low_color[channel] as f64 + (high_color[channel] as f64 - low_color[channel] as f64) * t).round() as u8;
+    }
+    color
+}
+
+impl HeightMap {
+    /// Colors every cell by its height with `colormap` and darkens it with a hillshade (sun in the north west),
+    /// like a hypsometric tint on a paper map. `hillshade_blend` is how much shading there is, 0 is flat color and 1
+    /// is full shading. Like `hillshade`, z needs to be in meters like x and y.
+    ///
+    /// The image is vertically flipped like `save_to_image`
+    pub fn to_color_relief_image(&self, colormap: &Colormap, hillshade_blend: f64) -> Result<RgbImage, LasToStlError>{
+        let shade = self.hillshade(RELIEF_AZIMUTH_DEGREES, RELIEF_ALTITUDE_DEGREES);
+        let hillshade_blend = hillshade_blend.clamp(0f64, 1f64);
+
+        ImageBuffer::from_vec(
+            self.x_res as u32,
+            self.y_res as u32,
+            self.data.iter().zip(&shade.data).flat_map(|(height, lit)| {
+                let brightness = 1f64 - hillshade_blend + hillshade_blend * lit;
+                colormap.get_color(*height, self.bounds.min_z, self.bounds.max_z)
+                    .map(|channel| (channel as f64 * brightness).round() as u8)
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)
+    }
+
+    /// Saves `to_color_relief_image` as a png (or whatever the extension is).
+    /// Much easier to read than `save_to_image`, e.g. `save_color_relief("map.png", &Colormap::Terrain, 0.6)`
+    pub fn save_color_relief<P: AsRef<Path>>(&self, path: P, colormap: &Colormap, hillshade_blend: f64) -> Result<(), LasToStlError>{
+        self.to_color_relief_image(colormap, hillshade_blend)?.save(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+#[cfg(feature = "geojson")]
+use std::fs::File;
+#[cfg(feature = "geojson")]
+use std::io::{BufWriter, Write};
+#[cfg(any(feature = "kml", feature = "geojson"))]
+use std::path::Path;
+use geo::{Coord, LineString};
+#[cfg(feature = "geojson")]
+use geojson::{Feature, FeatureCollection, JsonObject};
+#[cfg(feature = "kml")]
+use kml::types::{Element, Placemark};
+use log::info;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+#[cfg(feature = "kml")]
+use crate::kml_export::save_placemarks_as_kml;
+use crate::kml_utils::utm_linestring_to_lat_lon_linestring;
+
+/// An edge of the grid between two neighboring cells, where a contour line can cross.
+/// (true, x, y) is the edge from cell (x, y) to (x + 1, y), (false, x, y) from (x, y) to (x, y + 1)
+type GridEdge = (bool, usize, usize);
+
+impl HeightMap {
+    /// Finds contour lines every `interval` z units (multiples of `interval`) with marching squares.
+    ///
+    /// Returns (height, line) pairs, with the lines in UTM coordinates like `bounds`, so they can be drawn onto a mask
+    /// with `Mask::add_utm_trail`. Lines that close on themselves end with the same point they start with.
+    /// Cells next to empty cells (at or below `bounds.min_z`) are skipped, so there are no contours around holes.
+    pub fn contours(&self, interval: f64) -> Vec<(f64, LineString)> {
+        let mut contours: Vec<(f64, LineString)> = Vec::new();
+        if interval <= 0f64 || self.x_res < 2 || self.y_res < 2 {
+            return contours
+        }
+
+        // every level is worked out from its multiple instead of adding up `interval`, so rounding errors don't build up
+        let first_multiple = (self.bounds.min_z / interval).floor() + 1f64;
+        let levels = (0u64..)
+            .map(|step| (first_multiple + step as f64) * interval)
+            .take_while(|level| *level <= self.bounds.max_z);
+        for level in levels {
+            contours.extend(self.contours_at(level).into_iter().map(|line| (level, line)));
+        }
+
+        info!("found {} contour lines", contours.len());
+        contours
+    }
+
+    /// Same as `contours`, but in lat/lon (x = lon, y = lat), so they can be saved for other tools.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn lat_lon_contours(&self, interval: f64) -> Result<Vec<(f64, LineString)>, LasToStlError> {
+        let utm_zone = self.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+        self.contours(interval).into_iter().map(|(level, line)| {
+            Ok((level, utm_linestring_to_lat_lon_linestring(&line, utm_zone)?))
+        }).collect()
+    }
+
+    /// Saves the contour lines every `interval` (see `contours`) as lat/lon line strings in a KML file, to check them
+    /// against a published topo map in Google Earth. Every line is named after its elevation and has it as extended data
+    #[cfg(feature = "kml")]
+    pub fn save_contours_as_kml<P: AsRef<Path>>(&self, path: P, interval: f64) -> Result<(), LasToStlError> {
+        let placemarks = self.lat_lon_contours(interval)?.into_iter().map(|(level, line)| {
+            let value = Element { name: String::from("value"), content: Some(level.to_string()), ..Element::default() };
+            let data = Element {
+                name: String::from("Data"),
+                attrs: HashMap::from([(String::from("name"), String::from("elevation"))]),
+                children: vec![value],
+                ..Element::default()
+            };
+            Placemark {
+                name: Some(level.to_string()),
+                geometry: Some(kml::types::Geometry::LineString(kml::types::LineString::from(line))),
+                children: vec![Element { name: String::from("ExtendedData"), children: vec![data], ..Element::default() }],
+                ..Placemark::default()
+            }
+        }).collect();
+        save_placemarks_as_kml(path, placemarks)
+    }
+
+    /// Same as `save_contours_as_kml`, but saves a GeoJSON feature collection with an `elevation` property on every line
+    #[cfg(feature = "geojson")]
+    pub fn save_contours_as_geojson<P: AsRef<Path>>(&self, path: P, interval: f64) -> Result<(), LasToStlError> {
+        let features = self.lat_lon_contours(interval)?.into_iter().map(|(level, line)| {
+            let mut properties = JsonObject::new();
+            properties.insert(String::from("elevation"), level.into());
+            Feature {
+                geometry: Some(geojson::Geometry::from(&line)),
+                properties: Some(properties),
+                ..Feature::default()
+            }
+        }).collect();
+        let collection = FeatureCollection { features, bbox: None, foreign_members: None };
+
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, &collection)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Finds the contour lines at a single height with marching squares, in UTM coordinates. See `contours`
+    pub fn contours_at(&self, level: f64) -> Vec<LineString> {
+        let segments = self.get_contour_segments(level);
+
+        // every edge is the end of at most two segments, so the lines can be followed through the edges
+        let mut segments_at_edge: HashMap<GridEdge, Vec<usize>> = HashMap::new();
+        for (segment_index, (start, end)) in segments.iter().enumerate() {
+            segments_at_edge.entry(*start).or_default().push(segment_index);
+            segments_at_edge.entry(*end).or_default().push(segment_index);
+        }
+
+        let mut used = vec![false; segments.len()];
+        let mut lines: Vec<LineString> = Vec::new();
+
+        // open lines first (starting at an edge with only one segment), so they aren't started from the middle,
+        // then whatever is left is a loop
+        let open_starts: Vec<usize> = segments_at_edge.values()
+            .filter(|edge_segments| edge_segments.len() == 1)
+            .map(|edge_segments| edge_segments[0])
+            .collect();
+        let starts = open_starts.into_iter().chain(0..segments.len());
+
+        for start_segment in starts {
+            if used[start_segment] {
+                continue
+            }
+            let (first_edge, second_edge) = segments[start_segment];
+            // start from the end that has no other segment, if there is one
+            let mut current_edge = if segments_at_edge[&first_edge].len() == 1 { first_edge } else { second_edge };
+            let mut edges: Vec<GridEdge> = vec![current_edge];
+            let mut current_segment = Some(start_segment);
+
+            while let Some(segment_index) = current_segment {
+                used[segment_index] = true;
+                let (start, end) = segments[segment_index];
+                current_edge = if start == current_edge { end } else { start };
+                edges.push(current_edge);
+                current_segment = segments_at_edge[&current_edge].iter()
+                    .copied()
+                    .find(|next_segment| !used[*next_segment]);
+            }
+
+            lines.push(LineString::new(edges.iter().map(|edge| self.get_edge_crossing(*edge, level)).collect()));
+        }
+
+        lines
+    }
+
+    /// finds every piece of a contour line, as the two grid edges each piece goes between
+    fn get_contour_segments(&self, level: f64) -> Vec<(GridEdge, GridEdge)> {
+        let mut segments: Vec<(GridEdge, GridEdge)> = Vec::new();
+
+        for y in 0..self.y_res - 1 {
+            for x in 0..self.x_res - 1 {
+                let corners = [
+                    self.data[y * self.x_res + x],
+                    self.data[y * self.x_res + x + 1],
+                    self.data[(y + 1) * self.x_res + x + 1],
+                    self.data[(y + 1) * self.x_res + x],
+                ];
+                if corners.iter().any(|height| self.is_empty_height(*height)) {
+                    continue
+                }
+
+                let case = corners.iter().enumerate().fold(0usize, |case, (corner, height)| {
+                    if *height >= level { case | (1 << corner) } else { case }
+                });
+
+                let bottom: GridEdge = (true, x, y);
+                let right: GridEdge = (false, x + 1, y);
+                let top: GridEdge = (true, x, y + 1);
+                let left: GridEdge = (false, x, y);
+
+                // corners are numbered counter clockwise from the bottom left
+                match case {
+                    0 | 15 => {}
+                    1 | 14 => segments.push((left, bottom)),
+                    2 | 13 => segments.push((bottom, right)),
+                    3 | 12 => segments.push((left, right)),
+                    4 | 11 => segments.push((right, top)),
+                    6 | 9 => segments.push((bottom, top)),
+                    7 | 8 => segments.push((left, top)),
+                    5 | 10 => {
+                        // saddle, the average of the corners decides which way the lines bend
+                        let center_above = corners.iter().sum::<f64>() / 4f64 >= level;
+                        if (case == 5) == center_above {
+                            segments.push((left, top));
+                            segments.push((bottom, right));
+                        } else {
+                            segments.push((left, bottom));
+                            segments.push((right, top));
+                        }
+                    }
+                    _ => unreachable!("only 4 corners"),
+                }
+            }
+        }
+
+        segments
+    }
+
+    /// gets the UTM coordinate where the contour at `level` crosses `edge`
+    fn get_edge_crossing(&self, edge: GridEdge, level: f64) -> Coord {
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        let (horizontal, x, y) = edge;
+        let (end_x, end_y) = if horizontal { (x + 1, y) } else { (x, y + 1) };
+        let start_height = self.data[y * self.x_res + x];
+        let end_height = self.data[end_y * self.x_res + end_x];
+
+        let t = if end_height == start_height {
+            0.5
+        } else {
+            ((level - start_height) / (end_height - start_height)).clamp(0f64, 1f64)
+        };
+
+        Coord {
+            x: self.bounds.min_x + (x as f64 + t * (end_x - x) as f64) * x_tick,
+            y: self.bounds.min_y + (y as f64 + t * (end_y - y) as f64) * y_tick,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utm_bounds::UtmBoundingBox;
+
+    /// 5x3 cells a meter apart, going up a meter per cell to the east, from 0 to 4
+    fn east_ramp() -> HeightMap {
+        HeightMap {
+            data: (0..15).map(|index| (index % 5) as f64).collect(),
+            x_res: 5,
+            y_res: 3,
+            bounds: UtmBoundingBox::new(500000f64, 500004f64, 4000000f64, 4000002f64, -1f64, 4f64),
+            utm_zone: None,
+            geoid_correction: None,
+            crs: None,
+        }
+    }
+
+    #[test]
+    fn ramp_contours_are_straight_lines_at_their_height() {
+        let contours = east_ramp().contours(0.5);
+        let levels: Vec<f64> = contours.iter().map(|(level, _)| *level).collect();
+        assert_eq!(levels, vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0]);
+        for (level, line) in contours {
+            assert_eq!(line.0.len(), 3, "line at {level}");
+            for coord in line.coords() {
+                assert!((coord.x - (500000f64 + level)).abs() < 1e-9, "line at {level} has x {}", coord.x);
+            }
+        }
+    }
+
+    #[test]
+    fn small_intervals_dont_drift() {
+        let levels: Vec<f64> = east_ramp().contours(0.1).into_iter().map(|(level, _)| level).collect();
+        assert_eq!(levels.len(), 40);
+        for (index, level) in levels.iter().enumerate() {
+            assert_eq!(*level, (index + 1) as f64 * 0.1);
+        }
+    }
+
+    #[test]
+    fn contours_go_around_a_hill_and_not_around_holes() {
+        let mut height_map = east_ramp();
+        height_map.data = vec![
+            0f64, 0f64, 0f64, 0f64, 0f64,
+            0f64, 0f64, 2f64, 0f64, 0f64,
+            0f64, 0f64, 0f64, 0f64, 0f64,
+        ];
+        let lines = height_map.contours_at(1f64);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].is_closed());
+        assert_eq!(lines[0].0.len(), 5);
+
+        // the hill is an empty cell now, so every square touching it is skipped
+        height_map.data[7] = -1f64;
+        assert!(height_map.contours_at(-0.5).is_empty());
+    }
+}
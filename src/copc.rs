@@ -0,0 +1,280 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use las::{Header, Point, Read as LasRead, Reader};
+use laz::laszip::{ChunkTable, ChunkTableEntry};
+use laz::{LasZipDecompressor, LazVlr};
+use log::trace;
+use crate::errors::LasToStlError;
+use crate::utm_bounds::UtmBoundingBox;
+
+/// user id of the COPC VLRs
+pub const COPC_USER_ID: &str = "copc";
+
+/// record id of the COPC info VLR
+pub const COPC_INFO_RECORD_ID: u16 = 1;
+
+/// user id of the VLR that describes how the points are compressed
+const LASZIP_USER_ID: &str = "laszip encoded";
+
+/// record id of the laszip VLR
+const LASZIP_RECORD_ID: u16 = 22204;
+
+/// size in bytes of a single hierarchy entry
+const HIERARCHY_ENTRY_SIZE: usize = 32;
+
+/// returns true if the file looks like a [COPC](https://copc.io) file (ends with `.copc.laz`)
+pub fn is_copc_path(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase().ends_with(".copc.laz"))
+        .unwrap_or(false)
+}
+
+/// The COPC info VLR. Describes the cube the octree covers and where the hierarchy starts.
+#[derive(Clone, Copy, Debug)]
+pub struct CopcInfo {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub center_z: f64,
+    /// half the side length of the root cube
+    pub halfsize: f64,
+    /// space between points at the root level
+    pub spacing: f64,
+    /// file offset of the root hierarchy page
+    pub root_hier_offset: u64,
+    /// size in bytes of the root hierarchy page
+    pub root_hier_size: u64,
+    pub gpstime_min: f64,
+    pub gpstime_max: f64,
+}
+
+impl CopcInfo {
+    /// Reads the COPC info VLR from a LAS header. Returns `None` if it isn't a COPC file.
+    pub fn from_las_header(header: &Header) -> Option<CopcInfo> {
+        let vlr = header.vlrs().iter().find(|vlr| {
+            vlr.user_id == COPC_USER_ID && vlr.record_id == COPC_INFO_RECORD_ID
+        })?;
+
+        let mut reader = Cursor::new(&vlr.data);
+        Some(CopcInfo {
+            center_x: read_f64(&mut reader).ok()?,
+            center_y: read_f64(&mut reader).ok()?,
+            center_z: read_f64(&mut reader).ok()?,
+            halfsize: read_f64(&mut reader).ok()?,
+            spacing: read_f64(&mut reader).ok()?,
+            root_hier_offset: read_u64(&mut reader).ok()?,
+            root_hier_size: read_u64(&mut reader).ok()?,
+            gpstime_min: read_f64(&mut reader).ok()?,
+            gpstime_max: read_f64(&mut reader).ok()?,
+        })
+    }
+}
+
+/// Position of a node in the octree. The root is level 0, and every level splits each node into 8.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelKey {
+    pub level: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl VoxelKey {
+    /// gets the region of space this node covers
+    pub fn get_bounds(&self, info: &CopcInfo) -> UtmBoundingBox {
+        let root_cube = UtmBoundingBox::new(
+            info.center_x - info.halfsize, info.center_x + info.halfsize,
+            info.center_y - info.halfsize, info.center_y + info.halfsize,
+            info.center_z - info.halfsize, info.center_z + info.halfsize,
+        );
+        self.get_bounds_in_cube(&root_cube)
+    }
+
+    /// gets the region of space this node covers, if the root node covers `root_cube`
+    pub fn get_bounds_in_cube(&self, root_cube: &UtmBoundingBox) -> UtmBoundingBox {
+        let divisions = 2f64.powi(self.level);
+        let side_x = root_cube.x_range() / divisions;
+        let side_y = root_cube.y_range() / divisions;
+        let side_z = (root_cube.max_z - root_cube.min_z) / divisions;
+        let min_x = root_cube.min_x + self.x as f64 * side_x;
+        let min_y = root_cube.min_y + self.y as f64 * side_y;
+        let min_z = root_cube.min_z + self.z as f64 * side_z;
+        UtmBoundingBox::new(min_x, min_x + side_x, min_y, min_y + side_y, min_z, min_z + side_z)
+    }
+}
+
+impl FromStr for VoxelKey {
+    type Err = LasToStlError;
+
+    /// parses a key written like "level-x-y-z", the way EPT names its files
+    fn from_str(key: &str) -> Result<VoxelKey, LasToStlError> {
+        let parts: Vec<i32> = key.split('-')
+            .map(|part| part.parse::<i32>())
+            .collect::<Result<Vec<i32>, _>>()
+            .map_err(|_| LasToStlError::BadVoxelKeyError(key.to_string()))?;
+        match parts[..] {
+            [level, x, y, z] => {
+                Ok(VoxelKey { level, x, y, z })
+            }
+            _ => {
+                Err(LasToStlError::BadVoxelKeyError(key.to_string()))
+            }
+        }
+    }
+}
+
+impl Display for VoxelKey {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}-{}-{}-{}", self.level, self.x, self.y, self.z)
+    }
+}
+
+/// A node of the octree that has points, and where its compressed chunk of points is in the file
+#[derive(Clone, Copy, Debug)]
+pub struct CopcEntry {
+    pub key: VoxelKey,
+    pub offset: u64,
+    pub byte_size: u64,
+    pub point_count: u64,
+}
+
+/// Reads a [COPC](https://copc.io) (Cloud Optimized Point Cloud) file, using its octree to only decompress
+/// the points near a region of interest.
+///
+/// COPC files are also normal LAZ files, so they can be read with `las::Reader` as well, just without skipping anything.
+pub struct CopcReader {
+    pub path: PathBuf,
+    pub header: Header,
+    pub info: CopcInfo,
+    pub laz_vlr: LazVlr,
+    /// every node in the octree that has points
+    pub entries: Vec<CopcEntry>,
+}
+
+impl CopcReader {
+    /// Opens a COPC file and reads the whole octree hierarchy (not the points)
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CopcReader, LasToStlError> {
+        let path = path.as_ref().to_path_buf();
+        let header = Reader::from_path(&path)?.header().clone();
+
+        let info = CopcInfo::from_las_header(&header).ok_or_else(|| LasToStlError::NotCopcError(path.clone()))?;
+
+        let laz_vlr_data = &header.vlrs().iter().find(|vlr| {
+            vlr.user_id == LASZIP_USER_ID && vlr.record_id == LASZIP_RECORD_ID
+        }).ok_or_else(|| LasToStlError::NotCopcError(path.clone()))?.data;
+        let laz_vlr = LazVlr::from_buffer(laz_vlr_data).map_err(las::Error::from)?;
+
+        let mut file = BufReader::new(File::open(&path)?);
+        let mut entries: Vec<CopcEntry> = Vec::new();
+
+        // pages that still need to be read, as (offset, size)
+        let mut pages: Vec<(u64, u64)> = vec![(info.root_hier_offset, info.root_hier_size)];
+        while let Some((page_offset, page_size)) = pages.pop() {
+            file.seek(SeekFrom::Start(page_offset))?;
+            let mut page = vec![0u8; page_size as usize];
+            file.read_exact(&mut page)?;
+
+            for entry_bytes in page.chunks_exact(HIERARCHY_ENTRY_SIZE) {
+                let mut reader = Cursor::new(entry_bytes);
+                let key = VoxelKey {
+                    level: read_i32(&mut reader)?,
+                    x: read_i32(&mut reader)?,
+                    y: read_i32(&mut reader)?,
+                    z: read_i32(&mut reader)?,
+                };
+                let offset = read_u64(&mut reader)?;
+                let byte_size = read_i32(&mut reader)?;
+                let point_count = read_i32(&mut reader)?;
+
+                if point_count == -1 {
+                    // this entry points to another hierarchy page
+                    pages.push((offset, byte_size as u64));
+                } else if point_count > 0 {
+                    entries.push(CopcEntry {
+                        key,
+                        offset,
+                        byte_size: byte_size as u64,
+                        point_count: point_count as u64,
+                    });
+                }
+            }
+        }
+
+        trace!("{:?} has {} octree nodes with points", path.display(), entries.len());
+
+        Ok(CopcReader {
+            path,
+            header,
+            info,
+            laz_vlr,
+            entries,
+        })
+    }
+
+    /// gets the nodes that could have points in `bounds` (only x and y are checked), or every node if `bounds` is `None`
+    pub fn get_entries_in_bounds(&self, bounds: Option<&UtmBoundingBox>) -> Vec<CopcEntry> {
+        self.entries.iter().filter(|entry| {
+            match bounds {
+                Some(bounds) => {
+                    entry.key.get_bounds(&self.info).overlaps_xy(bounds)
+                }
+                None => {
+                    true
+                }
+            }
+        }).copied().collect()
+    }
+
+    /// decompresses every point in a single node
+    pub fn read_entry(&self, entry: &CopcEntry) -> Result<Vec<Point>, LasToStlError> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+
+        // every node is a standalone LAZ chunk, so wrap it up like a LAZ file with a single chunk
+        // (offset to the chunk table, the chunk, then the chunk table) for the decompressor
+        let mut buffer: Vec<u8> = Vec::with_capacity(entry.byte_size as usize + 64);
+        buffer.extend_from_slice(&(8 + entry.byte_size as i64).to_le_bytes());
+        buffer.resize(8 + entry.byte_size as usize, 0);
+        file.read_exact(&mut buffer[8..])?;
+
+        let mut chunk_table = ChunkTable::with_capacity(1);
+        chunk_table.push(ChunkTableEntry {
+            point_count: entry.point_count,
+            byte_count: entry.byte_size,
+        });
+        chunk_table.write_to(&mut buffer, &self.laz_vlr)?;
+
+        let record_size = self.laz_vlr.items_size() as usize;
+        let mut records = vec![0u8; record_size * entry.point_count as usize];
+
+        let mut decompressor = LasZipDecompressor::new(Cursor::new(buffer), self.laz_vlr.clone()).map_err(las::Error::from)?;
+        decompressor.decompress_many(&mut records)?;
+
+        let format = self.header.point_format();
+        let transforms = self.header.transforms();
+
+        records.chunks_exact(record_size).map(|record| {
+            Ok(Point::new(las::raw::Point::read_from(record, format)?, transforms))
+        }).collect()
+    }
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64, LasToStlError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, LasToStlError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> Result<i32, LasToStlError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
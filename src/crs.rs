@@ -0,0 +1,354 @@
+use std::fmt::Display;
+use geo::{Coord, LineString, MapCoords, Polygon};
+use las::Header;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::state_plane::StatePlane;
+use crate::utm_point::UtmZone;
+
+/// user id of the VLRs that hold projection info in a LAS header
+pub const LAS_PROJECTION_USER_ID: &str = "LASF_Projection";
+
+/// record id of the OGC WKT coordinate system VLR
+pub const WKT_RECORD_ID: u16 = 2112;
+
+/// record id of the GeoTIFF GeoKeyDirectoryTag VLR
+pub const GEO_KEY_DIRECTORY_RECORD_ID: u16 = 34735;
+
+/// GeoTIFF key for the EPSG code of a projected CRS (UTM, state plane, ...)
+const PROJECTED_CS_TYPE_GEO_KEY: u16 = 3072;
+
+/// GeoTIFF key for the EPSG code of a geographic CRS (lat/lon)
+const GEOGRAPHIC_TYPE_GEO_KEY: u16 = 2048;
+
+/// GeoTIFF uses this for "user defined", which is not a real EPSG code
+const USER_DEFINED_GEO_KEY_VALUE: u16 = 32767;
+
+/// EPSG code of WGS84 lat/lon, which KML, GPX and GeoJSON are always in
+pub const WGS84_LAT_LON_EPSG: u32 = 4326;
+
+/// A coordinate reference system, as read from a LAS header or given by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Crs {
+    /// an EPSG code, like 32610 for UTM zone 10N or 2226 for California zone 2 (survey feet)
+    Epsg(u32),
+    /// an OGC WKT definition
+    Wkt(String),
+}
+
+impl Crs {
+    /// Creates the CRS for a UTM zone on the WGS84 datum. (EPSG 326xx in the north, 327xx in the south)
+    pub fn utm_wgs84(zone: u8, north: bool) -> Crs {
+        UtmZone::new(zone, north).to_crs()
+    }
+
+    /// WGS84 lat/lon (EPSG 4326), the CRS of everything loaded with `kml_utils`
+    pub fn wgs84() -> Crs {
+        Crs::Epsg(WGS84_LAT_LON_EPSG)
+    }
+
+    /// true for lat/lon on WGS84 (EPSG 4326), NAD83 (EPSG 4269) or ETRS89 (EPSG 4258),
+    /// which are close enough to WGS84 to be converted to UTM without PROJ
+    pub fn is_lat_lon(&self) -> bool {
+        matches!(self, Crs::Epsg(4326 | 4269 | 4258))
+    }
+
+    /// Reads the CRS from the VLRs (and EVLRs) of a LAS header.
+    ///
+    /// A WKT VLR wins over a GeoKeyDirectory if a file has both, since LAS 1.4 says WKT is the real one.
+    /// Returns `None` if the header doesn't say, which is sadly common.
+    pub fn from_las_header(header: &Header) -> Option<Crs> {
+        let mut geo_key_crs: Option<Crs> = None;
+
+        for vlr in header.all_vlrs() {
+            if vlr.user_id != LAS_PROJECTION_USER_ID {
+                continue;
+            }
+            if vlr.record_id == WKT_RECORD_ID {
+                // WKT is null terminated (sometimes more than once)
+                let wkt = String::from_utf8_lossy(&vlr.data);
+                let wkt = wkt.trim_end_matches('\0').trim();
+                if !wkt.is_empty() {
+                    return Some(Crs::Wkt(wkt.to_string()));
+                }
+            } else if vlr.record_id == GEO_KEY_DIRECTORY_RECORD_ID {
+                geo_key_crs = geo_key_crs.or(Crs::from_geo_key_directory(&vlr.data));
+            }
+        }
+
+        geo_key_crs
+    }
+
+    /// Parses the EPSG code out of a GeoKeyDirectoryTag record.
+    /// The record is a list of u16s: a header of [version, revision, minor revision, number of keys],
+    /// then 4 u16s per key of [key id, tiff tag location, count, value].
+    /// Only keys stored directly in the value (location 0) are used.
+    ///
+    /// Returns `None` if the projected CRS is user defined, even if there's a geographic CRS,
+    /// because that's only the lat/lon the user defined projection is based on, not what the coordinates are in.
+    pub fn from_geo_key_directory(data: &[u8]) -> Option<Crs> {
+        let shorts: Vec<u16> = data.chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+
+        let num_keys = *shorts.get(3)? as usize;
+
+        let mut geographic: Option<u16> = None;
+
+        for key in shorts[4..].chunks_exact(4).take(num_keys) {
+            let (key_id, location, value) = (key[0], key[1], key[3]);
+            if key_id == PROJECTED_CS_TYPE_GEO_KEY && location == 0 && value == USER_DEFINED_GEO_KEY_VALUE {
+                return None
+            }
+            if location != 0 || value == 0 || value == USER_DEFINED_GEO_KEY_VALUE {
+                continue;
+            }
+            match key_id {
+                PROJECTED_CS_TYPE_GEO_KEY => {
+                    return Some(Crs::Epsg(value as u32))
+                }
+                GEOGRAPHIC_TYPE_GEO_KEY => {
+                    geographic = Some(value)
+                }
+                _ => {}
+            }
+        }
+
+        geographic.map(|value| Crs::Epsg(value as u32))
+    }
+
+    /// Gets the UTM zone of this CRS, if it is UTM on WGS84 (EPSG 326xx/327xx), NAD83 (EPSG 269xx),
+    /// ETRS89 (EPSG 258xx), or WKT with "UTM zone" and a zone like "10N" in its name.
+    pub fn get_utm_zone(&self) -> Option<UtmZone> {
+        match self {
+            Crs::Epsg(code) => {
+                let (zone, north) = match code {
+                    32601..=32660 => (code - 32600, true),
+                    32701..=32760 => (code - 32700, false),
+                    26901..=26923 => (code - 26900, true),
+                    25828..=25838 => (code - 25800, true),
+                    _ => return None,
+                };
+                Some(UtmZone::new(zone as u8, north))
+            }
+            Crs::Wkt(wkt) => {
+                // only the name of the CRS, not every UTM zone mentioned in the rest of the WKT
+                let name = wkt.split(',').next()?.to_lowercase().replace('_', " ");
+                let after_zone = &name[name.find("utm zone ")? + "utm zone ".len()..];
+                let digits: String = after_zone.chars().take_while(|c| c.is_ascii_digit()).collect();
+                let zone: u8 = digits.parse().ok()?;
+                let north = match after_zone[digits.len()..].chars().next()? {
+                    'n' => true,
+                    's' => false,
+                    _ => return None,
+                };
+                if (1..=60).contains(&zone) {
+                    Some(UtmZone::new(zone, north))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Meters per unit of the coordinates (and usually the heights) of this CRS.
+    /// 1 unless it's a state plane zone in feet (see `StatePlane`)
+    pub fn meters_per_unit(&self) -> f64 {
+        StatePlane::from_crs(self).map_or(1f64, |state_plane| state_plane.meters_per_unit)
+    }
+
+    /// Gets the string PROJ takes to create this CRS ("EPSG:32610" or the WKT itself)
+    pub fn to_proj_string(&self) -> String {
+        match self {
+            Crs::Epsg(code) => {
+                format!("EPSG:{code}")
+            }
+            Crs::Wkt(wkt) => {
+                wkt.clone()
+            }
+        }
+    }
+}
+
+impl Display for Crs {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Crs::Epsg(code) => {
+                write!(formatter, "EPSG:{code}")
+            }
+            Crs::Wkt(wkt) => {
+                // WKT is long, the name at the start is enough to tell what it is
+                write!(formatter, "WKT: {}", wkt.chars().take(64).collect::<String>())
+            }
+        }
+    }
+}
+
+/// A CRS that can be converted to and from lat/lon without PROJ
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuiltInCrs {
+    /// x = lon, y = lat
+    LatLon,
+    Utm(UtmZone),
+    StatePlane(StatePlane),
+}
+
+impl BuiltInCrs {
+    /// Gets the built in version of `crs`, if there is one
+    pub fn from_crs(crs: &Crs) -> Option<BuiltInCrs> {
+        if crs.is_lat_lon() {
+            Some(BuiltInCrs::LatLon)
+        } else if let Some(utm_zone) = crs.get_utm_zone() {
+            Some(BuiltInCrs::Utm(utm_zone))
+        } else {
+            StatePlane::from_crs(crs).map(BuiltInCrs::StatePlane)
+        }
+    }
+
+    /// converts x, y in this CRS to lat/lon (x = lon, y = lat)
+    pub fn to_lat_lon(&self, x: f64, y: f64) -> Result<Coord<f64>, LasToStlError> {
+        match self {
+            BuiltInCrs::LatLon => Ok(Coord{x, y}),
+            BuiltInCrs::Utm(utm_zone) => utm_zone.to_lat_lon(x, y),
+            BuiltInCrs::StatePlane(state_plane) => Ok(state_plane.to_lat_lon(x, y)),
+        }
+    }
+
+    /// converts lat/lon (x = lon, y = lat) to x, y in this CRS
+    pub fn from_lat_lon(&self, lat_lon: &Coord<f64>) -> (f64, f64) {
+        match self {
+            BuiltInCrs::LatLon => (lat_lon.x, lat_lon.y),
+            BuiltInCrs::Utm(utm_zone) => utm_zone.from_lat_lon(lat_lon),
+            BuiltInCrs::StatePlane(state_plane) => state_plane.from_lat_lon(lat_lon),
+        }
+    }
+}
+
+/// Converts x, y coordinates from one CRS to another.
+///
+/// Converting between UTM zones, lat/lon and US state plane zones (see `StatePlane`) is built in, anything else needs the `proj` feature (and libproj installed).
+/// Without it, `Reprojector::new` returns `LasToStlError::ProjFeatureDisabledError` for anything else.
+///
+/// PROJ contexts can't be shared between threads, so make one per thread.
+pub enum Reprojector {
+    /// goes through lat/lon to move UTM coordinates into another zone.
+    /// The small (< 2m) difference between WGS84, NAD83 and ETRS89 is ignored.
+    UtmZones {
+        from: UtmZone,
+        to: UtmZone,
+    },
+    /// goes through lat/lon, between any of the CRSs that are built in
+    BuiltIn {
+        from: BuiltInCrs,
+        to: BuiltInCrs,
+    },
+    #[cfg(feature = "proj")]
+    Proj(proj::Proj),
+}
+
+impl Reprojector {
+    /// Creates a reprojector from `from` to `to`.
+    /// Geographic coordinates are always in (lon, lat) order, no matter what the CRS definition says
+    pub fn new(from: &Crs, to: &Crs) -> Result<Reprojector, LasToStlError> {
+        if let (Some(from_zone), Some(to_zone)) = (from.get_utm_zone(), to.get_utm_zone()) {
+            return Reprojector::new_utm(from_zone, to_zone)
+        }
+        match (BuiltInCrs::from_crs(from), BuiltInCrs::from_crs(to)) {
+            (Some(from), Some(to)) => Ok(Reprojector::BuiltIn { from, to }),
+            _ => Reprojector::new_proj(from, to),
+        }
+    }
+
+    /// Creates a reprojector between two UTM zones.
+    ///
+    /// UTM gets really distorted away from its zone, so this fails with `LasToStlError::UtmZonesTooFarApartError`
+    /// if the zones aren't next to each other (or the same zone in the other hemisphere).
+    pub fn new_utm(from: UtmZone, to: UtmZone) -> Result<Reprojector, LasToStlError> {
+        if from.distance(&to) > 1 {
+            return Err(LasToStlError::UtmZonesTooFarApartError{from, to})
+        }
+        Ok(Reprojector::UtmZones {
+            from,
+            to,
+        })
+    }
+
+    #[cfg(feature = "proj")]
+    fn new_proj(from: &Crs, to: &Crs) -> Result<Reprojector, LasToStlError> {
+        let proj = proj::Proj::new_known_crs(&from.to_proj_string(), &to.to_proj_string(), None)
+            .map_err(|e| LasToStlError::ReprojectionError(format!("could not go from {from} to {to}: {e}")))?;
+        Ok(Reprojector::Proj(proj))
+    }
+
+    #[cfg(not(feature = "proj"))]
+    fn new_proj(_from: &Crs, _to: &Crs) -> Result<Reprojector, LasToStlError> {
+        Err(LasToStlError::ProjFeatureDisabledError)
+    }
+
+    /// converts a single coordinate
+    pub fn convert(&self, x: f64, y: f64) -> Result<(f64, f64), LasToStlError> {
+        match self {
+            Reprojector::UtmZones { from, to } => {
+                let lat_lon = from.to_lat_lon(x, y)?;
+                Ok(to.from_lat_lon(&lat_lon))
+            }
+            Reprojector::BuiltIn { from, to } => {
+                let lat_lon = from.to_lat_lon(x, y)?;
+                Ok(to.from_lat_lon(&lat_lon))
+            }
+            #[cfg(feature = "proj")]
+            Reprojector::Proj(proj) => {
+                proj.convert((x, y))
+                    .map_err(|e| LasToStlError::ReprojectionError(format!("could not convert ({x}, {y}): {e}")))
+            }
+        }
+    }
+
+    /// converts a single `Coord`
+    pub fn convert_coord(&self, coord: Coord) -> Result<Coord, LasToStlError> {
+        let (x, y) = self.convert(coord.x, coord.y)?;
+        Ok(Coord{x, y})
+    }
+
+    /// converts every point of a geometry (a `LineString`, `Polygon`, `GeometryCollection`, ...)
+    pub fn reproject<G: MapCoords<f64, f64, Output = G>>(&self, geometry: &G) -> Result<G, LasToStlError> {
+        geometry.try_map_coords(|coord| self.convert_coord(coord))
+    }
+}
+
+/// Reprojects every point of `line_string` from `from` to `to`,
+/// e.g. a trail from `Crs::wgs84()` into the state plane CRS of some lidar
+pub fn reproject_linestring(from: &Crs, to: &Crs, line_string: &LineString) -> Result<LineString, LasToStlError> {
+    Reprojector::new(from, to)?.reproject(line_string)
+}
+
+/// Same as `reproject_linestring`, for a polygon and its holes
+pub fn reproject_polygon(from: &Crs, to: &Crs, polygon: &Polygon) -> Result<Polygon, LasToStlError> {
+    Reprojector::new(from, to)?.reproject(polygon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a GeoKeyDirectory with the keys (key id, value), all stored directly in the value
+    fn geo_key_directory(keys: &[(u16, u16)]) -> Vec<u8> {
+        let mut shorts = vec![1, 1, 0, keys.len() as u16];
+        for (key_id, value) in keys {
+            shorts.extend([*key_id, 0, 1, *value]);
+        }
+        shorts.iter().flat_map(|short| short.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn user_defined_projection_is_not_its_geographic_crs() {
+        let projected = geo_key_directory(&[(GEOGRAPHIC_TYPE_GEO_KEY, 4269), (PROJECTED_CS_TYPE_GEO_KEY, 26910)]);
+        assert_eq!(Crs::from_geo_key_directory(&projected), Some(Crs::Epsg(26910)));
+
+        let geographic = geo_key_directory(&[(GEOGRAPHIC_TYPE_GEO_KEY, 4269)]);
+        assert_eq!(Crs::from_geo_key_directory(&geographic), Some(Crs::Epsg(4269)));
+
+        let user_defined = geo_key_directory(&[(GEOGRAPHIC_TYPE_GEO_KEY, 4269), (PROJECTED_CS_TYPE_GEO_KEY, USER_DEFINED_GEO_KEY_VALUE)]);
+        assert_eq!(Crs::from_geo_key_directory(&user_defined), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,150 @@
+use geo::{Coord, MapCoords};
+use serde::{Deserialize, Serialize};
+use crate::crs::Crs;
+use crate::state_plane::{eccentricity_squared, GRS80_A};
+
+/// milliarcseconds in a radian
+const MAS_PER_RADIAN: f64 = 180f64 / std::f64::consts::PI * 3600f64 * 1000f64;
+
+/// The ITRF2008 to NAD83(2011) transformation from NGS (Pearson & Snay 2013), at its reference epoch of 1997.0.
+/// WGS84 (G1762) is the same as ITRF2008 to a few centimeters
+const ITRF2008_TO_NAD83_EPOCH: f64 = 1997.0;
+const ITRF2008_TO_NAD83_TRANSLATION_M: [f64; 3] = [0.99343, -1.90331, -0.52655];
+const ITRF2008_TO_NAD83_ROTATION_MAS: [f64; 3] = [25.91467, 9.42645, 11.59935];
+const ITRF2008_TO_NAD83_SCALE_PPB: f64 = 1.71504;
+/// how much the above change per year, because NAD83 moves with the north american plate and WGS84 doesn't
+const ITRF2008_TO_NAD83_TRANSLATION_RATE_M: [f64; 3] = [0.00079, -0.00060, -0.00134];
+const ITRF2008_TO_NAD83_ROTATION_RATE_MAS: [f64; 3] = [0.06667, -0.75744, -0.05133];
+const ITRF2008_TO_NAD83_SCALE_RATE_PPB: f64 = -0.10201;
+
+/// The datums lat/lon and UTM coordinates are on here. Everything else treats them as the same,
+/// which is fine at a few meters per cell, but US agency lidar (NAD83) ends up a meter or two off
+/// from KML and GPS tracks (WGS84) at 1m resolution. See `DatumShift`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Datum {
+    /// what KML, GPX, GeoJSON and GPS are in
+    Wgs84,
+    /// NAD83 (2011), what USGS 3DEP, state and county lidar is usually in
+    Nad83,
+}
+
+impl Datum {
+    /// Gets the datum of a CRS, like `Datum::Nad83` for EPSG 26910 (NAD83 / UTM zone 10N) or a state plane CRS.
+    /// Returns `None` if it can't tell, or for datums that aren't supported (like NAD27 or ETRS89)
+    pub fn from_crs(crs: &Crs) -> Option<Datum> {
+        match crs {
+            Crs::Epsg(code) => match code {
+                4326 | 32601..=32660 | 32701..=32760 => Some(Datum::Wgs84),
+                // lat/lon, UTM, NAD83(2011) lat/lon and UTM
+                4269 | 26901..=26923 | 6318 | 6330..=6348 => Some(Datum::Nad83),
+                // the built in state plane zones, see `StatePlane::from_epsg`
+                26941..=26946 | 2225..=2230 | 32126 | 32127 | 2269 | 2270 | 32148 | 32149 | 2285 | 2286 => Some(Datum::Nad83),
+                _ => None,
+            },
+            Crs::Wkt(wkt) => {
+                let lowercase = wkt.to_lowercase().replace(['_', ' '], "");
+                if lowercase.contains("nad83") || lowercase.contains("northamericandatum1983") {
+                    Some(Datum::Nad83)
+                } else if lowercase.contains("wgs84") || lowercase.contains("wgs1984") {
+                    Some(Datum::Wgs84)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// A 7 parameter (Helmert) transformation between two datums, for lat/lon coordinates.
+/// Shifting KML into the datum of the lidar before converting it to UTM puts trails and regions
+/// where they belong instead of a meter or two off. See `Mask::datum_shift`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DatumShift {
+    /// x, y, z (earth centered) in meters
+    pub translation_m: [f64; 3],
+    /// around x, y, z in milliarcseconds, "coordinate frame" convention (what NGS and EPSG use)
+    pub rotation_mas: [f64; 3],
+    /// in parts per billion
+    pub scale_ppb: f64,
+}
+
+impl DatumShift {
+    /// does nothing, for two datums that are the same
+    pub fn identity() -> DatumShift {
+        DatumShift { translation_m: [0f64; 3], rotation_mas: [0f64; 3], scale_ppb: 0f64 }
+    }
+
+    /// WGS84 to NAD83(2011), for coordinates measured (by GPS, or drawn on recent imagery) at `epoch`, as a decimal year like 2024.5.
+    /// The shift is about a meter and a half in the US and grows by a couple centimeters a year
+    pub fn wgs84_to_nad83(epoch: f64) -> DatumShift {
+        let years = epoch - ITRF2008_TO_NAD83_EPOCH;
+        let at_epoch = |value: [f64; 3], rate: [f64; 3]| [0, 1, 2].map(|axis| value[axis] + rate[axis] * years);
+        DatumShift {
+            translation_m: at_epoch(ITRF2008_TO_NAD83_TRANSLATION_M, ITRF2008_TO_NAD83_TRANSLATION_RATE_M),
+            rotation_mas: at_epoch(ITRF2008_TO_NAD83_ROTATION_MAS, ITRF2008_TO_NAD83_ROTATION_RATE_MAS),
+            scale_ppb: ITRF2008_TO_NAD83_SCALE_PPB + ITRF2008_TO_NAD83_SCALE_RATE_PPB * years,
+        }
+    }
+
+    /// the opposite of `wgs84_to_nad83`
+    pub fn nad83_to_wgs84(epoch: f64) -> DatumShift {
+        DatumShift::wgs84_to_nad83(epoch).inverse()
+    }
+
+    /// The shift from `from` to `to` for coordinates from `epoch` (see `wgs84_to_nad83`)
+    pub fn between(from: Datum, to: Datum, epoch: f64) -> DatumShift {
+        match (from, to) {
+            (Datum::Wgs84, Datum::Nad83) => DatumShift::wgs84_to_nad83(epoch),
+            (Datum::Nad83, Datum::Wgs84) => DatumShift::nad83_to_wgs84(epoch),
+            _ => DatumShift::identity(),
+        }
+    }
+
+    /// the shift the other way. Exact enough for parameters this small (well under a millimeter off)
+    pub fn inverse(&self) -> DatumShift {
+        DatumShift {
+            translation_m: self.translation_m.map(|value| -value),
+            rotation_mas: self.rotation_mas.map(|value| -value),
+            scale_ppb: -self.scale_ppb,
+        }
+    }
+
+    /// shifts one lat/lon coordinate (x = lon, y = lat), on the surface of the ellipsoid
+    pub fn shift_lat_lon(&self, lat_lon: &Coord<f64>) -> Coord<f64> {
+        let [x, y, z] = lat_lon_to_ecef(lat_lon);
+        let [rx, ry, rz] = self.rotation_mas.map(|value| value / MAS_PER_RADIAN);
+        let scale = 1f64 + self.scale_ppb * 1e-9;
+        let [tx, ty, tz] = self.translation_m;
+        ecef_to_lat_lon([
+            tx + scale * x + rz * y - ry * z,
+            ty - rz * x + scale * y + rx * z,
+            tz + ry * x - rx * y + scale * z,
+        ])
+    }
+
+    /// shifts every point of a lat/lon geometry (a `LineString`, `Polygon`, `GeometryCollection`, ...)
+    pub fn shift<G: MapCoords<f64, f64, Output = G>>(&self, geometry: &G) -> G {
+        geometry.map_coords(|coord| self.shift_lat_lon(&coord))
+    }
+}
+
+/// lat/lon (x = lon, y = lat) at height 0 to earth centered x, y, z in meters
+fn lat_lon_to_ecef(lat_lon: &Coord<f64>) -> [f64; 3] {
+    let (lat, lon) = (lat_lon.y.to_radians(), lat_lon.x.to_radians());
+    let e2 = eccentricity_squared();
+    let n = GRS80_A / (1f64 - e2 * lat.sin().powi(2)).sqrt();
+    [n * lat.cos() * lon.cos(), n * lat.cos() * lon.sin(), n * (1f64 - e2) * lat.sin()]
+}
+
+/// the opposite of `lat_lon_to_ecef`, dropping the height
+fn ecef_to_lat_lon([x, y, z]: [f64; 3]) -> Coord<f64> {
+    let e2 = eccentricity_squared();
+    let p = x.hypot(y);
+    // converges to well under a millimeter in a few steps anywhere but right at the poles
+    let mut lat = z.atan2(p * (1f64 - e2));
+    for _ in 0..5 {
+        let n = GRS80_A / (1f64 - e2 * lat.sin().powi(2)).sqrt();
+        lat = (z + e2 * n * lat.sin()).atan2(p);
+    }
+    Coord { x: y.atan2(x).to_degrees(), y: lat.to_degrees() }
+}
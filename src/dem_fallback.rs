@@ -0,0 +1,109 @@
+use log::{info, warn};
+use crate::errors::LasToStlError;
+use crate::height_map::{fill_holes, min_z_below_heights, HeightMap, HeightMapIntermediate};
+use crate::mask::Mask;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+impl HeightMapIntermediate {
+    /// Converts to a `HeightMap` like `HeightMap::from`, but cells no points landed in (that `hole_filling` didn't fill either)
+    /// get their height from `dem` instead, e.g. a coarse SRTM tile loaded with `HeightMap::from_geotiff`.
+    /// For bounds that reach past the edge of the lidar tiles.
+    ///
+    /// Also returns a mask of the cells that came from the DEM, so the low fidelity areas can be checked
+    /// (`Mask::save_to_image` or `Mask::to_kml`), or left out of the model.
+    /// The z bounds grow to fit the DEM heights.
+    ///
+    /// `dem` doesn't need to have the same resolution or bounds, it is sampled with `HeightMap::sample_utm`.
+    /// Returns `LasToStlError::HeightMapUtmZoneMismatchError` if they say they're in different UTM zones,
+    /// and `LasToStlError::NoUtmZoneError` if neither knows its zone (the mask needs one)
+    pub fn to_height_map_with_dem_fallback(&self, dem: &HeightMap) -> Result<(HeightMap, Mask), LasToStlError>{
+        let utm_zone = get_shared_utm_zone(self.utm_zone, dem)?;
+
+        // fill the small gaps from the lidar around them first
+        let mut heights = self.get_heights();
+        if let Some(hole_filling) = self.hole_filling {
+            heights = fill_holes(&heights, self.x_res, self.y_res, hole_filling);
+        }
+
+        let mut bounds = self.bounds;
+        let from_dem = fill_from_dem(&mut heights, self.x_res, &mut bounds, dem);
+        let height_map = HeightMap{
+            data: heights.iter().map(|height| height.unwrap_or(bounds.min_z)).collect(),
+            x_res: self.x_res,
+            y_res: self.y_res,
+            bounds: UtmBoundingBox{ utm_zone: Some(utm_zone), ..bounds },
+            utm_zone: Some(utm_zone),
+            geoid_correction: None,
+            crs: None,
+        };
+
+        let mut mask = Mask::new_for_height_map(&height_map)?;
+        mask.data = from_dem;
+        Ok((height_map, mask))
+    }
+}
+
+impl HeightMap {
+    /// Same as `HeightMapIntermediate::to_height_map_with_dem_fallback`, for a heightmap that was already made (or saved and loaded).
+    /// Empty cells are the ones at or below `bounds.min_z`, like `fill_holes`, so fill holes from the lidar first if you want to.
+    ///
+    /// Returns the mask of the cells that came from the DEM. The z bounds grow to fit the DEM heights,
+    /// so make other masks after this
+    pub fn fill_holes_from_dem(&mut self, dem: &HeightMap) -> Result<Mask, LasToStlError>{
+        let utm_zone = get_shared_utm_zone(self.utm_zone, dem)?;
+        let mut heights = self.get_heights();
+
+        let from_dem = fill_from_dem(&mut heights, self.x_res, &mut self.bounds, dem);
+        self.data = heights.iter().map(|height| height.unwrap_or(self.bounds.min_z)).collect();
+        self.set_utm_zone(Some(utm_zone));
+
+        let mut mask = Mask::new_for_height_map(self)?;
+        mask.data = from_dem;
+        Ok(mask)
+    }
+}
+
+/// the zone both say they're in, or the one that says
+fn get_shared_utm_zone(utm_zone: Option<UtmZone>, dem: &HeightMap) -> Result<UtmZone, LasToStlError>{
+    match (utm_zone, dem.utm_zone) {
+        (Some(zone), Some(dem_zone)) if zone != dem_zone => {
+            Err(LasToStlError::HeightMapUtmZoneMismatchError{ first: zone, other: dem_zone })
+        }
+        (zone, dem_zone) => zone.or(dem_zone).ok_or(LasToStlError::NoUtmZoneError),
+    }
+}
+
+/// Sets the `None` heights to the height of `dem` there, where it has data, and grows the z of `bounds` to fit.
+/// Returns which cells were filled
+fn fill_from_dem(heights: &mut [Option<f64>], x_res: usize, bounds: &mut UtmBoundingBox, dem: &HeightMap) -> Vec<bool>{
+    let y_res = heights.len() / x_res;
+    let x_tick = bounds.x_range() / (x_res - 1) as f64;
+    let y_tick = bounds.y_range() / (y_res - 1) as f64;
+
+    let mut from_dem: Vec<bool> = vec![false; heights.len()];
+    for (index, height) in heights.iter_mut().enumerate() {
+        if height.is_some() {
+            continue
+        }
+        let utm_x = bounds.min_x + (index % x_res) as f64 * x_tick;
+        let utm_y = bounds.min_y + (index / x_res) as f64 * y_tick;
+        if let Some(dem_height) = dem.sample_utm(utm_x, utm_y) {
+            *height = Some(dem_height);
+            from_dem[index] = true;
+        }
+    }
+
+    let num_filled = from_dem.iter().filter(|filled| **filled).count();
+    let num_empty = heights.iter().filter(|height| height.is_none()).count();
+    info!("filled {num_filled} / {} cells ({:.1}%) from the DEM", heights.len(), num_filled as f64 / heights.len() as f64 * 100f64);
+    if num_empty > 0 {
+        warn!("{num_empty} cells have no lidar or DEM data");
+    }
+
+    for height in heights.iter().flatten() {
+        bounds.max_z = bounds.max_z.max(*height);
+    }
+    bounds.min_z = min_z_below_heights(bounds.min_z, heights);
+    from_dem
+}
@@ -0,0 +1,63 @@
+#[cfg(feature = "image")]
+use std::path::Path;
+#[cfg(feature = "image")]
+use image::{ImageBuffer, Luma};
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::utils::x_y_to_index;
+use crate::utm_bounds::UtmBoundingBox;
+
+/// A grid of how many points fell into each cell, with the same layout as a `HeightMap`.
+///
+/// Get one from `HeightMapIntermediate::get_density_map`. Useful to see which cells have sparse or no coverage
+/// before picking a resolution, since empty cells just default to the lowest point in the heightmap.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DensityMap{
+    pub data: Vec<u32>,
+    pub x_res: usize,
+    pub y_res: usize,
+    pub bounds: UtmBoundingBox
+}
+
+impl DensityMap{
+
+    /// Get the number of points at x, y. The coordinates are unit-less but evenly spaced.
+    pub fn get_count(&self, x: usize, y: usize) -> Result<u32, LasToStlError>{
+        Ok(self.data[x_y_to_index(self.x_res, self.y_res, x, y)?])
+    }
+
+    /// number of cells without a single point
+    pub fn get_empty_cell_count(&self) -> usize{
+        self.data.iter().filter(|count| **count == 0).count()
+    }
+
+    /// percentage (0 to 100) of cells with at least one point
+    pub fn get_percent_coverage(&self) -> f64{
+        100f64 * (self.data.len() - self.get_empty_cell_count()) as f64 / self.data.len() as f64
+    }
+
+    /// average number of points per cell, counting empty cells
+    pub fn get_average_count(&self) -> f64{
+        self.data.iter().map(|count| *count as f64).sum::<f64>() / self.data.len() as f64
+    }
+
+    /// saves as a black and white png with brightness representing the number of points.
+    /// Empty cells are black, and the brightness is scaled so the densest cell is white.
+    ///
+    /// Like `HeightMap::save_to_image`, the image is vertically flipped.
+    #[cfg(feature = "image")]
+    pub fn save_to_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        let max_count = self.data.iter().copied().max().unwrap_or(0).max(1);
+
+        let image: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_vec(
+            self.x_res as u32,
+            self.y_res as u32,
+            self.data.iter().map(|count| {
+                ((*count as u64 * 255) / max_count as u64) as u8
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)?;
+
+        image.save(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::{info, trace};
+use serde::Deserialize;
+use crate::copc::VoxelKey;
+use crate::crs::Crs;
+use crate::errors::LasToStlError;
+use crate::height_map::{HeightMap, HeightMapIntermediate};
+use crate::las_resampler::LasLoadOptions;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::utm_bounds::UtmBoundingBox;
+
+/// the only EPT data type that can be read, every node is a normal LAZ file
+const LASZIP_DATA_TYPE: &str = "laszip";
+
+/// stage reported to the `ProgressSink` while downloading nodes
+const DOWNLOADING_STAGE: &str = "downloading EPT nodes";
+
+/// The spatial reference of an EPT dataset, as written in `ept.json`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EptSrs {
+    /// usually "EPSG"
+    pub authority: Option<String>,
+    /// the code of the horizontal CRS, as a string (like "3857")
+    pub horizontal: Option<String>,
+    pub vertical: Option<String>,
+    pub wkt: Option<String>,
+}
+
+/// The `ept.json` metadata at the root of an [EPT](https://entwine.io/en/latest/entwine-point-tile.html) dataset
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EptInfo {
+    /// the cube the octree covers, as [min x, min y, min z, max x, max y, max z]
+    pub bounds: [f64; 6],
+    /// the tight bounds of the points, same order as `bounds`
+    pub bounds_conformance: [f64; 6],
+    /// how the points of every node are stored. Only "laszip" is supported
+    pub data_type: String,
+    /// total number of points in the dataset
+    pub points: u64,
+    #[serde(default)]
+    pub srs: EptSrs,
+}
+
+impl EptInfo {
+    /// gets the cube the octree covers
+    pub fn get_cube(&self) -> UtmBoundingBox {
+        let [min_x, min_y, min_z, max_x, max_y, max_z] = self.bounds;
+        UtmBoundingBox::new(min_x, max_x, min_y, max_y, min_z, max_z)
+    }
+
+    /// gets the bounds of the points themselves (smaller than the cube)
+    pub fn get_data_bounds(&self) -> UtmBoundingBox {
+        let [min_x, min_y, min_z, max_x, max_y, max_z] = self.bounds_conformance;
+        UtmBoundingBox::new(min_x, max_x, min_y, max_y, min_z, max_z)
+    }
+
+    /// Gets the CRS of the dataset, preferring the EPSG code over the WKT. `None` if `ept.json` doesn't say
+    pub fn get_crs(&self) -> Option<Crs> {
+        let epsg = match (&self.srs.authority, &self.srs.horizontal) {
+            (Some(authority), Some(horizontal)) if authority.eq_ignore_ascii_case("EPSG") => {
+                horizontal.parse::<u32>().ok()
+            }
+            _ => {
+                None
+            }
+        };
+        epsg.map(Crs::Epsg).or_else(|| self.srs.wkt.clone().map(Crs::Wkt))
+    }
+}
+
+/// A node of the octree that has points
+#[derive(Clone, Copy, Debug)]
+pub struct EptNode {
+    pub key: VoxelKey,
+    pub point_count: u64,
+}
+
+/// Reads a hosted [EPT](https://entwine.io/en/latest/entwine-point-tile.html) (Entwine Point Tiles) point cloud
+/// over HTTP, like the USGS 3DEP lidar on AWS. Only the nodes of the octree that overlap a region are downloaded.
+///
+/// EPT only stores the CRS in `ept.json`, not in the LAZ files of the nodes. Everything here passes it on
+/// as `LasLoadOptions::fallback_crs`, so keep that in mind if you load the downloaded files yourself.
+pub struct EptReader {
+    /// the url of the dataset, without the trailing "/ept.json"
+    pub base_url: String,
+    pub info: EptInfo,
+}
+
+impl EptReader {
+    /// Downloads `ept.json` and checks that the data can be read.
+    /// `url` can be to `ept.json` itself or the folder it's in.
+    pub fn open(url: &str) -> Result<EptReader, LasToStlError> {
+        let base_url = url.trim_end_matches('/').trim_end_matches("/ept.json").to_string();
+
+        let info: EptInfo = get_json(&format!("{base_url}/ept.json"))?;
+        if info.data_type != LASZIP_DATA_TYPE {
+            return Err(LasToStlError::UnsupportedEptDataTypeError(info.data_type))
+        }
+
+        info!("opened EPT dataset {base_url} with {} points", info.points);
+
+        Ok(EptReader {
+            base_url,
+            info,
+        })
+    }
+
+    /// Reads the parts of the hierarchy that overlap `bounds` (only x and y are checked) and returns the nodes
+    /// in it that have points. `bounds` is in the dataset's CRS (see `EptInfo::get_crs`).
+    ///
+    /// Every level of the octree has points, with the coarsest at the root.
+    /// `max_level` stops at that level for a quick low density preview. `None` gets everything.
+    pub fn get_nodes_in_bounds(&self, bounds: &UtmBoundingBox, max_level: Option<i32>) -> Result<Vec<EptNode>, LasToStlError> {
+        let cube = self.info.get_cube();
+        let mut nodes: Vec<EptNode> = Vec::new();
+
+        // hierarchy pages that still need to be read
+        let mut pages: Vec<String> = vec![String::from("0-0-0-0")];
+        while let Some(page) = pages.pop() {
+            let entries: HashMap<String, i64> = get_json(&format!("{}/ept-hierarchy/{page}.json", self.base_url))?;
+
+            for (key_string, point_count) in entries {
+                let key = VoxelKey::from_str(&key_string)?;
+                if max_level.is_some_and(|max_level| key.level > max_level) ||
+                    !key.get_bounds_in_cube(&cube).overlaps_xy(bounds) {
+                    continue
+                }
+                if point_count == -1 {
+                    // the rest of this subtree is in its own page
+                    pages.push(key_string);
+                } else if point_count > 0 {
+                    nodes.push(EptNode {
+                        key,
+                        point_count: point_count as u64,
+                    });
+                }
+            }
+        }
+
+        trace!("{} EPT nodes overlap {bounds}", nodes.len());
+        Ok(nodes)
+    }
+
+    /// Downloads the LAZ file of every node in `nodes` into `directory` (created if it doesn't exist) and returns their paths.
+    ///
+    /// Files that are already there are not downloaded again, so use the same directory to resume a download
+    /// or to load overlapping regions without downloading everything twice.
+    pub fn download_nodes<P: AsRef<Path>>(&self, nodes: &[EptNode], directory: P, progress: &dyn ProgressSink) -> Result<Vec<PathBuf>, LasToStlError> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)?;
+
+        progress.on_stage(DOWNLOADING_STAGE);
+
+        let mut paths: Vec<PathBuf> = Vec::with_capacity(nodes.len());
+        for (node_number, node) in nodes.iter().enumerate() {
+            let path = directory.join(format!("{}.laz", node.key));
+            if !path.exists() {
+                // download next to it and rename, so an interrupted download isn't mistaken for a finished one
+                let temp_path = path.with_extension("tmp");
+                let response = ureq::get(&format!("{}/ept-data/{}.laz", self.base_url, node.key))
+                    .call()
+                    .map_err(|e| LasToStlError::HttpError(e.to_string()))?;
+                let mut writer = BufWriter::new(File::create(&temp_path)?);
+                std::io::copy(&mut response.into_reader(), &mut writer)?;
+                writer.flush()?;
+                drop(writer);
+                std::fs::rename(&temp_path, &path)?;
+                trace!("downloaded EPT node {} with {} points", node.key, node.point_count);
+            }
+            paths.push(path);
+            progress.on_progress(DOWNLOADING_STAGE, (node_number + 1) as f64 / nodes.len() as f64);
+        }
+
+        progress.on_finish(DOWNLOADING_STAGE);
+        Ok(paths)
+    }
+
+    /// Downloads the nodes that overlap `bounds` (see `get_nodes_in_bounds`) and loads them like `HeightMapIntermediate::load_paths`.
+    ///
+    /// `options.fallback_crs` is set to the dataset's CRS if it isn't set already, and if `options.crop` and
+    /// `options.target_crs` aren't set, the heightmap is cropped to `bounds`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load<P: AsRef<Path>>(&self,
+                                bounds: &UtmBoundingBox,
+                                max_level: Option<i32>,
+                                download_directory: P,
+                                resolution_x_in: Option<usize>,
+                                resolution_y_in: Option<usize>,
+                                options: &LasLoadOptions,
+                                progress: &dyn ProgressSink)
+        -> Result<HeightMapIntermediate, LasToStlError>
+    {
+        let nodes = self.get_nodes_in_bounds(bounds, max_level)?;
+        info!("downloading {} EPT nodes with {} points", nodes.len(), nodes.iter().map(|node| node.point_count).sum::<u64>());
+        let paths = self.download_nodes(&nodes, download_directory, progress)?;
+
+        let mut options = options.clone();
+        if options.fallback_crs.is_none() {
+            options.fallback_crs = self.info.get_crs();
+        }
+        if options.crop.is_none() && options.target_crs.is_none() {
+            options.crop = Some(*bounds);
+        }
+
+        HeightMapIntermediate::load_paths(paths, resolution_x_in, resolution_y_in, &options, progress)
+    }
+}
+
+impl HeightMap {
+    /// Creates a heightmap of `bounds` from a hosted EPT point cloud, so you don't have to find and download LAZ tiles yourself.
+    /// `bounds` is in the dataset's CRS (often web mercator, EPSG 3857, for USGS data. See `EptInfo::get_crs`).
+    ///
+    /// The LAZ files of the nodes are downloaded into `download_directory` and kept there, see `EptReader::download_nodes`.
+    /// Everything in `options` works like it does for LAS files on disk. Reprojecting from web mercator needs the `proj` feature.
+    pub fn ept_get_height_map<P: AsRef<Path>>(ept_url: &str,
+                                              bounds: &UtmBoundingBox,
+                                              download_directory: P,
+                                              resolution_x_in: Option<usize>,
+                                              resolution_y_in: Option<usize>,
+                                              options: &LasLoadOptions)
+        -> Result<HeightMap, LasToStlError>
+    {
+        let reader = EptReader::open(ept_url)?;
+        let intermediate = reader.load(bounds, None, download_directory, resolution_x_in, resolution_y_in, options, &LogProgress)?;
+        let mut height_map = HeightMap::from(intermediate);
+        if let Some(geoid_correction) = &options.geoid_correction {
+            height_map.apply_geoid_correction(geoid_correction)?;
+        }
+        Ok(height_map)
+    }
+}
+
+/// downloads and parses a json file
+fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, LasToStlError> {
+    trace!("downloading {url}");
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| LasToStlError::HttpError(e.to_string()))?;
+    Ok(serde_json::from_reader(response.into_reader())?)
+}
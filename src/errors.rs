@@ -1,6 +1,9 @@
 
 use thiserror::Error;
 use crate::utm_bounds::UtmBoundingBox;
+use crate::crs::Crs;
+use crate::batch_report::BatchReport;
+use crate::utm_point::UtmZone;
 
 #[derive(Error, Debug)]
 pub enum LasToStlError {
@@ -17,12 +20,32 @@ pub enum LasToStlError {
     SerdeError(#[from] serde_json::Error),
     #[error("Error in LAS parsing Library:\n\t{0}")]
     LasError(#[from] las::Error),
+    #[cfg(feature = "csv")]
     #[error("Error saving data as csv:\n\t{0}")]
     CsvError(#[from] csv::Error),
+    #[cfg(feature = "image")]
     #[error("Error saving image to file:\n\t{0}")]
     ImageError(#[from] image::ImageError),
+    #[error("Error building thread pool:\n\t{0}")]
+    ThreadPoolError(#[from] rayon::ThreadPoolBuildError),
+    #[cfg(feature = "kml")]
     #[error("Error in KML library:\n\t{0}")]
     KmlError(#[from] kml::Error),
+    #[cfg(feature = "stl")]
+    #[error("Error writing zip archive:\n\t{0}")]
+    ZipError(#[from] zip::result::ZipError),
+    #[cfg(feature = "geojson")]
+    #[error("Error in GeoJSON library:\n\t{0}")]
+    GeoJsonError(Box<geojson::Error>),
+    #[cfg(feature = "gpx")]
+    #[error("Error reading XML:\n\t{0}")]
+    XmlError(#[from] quick_xml::Error),
+    #[cfg(feature = "geotiff")]
+    #[error("Error reading TIFF:\n\t{0}")]
+    TiffError(#[from] tiff::TiffError),
+    #[cfg(feature = "project")]
+    #[error("Error reading TOML:\n\t{0}")]
+    TomlError(#[from] toml::de::Error),
     #[error("attempted to access the first element of a UTM trail, but it is not present.
         This could either be because an empty KML file was provided,
         or a different error that I have to deal with")]
@@ -59,11 +82,14 @@ pub enum LasToStlError {
         See documentation for `glob_get_height_map`.")]
     NoResolutionError,
 
+    #[error("Resolution can't be 0")]
+    ZeroResolutionError,
+
     #[error("`ImageBuffer::from_vec` returned None. Idk what this means or why or how. \
         Talk to Image: (https://docs.rs/image/0.24.7/).")]
     ImageNoneError,
 
-    #[error("Attempted to parse a linestring as a closed polygon, but it is not closed.")]
+    #[error("Attempted to parse a linestring as a closed polygon, but it is not closed (or not close enough to closed).")]
     OpenLineStringError,
 
     #[error("Polygon does not have a bounding rectangle?? probably empty")]
@@ -81,7 +107,7 @@ pub enum LasToStlError {
         (https://docs.rs/geo/0.27.0/geo/geometry/struct.LineString.html#impl-LineInterpolatePoint%3CT%3E-for-LineString%3CT%3E)")]
     InterpolatePointError,
 
-    #[error("Attempted to apply a mask to a heightmap or combine two masks of different resolutions/bounds.
+    #[error("Attempted to apply a mask to a heightmap or combine two masks of different resolutions/bounds. (see `Mask::resample_to`)
         other_x_res: {other_x_res},
         other_y_res: {other_y_res},
         mask_x_res: {mask_x_res},
@@ -93,8 +119,162 @@ pub enum LasToStlError {
         other_y_res: usize,
         mask_x_res: usize,
         mask_y_res: usize,
-        other_bounds: UtmBoundingBox,
-        mask_bounds: UtmBoundingBox
+        other_bounds: Box<UtmBoundingBox>,
+        mask_bounds: Box<UtmBoundingBox>
+    },
+
+    #[error("Reprojecting between these coordinate systems needs the `proj` feature (and libproj installed). Only UTM zones can be converted without it")]
+    ProjFeatureDisabledError,
+
+    #[error("Error reprojecting coordinates with PROJ:\n\t{0}")]
+    ReprojectionError(String),
+
+    #[error("None of the LAS files overlap the crop region {0}")]
+    CropOutsideDataError(UtmBoundingBox),
+
+    #[error("The crop region {0} has less than 2x2 cells of the heightmap in it")]
+    CropOutsideHeightMapError(UtmBoundingBox),
+
+    #[error("File is not a checkpoint, or was saved by a different version of this library")]
+    BadCheckpointError,
+
+    #[error("{0:?} is not a COPC file (missing the COPC info or laszip VLR)")]
+    NotCopcError(std::path::PathBuf),
+
+    #[error("Can't reproject from UTM zone {from} to {to}, they are too far apart to be in one UTM grid")]
+    UtmZonesTooFarApartError{
+        from: UtmZone,
+        to: UtmZone
+    },
+
+    #[error("The heightmap doesn't know its UTM zone (the LAS files didn't say). Set `utm_zone` on it first")]
+    NoUtmZoneError,
+
+    #[error("HTTP request failed: {0}")]
+    HttpError(String),
+
+    #[error("EPT data type {0:?} is not supported, only \"laszip\" is")]
+    UnsupportedEptDataTypeError(String),
+
+    #[error("{0:?} is not a valid octree node key (should look like \"3-1-2-0\")")]
+    BadVoxelKeyError(String),
+
+    #[error("No heightmaps were given")]
+    NoHeightMapsError,
+
+    #[error("Heightmaps are in different UTM zones ({first} and {other}). Reproject the LAS files into one zone with `LasLoadOptions::target_crs`")]
+    HeightMapUtmZoneMismatchError{
+        first: UtmZone,
+        other: UtmZone
+    },
+
+    #[error("Can't combine things in different UTM zones ({first} and {other}), the same coordinates are different places in them")]
+    UtmZoneMismatchError{
+        first: UtmZone,
+        other: UtmZone
+    },
+
+    #[error("The heightmaps don't have any data in the same place")]
+    HeightMapsDontOverlapError,
+
+    #[error("{0:?} is not a valid GTX geoid grid")]
+    BadGeoidGridError(std::path::PathBuf),
+
+    #[error("{0:?} is not a valid shapefile")]
+    BadShapefileError(std::path::PathBuf),
+    #[error("{path:?} can't be loaded as a DEM: {reason}")]
+    BadGeoTiffError{ path: std::path::PathBuf, reason: String },
+
+    #[error("The geoid grid doesn't cover {0:?} (lon, lat)")]
+    GeoidGridCoverageError(geo::Coord),
+
+    #[error("A geoid correction was already applied to this heightmap ({0})")]
+    GeoidAlreadyAppliedError(String),
+
+    #[error("The mesh has {boundary_edges} edges with a triangle on only one side, so it has holes and isn't watertight")]
+    LeakyMeshError{ boundary_edges: usize },
+
+    #[error("The mesh has {non_manifold_edges} edges with more than two triangles, or two that face opposite ways")]
+    NonManifoldMeshError{ non_manifold_edges: usize },
+
+    #[error("The mesh has {degenerate_triangles} triangles with no area")]
+    DegenerateTrianglesError{ degenerate_triangles: usize },
+
+    #[error("A {x_res}x{y_res} heightmap is too small to make a mesh, it needs at least 2x2 cells")]
+    TooSmallToMeshError{ x_res: usize, y_res: usize },
+
+    #[error("The bounds {bounds} can't be used: {reason}")]
+    InvalidBoundsError{
+        bounds: UtmBoundingBox,
+        reason: String
+    },
+
+    #[error("The bounds {0} have no width or no height, so the mesh would be flat")]
+    ZeroAreaBoundsError(UtmBoundingBox),
+
+    #[error("No part of the mask is at least 2 pixels wide in both directions, so there's nothing to make a mesh of. \
+        Try `Mask::dilate`")]
+    EmptyMaskMeshError,
+
+    #[error("Can't split a {x_res}x{y_res} heightmap into {tiles_x}x{tiles_y} tiles, every tile needs at least one cell")]
+    TileCountError{
+        tiles_x: usize,
+        tiles_y: usize,
+        x_res: usize,
+        y_res: usize
+    },
+
+    #[error("This heightmap was saved in format version {found}, but this version of the library only reads up to {supported}")]
+    UnsupportedFormatVersionError{
+        found: u32,
+        supported: u32
+    },
+
+    #[error("The heightmap was saved in {saved}, not {expected}")]
+    SavedCrsMismatchError{
+        expected: Crs,
+        saved: Crs
     },
 
+    #[error("The heightmap was saved without its CRS, so it can't be checked to be in {0}")]
+    SavedCrsUnknownError(Crs),
+
+    #[error("Nothing to build a model from, give it LAS files (`las_glob`) or a heightmap")]
+    NoHeightMapSourceError,
+
+    #[error("The project doesn't have a mask named {0:?} (masks can only use masks above them)")]
+    UnknownProjectMaskError(String),
+
+    #[error("Cancelled")]
+    CancelledError,
+
+    #[error("{} of {} items were skipped", .0.skipped.len(), .0.num_items())]
+    IncompleteBatchError(BatchReport),
+
+    #[error("No LAS/LAZ files were given")]
+    NoLasFilesError,
+
+    #[error("The saved heightmap is corrupt: {0}")]
+    CorruptHeightMapFileError(String),
+
+    #[error("The height range {min} to {max} can't be used, both have to be numbers and the min can't be above the max")]
+    InvalidZRangeError{ min: f64, max: f64 },
+
+    #[error("The heightmap has {height_map}, but the new points would have {new_points}. \
+        Set `LasLoadOptions::geoid_correction` to what the heightmap was made with")]
+    GeoidCorrectionMismatchError{ height_map: String, new_points: String },
+
+    #[error("A {x_res}x{y_res} grid is too small, it needs at least 2x2 cells since the bounds go from the first cell to the last")]
+    ResolutionTooSmallError{ x_res: usize, y_res: usize },
+
+    #[error("Binary heightmaps need the `zstd` feature. Without it, save them as `HeightMapFormat::Json`")]
+    ZstdFeatureDisabledError,
+}
+
+// boxed so a `Result<_, LasToStlError>` stays small, geojson errors are much bigger than every other variant
+#[cfg(feature = "geojson")]
+impl From<geojson::Error> for LasToStlError {
+    fn from(error: geojson::Error) -> LasToStlError {
+        LasToStlError::GeoJsonError(Box::new(error))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use geo::Coord;
+use log::info;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+
+/// GTX files use this for cells without a value
+const GTX_NO_DATA: f32 = -88.8888;
+
+/// A grid of geoid heights (how far the geoid is above the WGS84 ellipsoid, in meters) covering a lat/lon region,
+/// like [GEOID18](https://geodesy.noaa.gov/GEOID/GEOID18/) or EGM2008 in the NOAA GTX format PROJ uses.
+#[derive(Clone, Debug)]
+pub struct GeoidGrid {
+    /// where the grid was loaded from, for `HeightMap::geoid_correction`
+    pub path: PathBuf,
+    /// latitude of the southernmost row
+    pub min_lat: f64,
+    /// longitude of the westernmost column. Can be from 0 to 360 instead of -180 to 180
+    pub min_lon: f64,
+    pub lat_spacing: f64,
+    pub lon_spacing: f64,
+    pub rows: usize,
+    pub columns: usize,
+    /// geoid heights, a row at a time from south to north, each from west to east
+    pub data: Vec<f32>,
+}
+
+impl GeoidGrid {
+    /// Loads a grid in the NOAA GTX format
+    pub fn load_gtx<P: AsRef<Path>>(path: P) -> Result<GeoidGrid, LasToStlError> {
+        let path = path.as_ref().to_path_buf();
+        let mut reader = BufReader::new(File::open(&path)?);
+
+        let min_lat = read_f64_be(&mut reader)?;
+        let min_lon = read_f64_be(&mut reader)?;
+        let lat_spacing = read_f64_be(&mut reader)?;
+        let lon_spacing = read_f64_be(&mut reader)?;
+        let rows = read_i32_be(&mut reader)?;
+        let columns = read_i32_be(&mut reader)?;
+        if rows < 2 || columns < 2 || lat_spacing <= 0f64 || lon_spacing <= 0f64 {
+            return Err(LasToStlError::BadGeoidGridError(path))
+        }
+        let (rows, columns) = (rows as usize, columns as usize);
+
+        let mut bytes = vec![0u8; rows * columns * 4];
+        reader.read_exact(&mut bytes)?;
+        let data: Vec<f32> = bytes.chunks_exact(4)
+            .map(|value| f32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+            .collect();
+
+        info!("loaded {rows}x{columns} geoid grid from {:?}", path.display());
+
+        Ok(GeoidGrid {
+            path,
+            min_lat,
+            min_lon,
+            lat_spacing,
+            lon_spacing,
+            rows,
+            columns,
+            data,
+        })
+    }
+
+    /// Gets the geoid height at a lat/lon coordinate (x = lon, y = lat) with bilinear interpolation.
+    /// `None` if the coordinate is outside of the grid or next to a cell without a value.
+    pub fn get_geoid_height(&self, lat_lon: &Coord) -> Option<f64> {
+        let mut lon = lat_lon.x;
+        // grids that go from 0 to 360
+        if lon < self.min_lon {
+            lon += 360f64;
+        }
+
+        let row = (lat_lon.y - self.min_lat) / self.lat_spacing;
+        let column = (lon - self.min_lon) / self.lon_spacing;
+        if row < 0f64 || column < 0f64 || row > (self.rows - 1) as f64 || column > (self.columns - 1) as f64 {
+            return None
+        }
+
+        let (row0, column0) = ((row as usize).min(self.rows - 2), (column as usize).min(self.columns - 2));
+        let (t_row, t_column) = (row - row0 as f64, column - column0 as f64);
+
+        let get = |row: usize, column: usize| {
+            let value = self.data[row * self.columns + column];
+            if (value - GTX_NO_DATA).abs() < 0.001 {
+                None
+            } else {
+                Some(value as f64)
+            }
+        };
+
+        let bottom = get(row0, column0)? * (1f64 - t_column) + get(row0, column0 + 1)? * t_column;
+        let top = get(row0 + 1, column0)? * (1f64 - t_column) + get(row0 + 1, column0 + 1)? * t_column;
+        Some(bottom * (1f64 - t_row) + top * t_row)
+    }
+}
+
+/// Turns ellipsoidal heights (what GPS and a lot of lidar use) into orthometric heights (height above sea level,
+/// what maps use) by subtracting the height of the geoid. The difference is tens of meters in most places.
+#[derive(Clone, Debug)]
+pub enum GeoidCorrection {
+    /// subtract the same geoid height everywhere. Good enough for small areas, where the geoid is pretty flat
+    Constant(f64),
+    /// subtract the geoid height from a grid at every cell. Needs the heightmap's UTM zone.
+    /// In an `Arc` so cloning `LasLoadOptions` doesn't copy the whole grid
+    Grid(Arc<GeoidGrid>),
+}
+
+impl Display for GeoidCorrection {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeoidCorrection::Constant(geoid_height) => {
+                write!(formatter, "constant geoid height of {geoid_height}")
+            }
+            GeoidCorrection::Grid(grid) => {
+                write!(formatter, "geoid grid {}", grid.path.display())
+            }
+        }
+    }
+}
+
+impl HeightMap {
+    /// Subtracts the geoid height from every cell (except empty ones) to turn ellipsoidal heights into orthometric heights,
+    /// and records what was done in `geoid_correction`. The z bounds are moved too.
+    ///
+    /// Returns `LasToStlError::GeoidAlreadyAppliedError` if a correction was already applied, `NoUtmZoneError`
+    /// if a grid is used and the heightmap doesn't know its UTM zone, and `GeoidGridCoverageError` if the grid doesn't cover the heightmap.
+    /// Nothing is changed if it returns an error.
+    pub fn apply_geoid_correction(&mut self, correction: &GeoidCorrection) -> Result<(), LasToStlError> {
+        if let Some(applied) = &self.geoid_correction {
+            return Err(LasToStlError::GeoidAlreadyAppliedError(applied.clone()))
+        }
+
+        let old_min_z = self.bounds.min_z;
+        let geoid_heights: Vec<f64> = match correction {
+            GeoidCorrection::Constant(geoid_height) => {
+                vec![*geoid_height; self.data.len()]
+            }
+            GeoidCorrection::Grid(grid) => {
+                let utm_zone = self.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+                let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+                let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+                (0..self.data.len()).map(|index| {
+                    let easting = self.bounds.min_x + (index % self.x_res) as f64 * x_tick;
+                    let northing = self.bounds.min_y + (index / self.x_res) as f64 * y_tick;
+                    let lat_lon = utm_zone.to_lat_lon(easting, northing)?;
+                    grid.get_geoid_height(&lat_lon).ok_or(LasToStlError::GeoidGridCoverageError(lat_lon))
+                }).collect::<Result<Vec<f64>, LasToStlError>>()?
+            }
+        };
+
+        let (min_geoid_height, max_geoid_height) = geoid_heights.iter()
+            .fold((f64::MAX, f64::MIN), |(min, max), geoid_height| (min.min(*geoid_height), max.max(*geoid_height)));
+        self.bounds.min_z -= max_geoid_height;
+        self.bounds.max_z -= min_geoid_height;
+
+        for (height, geoid_height) in self.data.iter_mut().zip(&geoid_heights) {
+            if *height <= old_min_z {
+                // empty cells stay empty
+                *height = self.bounds.min_z;
+            } else {
+                *height -= geoid_height;
+            }
+        }
+
+        info!("applied {correction}");
+        self.geoid_correction = Some(correction.to_string());
+        Ok(())
+    }
+}
+
+fn read_f64_be<R: Read>(reader: &mut R) -> Result<f64, LasToStlError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_be_bytes(bytes))
+}
+
+fn read_i32_be<R: Read>(reader: &mut R) -> Result<i32, LasToStlError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_be_bytes(bytes))
+}
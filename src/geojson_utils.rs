@@ -0,0 +1,183 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use geo::{Geometry, GeometryCollection, LineString, Point, Polygon};
+use geojson::{GeoJson, JsonObject, JsonValue};
+use log::{error, warn};
+use crate::batch_report::BatchReport;
+use crate::errors::LasToStlError;
+use crate::kml_utils::{get_regions, get_trails, get_waypoints};
+
+/// One feature of a GeoJSON file: its geometry and whatever properties the tool that made it added
+/// (names, colors, widths...), so they can decide what to do with every feature
+#[derive(Clone, Debug)]
+pub struct GeoJsonFeature {
+    /// multi polygons, multi line strings and multi points are split up into a `GeometryCollection`,
+    /// so `get_regions`, `get_trails` and `get_waypoints` find them
+    pub geometry: Geometry<f64>,
+    /// empty if the feature doesn't have any
+    pub properties: JsonObject,
+}
+
+impl GeoJsonFeature {
+    /// all polygons of the feature, see `kml_utils::get_regions`
+    pub fn regions(&self) -> Vec<Polygon>{
+        get_regions(GeometryCollection::new_from(vec![self.geometry.clone()]))
+    }
+
+    /// all line strings of the feature, see `kml_utils::get_trails`
+    pub fn trails(&self) -> Vec<LineString>{
+        get_trails(GeometryCollection::new_from(vec![self.geometry.clone()]))
+    }
+
+    /// all points of the feature, see `kml_utils::get_waypoints`
+    pub fn waypoints(&self) -> Vec<Point>{
+        get_waypoints(GeometryCollection::new_from(vec![self.geometry.clone()]))
+    }
+
+    /// the property called `name`, if it's a string
+    pub fn get_str(&self, name: &str) -> Option<&str>{
+        self.properties.get(name)?.as_str()
+    }
+
+    /// the property called `name`, if it's a number or a string of one (some tools write every property as a string)
+    pub fn get_f64(&self, name: &str) -> Option<f64>{
+        match self.properties.get(name)? {
+            JsonValue::Number(number) => number.as_f64(),
+            JsonValue::String(string) => string.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Same as `kml_utils::load_kml_file`, but for GeoJSON (.geojson or .json) files.
+/// Coordinates have to be lon/lat (WGS84), which the GeoJSON spec says they always are
+pub fn load_geojson_file<P: AsRef<Path>>(path: P) -> Result<GeometryCollection<f64>, LasToStlError>{
+    let features = load_geojson_features(path)?;
+    Ok(GeometryCollection::new_from(features.into_iter().map(|feature| feature.geometry).collect()))
+}
+
+/// loads a file for each path, same as `kml_utils::load_kml_files`.
+///
+/// # Error handling:
+/// Any files that are unable to be read will log the error with [log::error](https://docs.rs/log/0.4.20/log/enum.Level.html#variant.Error)
+/// and be listed in the returned `BatchReport`,
+/// but as long as at least one geometry is successfully loaded, this function will not return an error.
+pub fn load_geojson_files<P: AsRef<Path> + Debug>(paths: Vec<P>)
+    -> Result<(GeometryCollection<f64>, BatchReport), LasToStlError>
+{
+    let mut out_vec: Vec<Geometry<f64>> = Vec::new();
+    let mut report = BatchReport::new();
+
+    for (index, path) in paths.into_iter().enumerate(){
+        match load_geojson_file(&path){
+            Ok(mut gc) => {
+                out_vec.append(&mut gc.0);
+                report.add_success();
+            }
+            Err(e) => {
+                error!("error loading file {:?}:\n\t{:?}\nSkipping file.", path, e);
+                report.add_skipped(index, path.as_ref().display(), e);
+            }
+        }
+    }
+
+    if out_vec.is_empty(){
+        Err(LasToStlError::NoValidGeometriesError)
+    } else {
+        Ok((GeometryCollection::<f64>::new_from(out_vec), report))
+    }
+}
+
+/// Loads every feature of a GeoJSON file with its properties. A file that's just a geometry is one feature
+/// with no properties. Features without a geometry are skipped
+pub fn load_geojson_features<P: AsRef<Path>>(path: P) -> Result<Vec<GeoJsonFeature>, LasToStlError>{
+    let geojson: GeoJson = fs::read_to_string(path)?.parse()?;
+
+    let features = match geojson {
+        GeoJson::FeatureCollection(collection) => collection.features,
+        GeoJson::Feature(feature) => vec![feature],
+        GeoJson::Geometry(geometry) => {
+            return Ok(vec![GeoJsonFeature {
+                geometry: split_multi_geometries(Geometry::try_from(geometry)?),
+                properties: JsonObject::new(),
+            }])
+        }
+    };
+
+    let mut out_vec: Vec<GeoJsonFeature> = Vec::with_capacity(features.len());
+    for feature in features {
+        let Some(geometry) = feature.geometry else {
+            warn!("skipping GeoJSON feature {:?} without a geometry", feature.id);
+            continue
+        };
+        out_vec.push(GeoJsonFeature {
+            geometry: split_multi_geometries(Geometry::try_from(geometry)?),
+            properties: feature.properties.unwrap_or_default(),
+        });
+    }
+    Ok(out_vec)
+}
+
+/// turns multi geometries into collections (recursively), since that's what the KML functions look through
+fn split_multi_geometries(geometry: Geometry<f64>) -> Geometry<f64>{
+    match geometry {
+        Geometry::MultiPolygon(multi_polygon) => Geometry::GeometryCollection(
+            multi_polygon.into_iter().map(Geometry::Polygon).collect()
+        ),
+        Geometry::MultiLineString(multi_line_string) => Geometry::GeometryCollection(
+            multi_line_string.into_iter().map(Geometry::LineString).collect()
+        ),
+        Geometry::MultiPoint(multi_point) => Geometry::GeometryCollection(
+            multi_point.into_iter().map(Geometry::Point).collect()
+        ),
+        Geometry::GeometryCollection(collection) => Geometry::GeometryCollection(
+            collection.into_iter().map(split_multi_geometries).collect()
+        ),
+        geometry => geometry,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEATURES: &str = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {"type": "Feature", "properties": {"name": "parks", "width": "3.5"}, "geometry": {"type": "MultiPolygon", "coordinates": [
+                [[[-122.0, 45.0], [-121.9, 45.0], [-121.9, 45.1], [-122.0, 45.0]]],
+                [[[-121.0, 45.0], [-120.9, 45.0], [-120.9, 45.1], [-121.0, 45.0]]]
+            ]}},
+            {"type": "Feature", "properties": {"width": 2}, "geometry": {"type": "LineString", "coordinates": [[-122.0, 45.0], [-121.5, 45.5]]}},
+            {"type": "Feature", "properties": null, "geometry": {"type": "Point", "coordinates": [-122.0, 45.0]}},
+            {"type": "Feature", "properties": {"name": "nowhere"}, "geometry": null}
+        ]
+    }"#;
+
+    #[test]
+    fn features_keep_their_geometry_and_properties() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_geojson_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("features.geojson");
+        std::fs::write(&path, FEATURES).unwrap();
+
+        let features = load_geojson_features(&path).unwrap();
+        let collection = load_geojson_files(vec![path.clone(), directory.join("missing.geojson")]);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(features.len(), 3, "the feature without a geometry should be skipped");
+        assert_eq!(features[0].regions().len(), 2, "multi polygons should be split up");
+        assert_eq!(features[0].get_str("name"), Some("parks"));
+        assert_eq!(features[0].get_f64("width"), Some(3.5));
+        assert_eq!(features[1].trails().len(), 1);
+        assert_eq!(features[1].get_f64("width"), Some(2f64));
+        assert_eq!(features[2].waypoints(), vec![Point::new(-122f64, 45f64)]);
+        assert!(features[2].properties.is_empty());
+
+        let (collection, report) = collection.unwrap();
+        assert_eq!((get_regions(collection.clone()).len(), get_trails(collection.clone()).len(), get_waypoints(collection).len()), (2, 1, 1));
+        assert_eq!(report.num_succeeded, 1);
+        assert_eq!(report.skipped[0].index, 1);
+    }
+}
@@ -0,0 +1,396 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use log::warn;
+use rayon::prelude::*;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use tiff::ColorType;
+use crate::crs::Crs;
+use crate::errors::LasToStlError;
+use crate::height_map::{HeightMap, Interpolation};
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// GeoTIFF key saying if the tie point is the corner of a pixel (1, the default) or its center (2)
+const RASTER_TYPE_GEO_KEY: u16 = 1025;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+/// about how many meters a degree of latitude is
+const METERS_PER_DEGREE: f64 = 111_320f64;
+
+/// Where a GeoTIFF is: the top left corner of the top left pixel and the size of the pixels, in the units of its CRS.
+/// Rows go down (south), like in any image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GeoTransform {
+    pub(crate) origin_x: f64,
+    pub(crate) origin_y: f64,
+    pub(crate) pixel_width: f64,
+    pub(crate) pixel_height: f64,
+}
+
+impl GeoTransform {
+    /// the coordinates of the center of pixel (column, row)
+    pub(crate) fn pixel_center(&self, column: usize, row: usize) -> (f64, f64){
+        (self.origin_x + (column as f64 + 0.5) * self.pixel_width, self.origin_y - (row as f64 + 0.5) * self.pixel_height)
+    }
+}
+
+/// The georeferencing of a GeoTIFF, read from its tags
+#[derive(Clone, Debug)]
+pub(crate) struct GeoTiffInfo {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) transform: GeoTransform,
+    pub(crate) crs: Option<Crs>,
+    pub(crate) no_data: Option<f64>,
+}
+
+impl GeoTiffInfo {
+    pub(crate) fn read<R: Read + Seek>(decoder: &mut Decoder<R>, path: &Path) -> Result<GeoTiffInfo, LasToStlError>{
+        let bad_geotiff = |reason: &str| LasToStlError::BadGeoTiffError { path: path.to_path_buf(), reason: reason.to_string() };
+        let (width, height) = decoder.dimensions()?;
+
+        let geo_keys: Vec<u16> = decoder.find_tag_unsigned_vec(Tag::GeoKeyDirectoryTag)?.unwrap_or_default();
+        // the same layout as the GeoKeyDirectory VLR of a LAS file, see `Crs::from_geo_key_directory`
+        let geo_key_bytes: Vec<u8> = geo_keys.iter().flat_map(|short| short.to_le_bytes()).collect();
+        let crs = Crs::from_geo_key_directory(&geo_key_bytes);
+        let pixel_is_point = geo_keys.get(4..).unwrap_or_default().chunks_exact(4)
+            .any(|key| key[0] == RASTER_TYPE_GEO_KEY && key[1] == 0 && key[3] == RASTER_PIXEL_IS_POINT);
+
+        let transform = match decoder.get_tag_f64_vec(Tag::ModelTransformationTag) {
+            Ok(matrix) if matrix.len() >= 8 => {
+                if matrix[1] != 0f64 || matrix[4] != 0f64 {
+                    return Err(bad_geotiff("rotated rasters aren't supported"))
+                }
+                GeoTransform { origin_x: matrix[3], origin_y: matrix[7], pixel_width: matrix[0], pixel_height: -matrix[5] }
+            }
+            _ => {
+                let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag)
+                    .map_err(|_| bad_geotiff("it has no ModelPixelScale or ModelTransformation tag"))?;
+                let tie_point = decoder.get_tag_f64_vec(Tag::ModelTiepointTag)
+                    .map_err(|_| bad_geotiff("it has no ModelTiepoint or ModelTransformation tag"))?;
+                if scale.len() < 2 || tie_point.len() < 6 {
+                    return Err(bad_geotiff("its ModelPixelScale or ModelTiepoint tag is too short"))
+                }
+                // tie point is [column, row, _, x, y, _]
+                let (pixel_width, pixel_height) = (scale[0], scale[1]);
+                GeoTransform {
+                    origin_x: tie_point[3] - tie_point[0] * pixel_width,
+                    origin_y: tie_point[4] + tie_point[1] * pixel_height,
+                    pixel_width,
+                    pixel_height,
+                }
+            }
+        };
+        // a tie point on the center of a pixel is half a pixel from its corner
+        let transform = match pixel_is_point {
+            true => GeoTransform {
+                origin_x: transform.origin_x - transform.pixel_width / 2f64,
+                origin_y: transform.origin_y + transform.pixel_height / 2f64,
+                ..transform
+            },
+            false => transform,
+        };
+        if transform.pixel_width <= 0f64 || transform.pixel_height <= 0f64 {
+            return Err(bad_geotiff("its pixels don't have a positive size"))
+        }
+
+        // GDAL writes the no data value as text
+        let no_data = match decoder.find_tag(Tag::GdalNodata)? {
+            Some(value) => value.into_string().ok().and_then(|text| text.trim_end_matches('\0').trim().parse::<f64>().ok()),
+            None => None,
+        };
+
+        Ok(GeoTiffInfo { width: width as usize, height: height as usize, transform, crs, no_data })
+    }
+
+    /// EPSG codes from 4000 to 4999 are (almost all) lat/lon
+    pub(crate) fn is_lat_lon(&self) -> bool{
+        matches!(self.crs, Some(Crs::Epsg(4000..=4999)))
+    }
+}
+
+/// opens a GeoTIFF without the default limit on how big the image can be, since DEMs are usually huge
+pub(crate) fn open_geotiff(path: &Path) -> Result<Decoder<BufReader<File>>, LasToStlError>{
+    Ok(Decoder::new(BufReader::new(File::open(path)?))?.with_limits(Limits::unlimited()))
+}
+
+/// the first band of whatever the decoder gives, as f64
+pub(crate) fn decoding_result_to_f64(result: DecodingResult, color_type: ColorType) -> Vec<f64>{
+    let samples_per_pixel = match color_type {
+        ColorType::Gray(_) | ColorType::Palette(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) | ColorType::YCbCr(_) => 3,
+        ColorType::RGBA(_) | ColorType::CMYK(_) => 4,
+    };
+    match result {
+        DecodingResult::U8(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::U16(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::U32(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::U64(data) => data.into_iter().step_by(samples_per_pixel).map(|value| value as f64).collect(),
+        DecodingResult::F32(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::F64(data) => data.into_iter().step_by(samples_per_pixel).collect(),
+        DecodingResult::I8(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::I16(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::I32(data) => data.into_iter().step_by(samples_per_pixel).map(f64::from).collect(),
+        DecodingResult::I64(data) => data.into_iter().step_by(samples_per_pixel).map(|value| value as f64).collect(),
+    }
+}
+
+impl HeightMap {
+    /// Loads a DEM raster (like the USGS 1m DEMs, or SRTM) as a heightmap, so the mask/KML/STL stuff works without
+    /// any lidar. Only the first band is used, and pixels with the no data value (or NaN) become holes at the lowest height.
+    ///
+    /// Rasters in UTM keep their grid (one cell per pixel center) and get their `utm_zone` from the GeoKeys.
+    /// Rasters in lat/lon (EPSG 4xxx, like SRTM) are resampled into the UTM zone of their center.
+    /// Anything else is loaded in its own units with a warning and no `utm_zone`, like LAS files in state plane.
+    ///
+    /// `target_resolution` is the (x_res, y_res) of the heightmap, `None` for about one cell per pixel.
+    ///
+    /// Returns `LasToStlError::BadGeoTiffError` if the file isn't georeferenced or has no data at all
+    pub fn from_geotiff<P: AsRef<Path>>(path: P, target_resolution: Option<(usize, usize)>) -> Result<HeightMap, LasToStlError>{
+        let path = path.as_ref();
+        let mut decoder = open_geotiff(path)?;
+        let info = GeoTiffInfo::read(&mut decoder, path)?;
+        let color_type = decoder.colortype()?;
+        let pixels = decoding_result_to_f64(decoder.read_image()?, color_type);
+
+        let native = native_height_map(path, &info, &pixels)?;
+        native.georeference_geotiff(path, &info, target_resolution)
+    }
+
+    /// Same as `from_geotiff`, but only reads the tiles (or strips) of the raster that overlap `bounds`, so a small model
+    /// can be cut out of a huge DEM without decoding all of it. `bounds` (only x and y are used) are in the CRS of the raster,
+    /// so in degrees (x = lon, y = lat) for lat/lon rasters.
+    ///
+    /// If the file has overviews (smaller copies, like every Cloud-Optimized GeoTIFF), the smallest one that still has
+    /// a pixel for every cell of `target_resolution` is read instead of the full resolution.
+    ///
+    /// Returns `LasToStlError::BadGeoTiffError` if `bounds` don't overlap the raster
+    pub fn from_geotiff_window<P: AsRef<Path>>(path: P, bounds: &UtmBoundingBox, target_resolution: Option<(usize, usize)>) -> Result<HeightMap, LasToStlError>{
+        let path = path.as_ref();
+        read_geotiff_window(open_geotiff(path)?, path, bounds, target_resolution)
+    }
+
+    /// gives a heightmap fresh from the pixels of a GeoTIFF its UTM zone, converts it to UTM if it's in lat/lon,
+    /// and resamples it to `target_resolution`
+    fn georeference_geotiff(self, path: &Path, info: &GeoTiffInfo, target_resolution: Option<(usize, usize)>) -> Result<HeightMap, LasToStlError>{
+        if info.is_lat_lon() {
+            return self.lat_lon_to_utm(target_resolution)
+        }
+
+        let mut height_map = self;
+        height_map.set_utm_zone(info.crs.as_ref().and_then(Crs::get_utm_zone));
+        if height_map.utm_zone.is_none() {
+            height_map.crs = info.crs.clone();
+            warn!("{:?} isn't in UTM or lat/lon ({}), so its bounds are in its own units",
+                path, info.crs.as_ref().map_or(String::from("no CRS"), Crs::to_string));
+        }
+        match target_resolution {
+            Some((x_res, y_res)) => height_map.resample(x_res, y_res, Interpolation::Bilinear),
+            None => Ok(height_map),
+        }
+    }
+
+    /// resamples a heightmap with bounds in degrees (x = lon, y = lat) into the UTM zone of its center.
+    /// Only the part of it that's inside the lat/lon bounds on every side is kept
+    fn lat_lon_to_utm(&self, target_resolution: Option<(usize, usize)>) -> Result<HeightMap, LasToStlError>{
+        let bounds = self.bounds;
+        let center = bounds.center();
+        let utm_zone = UtmZone::from_lat_lon_coord(&center);
+
+        let corner = |lon: f64, lat: f64| utm_zone.from_lat_lon(&geo::Coord { x: lon, y: lat });
+        let (south_west, south_east) = (corner(bounds.min_x, bounds.min_y), corner(bounds.max_x, bounds.min_y));
+        let (north_west, north_east) = (corner(bounds.min_x, bounds.max_y), corner(bounds.max_x, bounds.max_y));
+        let utm_bounds = UtmBoundingBox::new_in_zone(
+            south_west.0.max(north_west.0),
+            south_east.0.min(north_east.0),
+            south_west.1.max(south_east.1),
+            north_west.1.min(north_east.1),
+            bounds.min_z,
+            bounds.max_z,
+            utm_zone,
+        );
+
+        let (x_res, y_res) = target_resolution.unwrap_or_else(|| {
+            // about as many meters per cell as the raster has per pixel
+            let x_tick = bounds.x_range() / (self.x_res - 1).max(1) as f64 * METERS_PER_DEGREE * center.y.to_radians().cos();
+            let y_tick = bounds.y_range() / (self.y_res - 1).max(1) as f64 * METERS_PER_DEGREE;
+            ((utm_bounds.x_range() / x_tick).round() as usize + 1, (utm_bounds.y_range() / y_tick).round() as usize + 1)
+        });
+        if x_res < 2 || y_res < 2 {
+            return Err(LasToStlError::TooSmallToMeshError { x_res, y_res })
+        }
+
+        let x_tick = utm_bounds.x_range() / (x_res - 1) as f64;
+        let y_tick = utm_bounds.y_range() / (y_res - 1) as f64;
+        let lon_tick = bounds.x_range() / (self.x_res - 1) as f64;
+        let lat_tick = bounds.y_range() / (self.y_res - 1) as f64;
+        let data = (0..x_res * y_res).into_par_iter().map(|index| {
+            let easting = utm_bounds.min_x + (index % x_res) as f64 * x_tick;
+            let northing = utm_bounds.min_y + (index / x_res) as f64 * y_tick;
+            let lat_lon = utm_zone.to_lat_lon(easting, northing)?;
+            Ok(self.interpolate((lat_lon.x - bounds.min_x) / lon_tick, (lat_lon.y - bounds.min_y) / lat_tick, Interpolation::Bilinear))
+        }).collect::<Result<Vec<f64>, LasToStlError>>()?;
+
+        let mut height_map = self.with_grid(data, x_res, y_res, utm_bounds);
+        height_map.set_utm_zone(Some(utm_zone));
+        Ok(height_map)
+    }
+}
+
+/// Reads the part of a GeoTIFF inside `bounds` (in its own CRS), from the best overview for `target_resolution`.
+/// Works on anything the decoder can seek in, like a file or `HttpRangeReader`
+pub(crate) fn read_geotiff_window<R: Read + Seek>(mut decoder: Decoder<R>,
+                                                 path: &Path,
+                                                 bounds: &UtmBoundingBox,
+                                                 target_resolution: Option<(usize, usize)>)
+    -> Result<HeightMap, LasToStlError>
+{
+    let bad_geotiff = |reason: &str| LasToStlError::BadGeoTiffError { path: path.to_path_buf(), reason: reason.to_string() };
+    // the georeferencing is only on the full resolution image, overviews cover the same area with bigger pixels
+    let full_info = GeoTiffInfo::read(&mut decoder, path)?;
+
+    let wanted_pixel_size = target_resolution.map_or(0f64, |(x_res, y_res)| {
+        (bounds.x_range() / (x_res.max(2) - 1) as f64).min(bounds.y_range() / (y_res.max(2) - 1) as f64)
+    });
+    let (image_index, width, height) = choose_overview(&mut decoder, &full_info, wanted_pixel_size)?;
+    decoder.seek_to_image(image_index)?;
+    let scale = full_info.width as f64 / width as f64;
+    let transform = GeoTransform {
+        pixel_width: full_info.transform.pixel_width * scale,
+        pixel_height: full_info.transform.pixel_height * full_info.height as f64 / height as f64,
+        ..full_info.transform
+    };
+
+    // pixels with their centers in the bounds, and one more on every side so the edges can be interpolated
+    let first_column = ((bounds.min_x - transform.origin_x) / transform.pixel_width - 1.5).floor().max(0f64) as usize;
+    let last_column = ((bounds.max_x - transform.origin_x) / transform.pixel_width + 0.5).ceil().min((width - 1) as f64);
+    let first_row = ((transform.origin_y - bounds.max_y) / transform.pixel_height - 1.5).floor().max(0f64) as usize;
+    let last_row = ((transform.origin_y - bounds.min_y) / transform.pixel_height + 0.5).ceil().min((height - 1) as f64);
+    if last_column < first_column as f64 || last_row < first_row as f64 {
+        return Err(bad_geotiff("the bounds don't overlap the raster"))
+    }
+    let (last_column, last_row) = (last_column as usize, last_row as usize);
+    let (window_width, window_height) = (last_column - first_column + 1, last_row - first_row + 1);
+
+    let color_type = decoder.colortype()?;
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let (chunk_width, chunk_height) = (chunk_width as usize, chunk_height as usize);
+    let chunks_across = width.div_ceil(chunk_width);
+
+    let mut pixels: Vec<f64> = vec![f64::NAN; window_width * window_height];
+    for chunk_row in first_row / chunk_height..=last_row / chunk_height {
+        for chunk_column in first_column / chunk_width..=last_column / chunk_width {
+            let chunk_index = (chunk_row * chunks_across + chunk_column) as u32;
+            let (data_width, _) = decoder.chunk_data_dimensions(chunk_index);
+            let chunk = decoding_result_to_f64(decoder.read_chunk(chunk_index)?, color_type);
+
+            for (row_in_chunk, chunk_row_pixels) in chunk.chunks(data_width as usize).enumerate() {
+                let row = chunk_row * chunk_height + row_in_chunk;
+                if row < first_row || row > last_row {
+                    continue
+                }
+                for (column_in_chunk, pixel) in chunk_row_pixels.iter().enumerate() {
+                    let column = chunk_column * chunk_width + column_in_chunk;
+                    if (first_column..=last_column).contains(&column) {
+                        pixels[(row - first_row) * window_width + column - first_column] = *pixel;
+                    }
+                }
+            }
+        }
+    }
+
+    let (origin_x, origin_y) = (
+        transform.origin_x + first_column as f64 * transform.pixel_width,
+        transform.origin_y - first_row as f64 * transform.pixel_height,
+    );
+    let window_info = GeoTiffInfo {
+        width: window_width,
+        height: window_height,
+        transform: GeoTransform { origin_x, origin_y, ..transform },
+        ..full_info.clone()
+    };
+    native_height_map(path, &window_info, &pixels)?
+        .crop(bounds)?
+        .georeference_geotiff(path, &full_info, target_resolution)
+}
+
+/// Finds the (index, width, height) of the smallest image in the file (the full resolution one, or an overview)
+/// with pixels no bigger than `wanted_pixel_size`. Masks are skipped
+fn choose_overview<R: Read + Seek>(decoder: &mut Decoder<R>, info: &GeoTiffInfo, wanted_pixel_size: f64) -> Result<(usize, usize, usize), LasToStlError>{
+    let mut best = (0, info.width, info.height);
+    let mut index = 0;
+    while decoder.more_images() {
+        decoder.next_image()?;
+        index += 1;
+        // bit 4 of NewSubfileType marks a transparency mask
+        if decoder.find_tag_unsigned::<u32>(Tag::NewSubfileType)?.unwrap_or(0) & 4 != 0 {
+            continue
+        }
+        let (width, height) = decoder.dimensions()?;
+        let pixel_size = info.transform.pixel_width * info.width as f64 / width as f64;
+        if pixel_size <= wanted_pixel_size && (width as usize) < best.1 {
+            best = (index, width as usize, height as usize);
+        }
+    }
+    Ok(best)
+}
+
+/// The pixels as a heightmap in the raster's own CRS, flipped so row 0 is the south edge.
+/// No data pixels are empty cells, just below the lowest height (see `HeightMap::is_empty_height`)
+pub(crate) fn native_height_map(path: &Path, info: &GeoTiffInfo, pixels: &[f64]) -> Result<HeightMap, LasToStlError>{
+    if pixels.len() < info.width * info.height {
+        return Err(LasToStlError::BadGeoTiffError { path: path.to_path_buf(), reason: String::from("it has fewer pixels than its size says") })
+    }
+    let is_valid = |height: &f64| height.is_finite() && Some(*height) != info.no_data;
+    let (min_z, max_z) = pixels.iter().filter(|height| is_valid(height))
+        .fold((f64::MAX, f64::MIN), |(min, max), height| (min.min(*height), max.max(*height)));
+    if min_z > max_z {
+        return Err(LasToStlError::BadGeoTiffError { path: path.to_path_buf(), reason: String::from("every pixel is no data") })
+    }
+
+    // empty cells have to be strictly below every real height, or the lowest pixels would count as no data too
+    let empty_z = min_z.next_down();
+
+    let (width, height) = (info.width, info.height);
+    let mut data: Vec<f64> = Vec::with_capacity(width * height);
+    for row in (0..height).rev() {
+        data.extend(pixels[row * width..(row + 1) * width].iter().map(|pixel| if is_valid(pixel) { *pixel } else { empty_z }));
+    }
+
+    let (min_x, max_y) = info.transform.pixel_center(0, 0);
+    let (max_x, min_y) = info.transform.pixel_center(width - 1, height - 1);
+    Ok(HeightMap {
+        data,
+        x_res: width,
+        y_res: height,
+        bounds: UtmBoundingBox::new(min_x, max_x, min_y, max_y, empty_z, max_z),
+        utm_zone: None,
+        geoid_correction: None,
+        crs: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_data_pixels_are_empty_and_the_lowest_pixel_is_not(){
+        let info = GeoTiffInfo {
+            width: 2,
+            height: 2,
+            transform: GeoTransform { origin_x: 0.0, origin_y: 2.0, pixel_width: 1.0, pixel_height: 1.0 },
+            crs: None,
+            no_data: Some(-9999.0),
+        };
+        let height_map = native_height_map(Path::new("test.tif"), &info, &[10.0, -9999.0, 12.0, f64::NAN]).unwrap();
+
+        // row 0 is the south edge, so the rows are flipped
+        assert_eq!(height_map.get_heights(), vec![Some(12.0), None, Some(10.0), None]);
+        assert!(height_map.bounds.min_z < 10.0);
+        assert_eq!(height_map.bounds.max_z, 12.0);
+    }
+}
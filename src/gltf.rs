@@ -0,0 +1,231 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageBuffer, ImageEncoder, RgbImage};
+use log::debug;
+use serde_json::json;
+use crate::color_map::ColorMap;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::{IndexedMesh, StlOptions};
+use crate::utm_bounds::UtmBoundingBox;
+
+/// 'glTF'
+const GLB_MAGIC: u32 = 0x46546C67;
+/// 'JSON'
+const JSON_CHUNK: u32 = 0x4E4F534A;
+/// 'BIN\0'
+const BIN_CHUNK: u32 = 0x004E4942;
+
+/// An image laid over the terrain in a glTF file, like a hillshade, the colors of the points or an orthophoto
+pub struct GltfTexture {
+    pub image: RgbImage,
+    /// the outer edges of the image in UTM (z is ignored). Doesn't have to match the heightmap, parts of the model
+    /// outside of the image get the color of its edge
+    pub bounds: UtmBoundingBox,
+    /// whether the first row of the image is the north edge (like most images),
+    /// or the south edge (like the images this library saves, see `HeightMap::save_to_image`)
+    pub north_up: bool,
+}
+
+impl GltfTexture {
+    /// loads any image the `image` crate can read, which covers `bounds`
+    pub fn load<P: AsRef<Path>>(path: P, bounds: UtmBoundingBox, north_up: bool) -> Result<GltfTexture, LasToStlError>{
+        Ok(GltfTexture {
+            image: image::open(path)?.to_rgb8(),
+            bounds,
+            north_up,
+        })
+    }
+
+    /// a grey shaded relief of the heightmap. See `HeightMap::hillshade`
+    pub fn hillshade(height_map: &HeightMap, azimuth_degrees: f64, altitude_degrees: f64) -> Result<GltfTexture, LasToStlError>{
+        let hillshade = height_map.hillshade(azimuth_degrees, altitude_degrees);
+        let image = ImageBuffer::from_vec(
+            hillshade.x_res as u32,
+            hillshade.y_res as u32,
+            hillshade.data.iter().flat_map(|lit| [(lit * 255f64) as u8; 3]).collect()
+        ).ok_or(LasToStlError::ImageNoneError)?;
+        Ok(GltfTexture::from_grid_image(image, &hillshade.bounds))
+    }
+
+    /// the colors of the LAS points. See `ColorMap::to_image`
+    pub fn from_color_map(color_map: &ColorMap) -> Result<GltfTexture, LasToStlError>{
+        Ok(GltfTexture::from_grid_image(color_map.to_image()?, &color_map.bounds))
+    }
+
+    /// an image with one pixel per cell of a grid with `bounds`, with row y of the image being row y of the grid
+    pub(crate) fn from_grid_image(image: RgbImage, bounds: &UtmBoundingBox) -> GltfTexture{
+        GltfTexture {
+            bounds: get_cell_edges(bounds, image.width() as usize, image.height() as usize),
+            image,
+            north_up: false,
+        }
+    }
+
+    /// where UTM (x, y) is in the image, 0 to 1 from the left and from the top
+    fn get_uv(&self, utm_x: f64, utm_y: f64) -> [f32; 2]{
+        let u = (utm_x - self.bounds.min_x) / self.bounds.x_range();
+        let v = match self.north_up {
+            true => (self.bounds.max_y - utm_y) / self.bounds.y_range(),
+            false => (utm_y - self.bounds.min_y) / self.bounds.y_range(),
+        };
+        [u as f32, v as f32]
+    }
+}
+
+impl HeightMap {
+    /// Same as `save_as_stl_with_options`, but saves a binary glTF (.glb) file with `texture` draped over it,
+    /// which three.js, Cesium, Blender and most other 3D viewers can open. The model is turned so up is +y like glTF wants,
+    /// and is in meters, assuming the model units are mm like in an STL. `options.format` is ignored
+    pub fn save_as_glb(&self, path: &str, options: &StlOptions, texture: Option<&GltfTexture>) -> Result<(), LasToStlError>{
+        self.save_as_glb_with_progress(path, options, texture, &LogProgress)
+    }
+
+    /// Same as `save_as_glb`, but reports progress to `progress`
+    pub fn save_as_glb_with_progress(&self,
+                                     path: &str,
+                                     options: &StlOptions,
+                                     texture: Option<&GltfTexture>,
+                                     progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        let now = SystemTime::now();
+
+        let mesh = self.get_mesh_with_progress(options, progress)?;
+
+        progress.on_stage("writing glb");
+        let (x_unit, y_unit, _) = self.get_stl_units(options);
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+        let uvs: Option<Vec<[f32; 2]>> = texture.map(|texture| mesh.vertices.iter().map(|vertex| {
+            texture.get_uv(
+                self.bounds.min_x + vertex[0] as f64 / x_unit * x_tick,
+                self.bounds.min_y + vertex[1] as f64 / y_unit * y_tick,
+            )
+        }).collect());
+
+        let mut extras = json!({ "bounds": self.bounds.to_string() });
+        if let Some(utm_zone) = self.utm_zone {
+            extras["utm_zone"] = json!(utm_zone.to_string());
+        }
+
+        options.overwrite.save(path, |file| {
+            write_glb(file, &mesh, uvs.as_deref().zip(texture.map(|texture| &texture.image)), extras)
+        })?;
+        progress.on_finish("writing glb");
+
+        debug!("saved as glb. took {:?}", now.elapsed());
+        Ok(())
+    }
+}
+
+/// Writes `mesh` as a binary glTF, with a texture if `textured` has the UV of every vertex and the image.
+/// `extras` ends up in the scene, for anything the viewer should know
+pub fn write_glb<W: Write>(writer: &mut W,
+                           mesh: &IndexedMesh,
+                           textured: Option<(&[[f32; 2]], &RgbImage)>,
+                           extras: serde_json::Value) -> Result<(), LasToStlError>{
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_views: Vec<serde_json::Value> = Vec::new();
+    let mut add_buffer_view = |buffer: &mut Vec<u8>, bytes: &[u8], target: Option<u32>| {
+        let mut buffer_view = json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": bytes.len() });
+        if let Some(target) = target {
+            buffer_view["target"] = json!(target);
+        }
+        buffer.extend_from_slice(bytes);
+        // everything has to start on 4 bytes
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        buffer_views.push(buffer_view);
+        buffer_views.len() - 1
+    };
+
+    let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+    let positions: Vec<u8> = mesh.vertices.iter().flat_map(|vertex| {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex[axis]);
+            max[axis] = max[axis].max(vertex[axis]);
+        }
+        [vertex[0], vertex[1], vertex[2]].into_iter().flat_map(f32::to_le_bytes)
+    }).collect();
+    let indices: Vec<u8> = mesh.faces.iter().flat_map(|face| face.vertices.into_iter().flat_map(u32::to_le_bytes)).collect();
+
+    let mut accessors = vec![
+        json!({
+            "bufferView": add_buffer_view(&mut buffer, &positions, Some(34962)),
+            "componentType": 5126,
+            "count": mesh.vertices.len(),
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }),
+        json!({
+            "bufferView": add_buffer_view(&mut buffer, &indices, Some(34963)),
+            "componentType": 5125,
+            "count": mesh.faces.len() * 3,
+            "type": "SCALAR",
+        }),
+    ];
+    let mut attributes = json!({ "POSITION": 0 });
+    let mut material = json!({ "pbrMetallicRoughness": { "baseColorFactor": [0.8, 0.8, 0.8, 1.0], "metallicFactor": 0.0, "roughnessFactor": 1.0 } });
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": concat!("las-kml-to-stl ", env!("CARGO_PKG_VERSION")) },
+        "scene": 0,
+        "scenes": [{ "nodes": [0], "extras": extras }],
+        // z up to y up, and mm to meters
+        "nodes": [{ "mesh": 0, "rotation": [-std::f32::consts::FRAC_1_SQRT_2, 0.0, 0.0, std::f32::consts::FRAC_1_SQRT_2], "scale": [0.001, 0.001, 0.001] }],
+    });
+
+    if let Some((uvs, image)) = textured {
+        let uv_bytes: Vec<u8> = uvs.iter().flatten().flat_map(|coordinate| coordinate.to_le_bytes()).collect();
+        accessors.push(json!({
+            "bufferView": add_buffer_view(&mut buffer, &uv_bytes, Some(34962)),
+            "componentType": 5126,
+            "count": uvs.len(),
+            "type": "VEC2",
+        }));
+        attributes["TEXCOORD_0"] = json!(accessors.len() - 1);
+
+        let mut png: Vec<u8> = Vec::new();
+        PngEncoder::new(&mut png).write_image(image.as_raw(), image.width(), image.height(), ColorType::Rgb8)?;
+        gltf["images"] = json!([{ "bufferView": add_buffer_view(&mut buffer, &png, None), "mimeType": "image/png" }]);
+        // linear filtering without mipmaps, and the edge color outside of the image
+        gltf["samplers"] = json!([{ "magFilter": 9729, "minFilter": 9729, "wrapS": 33071, "wrapT": 33071 }]);
+        gltf["textures"] = json!([{ "sampler": 0, "source": 0 }]);
+        material = json!({ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 }, "metallicFactor": 0.0, "roughnessFactor": 1.0 } });
+    }
+
+    gltf["meshes"] = json!([{ "primitives": [{ "attributes": attributes, "indices": 1, "material": 0 }] }]);
+    gltf["materials"] = json!([material]);
+    gltf["accessors"] = json!(accessors);
+    gltf["bufferViews"] = json!(buffer_views);
+    gltf["buffers"] = json!([{ "byteLength": buffer.len() }]);
+
+    let mut json_bytes = serde_json::to_vec(&gltf)?;
+    json_bytes.resize(json_bytes.len().next_multiple_of(4), b' ');
+
+    let total_length = 12 + 8 + json_bytes.len() + 8 + buffer.len();
+    for word in [GLB_MAGIC, 2, total_length as u32, json_bytes.len() as u32, JSON_CHUNK] {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    writer.write_all(&json_bytes)?;
+    for word in [buffer.len() as u32, BIN_CHUNK] {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    writer.write_all(&buffer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// the outer edges of a grid of cells, half a cell outside of `bounds` (which go through the middle of the edge cells)
+fn get_cell_edges(bounds: &UtmBoundingBox, x_res: usize, y_res: usize) -> UtmBoundingBox{
+    let half_x_tick = bounds.x_range() / (x_res - 1) as f64 / 2f64;
+    let half_y_tick = bounds.y_range() / (y_res - 1) as f64 / 2f64;
+    UtmBoundingBox{
+        min_x: bounds.min_x - half_x_tick,
+        max_x: bounds.max_x + half_x_tick,
+        min_y: bounds.min_y - half_y_tick,
+        max_y: bounds.max_y + half_y_tick,
+        ..*bounds
+    }
+}
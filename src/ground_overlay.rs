@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use geo::Coord;
+use image::{ImageBuffer, RgbaImage, RgbImage};
+use kml::types::Element;
+use kml::{Kml, KmlReader};
+use log::warn;
+use rayon::prelude::*;
+use crate::errors::LasToStlError;
+use crate::gltf::GltfTexture;
+use crate::height_map::HeightMap;
+use crate::mask::Mask;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// An image draped over the map by a KML `GroundOverlay`, like a scanned trail map or an orthophoto
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroundOverlay {
+    pub name: Option<String>,
+    /// the image of the overlay. Relative paths in the KML are relative to the KML file
+    pub image_path: PathBuf,
+    /// edges of the image in degrees (before it's rotated)
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+    /// how far the image is turned counter clockwise around its center, in degrees
+    pub rotation_degrees: f64,
+}
+
+/// Finds every `GroundOverlay` with a `LatLonBox` in a KML file, however deep in folders it is.
+/// Overlays placed with a `gx:LatLonQuad` or with an image on a web server are skipped with a warning
+pub fn load_ground_overlays<P: AsRef<Path>>(path: P) -> Result<Vec<GroundOverlay>, LasToStlError>{
+    let directory = path.as_ref().parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut kml_reader = KmlReader::<_, f64>::from_path(path)?;
+    let kml_data: Kml<f64> = kml_reader.read()?;
+
+    let mut overlays: Vec<GroundOverlay> = Vec::new();
+    collect_ground_overlays(&kml_data, &directory, &mut overlays);
+    Ok(overlays)
+}
+
+fn collect_ground_overlays(kml: &Kml<f64>, directory: &Path, overlays: &mut Vec<GroundOverlay>){
+    match kml {
+        Kml::KmlDocument(document) => {
+            document.elements.iter().for_each(|element| collect_ground_overlays(element, directory, overlays))
+        }
+        Kml::Document { elements, .. } | Kml::Folder { elements, .. } => {
+            elements.iter().for_each(|element| collect_ground_overlays(element, directory, overlays))
+        }
+        // the kml library doesn't know ground overlays, so they're plain elements
+        Kml::Element(element) => collect_element_ground_overlays(element, directory, overlays),
+        _ => {}
+    }
+}
+
+fn collect_element_ground_overlays(element: &Element, directory: &Path, overlays: &mut Vec<GroundOverlay>){
+    if element.name != "GroundOverlay" {
+        element.children.iter().for_each(|child| collect_element_ground_overlays(child, directory, overlays));
+        return
+    }
+
+    let get_child = |element: &Element, name: &str| element.children.iter().find(|child| child.name == name).cloned();
+    let get_text = |element: &Element, name: &str| get_child(element, name)
+        .and_then(|child| child.content)
+        .map(|content| content.trim().to_string());
+    let get_number = |element: &Element, name: &str| get_text(element, name).and_then(|text| text.parse::<f64>().ok());
+
+    let name = get_text(element, "name");
+    let Some(href) = get_child(element, "Icon").and_then(|icon| get_text(&icon, "href")) else {
+        warn!("skipping ground overlay {:?} without an image", name);
+        return
+    };
+    if href.starts_with("http://") || href.starts_with("https://") {
+        warn!("skipping ground overlay {:?}, its image ({}) is on a web server. Download it and change the href", name, href);
+        return
+    }
+    let Some(lat_lon_box) = get_child(element, "LatLonBox") else {
+        warn!("skipping ground overlay {:?}, only overlays placed with a LatLonBox are supported", name);
+        return
+    };
+    let (Some(north), Some(south), Some(east), Some(west)) = (
+        get_number(&lat_lon_box, "north"),
+        get_number(&lat_lon_box, "south"),
+        get_number(&lat_lon_box, "east"),
+        get_number(&lat_lon_box, "west"),
+    ) else {
+        warn!("skipping ground overlay {:?}, its LatLonBox is missing an edge", name);
+        return
+    };
+
+    overlays.push(GroundOverlay {
+        name,
+        image_path: directory.join(href),
+        north,
+        south,
+        east,
+        west,
+        rotation_degrees: get_number(&lat_lon_box, "rotation").unwrap_or(0f64),
+    });
+}
+
+impl GroundOverlay {
+    /// where a lat/lon point (x = lon, y = lat) is in the image, 0 to 1 from the left and from the top.
+    /// Outside of 0 to 1 is outside of the image
+    fn get_uv(&self, lat_lon: &Coord) -> (f64, f64){
+        let center = Coord { x: (self.east + self.west) / 2f64, y: (self.north + self.south) / 2f64 };
+        // turn the point the other way around the center, so it's where it'd be on the image before it was rotated
+        let (sin, cos) = (-self.rotation_degrees).to_radians().sin_cos();
+        let (dx, dy) = (lat_lon.x - center.x, lat_lon.y - center.y);
+        let lon = center.x + dx * cos - dy * sin;
+        let lat = center.y + dx * sin + dy * cos;
+        ((lon - self.west) / (self.east - self.west), (self.north - lat) / (self.north - self.south))
+    }
+}
+
+/// An RGBA image with one pixel per cell of a heightmap, like the heightmap's other layers (see `ColorMap`).
+/// Pixels with an alpha of 0 have no data. Row y of the data is row y of the heightmap, so images are vertically flipped
+#[derive(Clone, Debug)]
+pub struct RasterLayer {
+    pub data: Vec<[u8; 4]>,
+    pub x_res: usize,
+    pub y_res: usize,
+    pub bounds: UtmBoundingBox,
+    pub utm_zone: UtmZone,
+}
+
+impl RasterLayer {
+    /// an empty (transparent) layer lined up with `height_map`.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn new_for_height_map(height_map: &HeightMap) -> Result<RasterLayer, LasToStlError>{
+        Ok(RasterLayer {
+            data: vec![[0u8; 4]; height_map.x_res * height_map.y_res],
+            x_res: height_map.x_res,
+            y_res: height_map.y_res,
+            bounds: height_map.bounds,
+            utm_zone: height_map.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?,
+        })
+    }
+
+    /// Loads the image of `overlay` and draws it onto the layer (over whatever is already there, using its alpha).
+    /// Every cell gets the pixel of the image under its center
+    pub fn add_ground_overlay(&mut self, overlay: &GroundOverlay) -> Result<(), LasToStlError>{
+        let image = image::open(&overlay.image_path)?.to_rgba8();
+        let (width, height) = (image.width() as f64, image.height() as f64);
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+        let (x_res, bounds, utm_zone) = (self.x_res, self.bounds, self.utm_zone);
+
+        self.data.par_iter_mut().enumerate().try_for_each(|(index, pixel)| {
+            let easting = bounds.min_x + (index % x_res) as f64 * x_tick;
+            let northing = bounds.min_y + (index / x_res) as f64 * y_tick;
+            let (u, v) = overlay.get_uv(&utm_zone.to_lat_lon(easting, northing)?);
+            if (0f64..1f64).contains(&u) && (0f64..1f64).contains(&v) {
+                let source = image.get_pixel((u * width) as u32, (v * height) as u32).0;
+                *pixel = blend_over(*pixel, source);
+            }
+            Ok(())
+        })
+    }
+
+    /// Makes a mask that's true wherever `predicate` is true for the [red, green, blue, alpha] of the cell,
+    /// e.g. `|[red, green, blue, alpha]| alpha > 0 && blue > red` for the water on a map
+    pub fn to_mask<F: Fn([u8; 4]) -> bool>(&self, predicate: F) -> Mask{
+        let mut mask = Mask::new_with_dims_unchecked(self.x_res, self.y_res, self.bounds, self.utm_zone);
+        mask.data = self.data.iter().map(|pixel| predicate(*pixel)).collect();
+        mask
+    }
+
+    /// the layer as an image, vertically flipped like `HeightMap::save_to_image`
+    pub fn to_image(&self) -> Result<RgbaImage, LasToStlError>{
+        ImageBuffer::from_vec(self.x_res as u32, self.y_res as u32, self.data.iter().flatten().copied().collect())
+            .ok_or(LasToStlError::ImageNoneError)
+    }
+
+    /// saves `to_image` as a png (or whatever the extension is)
+    pub fn save_to_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        self.to_image()?.save(path)?;
+        Ok(())
+    }
+}
+
+impl HeightMap {
+    /// Draws every overlay, in order, onto a layer lined up with the heightmap. See `RasterLayer::add_ground_overlay`
+    pub fn rasterize_ground_overlays(&self, overlays: &[GroundOverlay]) -> Result<RasterLayer, LasToStlError>{
+        let mut layer = RasterLayer::new_for_height_map(self)?;
+        for overlay in overlays {
+            layer.add_ground_overlay(overlay)?;
+        }
+        Ok(layer)
+    }
+}
+
+impl GltfTexture {
+    /// the layer draped over the model. Cells without data are light grey
+    pub fn from_raster_layer(layer: &RasterLayer) -> Result<GltfTexture, LasToStlError>{
+        let image: RgbImage = ImageBuffer::from_vec(
+            layer.x_res as u32,
+            layer.y_res as u32,
+            layer.data.iter().flat_map(|pixel| {
+                let [red, green, blue, _] = blend_over([204, 204, 204, 255], *pixel);
+                [red, green, blue]
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)?;
+        Ok(GltfTexture::from_grid_image(image, &layer.bounds))
+    }
+}
+
+/// `top` drawn over `bottom`, using the alpha of both
+fn blend_over(bottom: [u8; 4], top: [u8; 4]) -> [u8; 4]{
+    let top_alpha = top[3] as f64 / 255f64;
+    let bottom_alpha = bottom[3] as f64 / 255f64 * (1f64 - top_alpha);
+    let alpha = top_alpha + bottom_alpha;
+    if alpha == 0f64 {
+        return [0u8; 4]
+    }
+    let mut out = [0u8; 4];
+    for channel in 0..3 {
+        out[channel] = ((top[channel] as f64 * top_alpha + bottom[channel] as f64 * bottom_alpha) / alpha).round() as u8;
+    }
+    out[3] = (alpha * 255f64).round() as u8;
+    out
+}
@@ -1,43 +1,216 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{BufWriter, Read, Write};
+#[cfg(feature = "image")]
+use std::io::{BufRead, Seek};
 use std::ops::{AddAssign};
 use std::path::{Path};
+#[cfg(feature = "csv")]
 use csv::WriterBuilder;
-use image::{ImageBuffer, Luma};
+#[cfg(feature = "image")]
+use image::codecs::png::PngEncoder;
+#[cfg(feature = "image")]
+use image::{ColorType, DynamicImage, ImageBuffer, ImageEncoder, Luma, Rgb};
+use geo::Coord;
 use las::Point;
+use log::{info, warn};
 use num::Zero;
 
-use crate::utils::{scale_float_to_uint_range, x_y_to_index};
+use crate::utils::{f64_max, f64_min, par_zip_for_each, reorient_grid, swap_bound_ranges, x_y_to_index, GridOrientation};
+#[cfg(feature = "image")]
+use crate::utils::scale_float_to_uint_range;
 use serde::{Deserialize, Serialize};
+use crate::color_map::{ColorAggregate, ColorMap};
+use crate::crs::{Crs, Reprojector};
+use crate::density_map::DensityMap;
+use crate::batch_report::BatchReport;
 use crate::errors::LasToStlError;
+use crate::intensity_map::IntensityMap;
 use crate::mask::Mask;
+#[cfg(feature = "image")]
+use crate::stats::ZRange;
 use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::{UtmCoord, UtmZone};
 
 
 
 
+/// How the heights of all the points that fall into one cell are combined into the height of that cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BinningStrategy {
+    /// average height of all points. Smears cliffs and treetops, but is the least noisy
+    #[default]
+    Mean,
+    /// lowest point in the cell. Useful for a bare minimum surface
+    Min,
+    /// highest point in the cell. Useful for canopy-top surfaces
+    Max,
+    /// median height of all points. Needs to keep every sample in memory
+    Median,
+    /// the given percentile (0 to 100) of the heights of all points, linearly interpolated between samples.
+    /// Needs to keep every sample in memory
+    Percentile(f32),
+}
+
+impl BinningStrategy {
+    /// returns true if this strategy needs every sample to be kept, instead of just a sum and counter
+    pub fn needs_samples(&self) -> bool {
+        matches!(self, BinningStrategy::Median | BinningStrategy::Percentile(_))
+    }
+}
+
+/// How to get rid of bad returns (birds, power lines, sensor noise) that would otherwise make huge spikes in the heightmap.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OutlierRejection {
+    /// drop points more than this many standard deviations from the mean of their cell before binning.
+    /// Needs to keep every sample in memory, and only works if cells have a few points each.
+    CellStdDev(f64),
+    /// after binning, replace cells that are more than `std_devs` standard deviations from the mean of the
+    /// cells within `radius` cells of them with that mean. Catches single cell spikes even with sparse data.
+    NeighborhoodStdDev{
+        std_devs: f64,
+        radius: usize,
+    },
+}
+
+impl OutlierRejection {
+    /// returns true if this needs every sample to be kept, instead of just a sum and counter
+    pub fn needs_samples(&self) -> bool {
+        matches!(self, OutlierRejection::CellStdDev(_))
+    }
+}
+
+/// How to get values between cells when resampling
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// the value of the closest cell. Blocky, but never makes up new heights
+    Nearest,
+    /// blends the 4 closest cells
+    Bilinear,
+    /// blends the 16 closest cells (Catmull-Rom). Smoother than bilinear when upsampling,
+    /// but can overshoot a little next to sharp edges
+    Bicubic,
+}
+
+/// What `HeightMap::merge` does where heightmaps overlap
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MergePolicy {
+    /// average the heights of every heightmap that has data there
+    Average,
+    /// use the first heightmap (in the order they were given) that has data there
+    Priority,
+}
+
+/// How to fill cells that no points landed in, instead of leaving them at `min_z` (which makes deep pits in the STL).
+/// Only populated cells are used to fill holes, never other filled holes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HoleFilling {
+    /// use the height of the closest populated cell, if there is one within `max_distance` cells
+    Nearest{
+        max_distance: usize,
+    },
+    /// average the populated cells within `radius` cells, weighted by 1 / distance^`power`.
+    /// A power of 2 is the usual choice, higher powers favor closer cells more
+    InverseDistanceWeighted{
+        power: f64,
+        radius: usize,
+    },
+}
+
 /// A sum and counter to be able to do calculations after reading from file.
+/// Also keeps every sample if the `BinningStrategy` needs them (median or percentile)
 /// This should only be used in the context of loading LAS files(s) into a heightmap
 /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct PointAggregate{
     point_sum: f64,
-    num_points: u16
+    num_points: u32,
+    min: f64,
+    max: f64,
+    samples: Option<Vec<f32>>,
 }
 
 impl Default for PointAggregate {
     fn default() -> Self {
         PointAggregate {
             point_sum: 0f64,
-            num_points: 0u16
+            num_points: 0u32,
+            min: f64::MAX,
+            max: f64::MIN,
+            samples: None,
         }
     }
 }
 
 impl PointAggregate{
+    /// creates an empty aggregate, keeping every sample if `keep_samples` is true
+    pub fn new(keep_samples: bool) -> PointAggregate{
+        PointAggregate {
+            samples: if keep_samples { Some(Vec::new()) } else { None },
+            ..PointAggregate::default()
+        }
+    }
+
     pub fn add_sample(&mut self, new_height: f64){
         self.point_sum += new_height;
         self.num_points += 1;
+        self.min = f64_min(self.min, new_height);
+        self.max = f64_max(self.max, new_height);
+        if let Some(samples) = &mut self.samples{
+            samples.push(new_height as f32);
+        }
+    }
+
+    /// returns true if no samples have been added
+    pub fn is_empty(&self) -> bool{
+        self.num_points.is_zero()
+    }
+
+    /// number of samples added
+    pub fn get_num_points(&self) -> u32{
+        self.num_points
+    }
+
+    /// returns true if this aggregate keeps every sample
+    pub fn keeps_samples(&self) -> bool{
+        self.samples.is_some()
+    }
+
+    /// returns a copy of self with only the samples within `std_devs` standard deviations of the mean.
+    /// Returns a plain copy if samples weren't kept.
+    pub fn without_outliers(&self, std_devs: f64) -> PointAggregate{
+        let samples = match &self.samples{
+            Some(samples) if samples.len() > 2 => {
+                samples
+            }
+            _ => {
+                return self.clone()
+            }
+        };
+
+        let num_samples = samples.len() as f64;
+        let mean = samples.iter().map(|sample| *sample as f64).sum::<f64>() / num_samples;
+        let variance = samples.iter().map(|sample| (*sample as f64 - mean).powi(2)).sum::<f64>() / num_samples;
+        let max_distance = variance.sqrt() * std_devs;
+
+        let mut filtered = PointAggregate::new(true);
+        for sample in samples{
+            if (*sample as f64 - mean).abs() <= max_distance{
+                filtered.add_sample(*sample as f64);
+            }
+        }
+        filtered
+    }
+
+    /// adds all samples from `other` to self, as if they had been added with `add_sample`
+    pub fn merge(&mut self, other: &PointAggregate){
+        self.point_sum += other.point_sum;
+        self.num_points += other.num_points;
+        self.min = f64_min(self.min, other.min);
+        self.max = f64_max(self.max, other.max);
+        if let (Some(samples), Some(other_samples)) = (&mut self.samples, &other.samples){
+            samples.extend_from_slice(other_samples);
+        }
     }
 
     /// returns average height from all added values, or `default` if there were no samples.
@@ -49,6 +222,113 @@ impl PointAggregate{
         }
 
     }
+
+    /// returns the height of the cell according to `strategy`, or `default` if there were no samples.
+    ///
+    /// Median and percentile fall back to the average if samples were not kept.
+    pub fn get_value_or_default(&self, strategy: BinningStrategy, default: f64) -> f64{
+        if self.num_points.is_zero(){
+            return default
+        }
+        match strategy{
+            BinningStrategy::Mean => {
+                self.get_average_or_default(default)
+            }
+            BinningStrategy::Min => {
+                self.min
+            }
+            BinningStrategy::Max => {
+                self.max
+            }
+            BinningStrategy::Median => {
+                self.get_percentile_or_default(50f32, default)
+            }
+            BinningStrategy::Percentile(percentile) => {
+                self.get_percentile_or_default(percentile, default)
+            }
+        }
+    }
+
+    /// returns the `percentile` (0 to 100) of all kept samples, linearly interpolated between samples.
+    /// Returns the average if samples weren't kept, or `default` if there were no samples.
+    pub fn get_percentile_or_default(&self, percentile: f32, default: f64) -> f64{
+        match &self.samples{
+            Some(samples) if !samples.is_empty() => {
+                let mut sorted = samples.clone();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+
+                let rank = (percentile.clamp(0f32, 100f32) / 100f32) * (sorted.len() - 1) as f32;
+                let lower = rank.floor() as usize;
+                let upper = rank.ceil() as usize;
+                let fraction = rank - lower as f32;
+
+                (sorted[lower] + (sorted[upper] - sorted[lower]) * fraction) as f64
+            }
+            _ => {
+                self.get_average_or_default(default)
+            }
+        }
+    }
+
+    /// writes this aggregate as a little endian binary record.
+    /// Used to spill partial grids to disk when loading LAS files in streaming mode.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        writer.write_all(&self.point_sum.to_le_bytes())?;
+        writer.write_all(&self.num_points.to_le_bytes())?;
+        writer.write_all(&self.min.to_le_bytes())?;
+        writer.write_all(&self.max.to_le_bytes())?;
+        match &self.samples{
+            Some(samples) => {
+                writer.write_all(&(samples.len() as u32).to_le_bytes())?;
+                for sample in samples{
+                    writer.write_all(&sample.to_le_bytes())?;
+                }
+            }
+            None => {
+                writer.write_all(&u32::MAX.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// reads a record written by `write_to`
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<PointAggregate, LasToStlError>{
+        let mut f64_bytes = [0u8; 8];
+        let mut u32_bytes = [0u8; 4];
+
+        reader.read_exact(&mut f64_bytes)?;
+        let point_sum = f64::from_le_bytes(f64_bytes);
+
+        reader.read_exact(&mut u32_bytes)?;
+        let num_points = u32::from_le_bytes(u32_bytes);
+
+        reader.read_exact(&mut f64_bytes)?;
+        let min = f64::from_le_bytes(f64_bytes);
+        reader.read_exact(&mut f64_bytes)?;
+        let max = f64::from_le_bytes(f64_bytes);
+
+        reader.read_exact(&mut u32_bytes)?;
+        let num_samples = u32::from_le_bytes(u32_bytes);
+
+        let samples = if num_samples == u32::MAX {
+            None
+        } else {
+            let mut samples = Vec::with_capacity(num_samples as usize);
+            for _ in 0..num_samples{
+                reader.read_exact(&mut u32_bytes)?;
+                samples.push(f32::from_le_bytes(u32_bytes));
+            }
+            Some(samples)
+        };
+
+        Ok(PointAggregate{
+            point_sum,
+            num_points,
+            min,
+            max,
+            samples,
+        })
+    }
 }
 
 /// The precursor to a heightmap. this should only be used in the context of loading data from LAS/LAZ file(s)
@@ -77,13 +357,70 @@ pub struct HeightMapIntermediate{
 
     /// bounds in meters (or units if you aren't using UTM)
     pub bounds: UtmBoundingBox,
+
+    /// how the points in each cell get combined when converting to a `HeightMap`
+    pub binning: BinningStrategy,
+
+    /// how spikes are removed when converting to a `HeightMap`, if at all
+    pub outlier_rejection: Option<OutlierRejection>,
+
+    /// how empty cells are filled when converting to a `HeightMap`, if at all
+    pub hole_filling: Option<HoleFilling>,
+
+    /// the UTM zone of the data, if known. Passed on to the `HeightMap`
+    pub utm_zone: Option<UtmZone>,
+
+    /// average intensity of the points in each cell, if `LasLoadOptions::aggregate_intensity` was set
+    pub intensity: Option<Vec<PointAggregate>>,
+
+    /// average color of the points in each cell, if `LasLoadOptions::aggregate_color` was set
+    pub color: Option<Vec<ColorAggregate>>,
+
+    /// which LAS/LAZ files were read into this and which were skipped (and why), since it was made or loaded from a checkpoint
+    pub load_report: BatchReport,
 }
 
 impl HeightMapIntermediate{
 
-    /// Creates a new `HeightMapIntermediate` ready to receive data from LAS files
+    /// Creates a new `HeightMapIntermediate` ready to receive data from LAS files.
+    /// Returns `LasToStlError::ResolutionTooSmallError` if either resolution is below 2,
+    /// and `LasToStlError::InvalidBoundsError` if the bounds are inverted or not finite (see `UtmBoundingBox::validate`)
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn new(x_res: usize, y_res: usize, utm_bounds: UtmBoundingBox) -> Result<HeightMapIntermediate, LasToStlError>{
+        HeightMapIntermediate::new_with_binning(x_res, y_res, utm_bounds, BinningStrategy::default())
+    }
+
+    /// Same as `new`, but combines the points in each cell according to `binning` instead of averaging them
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn new_with_binning(x_res: usize, y_res: usize, utm_bounds: UtmBoundingBox, binning: BinningStrategy) -> Result<HeightMapIntermediate, LasToStlError>{
+        HeightMapIntermediate::new_with_outlier_rejection(x_res, y_res, utm_bounds, binning, None)
+    }
+
+    /// Same as `new_with_binning`, but also removes spikes according to `outlier_rejection` when converting to a `HeightMap`
     /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
-    pub fn new(x_res: usize, y_res: usize, utm_bounds: UtmBoundingBox) -> HeightMapIntermediate{
+    pub fn new_with_outlier_rejection(x_res: usize,
+                                      y_res: usize,
+                                      utm_bounds: UtmBoundingBox,
+                                      binning: BinningStrategy,
+                                      outlier_rejection: Option<OutlierRejection>)
+        -> Result<HeightMapIntermediate, LasToStlError>
+    {
+        if x_res < 2 || y_res < 2 {
+            return Err(LasToStlError::ResolutionTooSmallError{ x_res, y_res })
+        }
+        utm_bounds.validate()?;
+        Ok(HeightMapIntermediate::new_unchecked(x_res, y_res, utm_bounds, binning, outlier_rejection))
+    }
+
+    /// `new_with_outlier_rejection` without checking the resolution and bounds
+    pub(crate) fn new_unchecked(x_res: usize,
+                                y_res: usize,
+                                utm_bounds: UtmBoundingBox,
+                                binning: BinningStrategy,
+                                outlier_rejection: Option<OutlierRejection>)
+        -> HeightMapIntermediate
+    {
+        let keep_samples = binning.needs_samples() || outlier_rejection.is_some_and(|rejection| rejection.needs_samples());
 
         let x_range = utm_bounds.x_range();
         let y_range = utm_bounds.y_range();
@@ -92,7 +429,7 @@ impl HeightMapIntermediate{
         let y_tick: f64 = y_range / (y_res - 1) as f64;
 
         HeightMapIntermediate{
-            data: vec![PointAggregate::default(); x_res*y_res],
+            data: vec![PointAggregate::new(keep_samples); x_res*y_res],
             x_res,
             y_res,
             x_tick,
@@ -100,7 +437,44 @@ impl HeightMapIntermediate{
             x_offset: utm_bounds.min_x,
             y_offset: utm_bounds.min_y,
             bounds: utm_bounds,
+            binning,
+            outlier_rejection,
+            hole_filling: None,
+            utm_zone: None,
+            intensity: None,
+            color: None,
+            load_report: BatchReport::new(),
+        }
+    }
+
+    /// returns true if the cells need to keep every sample for the binning strategy or outlier rejection
+    pub fn keeps_samples(&self) -> bool{
+        self.binning.needs_samples() || self.outlier_rejection.is_some_and(|rejection| rejection.needs_samples())
+    }
+
+    /// Gets the height of every cell according to the binning strategy and outlier rejection, or `None` for cells without points.
+    pub fn get_heights(&self) -> Vec<Option<f64>>{
+        let mut heights: Vec<Option<f64>> = self.data.iter().map(|aggregate| {
+            let aggregate = match self.outlier_rejection{
+                Some(OutlierRejection::CellStdDev(std_devs)) => {
+                    aggregate.without_outliers(std_devs)
+                }
+                _ => {
+                    aggregate.clone()
+                }
+            };
+            if aggregate.is_empty(){
+                None
+            } else {
+                Some(aggregate.get_value_or_default(self.binning, self.bounds.min_z))
+            }
+        }).collect();
+
+        if let Some(OutlierRejection::NeighborhoodStdDev { std_devs, radius }) = self.outlier_rejection{
+            heights = reject_neighborhood_outliers(&heights, self.x_res, self.y_res, std_devs, radius);
         }
+
+        heights
     }
 
     /// returns the index of where the point should go in data. This could be used in conjunction
@@ -115,11 +489,24 @@ impl HeightMapIntermediate{
         (y*self.x_res) + x
     }
 
+    /// same as `get_index`, but returns `None` if the point is outside of the grid
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn get_index_checked(&self, new_point: &Point) -> Option<usize>{
+        let x: f64 = (new_point.x - self.x_offset) / self.x_tick;
+        let y: f64 = (new_point.y - self.y_offset) / self.y_tick;
+
+        if x >= 0f64 && y >= 0f64 && (x as usize) < self.x_res && (y as usize) < self.y_res{
+            Some((y as usize * self.x_res) + x as usize)
+        } else {
+            None
+        }
+    }
+
     /// adds a height value by its index.
     /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
     pub fn add_point_by_index(&mut self, height: f64, index: usize){
         if index > self.x_res * self.y_res{
-            println!("out of bounds point, moving on");
+            warn!("out of bounds point, moving on");
             return;
         }
         self.data[index].add_sample(height)
@@ -157,29 +544,209 @@ impl HeightMapIntermediate{
         }
 
     }
+
+    /// converts the intensity grid into an `IntensityMap`, if intensity was aggregated
+    /// (see `LasLoadOptions::aggregate_intensity`). Cells without points get an intensity of 0.
+    pub fn get_intensity_map(&self) -> Option<IntensityMap>{
+        self.intensity.as_ref().map(|intensity| {
+            IntensityMap{
+                data: intensity.iter().map(|p| p.get_average_or_default(0f64)).collect(),
+                x_res: self.x_res,
+                y_res: self.y_res,
+                bounds: self.bounds,
+            }
+        })
+    }
+
+    /// counts the points in every cell. Always available, since every cell keeps a counter
+    pub fn get_density_map(&self) -> DensityMap{
+        DensityMap{
+            data: self.data.iter().map(|p| p.get_num_points()).collect(),
+            x_res: self.x_res,
+            y_res: self.y_res,
+            bounds: self.bounds,
+        }
+    }
+
+    /// converts the color grid into a `ColorMap`, if color was aggregated
+    /// (see `LasLoadOptions::aggregate_color`). Cells without colored points are black.
+    pub fn get_color_map(&self) -> Option<ColorMap>{
+        self.color.as_ref().map(|color| {
+            ColorMap{
+                data: color.iter().map(|c| c.get_average_or_black()).collect(),
+                x_res: self.x_res,
+                y_res: self.y_res,
+                bounds: self.bounds,
+            }
+        })
+    }
+
+    /// adds all the samples from another `HeightMapIntermediate` with the same resolution and bounds.
+    /// Used to combine partial grids that were filled on different threads.
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn merge(&mut self, other: &HeightMapIntermediate){
+        for (own_aggregate, other_aggregate) in self.data.iter_mut().zip(other.data.iter()){
+            own_aggregate.merge(other_aggregate);
+        }
+        if let (Some(intensity), Some(other_intensity)) = (&mut self.intensity, &other.intensity){
+            for (own_aggregate, other_aggregate) in intensity.iter_mut().zip(other_intensity.iter()){
+                own_aggregate.merge(other_aggregate);
+            }
+        }
+        if let (Some(color), Some(other_color)) = (&mut self.color, &other.color){
+            for (own_aggregate, other_aggregate) in color.iter_mut().zip(other_color.iter()){
+                own_aggregate.merge(other_aggregate);
+            }
+        }
+        self.load_report.merge(other.load_report.clone());
+    }
 }
 
 /// A grid of height values (in meters) spanning `bounds` (in utm)
 /// The primary struct used by this library
+///
+/// Cells without data (no LAS points, GeoTIFF no-data) are set to `bounds.min_z`, and every real height is kept
+/// above it, so `min_z` is the no-data value of the grid. See `is_empty_height`
 #[derive(Serialize, Deserialize, Debug)]
 pub struct HeightMap{
     pub data: Vec<f64>,
     pub x_res: usize,
     pub y_res: usize,
-    pub bounds: UtmBoundingBox
+    pub bounds: UtmBoundingBox,
+
+    /// The UTM zone the bounds are in, from the CRS in the LAS headers (or `LasLoadOptions::target_crs`).
+    /// `None` if the LAS files didn't say, or the heightmap was saved by an older version.
+    /// See `kml_utils::infer_utm_zone` to get it from KML data instead
+    #[serde(default)]
+    pub utm_zone: Option<UtmZone>,
+
+    /// What geoid correction has been applied to the heights, if any. See `HeightMap::apply_geoid_correction`
+    #[serde(default)]
+    pub geoid_correction: Option<String>,
+
+    /// The CRS the bounds are in, if it's known and isn't just `utm_zone`,
+    /// like after `convert_projection` into a state plane CRS. See `get_crs`
+    #[serde(default)]
+    pub crs: Option<Crs>,
+}
+
+/// Version of what `HeightMap::save` writes (in either `HeightMapFormat`). Files saved before it had a version are version 0
+pub const HEIGHT_MAP_FORMAT_VERSION: u32 = 1;
+
+/// what binary heightmap files start with, so `HeightMap::load` can tell them apart from JSON ones
+const BINARY_HEIGHT_MAP_MAGIC: &[u8; 8] = b"LKSHMAP\0";
+
+/// number of heights encoded or decoded at a time in the binary format
+#[cfg(feature = "zstd")]
+const BINARY_HEIGHTS_PER_CHUNK: usize = 1 << 13;
+
+/// How `HeightMap::save_with_format` writes a heightmap. `HeightMap::load` reads either one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeightMapFormat {
+    /// `BINARY_HEIGHT_MAP_MAGIC`, then a zstd stream of the length of the JSON metadata (u64, little endian),
+    /// the metadata, and every height as a little endian f64.
+    /// A fraction of the size of the JSON and much faster to load. Needs the `zstd` feature
+    #[cfg_attr(feature = "zstd", default)]
+    Binary,
+    /// everything as one JSON object, for reading the heightmap with something other than this library.
+    /// The default without the `zstd` feature
+    #[cfg_attr(not(feature = "zstd"), default)]
+    Json,
+}
+
+/// What `HeightMap::save` writes: the heightmap and what its coordinates mean,
+/// so a file can't be loaded months later as if it was in some other CRS or unit.
+/// In the binary format `data` is left empty and the heights come after it
+#[derive(Serialize, Deserialize)]
+struct HeightMapFile<'a> {
+    #[serde(default)]
+    format_version: u32,
+    /// the CRS of the bounds, if it's known
+    #[serde(default)]
+    crs: Option<Crs>,
+    /// meters per unit of the bounds (x and y), if the CRS is known. Heights are always in meters
+    #[serde(default)]
+    meters_per_unit: Option<f64>,
+    data: Cow<'a, [f64]>,
+    x_res: usize,
+    y_res: usize,
+    bounds: UtmBoundingBox,
+    #[serde(default)]
+    utm_zone: Option<UtmZone>,
+    #[serde(default)]
+    geoid_correction: Option<String>,
 }
 
 impl HeightMap{
 
+    /// creates a heightmap with a new grid, but the same UTM zone and other metadata as self
+    pub(crate) fn with_grid(&self, data: Vec<f64>, x_res: usize, y_res: usize, bounds: UtmBoundingBox) -> HeightMap{
+        HeightMap{
+            data,
+            x_res,
+            y_res,
+            bounds,
+            utm_zone: self.utm_zone,
+            geoid_correction: self.geoid_correction.clone(),
+            crs: self.crs.clone(),
+        }
+    }
+
+    /// returns true if `height` is the height of an empty cell, one without data. Empty cells are at `bounds.min_z`
+    /// and everything that makes or changes a heightmap keeps real heights above it (see `with_heights`),
+    /// so the lowest real cells are never mistaken for holes
+    pub fn is_empty_height(&self, height: f64) -> bool{
+        height <= self.bounds.min_z
+    }
+
+    /// Gets the height of every cell, or `None` for empty cells (see `is_empty_height`)
+    pub fn get_heights(&self) -> Vec<Option<f64>>{
+        self.data.iter().map(|height| (!self.is_empty_height(*height)).then_some(*height)).collect()
+    }
+
+    /// creates a heightmap from heights with `None` for empty cells, with the same UTM zone and other metadata as self.
+    /// Empty cells are set to `bounds.min_z`, which is first lowered to just below the lowest real height if it isn't below it already
+    pub(crate) fn with_heights(&self, heights: &[Option<f64>], x_res: usize, y_res: usize, mut bounds: UtmBoundingBox) -> HeightMap{
+        bounds.min_z = min_z_below_heights(bounds.min_z, heights);
+        let data = heights.iter().map(|height| height.unwrap_or(bounds.min_z)).collect();
+        self.with_grid(data, x_res, y_res, bounds)
+    }
+
     /// Tet the height at x, y. The coordinates are unit-less but evenly spaced.
     pub fn get_height(&self, x: usize, y: usize) -> Result<f64, LasToStlError>{
         Ok(self.data[x_y_to_index(self.x_res, self.y_res, x, y)?])
     }
 
+    /// gets the UTM coordinates of the cell at x, y (in the zone of self, if it knows it)
+    pub fn cell_to_utm(&self, x: usize, y: usize) -> Result<UtmCoord, LasToStlError>{
+        x_y_to_index(self.x_res, self.y_res, x, y)?;
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+        Ok(UtmCoord{
+            zone: self.utm_zone,
+            ..UtmCoord::new((self.bounds.min_x + x as f64 * x_tick, self.bounds.min_y + y as f64 * y_tick))
+        })
+    }
+
+    /// gets the lat/lon (x = lon, y = lat) of the cell at x, y, to report or export things found in the heightmap in GPS coordinates.
+    ///
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn cell_to_lat_lon(&self, x: usize, y: usize) -> Result<Coord, LasToStlError>{
+        self.cell_to_utm(x, y)?.to_lat_lon_in_own_zone()
+    }
+
     /// This was used at some point as a sanity check to validate the data, but now that image and stl work, this is pointless.
     /// Nonetheless I will keep it for that on MF who wants his height data represented by a unit-less csv file.
+    #[cfg(feature = "csv")]
     pub fn save_to_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
-        let mut output = WriterBuilder::new().has_headers(false).from_path(path)?;
+        self.write_csv(&mut File::create(path)?)
+    }
+
+    /// Same as `save_to_csv`, but writes into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        let mut output = WriterBuilder::new().has_headers(false).from_writer(writer);
         for row in self.data.chunks(self.x_res){
             output.serialize(row)?;
             output.flush()?;
@@ -187,119 +754,991 @@ impl HeightMap{
         Ok(())
     }
 
-    /// Loads from a JSON file. Extremely useful because parsing LAS/LAZ data can take a while
+    /// Saves every cell as an `easting northing height` line (in the units of `bounds`, to the millimeter),
+    /// the plain XYZ point format surveying and CAD software can read. Empty cells (at or below `bounds.min_z`) are left out
+    pub fn save_as_xyz<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        let mut output = BufWriter::new(File::create(path)?);
+        self.write_xyz(&mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Same as `save_as_xyz`, but only the cells where `mask` is true.
+    /// Returns `LasToStlError::MaskBoundMismatchError` if `mask` doesn't have the same resolution and x/y bounds as self
+    pub fn save_as_xyz_masked<P: AsRef<Path>>(&self, path: P, mask: &Mask) -> Result<(), LasToStlError>{
+        let mut output = BufWriter::new(File::create(path)?);
+        self.write_xyz_masked(&mut output, mask)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Same as `save_as_xyz`, but writes into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    pub fn write_xyz<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        self.write_xyz_filtered(writer, |_| true)
+    }
+
+    /// Same as `save_as_xyz_masked`, but writes into `writer` instead of a file
+    pub fn write_xyz_masked<W: Write>(&self, writer: &mut W, mask: &Mask) -> Result<(), LasToStlError>{
+        self.check_mask_matches(mask)?;
+        self.write_xyz_filtered(writer, |index| mask.data[index])
+    }
+
+    fn write_xyz_filtered<W: Write, F: Fn(usize) -> bool>(&self, writer: &mut W, include: F) -> Result<(), LasToStlError>{
+        let x_tick = self.bounds.x_range() / (self.x_res - 1).max(1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1).max(1) as f64;
+
+        for (index, height) in self.data.iter().enumerate() {
+            if self.is_empty_height(*height) || !include(index) {
+                continue
+            }
+            let easting = self.bounds.min_x + (index % self.x_res) as f64 * x_tick;
+            let northing = self.bounds.min_y + (index / self.x_res) as f64 * y_tick;
+            writeln!(writer, "{easting:.3} {northing:.3} {height:.3}")?;
+        }
+        Ok(())
+    }
+
+    /// Loads from a file made with `save`. Extremely useful because parsing LAS/LAZ data can take a while
     /// (depending on the area ofc) but adding kml regions and waypoints is almost instant.
-    /// So instead of rerunning the entire process to add a waypoint you can just load the JSON of
+    /// So instead of rerunning the entire process to add a waypoint you can just load the saved heightmap of
     /// the same region and avoid parsing the same data over and over.
     /// This does NOT use a standard format and unless this project goes viral, will never be a standard.
+    ///
+    /// Reads both `HeightMapFormat`s (and JSON saved by older versions), whatever the file is called.
+    /// Returns `LasToStlError::UnsupportedFormatVersionError` if it was saved by a newer version of this library,
+    /// `LasToStlError::SavedCrsMismatchError` if the CRS saved with it doesn't match its UTM zone,
+    /// and `LasToStlError::CorruptHeightMapFileError` if it doesn't have one height for every cell
     pub fn load<P: AsRef<Path>>(path: P) -> Result<HeightMap, LasToStlError> {
-        let mut file = File::open(path)?;
+        let mut file = File::open(&path)?;
+        HeightMap::read_saved(&mut file, &format!("{:?}", path.as_ref().display()))
+    }
+
+    /// Same as `load`, but reads from `reader` (like a `&[u8]` or a socket) instead of a file
+    pub fn load_from_reader<R: Read>(reader: &mut R) -> Result<HeightMap, LasToStlError> {
+        HeightMap::read_saved(reader, "the heightmap")
+    }
+
+    /// does the work of `load`. `name` is what the heightmap is called in the warning for old files
+    fn read_saved<R: Read>(reader: &mut R, name: &str) -> Result<HeightMap, LasToStlError> {
         let mut buf = vec![];
-        file.read_to_end(&mut buf)?;
-        serde_json::from_slice::<HeightMap>(&buf[..]).map_err(|e|{LasToStlError::SerdeError(e)})
+        reader.by_ref().take(BINARY_HEIGHT_MAP_MAGIC.len() as u64).read_to_end(&mut buf)?;
+        if buf == BINARY_HEIGHT_MAP_MAGIC {
+            return HeightMap::from_saved(HeightMap::read_binary(reader)?, name)
+        }
+
+        reader.read_to_end(&mut buf)?;
+        let saved = serde_json::from_slice::<HeightMapFile>(&buf[..]).map_err(|e|{LasToStlError::SerdeError(e)})?;
+        HeightMap::from_saved(saved, name)
+    }
+
+    /// reads the binary format after `BINARY_HEIGHT_MAP_MAGIC`, see `HeightMapFormat::Binary`.
+    /// The header isn't trusted: the heights are read a chunk at a time, so a corrupt resolution is an error
+    /// once the stream runs out instead of a huge allocation up front
+    #[cfg(feature = "zstd")]
+    fn read_binary<R: Read>(reader: &mut R) -> Result<HeightMapFile<'static>, LasToStlError> {
+        let corrupt = |reason: String| LasToStlError::CorruptHeightMapFileError(reason);
+        let mut decoder = zstd::Decoder::new(reader)?;
+        let mut length_bytes = [0u8; 8];
+        decoder.read_exact(&mut length_bytes)?;
+        let metadata_length = u64::from_le_bytes(length_bytes);
+        let mut metadata = vec![];
+        decoder.by_ref().take(metadata_length).read_to_end(&mut metadata)?;
+        if metadata.len() as u64 != metadata_length {
+            return Err(corrupt(format!("it ends in its metadata, after {} of {metadata_length} bytes", metadata.len())))
+        }
+        let mut saved = serde_json::from_slice::<HeightMapFile>(&metadata[..])?;
+
+        let num_heights = saved.x_res.checked_mul(saved.y_res)
+            .ok_or_else(|| corrupt(format!("{}x{} cells is too many", saved.x_res, saved.y_res)))?;
+        let mut data = Vec::with_capacity(num_heights.min(BINARY_HEIGHTS_PER_CHUNK));
+        let mut chunk = vec![0u8; BINARY_HEIGHTS_PER_CHUNK * 8];
+        while data.len() < num_heights {
+            let chunk = &mut chunk[..(num_heights - data.len()).min(BINARY_HEIGHTS_PER_CHUNK) * 8];
+            decoder.read_exact(chunk).map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => corrupt(format!("it ends after {} of its {num_heights} heights", data.len())),
+                _ => LasToStlError::IoError(e),
+            })?;
+            data.extend(chunk.chunks_exact(8).map(|bytes| {
+                let mut height = [0u8; 8];
+                height.copy_from_slice(bytes);
+                f64::from_le_bytes(height)
+            }));
+        }
+        if decoder.read(&mut [0u8; 1])? != 0 {
+            return Err(corrupt(format!("it has more than the {num_heights} heights of a {}x{} grid", saved.x_res, saved.y_res)))
+        }
+        saved.data = Cow::Owned(data);
+        Ok(saved)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn read_binary<R: Read>(_reader: &mut R) -> Result<HeightMapFile<'static>, LasToStlError> {
+        Err(LasToStlError::ZstdFeatureDisabledError)
+    }
+
+    /// checks what was saved with a heightmap and turns it back into one.
+    /// Either format can be edited or corrupted, so the grid is checked here too and not only when reading the heights
+    fn from_saved(saved: HeightMapFile, name: &str) -> Result<HeightMap, LasToStlError> {
+        if saved.format_version > HEIGHT_MAP_FORMAT_VERSION {
+            return Err(LasToStlError::UnsupportedFormatVersionError{ found: saved.format_version, supported: HEIGHT_MAP_FORMAT_VERSION })
+        }
+        if saved.x_res < 2 || saved.y_res < 2 {
+            return Err(LasToStlError::ResolutionTooSmallError{ x_res: saved.x_res, y_res: saved.y_res })
+        }
+        if saved.x_res.checked_mul(saved.y_res) != Some(saved.data.len()) {
+            return Err(LasToStlError::CorruptHeightMapFileError(
+                format!("it has {} heights, but a {}x{} grid has {}", saved.data.len(), saved.x_res, saved.y_res, saved.x_res as u128 * saved.y_res as u128)
+            ))
+        }
+        saved.bounds.validate()?;
+        if saved.format_version == 0 {
+            warn!("{name} was saved by an older version without CRS info, so its bounds are assumed to be in {}",
+                saved.utm_zone.map_or(String::from("an unknown CRS"), |zone| zone.to_crs().to_string()));
+        }
+        // only keep the CRS if it says more than the UTM zone
+        let crs = match (saved.crs, saved.utm_zone) {
+            (Some(crs), Some(utm_zone)) if crs.get_utm_zone() != Some(utm_zone) => {
+                return Err(LasToStlError::SavedCrsMismatchError{ expected: utm_zone.to_crs(), saved: crs })
+            }
+            (_, Some(_)) => None,
+            (crs, None) => crs,
+        };
+
+        Ok(HeightMap{
+            data: saved.data.into_owned(),
+            x_res: saved.x_res,
+            y_res: saved.y_res,
+            bounds: UtmBoundingBox{ utm_zone: saved.utm_zone, ..saved.bounds },
+            utm_zone: saved.utm_zone,
+            geoid_correction: saved.geoid_correction,
+            crs,
+        })
+    }
+
+    /// Same as `load`, but returns `LasToStlError::SavedCrsMismatchError` if the heightmap wasn't saved in `expected_crs`,
+    /// or `LasToStlError::SavedCrsUnknownError` if it was saved without knowing its CRS, so a project can't accidentally use a heightmap from somewhere else
+    pub fn load_in_crs<P: AsRef<Path>>(path: P, expected_crs: &Crs) -> Result<HeightMap, LasToStlError> {
+        HeightMap::load(path)?.check_saved_crs(expected_crs)
+    }
+
+    /// Same as `load_in_crs`, but reads from `reader` instead of a file
+    pub fn load_in_crs_from_reader<R: Read>(reader: &mut R, expected_crs: &Crs) -> Result<HeightMap, LasToStlError> {
+        HeightMap::load_from_reader(reader)?.check_saved_crs(expected_crs)
+    }
+
+    fn check_saved_crs(self, expected_crs: &Crs) -> Result<HeightMap, LasToStlError> {
+        match self.get_crs() {
+            Some(crs) if crs == *expected_crs => Ok(self),
+            Some(crs) => Err(LasToStlError::SavedCrsMismatchError{ expected: expected_crs.clone(), saved: crs }),
+            None => Err(LasToStlError::SavedCrsUnknownError(expected_crs.clone())),
+        }
     }
 
-    /// Saves to a JSON file. Extremely useful because parsing LAS/LAZ data can take a while
+    /// Saves to a file in the compact binary format (or JSON without the `zstd` feature, see `HeightMapFormat`). Extremely useful because parsing LAS/LAZ data can take a while
     /// (depending on the area ofc) but adding kml regions and waypoints is almost instant.
-    /// So instead of rerunning the entire process to add a waypoint you can just load the JSON of
+    /// So instead of rerunning the entire process to add a waypoint you can just load the saved heightmap of
     /// the same region and avoid parsing the same data over and over.
     /// This does NOT use a standard format and unless this project goes viral, will never be a standard.
+    ///
+    /// The CRS (see `get_crs`), its units, and `HEIGHT_MAP_FORMAT_VERSION` are saved with it
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
-        let mut f = File::create(path)?;
-        let buf = serde_json::to_vec(self)?;
-        f.write_all(&buf[..])?;
+        self.save_with_format(path, HeightMapFormat::default())
+    }
+
+    /// Same as `save`, but in `format`. `HeightMapFormat::Json` is easier to read from other programs, but much bigger
+    pub fn save_with_format<P: AsRef<Path>>(&self, path: P, format: HeightMapFormat) -> Result<(), LasToStlError>{
+        let mut file = BufWriter::new(File::create(path)?);
+        self.save_to_writer_with_format(&mut file, format)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Same as `save`, but writes into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    pub fn save_to_writer<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        self.save_to_writer_with_format(writer, HeightMapFormat::default())
+    }
 
+    /// Same as `save_with_format`, but writes into `writer` instead of a file
+    pub fn save_to_writer_with_format<W: Write>(&self, writer: &mut W, format: HeightMapFormat) -> Result<(), LasToStlError>{
+        match format {
+            HeightMapFormat::Binary => self.write_binary(writer),
+            HeightMapFormat::Json => {
+                serde_json::to_writer(writer, &self.to_saved(Cow::Borrowed(&self.data)))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// writes `HeightMapFormat::Binary`
+    #[cfg(feature = "zstd")]
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        writer.write_all(BINARY_HEIGHT_MAP_MAGIC)?;
+        let metadata = serde_json::to_vec(&self.to_saved(Cow::Borrowed(&[])))?;
+        let mut encoder = zstd::Encoder::new(writer, 0)?;
+        encoder.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        encoder.write_all(&metadata)?;
+
+        let mut chunk = Vec::with_capacity(BINARY_HEIGHTS_PER_CHUNK * 8);
+        for heights in self.data.chunks(BINARY_HEIGHTS_PER_CHUNK) {
+            chunk.clear();
+            chunk.extend(heights.iter().flat_map(|height| height.to_le_bytes()));
+            encoder.write_all(&chunk)?;
+        }
+        encoder.finish()?;
         Ok(())
     }
 
+    #[cfg(not(feature = "zstd"))]
+    fn write_binary<W: Write>(&self, _writer: &mut W) -> Result<(), LasToStlError>{
+        Err(LasToStlError::ZstdFeatureDisabledError)
+    }
+
+    /// what's saved with the heightmap, with `data` as its heights
+    fn to_saved<'a>(&self, data: Cow<'a, [f64]>) -> HeightMapFile<'a>{
+        let crs = self.get_crs();
+        HeightMapFile{
+            format_version: HEIGHT_MAP_FORMAT_VERSION,
+            meters_per_unit: crs.as_ref().map(Crs::meters_per_unit),
+            crs,
+            data,
+            x_res: self.x_res,
+            y_res: self.y_res,
+            bounds: self.bounds,
+            utm_zone: self.utm_zone,
+            geoid_correction: self.geoid_correction.clone(),
+        }
+    }
+
+    /// Sets the zone of the heightmap and of its bounds, like after guessing it from KML data with `kml_utils::infer_utm_zone`
+    pub fn set_utm_zone(&mut self, utm_zone: Option<UtmZone>){
+        self.utm_zone = utm_zone;
+        self.bounds.utm_zone = utm_zone;
+    }
+
+    /// Gets the CRS the bounds are in: `crs` if it's set, otherwise the CRS of `utm_zone`.
+    /// `None` if neither is known
+    pub fn get_crs(&self) -> Option<Crs>{
+        self.crs.clone().or_else(|| self.utm_zone.map(|utm_zone| utm_zone.to_crs()))
+    }
+
     /// saves as a black and white png with brightness representing relative height.
     /// This is useful for doing a sanity check on your data and comparing it to a map.
     ///
     /// Note that the image is vertically flipped.
     /// This is normal and means that the stl data will be correct when saved as STL.
     /// If that's a problem, rotate your monitor and then it will be horizontally flipped.
+    /// (or flip a copy with `reorient(GridOrientation::FlipY)` before saving the image)
+    #[cfg(feature = "image")]
     pub fn save_to_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
-        let image: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_vec(
+        self.save_to_image_with_z_range(path, ZRange::Full)
+    }
+
+    /// Same as `save_to_image`, but black and white are the bottom and top of `z_range` instead of the z bounds.
+    /// Heights outside of it are clamped. e.g. `ZRange::Percentile{low: 1.0, high: 99.0}` so a few outliers don't make everything grey
+    #[cfg(feature = "image")]
+    pub fn save_to_image_with_z_range<P: AsRef<Path>>(&self, path: P, z_range: ZRange) -> Result<(), LasToStlError>{
+        // write it out to a file
+        self.to_image(z_range)?.save(path)?;
+        Ok(())
+    }
+
+    /// Same as `save_to_image`, but writes a PNG into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    #[cfg(feature = "image")]
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        self.write_png_with_z_range(writer, ZRange::Full)
+    }
+
+    /// Same as `save_to_image_with_z_range`, but writes a PNG into `writer` instead of a file
+    #[cfg(feature = "image")]
+    pub fn write_png_with_z_range<W: Write>(&self, writer: &mut W, z_range: ZRange) -> Result<(), LasToStlError>{
+        let image = self.to_image(z_range)?;
+        PngEncoder::new(writer).write_image(image.as_raw(), image.width(), image.height(), ColorType::L8)?;
+        Ok(())
+    }
+
+    /// gets the image `save_to_image_with_z_range` saves
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, z_range: ZRange) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>, LasToStlError>{
+        let (min_z, max_z) = self.get_z_range(z_range)?;
+        ImageBuffer::from_vec(
             self.x_res as u32,
             self.y_res as u32,
             self.data.iter().map(|height| {
-                scale_float_to_uint_range(height, self.bounds.min_z, self.bounds.max_z, 255) as u8
+                scale_float_to_uint_range(&height.clamp(min_z, max_z), min_z, max_z, 255) as u8
             }).collect()
-        ).ok_or(LasToStlError::ImageNoneError)?;
-
-        // write it out to a file
-        image.save(path)?;
-        Ok(())
+        ).ok_or(LasToStlError::ImageNoneError)
     }
 
-    /// adds `offset` to all height values with coordinates that are set to true in mask.
-    /// Mask must have the same resolution and bounds as self.
-    ///
-    /// You can guarantee this by constructing the mask with parameters from the heightmap you intend on applying it to.
+    /// Creates a heightmap from a grayscale image (color images are converted to grayscale), so terrain can be sketched
+    /// in an image editor or made from a displacement map. Black is `min_z` and white is `max_z`, 16 bit images keep their precision.
+    /// Only the x/y of `bounds` are used. There is no UTM zone, set `utm_zone` to use KML masks.
+    /// Images have no "no data", so every pixel is a real height, even black ones (`bounds.min_z` is set just below `min_z`).
     ///
-    /// (e.g. `Mask::new_with_dims(hm.x_res, hm.y_res, hm.bounds)`)
-    pub fn offset_by_mask(&mut self, mask: &Mask, offset: f64) -> Result<(), LasToStlError>{
-        if self.x_res == mask.x_res && self.y_res == mask.y_res && self.bounds == mask.bounds{
-            let data_iter = self.data.iter_mut();
-            let mask_iter = mask.data.iter();
+    /// The image is vertically flipped like `save_to_image`, so the top row is the south edge and saved images load back the same.
+    /// For an image drawn with north up, `reorient(GridOrientation::FlipY)` the heightmap after loading.
+    #[cfg(feature = "image")]
+    pub fn from_image<P: AsRef<Path>>(path: P, bounds: UtmBoundingBox, min_z: f64, max_z: f64) -> Result<HeightMap, LasToStlError>{
+        HeightMap::from_dynamic_image(image::open(path)?, bounds, min_z, max_z)
+    }
 
-            for (height, mask_state) in data_iter.zip(mask_iter){
-                if *mask_state {
-                    height.add_assign(offset);
-                }
-            }
+    /// Same as `from_image`, but reads the image from `reader` (like a `Cursor` over the bytes of a PNG) instead of a file.
+    /// The format is guessed from the first few bytes
+    #[cfg(feature = "image")]
+    pub fn from_image_reader<R: BufRead + Seek>(reader: R, bounds: UtmBoundingBox, min_z: f64, max_z: f64) -> Result<HeightMap, LasToStlError>{
+        let image = image::io::Reader::new(reader).with_guessed_format()?.decode()?;
+        HeightMap::from_dynamic_image(image, bounds, min_z, max_z)
+    }
 
-            Ok(())
-        } else {
-            Err(LasToStlError::MaskBoundMismatchError{
-                other_x_res: self.x_res,
-                other_y_res: self.y_res,
-                mask_x_res: mask.x_res,
-                mask_y_res: mask.y_res,
-                other_bounds: self.bounds,
-                mask_bounds: mask.bounds,
-            })
+    #[cfg(feature = "image")]
+    fn from_dynamic_image(image: DynamicImage, bounds: UtmBoundingBox, min_z: f64, max_z: f64) -> Result<HeightMap, LasToStlError>{
+        let image = image.to_luma16();
+        let (x_res, y_res) = (image.width() as usize, image.height() as usize);
+        if x_res < 2 || y_res < 2 {
+            return Err(LasToStlError::ResolutionTooSmallError{ x_res, y_res })
         }
+        let data: Vec<f64> = image.pixels()
+            .map(|pixel| min_z + pixel.0[0] as f64 / u16::MAX as f64 * (max_z - min_z))
+            .collect();
+
+        Ok(HeightMap{
+            data,
+            x_res,
+            y_res,
+            // strictly below black, so black pixels aren't empty cells
+            bounds: UtmBoundingBox { min_z: min_z.next_down(), max_z, ..bounds },
+            utm_zone: None,
+            geoid_correction: None,
+            crs: None,
+        })
     }
 
-    /// assigns `value_to_set_where_mask_true` to all points with coordinates that are set to true in `mask`.
-    /// Mask must have the same resolution and bounds as self.
+    /// Fills cells that no points landed in from the populated cells around them. See `HoleFilling`.
     ///
-    /// You can guarantee this by constructing the mask with parameters from the heightmap you intend on applying it to.
+    /// Holes are the cells at `bounds.min_z`, see `is_empty_height`. To fill holes while loading instead,
+    /// use `LasLoadOptions::hole_filling`
+    pub fn fill_holes(&mut self, hole_filling: HoleFilling){
+        let heights = self.get_heights();
+
+        self.data = fill_holes(&heights, self.x_res, self.y_res, hole_filling).iter().map(|height| {
+            height.unwrap_or(self.bounds.min_z)
+        }).collect();
+    }
+
+    /// Stitches heightmaps (like tiles that were processed separately) into one heightmap that covers all of them.
     ///
-    /// (e.g. `Mask::new_with_dims(hm.x_res, hm.y_res, hm.bounds)`)
-    pub fn set_by_mask(&mut self, mask: &Mask, value_to_set_where_mask_true: f64) -> Result<(), LasToStlError>{
-        if self.x_res == mask.x_res && self.y_res == mask.y_res && self.bounds == mask.bounds{
-            let data_iter = self.data.iter_mut();
-            let mask_iter = mask.data.iter();
+    /// The result has the smallest cell spacing of the inputs, and heights are sampled from each input with bilinear interpolation.
+    /// Empty cells of the inputs (see `is_empty_height`) are treated as having no data, like `fill_holes` does.
+    /// Where inputs overlap, `policy` decides which height is used.
+    /// Cells that none of the inputs have data for are empty in the result too.
+    ///
+    /// Returns an error if `height_maps` is empty, or if the heightmaps say they're in different UTM zones.
+    pub fn merge(height_maps: &[HeightMap], policy: MergePolicy) -> Result<HeightMap, LasToStlError>{
+        let first = height_maps.first().ok_or(LasToStlError::NoHeightMapsError)?;
 
-            for (height, mask_state) in data_iter.zip(mask_iter){
-                if *mask_state {
-                    *height = value_to_set_where_mask_true;
+        let mut utm_zone: Option<UtmZone> = None;
+        let mut bounds = first.bounds;
+        let mut x_tick = f64::MAX;
+        let mut y_tick = f64::MAX;
+        for height_map in height_maps {
+            match (utm_zone, height_map.utm_zone) {
+                (Some(zone), Some(other_zone)) if zone != other_zone => {
+                    return Err(LasToStlError::HeightMapUtmZoneMismatchError{
+                        first: zone,
+                        other: other_zone,
+                    })
                 }
+                (None, other_zone) => {
+                    utm_zone = other_zone;
+                }
+                _ => {}
             }
+            bounds = bounds.union(&height_map.bounds)?;
+            x_tick = f64_min(x_tick, height_map.bounds.x_range() / (height_map.x_res - 1) as f64);
+            y_tick = f64_min(y_tick, height_map.bounds.y_range() / (height_map.y_res - 1) as f64);
+        }
 
-            Ok(())
-        } else {
-            Err(LasToStlError::MaskBoundMismatchError{
+        let x_res = (bounds.x_range() / x_tick).round() as usize + 1;
+        let y_res = (bounds.y_range() / y_tick).round() as usize + 1;
+        // the ticks are rounded to fit the bounds exactly
+        let x_tick = bounds.x_range() / (x_res - 1) as f64;
+        let y_tick = bounds.y_range() / (y_res - 1) as f64;
+
+        info!("merging {} heightmaps into a {x_res}x{y_res} heightmap", height_maps.len());
+
+        let mut heights: Vec<Option<f64>> = Vec::with_capacity(x_res * y_res);
+        for y in 0..y_res {
+            let utm_y = bounds.min_y + y as f64 * y_tick;
+            for x in 0..x_res {
+                let utm_x = bounds.min_x + x as f64 * x_tick;
+
+                let mut samples = height_maps.iter().filter_map(|height_map| height_map.sample_utm(utm_x, utm_y));
+                let height = match policy {
+                    MergePolicy::Average => {
+                        let (sum, count) = samples.fold((0f64, 0usize), |(sum, count), sample| (sum + sample, count + 1));
+                        if count > 0 {
+                            Some(sum / count as f64)
+                        } else {
+                            None
+                        }
+                    }
+                    MergePolicy::Priority => {
+                        samples.next()
+                    }
+                };
+                heights.push(height);
+            }
+        }
+
+        let mut merged = first.with_heights(&heights, x_res, y_res, bounds);
+        merged.set_utm_zone(utm_zone);
+        Ok(merged)
+    }
+
+    /// Gets how fast the height changes (in z units per UTM meter) in the x and y directions at a cell, using the cells on either side
+    /// (or just one side at the edges and next to empty cells, and 0 if neither side has data, so holes don't look like cliffs).
+    /// Assumes z is in meters like x and y, which isn't always true (see the README).
+    pub fn get_gradient(&self, x: usize, y: usize) -> (f64, f64){
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        let get = |x: usize, y: usize| self.data[y * self.x_res + x];
+        let has_data = |x: usize, y: usize| !self.is_empty_height(get(x, y));
+
+        let left = if x > 0 && has_data(x - 1, y) { x - 1 } else { x };
+        let right = if x + 1 < self.x_res && has_data(x + 1, y) { x + 1 } else { x };
+        let down = if y > 0 && has_data(x, y - 1) { y - 1 } else { y };
+        let up = if y + 1 < self.y_res && has_data(x, y + 1) { y + 1 } else { y };
+
+        let dz_dx = match right - left {
+            0 => 0f64,
+            cells => (get(right, y) - get(left, y)) / (cells as f64 * x_tick),
+        };
+        let dz_dy = match up - down {
+            0 => 0f64,
+            cells => (get(x, up) - get(x, down)) / (cells as f64 * y_tick),
+        };
+        (dz_dx, dz_dy)
+    }
+
+    /// Creates a heightmap of the slope of every cell in degrees (0 is flat, 90 is a wall), with the same resolution and bounds,
+    /// except the z bounds which are the smallest and largest slope. Useful to look at with `save_to_image`,
+    /// or to find steep terrain with `Mask::from_height_map`.
+    ///
+    /// Empty cells stay empty, and the slopes next to them only use the cells with data (see `get_gradient`).
+    /// Uses the real spacing between cells, so z needs to be in meters like x and y for the angles to be right.
+    pub fn slope_degrees(&self) -> HeightMap{
+        let slopes: Vec<Option<f64>> = (0..self.data.len()).map(|index| {
+            if self.is_empty_height(self.data[index]) {
+                return None
+            }
+            let (dz_dx, dz_dy) = self.get_gradient(index % self.x_res, index / self.x_res);
+            Some((dz_dx * dz_dx + dz_dy * dz_dy).sqrt().atan().to_degrees())
+        }).collect();
+
+        // no data at all keeps the whole range of slopes
+        let (min_z, max_z) = slopes.iter().flatten()
+            .fold((90f64, 0f64), |(min, max), slope| (f64_min(min, *slope), f64_max(max, *slope)));
+        let bounds = UtmBoundingBox{ min_z: f64_min(min_z, max_z), max_z: f64_max(min_z, max_z), ..self.bounds };
+
+        self.with_heights(&slopes, self.x_res, self.y_res, bounds)
+    }
+
+    /// Creates a heightmap of the aspect of every cell, the compass direction the ground faces (the way water would run off),
+    /// in degrees clockwise from north (+y): 0 faces north, 90 east, 180 south, 270 west.
+    /// Useful to find north facing ski slopes or sunny trails with `Mask::from_height_map`.
+    ///
+    /// Flat cells and empty cells (at or below `bounds.min_z`) don't face anywhere and are set to -1,
+    /// which is the `min_z` of the result, so they count as empty. `max_z` is 360.
+    pub fn aspect_degrees(&self) -> HeightMap{
+        let data: Vec<f64> = (0..self.data.len()).map(|index| {
+            if self.is_empty_height(self.data[index]) {
+                return -1f64
+            }
+            let (dz_dx, dz_dy) = self.get_gradient(index % self.x_res, index / self.x_res);
+            if dz_dx == 0f64 && dz_dy == 0f64 {
+                return -1f64
+            }
+            // downhill is the opposite of the gradient, atan2(x, y) to measure clockwise from north
+            (-dz_dx).atan2(-dz_dy).to_degrees().rem_euclid(360f64)
+        }).collect();
+
+        let mut bounds = self.bounds;
+        bounds.min_z = -1f64;
+        bounds.max_z = 360f64;
+
+        self.with_grid(data, self.x_res, self.y_res, bounds)
+    }
+
+    /// Saves `aspect_degrees` as a color image, with the direction as a hue that wraps around
+    /// (north red, east yellow-green, south cyan, west purple) so there is no seam at north. Flat and empty cells are grey.
+    #[cfg(feature = "image")]
+    pub fn save_aspect_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        self.to_aspect_image()?.save(path)?;
+        Ok(())
+    }
+
+    /// Same as `save_aspect_image`, but writes a PNG into `writer` instead of a file
+    #[cfg(feature = "image")]
+    pub fn write_aspect_png<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        let image = self.to_aspect_image()?;
+        PngEncoder::new(writer).write_image(image.as_raw(), image.width(), image.height(), ColorType::Rgb8)?;
+        Ok(())
+    }
+
+    /// gets the image `save_aspect_image` saves
+    #[cfg(feature = "image")]
+    pub fn to_aspect_image(&self) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, LasToStlError>{
+        let aspect = self.aspect_degrees();
+        ImageBuffer::from_vec(
+            aspect.x_res as u32,
+            aspect.y_res as u32,
+            aspect.data.iter().flat_map(|direction| {
+                if *direction < 0f64 {
+                    [128u8; 3]
+                } else {
+                    hue_to_rgb(*direction)
+                }
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)
+    }
+
+    /// Creates a heightmap of how lit every cell is (0 is in shadow, 1 faces the sun) with the sun at `azimuth_degrees`
+    /// (clockwise from north) and `altitude_degrees` above the horizon, like a shaded relief map. 315 and 45 is the usual.
+    /// The z bounds of the result are 0 to 1, so `save_to_image` shows it as is.
+    ///
+    /// Like `slope_degrees`, z needs to be in meters like x and y for the shading to be right.
+    pub fn hillshade(&self, azimuth_degrees: f64, altitude_degrees: f64) -> HeightMap{
+        let (azimuth, altitude) = (azimuth_degrees.to_radians(), altitude_degrees.to_radians());
+        let light = [azimuth.sin() * altitude.cos(), azimuth.cos() * altitude.cos(), altitude.sin()];
+
+        let data: Vec<f64> = (0..self.data.len()).map(|index| {
+            let (dz_dx, dz_dy) = self.get_gradient(index % self.x_res, index / self.x_res);
+            // dot product of the light and the (not yet normalized) surface normal (-dz_dx, -dz_dy, 1)
+            let lit = -dz_dx * light[0] - dz_dy * light[1] + light[2];
+            (lit / (dz_dx * dz_dx + dz_dy * dz_dy + 1f64).sqrt()).max(0f64)
+        }).collect();
+
+        let mut bounds = self.bounds;
+        bounds.min_z = 0f64;
+        bounds.max_z = 1f64;
+
+        self.with_grid(data, self.x_res, self.y_res, bounds)
+    }
+
+    /// Creates a heightmap of how much higher self is than `other` at every cell of self (self - other),
+    /// for change detection between two lidar acquisitions. e.g. `new.diff(&old)` is positive where ground was built up
+    /// and negative where it eroded away.
+    ///
+    /// `other` doesn't need to have the same resolution or bounds, it is sampled at every cell of self with
+    /// bilinear interpolation. Cells where either heightmap has no data (at or below its `bounds.min_z`) are empty in the result.
+    /// The z bounds of the result are the smallest and largest change, with `min_z` just below it so no change is mistaken for a hole.
+    ///
+    /// Returns an error if they don't overlap at all, or say they're in different UTM zones.
+    pub fn diff(&self, other: &HeightMap) -> Result<HeightMap, LasToStlError>{
+        if let (Some(zone), Some(other_zone)) = (self.utm_zone, other.utm_zone) {
+            if zone != other_zone {
+                return Err(LasToStlError::HeightMapUtmZoneMismatchError{
+                    first: zone,
+                    other: other_zone,
+                })
+            }
+        }
+
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        let changes: Vec<Option<f64>> = self.data.iter().enumerate().map(|(index, height)| {
+            if self.is_empty_height(*height) {
+                return None
+            }
+            let utm_x = self.bounds.min_x + (index % self.x_res) as f64 * x_tick;
+            let utm_y = self.bounds.min_y + (index / self.x_res) as f64 * y_tick;
+            other.sample_utm(utm_x, utm_y).map(|other_height| height - other_height)
+        }).collect();
+
+        let num_compared = changes.iter().flatten().count();
+        if num_compared == 0 {
+            return Err(LasToStlError::HeightMapsDontOverlapError)
+        }
+        info!("compared {num_compared} / {} cells", changes.len());
+
+        let (min_z, max_z) = changes.iter().flatten()
+            .fold((f64::MAX, f64::MIN), |(min, max), change| (f64_min(min, *change), f64_max(max, *change)));
+        let bounds = UtmBoundingBox{ min_z, max_z, ..self.bounds };
+
+        let mut diff = self.with_heights(&changes, self.x_res, self.y_res, bounds);
+        diff.set_utm_zone(self.utm_zone.or(other.utm_zone));
+        Ok(diff)
+    }
+
+    /// gets the height at a UTM coordinate with bilinear interpolation (or the closest cell next to empty cells),
+    /// or `None` if it's outside of self or the closest cell is empty (at or below `bounds.min_z`)
+    pub fn sample_utm(&self, utm_x: f64, utm_y: f64) -> Option<f64>{
+        if !self.bounds.contains_xy(utm_x, utm_y) {
+            return None
+        }
+        let x = (utm_x - self.bounds.min_x) / (self.bounds.x_range() / (self.x_res - 1) as f64);
+        let y = (utm_y - self.bounds.min_y) / (self.bounds.y_range() / (self.y_res - 1) as f64);
+        self.interpolate_height(x, y, Interpolation::Bilinear)
+    }
+
+    /// Same as `interpolate`, but without blending in empty cells: `None` if the closest cell is empty,
+    /// and the height of the closest cell if any of the other cells `interpolation` uses are
+    pub(crate) fn interpolate_height(&self, x: f64, y: f64, interpolation: Interpolation) -> Option<f64>{
+        let nearest = self.interpolate(x, y, Interpolation::Nearest);
+        if self.is_empty_height(nearest) {
+            return None
+        }
+        // offsets of the cells that are blended, from the one below and left of (x, y)
+        let offsets = match interpolation {
+            Interpolation::Nearest => return Some(nearest),
+            Interpolation::Bilinear => 0..=1,
+            Interpolation::Bicubic => -1..=2,
+        };
+        // blending with empty cells would drag the edges of the data down to min_z
+        let (x0, y0) = (x.floor(), y.floor());
+        let next_to_hole = offsets.clone().any(|dy| offsets.clone().any(|dx| {
+            self.is_empty_height(self.interpolate(x0 + dx as f64, y0 + dy as f64, Interpolation::Nearest))
+        }));
+        if next_to_hole {
+            return Some(nearest)
+        }
+        Some(self.interpolate(x, y, interpolation))
+    }
+
+    /// Creates a heightmap of just the cells of self inside `crop` (only x and y are used), with bounds shrunk to fit those cells.
+    /// The spacing between cells stays the same, so the result has a lower resolution.
+    /// Handy to carve a small region out of a big saved heightmap for a quick test print.
+    ///
+    /// The z bounds are recalculated from the cells that are left, and empty cells stay empty.
+    /// Returns `LasToStlError::CropOutsideHeightMapError` if less than 2x2 cells are inside `crop`.
+    pub fn crop(&self, crop: &UtmBoundingBox) -> Result<HeightMap, LasToStlError>{
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        // first and last cell inside the crop on each axis
+        let first_x = ((crop.min_x - self.bounds.min_x) / x_tick).ceil().max(0f64) as usize;
+        let last_x = ((crop.max_x - self.bounds.min_x) / x_tick).floor().min((self.x_res - 1) as f64);
+        let first_y = ((crop.min_y - self.bounds.min_y) / y_tick).ceil().max(0f64) as usize;
+        let last_y = ((crop.max_y - self.bounds.min_y) / y_tick).floor().min((self.y_res - 1) as f64);
+
+        if last_x < (first_x + 1) as f64 || last_y < (first_y + 1) as f64 {
+            return Err(LasToStlError::CropOutsideHeightMapError(*crop))
+        }
+        let (last_x, last_y) = (last_x as usize, last_y as usize);
+
+        let all_heights = self.get_heights();
+        let mut heights: Vec<Option<f64>> = Vec::with_capacity((last_x - first_x + 1) * (last_y - first_y + 1));
+        for row in all_heights.chunks(self.x_res).skip(first_y).take(last_y - first_y + 1) {
+            heights.extend_from_slice(&row[first_x..=last_x]);
+        }
+
+        // an empty crop keeps the z bounds of self
+        let (min_z, max_z) = heights.iter().flatten()
+            .fold((self.bounds.max_z, self.bounds.min_z), |(min, max), height| (f64_min(min, *height), f64_max(max, *height)));
+        let bounds = UtmBoundingBox{
+            min_x: self.bounds.min_x + first_x as f64 * x_tick,
+            max_x: self.bounds.min_x + last_x as f64 * x_tick,
+            min_y: self.bounds.min_y + first_y as f64 * y_tick,
+            max_y: self.bounds.min_y + last_y as f64 * y_tick,
+            min_z: f64_min(min_z, max_z),
+            max_z: f64_max(min_z, max_z),
+            utm_zone: self.bounds.utm_zone,
+        };
+
+        Ok(self.with_heights(&heights, last_x - first_x + 1, last_y - first_y + 1, bounds))
+    }
+
+    /// Creates a copy of self with a different resolution but the same bounds, so smaller grids (for draft prints)
+    /// can be made from one high resolution heightmap without reading the LAS files again.
+    ///
+    /// Empty cells aren't blended into the cells around them: new cells closest to an empty cell are empty,
+    /// and new cells next to one take the height of the closest old cell (like `sample_utm`).
+    /// The z bounds grow to fit if bicubic interpolation overshoots them.
+    ///
+    /// Returns `LasToStlError::ResolutionTooSmallError` if either resolution is below 2.
+    pub fn resample(&self, new_x_res: usize, new_y_res: usize, interpolation: Interpolation) -> Result<HeightMap, LasToStlError>{
+        if new_x_res < 2 || new_y_res < 2 {
+            return Err(LasToStlError::ResolutionTooSmallError{ x_res: new_x_res, y_res: new_y_res })
+        }
+
+        // cells are samples at the edges and evenly spaced between, so the first and last cells stay where they are
+        let x_scale = (self.x_res - 1) as f64 / (new_x_res - 1) as f64;
+        let y_scale = (self.y_res - 1) as f64 / (new_y_res - 1) as f64;
+
+        let mut heights: Vec<Option<f64>> = Vec::with_capacity(new_x_res * new_y_res);
+        for y in 0..new_y_res {
+            // position of the new cell, in old cells
+            let old_y = y as f64 * y_scale;
+            for x in 0..new_x_res {
+                let old_x = x as f64 * x_scale;
+                heights.push(self.interpolate_height(old_x, old_y, interpolation));
+            }
+        }
+
+        // `with_heights` takes care of min_z
+        let max_z = heights.iter().flatten().copied().fold(self.bounds.max_z, f64_max);
+        Ok(self.with_heights(&heights, new_x_res, new_y_res, UtmBoundingBox{ max_z, ..self.bounds }))
+    }
+
+    /// gets the height at a fractional cell position, where cell (x, y) is at (x.0, y.0)
+    pub fn interpolate(&self, x: f64, y: f64, interpolation: Interpolation) -> f64{
+        // heights outside of the grid are the same as the closest edge cell
+        let get = |x: i64, y: i64| {
+            let x = x.clamp(0, self.x_res as i64 - 1) as usize;
+            let y = y.clamp(0, self.y_res as i64 - 1) as usize;
+            self.data[y * self.x_res + x]
+        };
+
+        match interpolation {
+            Interpolation::Nearest => {
+                get(x.round() as i64, y.round() as i64)
+            }
+            Interpolation::Bilinear => {
+                let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+                let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+                let top = get(x0, y0) * (1f64 - tx) + get(x0 + 1, y0) * tx;
+                let bottom = get(x0, y0 + 1) * (1f64 - tx) + get(x0 + 1, y0 + 1) * tx;
+                top * (1f64 - ty) + bottom * ty
+            }
+            Interpolation::Bicubic => {
+                let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+                let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+                let rows: Vec<f64> = (-1..=2).map(|dy| {
+                    catmull_rom(get(x0 - 1, y0 + dy), get(x0, y0 + dy), get(x0 + 1, y0 + dy), get(x0 + 2, y0 + dy), tx)
+                }).collect();
+                catmull_rom(rows[0], rows[1], rows[2], rows[3], ty)
+            }
+        }
+    }
+
+    /// Limits every height to between `min` and `max` and sets the z bounds to match,
+    /// so a single quarry pit or radio tower doesn't squash everything else in the image and STL.
+    /// Empty cells stay empty, `bounds.min_z` ends up just below `min` if any cells were clamped to it (see `is_empty_height`).
+    ///
+    /// Returns `LasToStlError::InvalidZRangeError` if `min` is bigger than `max` or either isn't a finite number
+    pub fn clamp(&mut self, min: f64, max: f64) -> Result<(), LasToStlError>{
+        check_z_range(min, max)?;
+        let heights: Vec<Option<f64>> = self.get_heights().iter()
+            .map(|height| height.map(|height| height.clamp(min, max)))
+            .collect();
+        *self = self.with_heights(&heights, self.x_res, self.y_res, UtmBoundingBox{ min_z: min, max_z: max, ..self.bounds });
+        Ok(())
+    }
+
+    /// Linearly rescales every height from `bounds.min_z`..`bounds.max_z` to `new_min`..`new_max` and sets the z bounds to match.
+    /// If every cell has the same height, they are all set to `new_min`. Empty cells stay empty, like `clamp`.
+    ///
+    /// Returns `LasToStlError::InvalidZRangeError` if `new_min` is bigger than `new_max` or either isn't a finite number
+    pub fn normalize(&mut self, new_min: f64, new_max: f64) -> Result<(), LasToStlError>{
+        check_z_range(new_min, new_max)?;
+        let old_min = self.bounds.min_z;
+        let old_range = self.bounds.max_z - self.bounds.min_z;
+        let heights: Vec<Option<f64>> = self.get_heights().iter().map(|height| height.map(|height| {
+            if old_range > 0f64 {
+                new_min + (height - old_min) / old_range * (new_max - new_min)
+            } else {
+                new_min
+            }
+        })).collect();
+        *self = self.with_heights(&heights, self.x_res, self.y_res, UtmBoundingBox{ min_z: new_min, max_z: new_max, ..self.bounds });
+        Ok(())
+    }
+
+    /// Snaps every height down to a multiple of `step` (in z units), for the stepped look of a laser cut topo map.
+    /// The steps line up with the lines from `contours` with the same interval.
+    ///
+    /// Heights are never snapped below `bounds.min_z`, so the lowest step may be thinner than the rest.
+    /// Empty cells stay empty, and `bounds.min_z` is moved just below the lowest step if cells were snapped onto it
+    /// (see `is_empty_height`). Does nothing if `step` isn't positive.
+    pub fn terrace(&mut self, step: f64){
+        if step <= 0f64 {
+            return
+        }
+        let min_z = self.bounds.min_z;
+        let heights: Vec<Option<f64>> = self.get_heights().iter()
+            .map(|height| height.map(|height| f64_max((height / step).floor() * step, min_z)))
+            .collect();
+        *self = self.with_heights(&heights, self.x_res, self.y_res, self.bounds);
+    }
+
+    /// Replaces every cell with the median of the cells within `radius` cells of it (a square window, cut off at the edges).
+    ///
+    /// Unlike averaging, this removes single cell spikes (birds, stray returns) without rounding off cliff edges,
+    /// as long as the spikes are smaller than half the window. A radius of 1 (a 3x3 window) is usually enough.
+    ///
+    /// Empty cells are left out of the windows and stay empty, so the edges of the data aren't pulled down into holes.
+    pub fn median_filter(&mut self, radius: usize){
+        let heights = self.get_heights();
+        let filtered: Vec<Option<f64>> = heights.iter().enumerate().map(|(index, height)| {
+            height.map(|_| window_median(&heights, self.x_res, self.y_res, index, radius))
+        }).collect();
+        *self = self.with_heights(&filtered, self.x_res, self.y_res, self.bounds);
+    }
+
+    /// Same as `median_filter`, but only replaces cells that are more than `max_difference` (in z units) away from
+    /// the median of their window, so everything that isn't a spike or pit is left exactly as it was.
+    pub fn despike(&mut self, radius: usize, max_difference: f64){
+        let mut num_replaced: usize = 0;
+        let heights = self.get_heights();
+        let despiked: Vec<Option<f64>> = heights.iter().enumerate().map(|(index, height)| {
+            height.map(|height| {
+                let median = window_median(&heights, self.x_res, self.y_res, index, radius);
+                if (height - median).abs() > max_difference {
+                    num_replaced += 1;
+                    median
+                } else {
+                    height
+                }
+            })
+        }).collect();
+        *self = self.with_heights(&despiked, self.x_res, self.y_res, self.bounds);
+        info!("replaced {num_replaced} spikes with the median of their neighbors");
+    }
+
+    /// Rotates or flips the heightmap, to fix the orientation of the image or turn the model to fit the print bed.
+    ///
+    /// Rotating by 90 degrees swaps the resolutions and the x and y ranges of the bounds (keeping `min_x` and `min_y`),
+    /// so the heightmap no longer lines up with the real world. Do the same thing to masks with `Mask::reorient`
+    /// so they still match, or apply masks first.
+    pub fn reorient(&mut self, orientation: GridOrientation){
+        let (data, x_res, y_res) = reorient_grid(&self.data, self.x_res, self.y_res, orientation);
+        self.data = data;
+        self.x_res = x_res;
+        self.y_res = y_res;
+        if orientation.swaps_axes() {
+            self.bounds = swap_bound_ranges(self.bounds);
+        }
+    }
+
+    /// Replaces every height with what `transform` returns for it, for custom exaggeration curves, datum shifts, etc.
+    /// `transform` gets the x and y index of the cell, its UTM coordinate, and its height.
+    ///
+    /// The z bounds are not changed.
+    pub fn map_heights<F>(&mut self, mut transform: F)
+        where F: FnMut(usize, usize, UtmCoord, f64) -> f64
+    {
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        for (index, height) in self.data.iter_mut().enumerate() {
+            let (x, y) = (index % self.x_res, index / self.x_res);
+            let utm_coord = UtmCoord{
+                zone: self.utm_zone,
+                ..UtmCoord::new((self.bounds.min_x + x as f64 * x_tick, self.bounds.min_y + y as f64 * y_tick))
+            };
+            *height = transform(x, y, utm_coord, *height);
+        }
+    }
+
+    /// Same as `map_heights`, but only for cells that are set to true in `mask`.
+    /// Mask must have the same resolution and x/y bounds as self, and the same UTM zone if self knows its zone.
+    pub fn map_heights_by_mask<F>(&mut self, mask: &Mask, mut transform: F) -> Result<(), LasToStlError>
+        where F: FnMut(usize, usize, UtmCoord, f64) -> f64
+    {
+        self.check_mask_matches(mask)?;
+        let mask_data = &mask.data;
+        self.map_heights(|x, y, utm_coord, height| {
+            if mask_data[y * mask.x_res + x] {
+                transform(x, y, utm_coord, height)
+            } else {
+                height
+            }
+        });
+        Ok(())
+    }
+
+    /// Returns `LasToStlError::MaskBoundMismatchError` if `mask` doesn't have the same resolution and x/y bounds as self.
+    /// The z bounds don't matter, a mask is only a grid of cells
+    pub(crate) fn check_mask_matches(&self, mask: &Mask) -> Result<(), LasToStlError>{
+        if let Some(utm_zone) = self.utm_zone {
+            utm_zone.check_matches(&mask.utm_zone)?;
+        }
+        if self.x_res != mask.x_res || self.y_res != mask.y_res || !self.bounds.eq_xy(&mask.bounds) {
+            return Err(LasToStlError::MaskBoundMismatchError{
                 other_x_res: self.x_res,
                 other_y_res: self.y_res,
                 mask_x_res: mask.x_res,
                 mask_y_res: mask.y_res,
-                other_bounds: self.bounds,
-                mask_bounds: mask.bounds,
+                other_bounds: Box::new(self.bounds),
+                mask_bounds: Box::new(mask.bounds),
             })
         }
+        Ok(())
+    }
+
+    /// adds `offset` to all height values with coordinates that are set to true in mask.
+    /// Mask must have the same resolution and x/y bounds as self, and the same UTM zone if self knows its zone.
+    ///
+    /// You can guarantee this by constructing the mask with parameters from the heightmap you intend on applying it to.
+    ///
+    /// (e.g. `Mask::new_for_height_map(&hm)`)
+    pub fn offset_by_mask(&mut self, mask: &Mask, offset: f64) -> Result<(), LasToStlError>{
+        self.check_mask_matches(mask)?;
+        par_zip_for_each(&mut self.data, &mask.data, |height, mask_state| {
+            if *mask_state {
+                height.add_assign(offset);
+            }
+        });
+        Ok(())
+    }
+
+    /// assigns `value_to_set_where_mask_true` to all points with coordinates that are set to true in `mask`.
+    /// Mask must have the same resolution and x/y bounds as self, and the same UTM zone if self knows its zone.
+    ///
+    /// You can guarantee this by constructing the mask with parameters from the heightmap you intend on applying it to.
+    ///
+    /// (e.g. `Mask::new_for_height_map(&hm)`)
+    pub fn set_by_mask(&mut self, mask: &Mask, value_to_set_where_mask_true: f64) -> Result<(), LasToStlError>{
+        self.check_mask_matches(mask)?;
+        par_zip_for_each(&mut self.data, &mask.data, |height, mask_state| {
+            if *mask_state {
+                *height = value_to_set_where_mask_true;
+            }
+        });
+        Ok(())
     }
 
     /// to change the units to proper UTM (which is assumed for all heightmaps),
     /// convert this object's bounds to UTM and pass them here. Z axis does not matter.
     /// [espg](https://epsg.io/) is a great tool for converting various coordinate systems
     ///
-    /// despite the name, there is no 'checked' variation (for now)
+    /// This only relabels the bounds, so it shears the heightmap if the projections aren't just offset and scaled versions
+    /// of each other. Use `convert_projection` to actually reproject it
     pub fn convert_projection_unchecked(&mut self, new_bounds: UtmBoundingBox){
         self.bounds = new_bounds;
     }
+
+    /// Reprojects the heightmap from `from` into `to` (e.g. from a state plane CRS into UTM, see `Reprojector` for what can
+    /// be converted without the `proj` feature). A new grid with about as many cells is laid out over the reprojected bounds,
+    /// and the height of each cell is sampled (bilinear, see `sample_utm`) where it is in the old heightmap.
+    ///
+    /// Cells that land outside of the old heightmap, or in its empty cells, are empty (`bounds.min_z`).
+    /// Heights are converted from feet if one of the CRSs is in feet
+    pub fn convert_projection(&self, from: &Crs, to: &Crs) -> Result<HeightMap, LasToStlError>{
+        let bounds = self.bounds.reproject(from, to)?;
+        let inverse = Reprojector::new(to, from)?;
+        let z_scale = from.meters_per_unit() / to.meters_per_unit();
+
+        // square cells, about as many as before
+        let cell_size = (bounds.x_range() * bounds.y_range() / (self.x_res * self.y_res) as f64).sqrt();
+        let x_res = ((bounds.x_range() / cell_size).round() as usize + 1).max(2);
+        let y_res = ((bounds.y_range() / cell_size).round() as usize + 1).max(2);
+        let x_tick = bounds.x_range() / (x_res - 1) as f64;
+        let y_tick = bounds.y_range() / (y_res - 1) as f64;
+
+        let data = (0..x_res * y_res).map(|index| {
+            let new_x = bounds.min_x + (index % x_res) as f64 * x_tick;
+            let new_y = bounds.min_y + (index / x_res) as f64 * y_tick;
+            let (old_x, old_y) = inverse.convert(new_x, new_y)?;
+            Ok(self.sample_utm(old_x, old_y).map_or(bounds.min_z, |height| height * z_scale))
+        }).collect::<Result<Vec<f64>, LasToStlError>>()?;
+
+        let mut height_map = self.with_grid(data, x_res, y_res, bounds);
+        height_map.set_utm_zone(to.get_utm_zone());
+        height_map.crs = height_map.utm_zone.is_none().then(|| to.clone());
+        Ok(height_map)
+    }
 }
 
 impl From<HeightMapIntermediate> for HeightMap{
@@ -309,13 +1748,523 @@ impl From<HeightMapIntermediate> for HeightMap{
     ///
     /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
     fn from(height_map_intermediate: HeightMapIntermediate) -> Self{
+        let mut heights = height_map_intermediate.get_heights();
+        if let Some(hole_filling) = height_map_intermediate.hole_filling{
+            heights = fill_holes(&heights, height_map_intermediate.x_res, height_map_intermediate.y_res, hole_filling);
+        }
+        // with `BinningStrategy::Min` (or a single point) the lowest cell is right at the min_z of the LAS headers
+        let bounds = UtmBoundingBox{
+            min_z: min_z_below_heights(height_map_intermediate.bounds.min_z, &heights),
+            utm_zone: height_map_intermediate.utm_zone,
+            ..height_map_intermediate.bounds
+        };
         HeightMap{
-            data: height_map_intermediate.data.iter().map(|p| {p.get_average_or_default(height_map_intermediate.bounds.min_z)}).collect(),
+            data: heights.iter().map(|height| {
+                height.unwrap_or(bounds.min_z)
+            }).collect(),
             x_res: height_map_intermediate.x_res,
             y_res: height_map_intermediate.y_res,
-            bounds: height_map_intermediate.bounds,
+            bounds,
+            utm_zone: height_map_intermediate.utm_zone,
+            geoid_correction: None,
+            crs: None,
+        }
+
+    }
+}
+
+/// Returns `LasToStlError::InvalidZRangeError` unless `min` and `max` are finite and `min` isn't bigger than `max`
+pub(crate) fn check_z_range(min: f64, max: f64) -> Result<(), LasToStlError>{
+    if min.is_finite() && max.is_finite() && min <= max {
+        Ok(())
+    } else {
+        Err(LasToStlError::InvalidZRangeError{ min, max })
+    }
+}
+
+/// `min_z`, or just below the lowest of `heights` if it isn't below all of them, so the empty cells that get set to it
+/// can't be mistaken for the lowest real ones
+pub(crate) fn min_z_below_heights(min_z: f64, heights: &[Option<f64>]) -> f64{
+    heights.iter().flatten().fold(min_z, |min_z, height| if *height <= min_z { height.next_down() } else { min_z })
+}
+
+/// fills the `None` cells in `heights` from the `Some` cells around them according to `hole_filling`.
+/// Cells with nothing close enough to fill them from stay `None`
+pub(crate) fn fill_holes(heights: &[Option<f64>], x_res: usize, y_res: usize, hole_filling: HoleFilling) -> Vec<Option<f64>>{
+    let mut num_filled: usize = 0;
+    let mut num_holes: usize = 0;
+
+    let filled: Vec<Option<f64>> = heights.iter().enumerate().map(|(index, height)| {
+        if height.is_some(){
+            return *height
+        }
+        num_holes += 1;
+        let x = index % x_res;
+        let y = index / x_res;
+
+        let fill = match hole_filling{
+            HoleFilling::Nearest { max_distance } => {
+                nearest_height(heights, x_res, y_res, x, y, max_distance)
+            }
+            HoleFilling::InverseDistanceWeighted { power, radius } => {
+                let mut weighted_sum = 0f64;
+                let mut weight_sum = 0f64;
+                for neighbor_y in y.saturating_sub(radius)..=(y + radius).min(y_res - 1){
+                    for neighbor_x in x.saturating_sub(radius)..=(x + radius).min(x_res - 1){
+                        if let Some(neighbor_height) = heights[neighbor_y * x_res + neighbor_x]{
+                            let distance = cell_distance(x, y, neighbor_x, neighbor_y);
+                            if distance <= radius as f64{
+                                let weight = 1f64 / distance.powf(power);
+                                weighted_sum += weight * neighbor_height;
+                                weight_sum += weight;
+                            }
+                        }
+                    }
+                }
+                if weight_sum > 0f64 {
+                    Some(weighted_sum / weight_sum)
+                } else {
+                    None
+                }
+            }
+        };
+        if fill.is_some(){
+            num_filled += 1;
+        }
+        fill
+    }).collect();
+
+    info!("filled {num_filled} / {num_holes} empty cells");
+
+    filled
+}
+
+/// finds the height of the closest `Some` cell to (x, y) within `max_distance` cells, searching outwards a ring at a time
+fn nearest_height(heights: &[Option<f64>], x_res: usize, y_res: usize, x: usize, y: usize, max_distance: usize) -> Option<f64>{
+    // (distance, height) of the closest cell found so far
+    let mut closest: Option<(f64, f64)> = None;
+
+    for ring in 1..=max_distance{
+        // a cell in this ring is at least `ring` away, so nothing here can beat what's already been found
+        if closest.is_some_and(|(distance, _)| distance <= ring as f64){
+            break;
+        }
+        for neighbor_y in y.saturating_sub(ring)..=(y + ring).min(y_res - 1){
+            for neighbor_x in x.saturating_sub(ring)..=(x + ring).min(x_res - 1){
+                if neighbor_x.abs_diff(x).max(neighbor_y.abs_diff(y)) != ring{
+                    continue;
+                }
+                if let Some(neighbor_height) = heights[neighbor_y * x_res + neighbor_x]{
+                    let distance = cell_distance(x, y, neighbor_x, neighbor_y);
+                    if distance <= max_distance as f64 && closest.is_none_or(|(closest_distance, _)| distance < closest_distance){
+                        closest = Some((distance, neighbor_height));
+                    }
+                }
+            }
         }
+    }
+
+    closest.map(|(_, height)| height)
+}
 
+/// Catmull-Rom spline through p1 and p2, `t` is from 0 (p1) to 1 (p2)
+fn catmull_rom(p0: f64, p1: f64, p2: f64, p3: f64, t: f64) -> f64{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2f64 * p1 +
+        (p2 - p0) * t +
+        (2f64 * p0 - 5f64 * p1 + 4f64 * p2 - p3) * t2 +
+        (3f64 * p1 - p0 - 3f64 * p2 + p3) * t3)
+}
+
+/// gets the median of the cells within `radius` cells of `index` (including itself), leaving out empty cells.
+/// The cell at `index` can't be empty, so there is always at least one
+fn window_median(heights: &[Option<f64>], x_res: usize, y_res: usize, index: usize, radius: usize) -> f64{
+    let x = index % x_res;
+    let y = index / x_res;
+
+    let mut window: Vec<f64> = Vec::with_capacity((2 * radius + 1).pow(2));
+    for neighbor_y in y.saturating_sub(radius)..=(y + radius).min(y_res - 1){
+        for neighbor_x in x.saturating_sub(radius)..=(x + radius).min(x_res - 1){
+            window.extend(heights[neighbor_y * x_res + neighbor_x]);
+        }
     }
+
+    let middle = window.len() / 2;
+    let (_, median, _) = window.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+    *median
 }
 
+/// distance between two cells, in cells
+fn cell_distance(x1: usize, y1: usize, x2: usize, y2: usize) -> f64{
+    let dx = x1.abs_diff(x2) as f64;
+    let dy = y1.abs_diff(y2) as f64;
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// replaces every cell that is more than `std_devs` standard deviations from the mean of its non empty neighbors
+/// (within `radius` cells) with that mean. Empty cells are left alone.
+fn reject_neighborhood_outliers(heights: &[Option<f64>], x_res: usize, y_res: usize, std_devs: f64, radius: usize) -> Vec<Option<f64>>{
+    let mut num_rejected: usize = 0;
+
+    let filtered: Vec<Option<f64>> = heights.iter().enumerate().map(|(index, height)| {
+        let height = (*height)?;
+        let x = index % x_res;
+        let y = index / x_res;
+
+        let mut neighbors: Vec<f64> = Vec::new();
+        for neighbor_y in y.saturating_sub(radius)..=(y + radius).min(y_res - 1){
+            for neighbor_x in x.saturating_sub(radius)..=(x + radius).min(x_res - 1){
+                if neighbor_x == x && neighbor_y == y{
+                    continue;
+                }
+                if let Some(neighbor_height) = heights[neighbor_y * x_res + neighbor_x]{
+                    neighbors.push(neighbor_height);
+                }
+            }
+        }
+
+        if neighbors.len() < 2{
+            return Some(height)
+        }
+
+        let num_neighbors = neighbors.len() as f64;
+        let mean = neighbors.iter().sum::<f64>() / num_neighbors;
+        let variance = neighbors.iter().map(|neighbor| (neighbor - mean).powi(2)).sum::<f64>() / num_neighbors;
+
+        if (height - mean).abs() > variance.sqrt() * std_devs{
+            num_rejected += 1;
+            Some(mean)
+        } else {
+            Some(height)
+        }
+    }).collect();
+
+    info!("replaced {num_rejected} outlier cells with the mean of their neighbors");
+
+    filtered
+}
+
+/// converts a hue in degrees (0 to 360) to a fully saturated color
+#[cfg(feature = "image")]
+fn hue_to_rgb(hue: f64) -> [u8; 3] {
+    let sector = (hue.rem_euclid(360f64) / 60f64) as usize;
+    let fraction = hue.rem_euclid(360f64) / 60f64 - sector as f64;
+    let rising = (fraction * 255f64) as u8;
+    let falling = 255 - rising;
+    match sector {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ZRange;
+
+    /// `x_res` by `y_res` cells a meter apart, with every height from `height`
+    fn height_map_from<F: Fn(usize, usize) -> f64>(x_res: usize, y_res: usize, min_z: f64, height: F) -> HeightMap {
+        let data: Vec<f64> = (0..x_res * y_res).map(|index| height(index % x_res, index / x_res)).collect();
+        let max_z = data.iter().copied().fold(min_z, f64_max);
+        HeightMap {
+            data,
+            x_res,
+            y_res,
+            bounds: UtmBoundingBox::new_in_zone(0f64, (x_res - 1) as f64, 0f64, (y_res - 1) as f64, min_z, max_z, UtmZone::new(10, true)),
+            utm_zone: Some(UtmZone::new(10, true)),
+            geoid_correction: None,
+            crs: None,
+        }
+    }
+
+    #[test]
+    fn point_aggregate_counts_past_u16_and_round_trips() {
+        let mut aggregate = PointAggregate::new(false);
+        for _ in 0..70_000 {
+            aggregate.add_sample(2f64);
+        }
+        let mut merged = aggregate.clone();
+        merged.merge(&aggregate);
+
+        let mut bytes = Vec::new();
+        merged.write_to(&mut bytes).unwrap();
+        let read = PointAggregate::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.get_num_points(), 140_000);
+        assert_eq!(read.get_average_or_default(0f64), 2f64);
+        assert!(!read.keeps_samples());
+    }
+
+    #[test]
+    fn crop_keeps_the_lowest_real_cell() {
+        // one empty cell in the corner, the rest rising to the east from 10
+        let height_map = height_map_from(6, 6, 0f64, |x, y| if x == 0 && y == 0 { 0f64 } else { 10f64 + x as f64 });
+        let cropped = height_map.crop(&UtmBoundingBox::new(0f64, 3f64, 0f64, 3f64, 0f64, 0f64)).unwrap();
+
+        assert_eq!(cropped.get_heights().iter().filter(|height| height.is_none()).count(), 1);
+        assert_eq!(cropped.get_height(0, 1).unwrap(), 10f64);
+        assert!(!cropped.is_empty_height(10f64));
+        assert_eq!(cropped.bounds.max_z, 13f64);
+    }
+
+    #[test]
+    fn clamp_keeps_clamped_cells_and_holes_apart() {
+        let mut height_map = height_map_from(4, 4, 0f64, |x, _| [0f64, 5f64, 10f64, 50f64][x]);
+        height_map.clamp(8f64, 20f64).unwrap();
+
+        assert_eq!(height_map.get_heights()[..4], [None, Some(8f64), Some(10f64), Some(20f64)]);
+        assert_eq!(height_map.bounds.max_z, 20f64);
+        assert!(matches!(height_map.clamp(20f64, 8f64), Err(LasToStlError::InvalidZRangeError{ .. })));
+
+        height_map.normalize(0f64, 1f64).unwrap();
+        assert_eq!(height_map.get_heights().iter().filter(|height| height.is_none()).count(), 4);
+        assert!(matches!(height_map.normalize(1f64, f64::NAN), Err(LasToStlError::InvalidZRangeError{ .. })));
+    }
+
+    #[test]
+    fn merge_keeps_the_lowest_cells_of_every_input() {
+        let height_map = height_map_from(6, 6, 0f64, |x, y| 10f64 + x as f64 + y as f64);
+        let west = height_map.crop(&UtmBoundingBox::new(0f64, 3f64, 0f64, 5f64, 0f64, 0f64)).unwrap();
+        let east = height_map.crop(&UtmBoundingBox::new(2f64, 5f64, 0f64, 5f64, 0f64, 0f64)).unwrap();
+
+        let merged = HeightMap::merge(&[west, east], MergePolicy::Priority).unwrap();
+        assert_eq!((merged.x_res, merged.y_res), (6, 6));
+        assert!(merged.get_heights().iter().all(Option::is_some));
+        for (merged_height, height) in merged.data.iter().zip(&height_map.data) {
+            assert!((merged_height - height).abs() < 1e-9);
+        }
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn black_image_pixels_are_real_heights() {
+        let image = ImageBuffer::<Luma<u8>, Vec<u8>>::from_vec(2, 2, vec![0, 85, 170, 255]).unwrap();
+        let bounds = UtmBoundingBox::new(0f64, 1f64, 0f64, 1f64, 0f64, 0f64);
+        let height_map = HeightMap::from_dynamic_image(DynamicImage::ImageLuma8(image), bounds, 10f64, 40f64).unwrap();
+
+        assert!(height_map.get_heights().iter().all(Option::is_some));
+        assert_eq!(height_map.get_height(0, 0).unwrap(), 10f64);
+        assert_eq!(height_map.get_height(1, 1).unwrap(), 40f64);
+    }
+
+    #[test]
+    fn inverted_z_ranges_are_errors_not_panics() {
+        let height_map = height_map_from(4, 4, 0f64, |x, _| 10f64 + x as f64);
+        let inverted = ZRange::Fixed{ min: 20f64, max: 10f64 };
+
+        assert!(matches!(height_map.get_z_range(inverted), Err(LasToStlError::InvalidZRangeError{ .. })));
+        assert!(matches!(height_map.get_z_range(ZRange::Percentile{ low: 90f64, high: 10f64 }), Err(LasToStlError::InvalidZRangeError{ .. })));
+        #[cfg(feature = "image")]
+        assert!(matches!(height_map.to_image(inverted), Err(LasToStlError::InvalidZRangeError{ .. })));
+    }
+
+    #[cfg(feature = "stl")]
+    #[test]
+    fn stl_z_range_clips_like_clip_z_range() {
+        use crate::stl::StlOptions;
+
+        let height_map = height_map_from(4, 4, 0f64, |x, y| 10f64 + x as f64 * y as f64);
+        let z_range = ZRange::Fixed{ min: 11f64, max: 14f64 };
+        let mesh = height_map.get_mesh(&StlOptions { z_range, ..StlOptions::default() }).unwrap();
+
+        let mut clipped = height_map_from(4, 4, 0f64, |x, y| 10f64 + x as f64 * y as f64);
+        clipped.clip_z_range(z_range).unwrap();
+        let expected = clipped.get_mesh(&StlOptions::default()).unwrap();
+        assert_eq!(mesh.vertices, expected.vertices);
+    }
+
+    #[test]
+    fn resample_keeps_a_ramp_and_its_bounds() {
+        let ramp = |x: f64, y: f64| 10f64 + x + 2f64 * y;
+        let height_map = height_map_from(5, 5, 0f64, |x, y| ramp(x as f64, y as f64));
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic] {
+            // every new cell is on an old one
+            let smaller = height_map.resample(3, 3, interpolation).unwrap();
+            assert_eq!((smaller.x_res, smaller.y_res), (3, 3));
+            assert_eq!(smaller.bounds, height_map.bounds);
+            for y in 0..3 {
+                for x in 0..3 {
+                    assert_eq!(smaller.get_height(x, y).unwrap(), ramp(x as f64 * 2f64, y as f64 * 2f64), "{interpolation:?} ({x}, {y})");
+                }
+            }
+        }
+
+        // new cells half way between old ones
+        let bilinear = height_map.resample(9, 9, Interpolation::Bilinear).unwrap();
+        let bicubic = height_map.resample(9, 9, Interpolation::Bicubic).unwrap();
+        let nearest = height_map.resample(9, 9, Interpolation::Nearest).unwrap();
+        assert_eq!(bilinear.bounds, height_map.bounds);
+        for y in 0..9 {
+            for x in 0..9 {
+                let expected = ramp(x as f64 / 2f64, y as f64 / 2f64);
+                assert!((bilinear.get_height(x, y).unwrap() - expected).abs() < 1e-9);
+                assert!((nearest.get_height(x, y).unwrap() - expected).abs() <= 1.5);
+                // catmull-rom is exact for a ramp, except next to the edges where there's no cell past the last one
+                if (2..=6).contains(&x) && (2..=6).contains(&y) {
+                    assert!((bicubic.get_height(x, y).unwrap() - expected).abs() < 1e-9);
+                }
+            }
+        }
+
+        assert!(matches!(height_map.resample(1, 9, Interpolation::Bilinear), Err(LasToStlError::ResolutionTooSmallError{ .. })));
+    }
+
+    #[test]
+    fn saved_height_maps_load_back_the_same() {
+        let height_map = height_map_from(7, 5, 0f64, |x, y| if x == 3 { 0f64 } else { 100f64 + x as f64 * 0.25 - y as f64 });
+        let formats = [
+            HeightMapFormat::Json,
+            #[cfg(feature = "zstd")]
+            HeightMapFormat::Binary,
+        ];
+        for format in formats {
+            let mut bytes = Vec::new();
+            height_map.save_to_writer_with_format(&mut bytes, format).unwrap();
+            let loaded = HeightMap::load_from_reader(&mut bytes.as_slice()).unwrap();
+            assert_eq!(loaded.data, height_map.data, "{format:?}");
+            assert_eq!((loaded.x_res, loaded.y_res, loaded.bounds), (7, 5, height_map.bounds));
+            assert_eq!(loaded.utm_zone, height_map.utm_zone);
+        }
+    }
+
+    /// a binary heightmap file with `metadata` as its metadata and `heights` after it
+    #[cfg(feature = "zstd")]
+    fn binary_file(metadata: &HeightMapFile, heights: &[f64]) -> Vec<u8> {
+        let metadata = serde_json::to_vec(metadata).unwrap();
+        let mut bytes = BINARY_HEIGHT_MAP_MAGIC.to_vec();
+        let mut encoder = zstd::Encoder::new(&mut bytes, 0).unwrap();
+        encoder.write_all(&(metadata.len() as u64).to_le_bytes()).unwrap();
+        encoder.write_all(&metadata).unwrap();
+        for height in heights {
+            encoder.write_all(&height.to_le_bytes()).unwrap();
+        }
+        encoder.finish().unwrap();
+        bytes
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn corrupt_binary_headers_are_errors_not_panics() {
+        let height_map = height_map_from(2, 2, 0f64, |x, y| 10f64 + x as f64 + y as f64);
+        let load = |x_res: usize, y_res: usize, heights: &[f64]| {
+            let metadata = HeightMapFile { x_res, y_res, ..height_map.to_saved(Cow::Borrowed(&[])) };
+            HeightMap::load_from_reader(&mut binary_file(&metadata, heights).as_slice())
+        };
+
+        assert!(load(2, 2, &height_map.data).is_ok());
+        assert!(matches!(load(usize::MAX / 2, 3, &height_map.data), Err(LasToStlError::CorruptHeightMapFileError(_))));
+        assert!(matches!(load(1 << 40, 1 << 20, &height_map.data), Err(LasToStlError::CorruptHeightMapFileError(_))));
+        assert!(matches!(load(2, 2, &height_map.data[..3]), Err(LasToStlError::CorruptHeightMapFileError(_))));
+        assert!(matches!(load(2, 2, &[1f64; 5]), Err(LasToStlError::CorruptHeightMapFileError(_))));
+    }
+
+    #[test]
+    fn json_files_with_the_wrong_grid_are_errors_not_panics() {
+        let height_map = height_map_from(2, 2, 0f64, |x, y| 10f64 + x as f64 + y as f64);
+        let load = |saved: HeightMapFile| {
+            HeightMap::load_from_reader(&mut serde_json::to_vec(&saved).unwrap().as_slice())
+        };
+
+        assert!(load(height_map.to_saved(Cow::Borrowed(&height_map.data))).is_ok());
+        let too_few = height_map.to_saved(Cow::Borrowed(&height_map.data[..3]));
+        assert!(matches!(load(too_few), Err(LasToStlError::CorruptHeightMapFileError(_))));
+        let one_row = HeightMapFile { y_res: 1, ..height_map.to_saved(Cow::Borrowed(&height_map.data[..2])) };
+        assert!(matches!(load(one_row), Err(LasToStlError::ResolutionTooSmallError{ x_res: 2, y_res: 1 })));
+        let mut inverted = height_map.to_saved(Cow::Borrowed(&height_map.data));
+        inverted.bounds.max_x = -1f64;
+        assert!(matches!(load(inverted), Err(LasToStlError::InvalidBoundsError{ .. })));
+    }
+
+    #[test]
+    fn terracing_onto_min_z_keeps_the_cells() {
+        let mut height_map = height_map_from(4, 4, 104.99, |x, y| 105f64 + (x + y) as f64 / 2f64);
+        height_map.data[0] = 104.99;
+        height_map.terrace(10f64);
+
+        let heights = height_map.get_heights();
+        assert_eq!(heights[0], None);
+        assert!(heights[1..].iter().all(|height| *height == Some(104.99)), "{heights:?}");
+        assert_eq!(height_map.stats().unwrap().num_empty, 1);
+    }
+
+    #[test]
+    fn flat_cells_have_a_slope_and_holes_dont() {
+        // flat on the west half, 45 degrees on the east half
+        let mut height_map = height_map_from(10, 10, 0f64, |x, _| 10f64 + (x as f64 - 4f64).max(0f64));
+        let stats = height_map.slope_degrees().stats().unwrap();
+        assert_eq!((stats.num_cells, stats.num_empty, stats.min), (100, 0, 0f64));
+        assert!((stats.max - 45f64).abs() < 1e-9);
+
+        // a hole in the flat part is still a hole, and isn't a cliff for the cells around it
+        height_map.data[22] = 0f64;
+        let slopes = height_map.slope_degrees().get_heights();
+        assert_eq!(slopes[22], None);
+        for neighbor in [12, 21, 23, 32] {
+            assert_eq!(slopes[neighbor], Some(0f64), "cell {neighbor}");
+        }
+    }
+
+    #[test]
+    fn diff_keeps_unchanged_cells_and_leaves_holes_empty() {
+        let old = height_map_from(5, 5, 0f64, |x, _| 10f64 + x as f64);
+        // the first column didn't change, and the middle cell has no new data
+        let mut new = height_map_from(5, 5, 0f64, |x, _| 10f64 + 2f64 * x as f64);
+        new.data[12] = 0f64;
+
+        let diff = new.diff(&old).unwrap();
+        let changes = diff.get_heights();
+        assert_eq!(changes[12], None);
+        assert_eq!(changes[0], Some(0f64));
+        assert_eq!(changes[4], Some(4f64));
+        let stats = diff.stats().unwrap();
+        assert_eq!((stats.num_empty, stats.min, stats.max), (1, 0f64, 4f64));
+    }
+
+    #[test]
+    fn median_filters_skip_holes_and_leave_them_empty() {
+        // a hole in the corner, a spike in the middle, and a plateau at 20 on the east side
+        let mut height_map = height_map_from(5, 5, 0f64, |x, _| if x >= 3 { 20f64 } else { 10f64 });
+        height_map.data[0] = 0f64;
+        height_map.data[12] = 50f64;
+
+        let mut filtered = height_map.with_grid(height_map.data.clone(), 5, 5, height_map.bounds);
+        filtered.median_filter(1);
+        let heights = filtered.get_heights();
+        assert_eq!(heights[0], None);
+        // next to the hole, only the cells with data count
+        assert_eq!(heights[1], Some(10f64));
+        assert_eq!(heights[5], Some(10f64));
+        assert_eq!(heights[12], Some(10f64));
+
+        height_map.despike(1, 5f64);
+        let heights = height_map.get_heights();
+        assert_eq!(heights[0], None);
+        assert_eq!(heights[12], Some(10f64));
+        assert_eq!(heights[13], Some(20f64));
+    }
+
+    #[test]
+    fn resampling_keeps_holes_out_of_the_heights_around_them() {
+        // a cliff from 10 to 20 for bicubic to overshoot, and a hole in the middle
+        let mut height_map = height_map_from(5, 5, 0f64, |x, _| if x >= 3 { 20f64 } else { 10f64 });
+        height_map.data[12] = 0f64;
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Bilinear, Interpolation::Bicubic] {
+            let resampled = height_map.resample(9, 9, interpolation).unwrap();
+            let heights = resampled.get_heights();
+            assert_eq!(heights[4 * 9 + 4], None, "{interpolation:?}");
+            assert_eq!(heights[0], Some(10f64), "{interpolation:?}");
+            // next to the hole, the closest cell's height instead of something dragged down towards min_z
+            assert_eq!(heights[4 * 9 + 2], Some(10f64), "{interpolation:?}");
+            for height in heights.iter().flatten() {
+                assert!(!resampled.is_empty_height(*height) && *height <= resampled.bounds.max_z, "{interpolation:?} {height}");
+                assert!(*height >= 9f64, "{interpolation:?} {height}");
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+#[cfg(feature = "image")]
+use std::path::Path;
+#[cfg(feature = "image")]
+use image::{ImageBuffer, Luma};
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::utils::x_y_to_index;
+#[cfg(feature = "image")]
+use crate::utils::{f64_max, f64_min, scale_float_to_uint_range};
+use crate::utm_bounds::UtmBoundingBox;
+
+/// A grid of average LAS intensity values spanning `bounds` (in utm), with the same layout as a `HeightMap`.
+///
+/// Get one from `HeightMap::glob_get_maps` (or `HeightMapIntermediate::get_intensity_map`) by loading with `LasLoadOptions::aggregate_intensity`.
+/// Intensity is the strength of the laser return, so it's useful as a sanity check (roads and paint show up really well)
+/// and as a texture for exports.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IntensityMap{
+    pub data: Vec<f64>,
+    pub x_res: usize,
+    pub y_res: usize,
+    pub bounds: UtmBoundingBox
+}
+
+impl IntensityMap{
+
+    /// Get the intensity at x, y. The coordinates are unit-less but evenly spaced.
+    pub fn get_intensity(&self, x: usize, y: usize) -> Result<f64, LasToStlError>{
+        Ok(self.data[x_y_to_index(self.x_res, self.y_res, x, y)?])
+    }
+
+    /// saves as a black and white png with brightness representing relative intensity.
+    /// The brightness is scaled between the lowest and highest intensity in the map.
+    ///
+    /// Like `HeightMap::save_to_image`, the image is vertically flipped.
+    #[cfg(feature = "image")]
+    pub fn save_to_image<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        let min_intensity = self.data.iter().fold(f64::MAX, |a, b| f64_min(a, *b));
+        let max_intensity = self.data.iter().fold(f64::MIN, |a, b| f64_max(a, *b));
+
+        let image: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_vec(
+            self.x_res as u32,
+            self.y_res as u32,
+            self.data.iter().map(|intensity| {
+                scale_float_to_uint_range(intensity, min_intensity, max_intensity, 255) as u8
+            }).collect()
+        ).ok_or(LasToStlError::ImageNoneError)?;
+
+        image.save(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use geo::{Area, Contains, Coord, LineString, Polygon};
+use kml::types::{Placemark, Polygon as KmlPolygon};
+use kml::{Kml, KmlDocument, KmlWriter};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::kml_utils::utm_polygon_to_lat_lon_polygon;
+use crate::mask::Mask;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// Saves lat/lon polygons (x = lon, y = lat) as a KML file with one placemark per (name, polygon),
+/// which Google Earth and most GIS tools can open
+pub fn save_lat_lon_polygons_as_kml<P: AsRef<Path>>(path: P, polygons: &[(String, Polygon)]) -> Result<(), LasToStlError>{
+    save_placemarks_as_kml(path, polygons.iter().map(|(name, polygon)| Placemark {
+        name: Some(name.clone()),
+        geometry: Some(kml::types::Geometry::Polygon(KmlPolygon::from(polygon.clone()))),
+        ..Placemark::default()
+    }).collect())
+}
+
+/// saves `placemarks` in one document
+pub(crate) fn save_placemarks_as_kml<P: AsRef<Path>>(path: P, placemarks: Vec<Placemark>) -> Result<(), LasToStlError>{
+    let placemarks: Vec<Kml<f64>> = placemarks.into_iter().map(Kml::Placemark).collect();
+
+    let document = Kml::KmlDocument(KmlDocument {
+        attrs: HashMap::from([(String::from("xmlns"), String::from("http://www.opengis.net/kml/2.2"))]),
+        elements: vec![Kml::Document { attrs: HashMap::new(), elements: placemarks }],
+        ..KmlDocument::default()
+    });
+
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    KmlWriter::<_, f64>::from_writer(&mut file).write(&document)?;
+    file.flush()?;
+    Ok(())
+}
+
+impl UtmBoundingBox {
+    /// Saves the x/y of the bounds as a lat/lon KML polygon, so it can be checked against other data in Google Earth
+    pub fn to_kml<P: AsRef<Path>>(&self, path: P, utm_zone: UtmZone) -> Result<(), LasToStlError>{
+        save_lat_lon_polygons_as_kml(path, &[(String::from("bounds"), self.to_lat_lon_polygon(utm_zone)?)])
+    }
+}
+
+impl HeightMap {
+    /// Saves the area the heightmap covers as a lat/lon KML polygon. See `UtmBoundingBox::to_kml`.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn footprint_to_kml<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        self.bounds.to_kml(path, self.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?)
+    }
+}
+
+impl Mask {
+    /// Traces the outlines of the set pixels into UTM polygons, one per group of pixels that touch on a side, with their holes.
+    /// Every pixel is a square a tick wide around its center, cut off at the bounds
+    pub fn to_utm_polygons(&self) -> Vec<Polygon>{
+        let is_set = |x: isize, y: isize| {
+            x >= 0 && y >= 0 && (x as usize) < self.x_res && (y as usize) < self.y_res && self.data[y as usize * self.x_res + x as usize]
+        };
+
+        // corner (x, y) is the bottom left corner of pixel (x, y). Edges go counter clockwise around the set pixels,
+        // so the set side is always on the left
+        let mut edges: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+        for y in 0..self.y_res {
+            for x in 0..self.x_res {
+                if !self.data[y * self.x_res + x] {
+                    continue
+                }
+                let (signed_x, signed_y) = (x as isize, y as isize);
+                let mut add_edge = |start: (usize, usize), end: (usize, usize)| edges.entry(start).or_default().push(end);
+                if !is_set(signed_x, signed_y - 1) { add_edge((x, y), (x + 1, y)); }
+                if !is_set(signed_x + 1, signed_y) { add_edge((x + 1, y), (x + 1, y + 1)); }
+                if !is_set(signed_x, signed_y + 1) { add_edge((x + 1, y + 1), (x, y + 1)); }
+                if !is_set(signed_x - 1, signed_y) { add_edge((x, y + 1), (x, y)); }
+            }
+        }
+
+        let mut rings: Vec<Vec<(usize, usize)>> = Vec::new();
+        while let Some(&start) = edges.keys().next() {
+            let mut ring: Vec<(usize, usize)> = vec![start];
+            let mut current = start;
+            let mut direction: Option<(isize, isize)> = None;
+            while let Some(ends) = edges.get_mut(&current) {
+                // where two groups touch at a corner, turn left so they stay separate
+                let index = direction.and_then(|(dx, dy)| {
+                    let left = (current.0 as isize - dy, current.1 as isize + dx);
+                    ends.iter().position(|end| (end.0 as isize, end.1 as isize) == left)
+                }).unwrap_or(0);
+                let next = ends.swap_remove(index);
+                if ends.is_empty() {
+                    edges.remove(&current);
+                }
+                direction = Some((next.0 as isize - current.0 as isize, next.1 as isize - current.1 as isize));
+                current = next;
+                if current == start {
+                    break
+                }
+                ring.push(current);
+            }
+            rings.push(remove_collinear_corners(ring));
+        }
+
+        // rings around set pixels are counter clockwise, holes are clockwise
+        let to_line_string = |ring: &[(usize, usize)]| LineString::from(
+            ring.iter().map(|(x, y)| Coord { x: *x as f64, y: *y as f64 }).collect::<Vec<Coord>>()
+        );
+        let (outer_rings, holes): (Vec<LineString>, Vec<LineString>) = rings.iter()
+            .map(|ring| to_line_string(ring))
+            .partition(|ring| Polygon::new(ring.clone(), vec![]).signed_area() > 0f64);
+
+        let polygons: Vec<Polygon> = outer_rings.into_iter().map(|ring| Polygon::new(ring, vec![])).collect();
+        let mut interiors: Vec<Vec<LineString>> = vec![Vec::new(); polygons.len()];
+        for hole in holes {
+            // a point a quarter pixel to the right of the first edge is in the hole, so it's in the smallest outer ring around it
+            let (start, end) = (hole.0[0], hole.0[1]);
+            let (dx, dy) = (end.x - start.x, end.y - start.y);
+            let length = dx.hypot(dy);
+            let inside = Coord { x: (start.x + end.x) / 2f64 + dy / length / 4f64, y: (start.y + end.y) / 2f64 - dx / length / 4f64 };
+            let smallest = polygons.iter().enumerate()
+                .filter(|(_, polygon)| polygon.contains(&inside))
+                .min_by(|(_, a), (_, b)| a.unsigned_area().total_cmp(&b.unsigned_area()))
+                .map(|(index, _)| index);
+            if let Some(index) = smallest {
+                interiors[index].push(hole);
+            }
+        }
+
+        // corners to UTM, cut off at the bounds
+        let to_utm = |ring: &LineString| LineString::from(ring.0.iter().map(|corner| Coord {
+            x: (self.bounds.min_x + (corner.x - 0.5) * self.x_tick).clamp(self.bounds.min_x, self.bounds.max_x),
+            y: (self.bounds.min_y + (corner.y - 0.5) * self.y_tick).clamp(self.bounds.min_y, self.bounds.max_y),
+        }).collect::<Vec<Coord>>());
+        polygons.iter().zip(interiors).map(|(polygon, holes)| {
+            Polygon::new(to_utm(polygon.exterior()), holes.iter().map(to_utm).collect())
+        }).collect()
+    }
+
+    /// Saves the set pixels as lat/lon KML polygons (see `to_utm_polygons`), so the mask can be checked against
+    /// the KML it came from in Google Earth before a long export
+    pub fn to_kml<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        let polygons = self.to_utm_polygons().iter().enumerate().map(|(index, polygon)| {
+            Ok((format!("mask {}", index + 1), utm_polygon_to_lat_lon_polygon(polygon, self.utm_zone)?))
+        }).collect::<Result<Vec<(String, Polygon)>, LasToStlError>>()?;
+        save_lat_lon_polygons_as_kml(path, &polygons)
+    }
+}
+
+/// only keeps the corners of a ring where it turns
+fn remove_collinear_corners(ring: Vec<(usize, usize)>) -> Vec<(usize, usize)>{
+    let length = ring.len();
+    (0..length).filter(|index| {
+        let (previous, corner, next) = (ring[(index + length - 1) % length], ring[*index], ring[(index + 1) % length]);
+        let (dx_in, dy_in) = (corner.0 as isize - previous.0 as isize, corner.1 as isize - previous.1 as isize);
+        let (dx_out, dy_out) = (next.0 as isize - corner.0 as isize, next.1 as isize - corner.1 as isize);
+        dx_in * dy_out - dy_in * dx_out != 0
+    }).map(|index| ring[index]).collect()
+}
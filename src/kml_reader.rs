@@ -0,0 +1,175 @@
+use std::fmt::Debug;
+use std::path::Path;
+use geo::{Coord, Geometry, GeometryCollection, LineString, Point, Polygon};
+use kml::types::Element;
+use kml::{Kml, KmlReader};
+use log::{debug, error};
+use crate::batch_report::BatchReport;
+use crate::crs::{Crs, Reprojector};
+use crate::errors::LasToStlError;
+
+/// basically a wrapper for some functions from the kml library
+/// given a path to a kml file, it returns a collection of geometry stuff.
+/// Everything in `Document`s, `Folder`s, `MultiGeometry`s and Google Earth tracks (`gx:Track`, `gx:MultiTrack`) is found,
+/// however deep it is. See `kml_to_geometry_collection`
+pub fn load_kml_file<P: AsRef<Path>>(path: P) -> Result<GeometryCollection<f64>, LasToStlError>{
+    let mut kml_reader = KmlReader::<_, f64>::from_path(path)?;
+
+    let kml_data: Kml<f64> = kml_reader.read()?;
+
+    Ok(kml_to_geometry_collection(kml_data))
+}
+
+/// Same as `load_kml_file`, but with the text of a KML file instead of a path, like a file uploaded to a web page
+pub fn load_kml_str(kml_text: &str) -> Result<GeometryCollection<f64>, LasToStlError>{
+    let kml_data: Kml<f64> = KmlReader::<_, f64>::from_string(kml_text).read()?;
+
+    Ok(kml_to_geometry_collection(kml_data))
+}
+
+/// Same as `load_kml_file`, but with everything reprojected from lat/lon into `target_crs`,
+/// for lidar that isn't in UTM (like a state plane CRS). Anything but UTM zones needs the `proj` feature
+pub fn load_kml_file_in_crs<P: AsRef<Path>>(path: P, target_crs: &Crs) -> Result<GeometryCollection<f64>, LasToStlError>{
+    let geometry_collection = load_kml_file(path)?;
+    Reprojector::new(&Crs::wgs84(), target_crs)?.reproject(&geometry_collection)
+}
+
+/// Gets every geometry in `kml`, going through every `Document`, `Folder`, `Placemark` and `MultiGeometry`.
+/// `MultiGeometry`s become `GeometryCollection`s. Tracks become line strings, and anything the kml library doesn't
+/// know (which it keeps as plain elements) is searched for points, line strings and polygons too.
+///
+/// Unlike `GeometryCollection::try_from`, one geometry that can't be converted doesn't lose the rest of its folder
+pub fn kml_to_geometry_collection(kml: Kml<f64>) -> GeometryCollection<f64>{
+    let mut out_vec: Vec<Geometry<f64>> = Vec::new();
+    collect_kml_geometries(kml, &mut out_vec);
+    GeometryCollection::new_from(out_vec)
+}
+
+fn collect_kml_geometries(kml: Kml<f64>, out_vec: &mut Vec<Geometry<f64>>){
+    match kml {
+        Kml::KmlDocument(document) => {
+            document.elements.into_iter().for_each(|element| collect_kml_geometries(element, out_vec))
+        }
+        Kml::Document { elements, .. } | Kml::Folder { elements, .. } => {
+            elements.into_iter().for_each(|element| collect_kml_geometries(element, out_vec))
+        }
+        Kml::Placemark(placemark) => {
+            if let Some(geometry) = placemark.geometry {
+                collect_kml_geometry(geometry, out_vec);
+            }
+            // tracks and anything else the kml library doesn't know end up here
+            placemark.children.iter().for_each(|element| collect_element_geometries(element, out_vec));
+        }
+        Kml::Point(point) => collect_kml_geometry(kml::types::Geometry::Point(point), out_vec),
+        Kml::LineString(line_string) => collect_kml_geometry(kml::types::Geometry::LineString(line_string), out_vec),
+        Kml::LinearRing(linear_ring) => collect_kml_geometry(kml::types::Geometry::LinearRing(linear_ring), out_vec),
+        Kml::Polygon(polygon) => collect_kml_geometry(kml::types::Geometry::Polygon(polygon), out_vec),
+        Kml::MultiGeometry(multi_geometry) => collect_kml_geometry(kml::types::Geometry::MultiGeometry(multi_geometry), out_vec),
+        Kml::Element(element) => collect_element_geometries(&element, out_vec),
+        _ => {}
+    }
+}
+
+fn collect_kml_geometry(geometry: kml::types::Geometry<f64>, out_vec: &mut Vec<Geometry<f64>>){
+    match geometry {
+        kml::types::Geometry::Point(point) => out_vec.push(Geometry::Point(Point::from(point))),
+        kml::types::Geometry::LineString(line_string) => out_vec.push(Geometry::LineString(LineString::from(line_string))),
+        kml::types::Geometry::LinearRing(linear_ring) => out_vec.push(Geometry::LineString(LineString::from(linear_ring))),
+        kml::types::Geometry::Polygon(polygon) => out_vec.push(Geometry::Polygon(Polygon::from(polygon))),
+        kml::types::Geometry::MultiGeometry(multi_geometry) => {
+            let mut geometries: Vec<Geometry<f64>> = Vec::new();
+            multi_geometry.geometries.into_iter().for_each(|geometry| collect_kml_geometry(geometry, &mut geometries));
+            out_vec.push(Geometry::GeometryCollection(GeometryCollection::new_from(geometries)));
+        }
+        kml::types::Geometry::Element(element) => collect_element_geometries(&element, out_vec),
+        _ => debug!("skipping a KML geometry of an unknown type"),
+    }
+}
+
+/// Finds geometries in an element the kml library didn't parse, by looking for the tags KML geometries have.
+/// Anything else is searched through, so placemarks in unknown containers are found too
+fn collect_element_geometries(element: &Element, out_vec: &mut Vec<Geometry<f64>>){
+    let get_child = |element: &Element, name: &str| element.children.iter().find(|child| child.name == name).cloned();
+    let get_coordinates = |element: &Element| get_child(element, "coordinates")
+        .and_then(|coordinates| coordinates.content)
+        .map(|content| parse_kml_coordinates(&content))
+        .unwrap_or_default();
+
+    match element.name.as_str() {
+        "Track" => {
+            // gx:coord is "lon lat alt", unlike coordinates
+            let coords: Vec<Coord> = element.children.iter()
+                .filter(|child| child.name == "coord")
+                .filter_map(|child| {
+                    let mut values = child.content.as_deref()?.split_whitespace().map(|value| value.parse::<f64>());
+                    Some(Coord { x: values.next()?.ok()?, y: values.next()?.ok()? })
+                })
+                .collect();
+            if coords.len() >= 2 {
+                out_vec.push(Geometry::LineString(LineString::from(coords)));
+            }
+        }
+        "Point" => {
+            if let Some(coord) = get_coordinates(element).first() {
+                out_vec.push(Geometry::Point(Point::from(*coord)));
+            }
+        }
+        "LineString" | "LinearRing" => {
+            out_vec.push(Geometry::LineString(LineString::from(get_coordinates(element))));
+        }
+        "Polygon" => {
+            let get_ring = |boundary: &Element| get_child(boundary, "LinearRing")
+                .map(|ring| LineString::from(get_coordinates(&ring)));
+            let Some(exterior) = get_child(element, "outerBoundaryIs").and_then(|boundary| get_ring(&boundary)) else {
+                debug!("skipping a polygon without an outer boundary");
+                return
+            };
+            let interiors: Vec<LineString> = element.children.iter()
+                .filter(|child| child.name == "innerBoundaryIs")
+                .filter_map(get_ring)
+                .collect();
+            out_vec.push(Geometry::Polygon(Polygon::new(exterior, interiors)));
+        }
+        _ => element.children.iter().for_each(|child| collect_element_geometries(child, out_vec)),
+    }
+}
+
+/// parses the contents of a KML `coordinates` tag: "lon,lat[,alt]" tuples separated by whitespace
+fn parse_kml_coordinates(content: &str) -> Vec<Coord>{
+    content.split_whitespace().filter_map(|tuple| {
+        let mut values = tuple.split(',').map(|value| value.parse::<f64>());
+        Some(Coord { x: values.next()?.ok()?, y: values.next()?.ok()? })
+    }).collect()
+}
+
+/// loads a file for each path.
+///
+/// # Error handling:
+/// Any files that are unable to be read will log the error with [log::error](https://docs.rs/log/0.4.20/log/enum.Level.html#variant.Error)
+/// and be listed in the returned `BatchReport`,
+/// but as long as at least one geometry is successfully loaded, this function will not return an error.
+pub fn load_kml_files<P: AsRef<Path> + Debug>(paths: Vec<P>)
+    -> Result<(GeometryCollection<f64>, BatchReport), LasToStlError>
+{
+    let mut out_vec: Vec<Geometry<f64>> = Vec::new();
+    let mut report = BatchReport::new();
+
+    for (index, path) in paths.into_iter().enumerate(){
+        match load_kml_file(&path){
+            Ok(mut gc) => {
+                out_vec.append(&mut gc.0);
+                report.add_success();
+            }
+            Err(e) => {
+                error!("error loading file {:?}:\n\t{:?}\nSkipping file.", path, e);
+                report.add_skipped(index, path.as_ref().display(), e);
+            }
+        }
+    }
+
+    if out_vec.is_empty(){
+        Err(LasToStlError::NoValidGeometriesError)
+    } else {
+        Ok((GeometryCollection::<f64>::new_from(out_vec), report))
+    }
+}
@@ -1,50 +1,10 @@
-use std::fmt::Debug;
-use std::path::Path;
-use geo::{Coord, Geometry, GeometryCollection, LineString, Point, Polygon};
-use kml::{Kml, KmlReader, quick_collection};
-use log::error;
+use geo::{BoundingRect, Coord, Geometry, GeometryCollection, LineString, Point, Polygon};
 use crate::errors::LasToStlError;
-use crate::utm_point::UtmCoord;
+use crate::utm_point::{UtmCoord, UtmZone};
+#[cfg(feature = "kml")]
+pub use crate::kml_reader::{kml_to_geometry_collection, load_kml_file, load_kml_file_in_crs, load_kml_files, load_kml_str};
 
-/// basically a wrapper for some functions from the kml library
-/// given a path to a kml file, it returns a collection of geometry stuff
-pub fn load_kml_file<P: AsRef<Path>>(path: P) -> Result<GeometryCollection<f64>, LasToStlError>{
-    let mut kml_reader = KmlReader::<_, f64>::from_path(path)?;
-
-    let kml_data: Kml<f64> = kml_reader.read()?;
-
-    Ok(quick_collection(kml_data)?)
-}
-
-/// loads a file for each path.
-///
-/// # Error handling:
-/// Any files that are unable to be read will log the error with [log::error](https://docs.rs/log/0.4.20/log/enum.Level.html#variant.Error),
-/// but as long as at least one geometry is successfully loaded, this function will not return an error.
-pub fn load_kml_files<P: AsRef<Path> + Debug>(paths: Vec<P>)
-    -> Result<GeometryCollection<f64>, LasToStlError>
-{
-    let mut out_vec: Vec<Geometry<f64>> = Vec::new();
-
-    for path in paths{
-        match load_kml_file(&path){
-            Ok(mut gc) => {
-                out_vec.append(&mut gc.0)
-            }
-            Err(e) => {
-                error!("error loading file {:?}:\n\t{:?}\nSkipping file.", path, e)
-            }
-        }
-    }
-
-    if out_vec.is_empty(){
-        Err(LasToStlError::NoValidGeometriesError)
-    } else {
-        Ok(GeometryCollection::<f64>::new_from(out_vec))
-    }
-}
-
-/// recursively gets all polygons in the collection. Vec may be empty
+/// recursively gets all polygons in the collection (and in multi polygons), with their holes. Vec may be empty
 pub fn get_regions(geometry_collection: GeometryCollection<f64>) -> Vec<Polygon>{
 
     let mut out_vec: Vec<Polygon> = Vec::new();
@@ -54,6 +14,9 @@ pub fn get_regions(geometry_collection: GeometryCollection<f64>) -> Vec<Polygon>
             Geometry::Polygon(poly) => {
                 out_vec.push(poly);
             }
+            Geometry::MultiPolygon(multi_polygon) => {
+                out_vec.extend(multi_polygon);
+            }
             Geometry::GeometryCollection(gc) => {
                 out_vec.extend(get_regions(gc));
             }
@@ -64,7 +27,7 @@ pub fn get_regions(geometry_collection: GeometryCollection<f64>) -> Vec<Polygon>
     out_vec
 }
 
-/// recursively gets all line strings in the collection. Vec may be empty
+/// recursively gets all line strings in the collection (and in multi line strings). Vec may be empty
 pub fn get_trails(geometry_collection: GeometryCollection<f64>) -> Vec<LineString>{
 
     let mut out_vec: Vec<LineString> = Vec::new();
@@ -74,6 +37,9 @@ pub fn get_trails(geometry_collection: GeometryCollection<f64>) -> Vec<LineStrin
             Geometry::LineString(ls) => {
                 out_vec.push(ls);
             }
+            Geometry::MultiLineString(multi_line_string) => {
+                out_vec.extend(multi_line_string);
+            }
             Geometry::GeometryCollection(gc) => {
                 out_vec.extend(get_trails(gc));
             }
@@ -84,7 +50,7 @@ pub fn get_trails(geometry_collection: GeometryCollection<f64>) -> Vec<LineStrin
     out_vec
 }
 
-/// recursively gets all points in the collection. Vec may be empty
+/// recursively gets all points in the collection (and in multi points). Vec may be empty
 pub fn get_waypoints(geometry_collection: GeometryCollection<f64>) -> Vec<Point>{
 
     let mut out_vec: Vec<Point> = Vec::new();
@@ -94,6 +60,9 @@ pub fn get_waypoints(geometry_collection: GeometryCollection<f64>) -> Vec<Point>
             Geometry::Point(pt) => {
                 out_vec.push(pt);
             }
+            Geometry::MultiPoint(multi_point) => {
+                out_vec.extend(multi_point);
+            }
             Geometry::GeometryCollection(gc) => {
                 out_vec.extend(get_waypoints(gc));
             }
@@ -104,13 +73,22 @@ pub fn get_waypoints(geometry_collection: GeometryCollection<f64>) -> Vec<Point>
     out_vec
 }
 
-pub fn linestring_to_utm_linestring(lat_lon_line_string: &LineString, utm_zone: u8) -> LineString{
+/// Gets the UTM zone (and hemisphere) of the center of everything in `geometry_collection`.
+/// Returns `None` if the collection is empty.
+///
+/// Use this to find the zone of a heightmap whose LAS files didn't say what zone they're in
+pub fn infer_utm_zone(geometry_collection: &GeometryCollection<f64>) -> Option<UtmZone>{
+    let bounding_rect = geometry_collection.bounding_rect()?;
+    Some(UtmZone::from_lat_lon_coord(&bounding_rect.center()))
+}
+
+pub fn linestring_to_utm_linestring(lat_lon_line_string: &LineString, utm_zone: UtmZone) -> LineString{
     lat_lon_line_string.into_iter().map(|coord|{
-        Coord::from(&UtmCoord::from_gps_coord_zoned(coord, utm_zone))
+        Coord::from(&UtmCoord::from_gps_coord_in_zone(coord, &utm_zone))
     }).collect::<LineString>()
 }
 
-pub fn polygon_to_utm_polygon(polygon: &Polygon, utm_zone: u8) -> Polygon{
+pub fn polygon_to_utm_polygon(polygon: &Polygon, utm_zone: UtmZone) -> Polygon{
     Polygon::new(
 
         linestring_to_utm_linestring(polygon.exterior(), utm_zone),
@@ -119,4 +97,21 @@ pub fn polygon_to_utm_polygon(polygon: &Polygon, utm_zone: u8) -> Polygon{
             linestring_to_utm_linestring(line_string, utm_zone)
         }).collect::<Vec<LineString>>()
     )
+}
+
+/// the opposite of `linestring_to_utm_linestring`: UTM coordinates in `utm_zone` to lat/lon (x = lon, y = lat)
+pub fn utm_linestring_to_lat_lon_linestring(utm_line_string: &LineString, utm_zone: UtmZone) -> Result<LineString, LasToStlError>{
+    utm_line_string.into_iter().map(|coord|{
+        utm_zone.to_lat_lon(coord.x, coord.y)
+    }).collect::<Result<LineString, LasToStlError>>()
+}
+
+/// the opposite of `polygon_to_utm_polygon`
+pub fn utm_polygon_to_lat_lon_polygon(utm_polygon: &Polygon, utm_zone: UtmZone) -> Result<Polygon, LasToStlError>{
+    Ok(Polygon::new(
+        utm_linestring_to_lat_lon_linestring(utm_polygon.exterior(), utm_zone)?,
+        utm_polygon.interiors().iter().map(|line_string|{
+            utm_linestring_to_lat_lon_linestring(line_string, utm_zone)
+        }).collect::<Result<Vec<LineString>, LasToStlError>>()?
+    ))
 }
\ No newline at end of file
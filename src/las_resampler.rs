@@ -1,12 +1,249 @@
-use std::time::SystemTime;
-use las::{Read, Reader};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use geo::{BoundingRect, Contains, Coord, Polygon};
+use las::{Header, Point, Read, Reader};
 use log::{info, trace, warn};
+use rayon::prelude::*;
+use crate::batch_report::BatchReport;
+use crate::checkpoint::Checkpoint;
+use crate::color_map::{ColorAggregate, ColorMap};
+use crate::copc::{is_copc_path, CopcReader};
+use crate::geoid::GeoidCorrection;
+use crate::crs::{reproject_polygon, Crs, Reprojector};
 use crate::errors::LasToStlError;
-use crate::height_map::{HeightMap, HeightMapIntermediate};
+use crate::height_map::{BinningStrategy, HeightMap, HeightMapIntermediate, HoleFilling, OutlierRejection, PointAggregate};
+use crate::intensity_map::IntensityMap;
+use crate::kml_utils::polygon_to_utm_polygon;
+use crate::point_filter::PointFilter;
+use crate::state_plane::StatePlane;
+use crate::progress::{LogProgress, PipelineEvent, ProgressSink};
 use crate::utils;
+use crate::utils::{f64_max, install_in_pool, Stopwatch};
 use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
 
 
+/// Number of points read from a file at a time unless `LasLoadOptions::chunk_size` says otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 65536;
+
+/// the progress stage for reading all the files, see `HeightMap::glob_get_height_map_with_progress`
+const LOADING_STAGE: &str = "loading LAS files";
+
+/// how many points are read between progress updates while reading a file
+const POINTS_PER_PROGRESS_UPDATE: usize = 2097152;
+
+/// a LAS/LAZ file with its position in the list the caller gave, for the load report
+type NumberedFile = (usize, PathBuf);
+
+/// numbers the spill folders of the loads in this process, see `LasLoadOptions::spill_directory`
+static NEXT_SPILL_LOAD: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of files read between checkpoints unless `LasLoadOptions::checkpoint_interval` says otherwise
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 16;
+
+/// Settings for loading LAS/LAZ files into a heightmap.
+///
+/// The default options bin every point and use one thread per core.
+#[derive(Clone, Debug)]
+pub struct LasLoadOptions {
+    /// Which points to bin. See `PointFilter`
+    pub filter: PointFilter,
+
+    /// How the points in each cell are combined into one height. See `BinningStrategy`
+    pub binning: BinningStrategy,
+
+    /// How spikes from bad returns are removed, if at all. See `OutlierRejection`.
+    /// To drop the points that the data provider already classified as noise, use `PointFilter::without_noise`
+    pub outlier_rejection: Option<OutlierRejection>,
+
+    /// How cells without any points are filled in when converting to a `HeightMap`. See `HoleFilling`.
+    /// `None` leaves them at the lowest height in the data
+    pub hole_filling: Option<HoleFilling>,
+
+    /// Subtract the geoid height from the heights once they're loaded, to turn ellipsoidal heights into heights above sea level.
+    /// See `GeoidCorrection`. Only applied by the functions that make a `HeightMap`, not a `HeightMapIntermediate`.
+    pub geoid_correction: Option<GeoidCorrection>,
+
+    /// Also average the intensity channel of the points in each cell into a second grid.
+    /// Get it from `HeightMap::glob_get_maps` (or `HeightMapIntermediate::get_intensity_map`)
+    pub aggregate_intensity: bool,
+
+    /// Also average the RGB color of the points in each cell into a third grid.
+    /// Points without color are ignored. Get it from `HeightMap::glob_get_maps` (or `HeightMapIntermediate::get_color_map`)
+    pub aggregate_color: bool,
+
+    /// Number of files to read at the same time. Files are read into partial grids that are merged at the end.
+    /// Rayon decides how many partial grids there are, it's at least one per thread and can be up to one per file,
+    /// so memory use is not bounded by the number of threads. Use `spill_directory` if the grids don't fit in RAM.
+    ///
+    /// `None` uses one thread per core, `Some(1)` reads the files one at a time.
+    pub num_threads: Option<usize>,
+
+    /// Number of points read from a file at a time. Each thread only ever holds one chunk of points in memory.
+    pub chunk_size: usize,
+
+    /// Streaming mode for datasets that don't fit in RAM. If set, there are no partial grids.
+    /// Instead every chunk is binned into only the cells it touches and appended to a spill file in this directory.
+    /// Once all files are read, the spill files are merged into the final grid one at a time and deleted.
+    ///
+    /// Every load uses its own folder in it (deleted at the end), so several loads can share this directory.
+    /// The directory is created if it doesn't exist.
+    pub spill_directory: Option<PathBuf>,
+
+    /// Reproject every file into this CRS while loading, using the CRS in each file's header.
+    /// Use this for data that isn't in UTM (like California 2 SP83 survey feet), e.g. `Crs::utm_wgs84(10, true)`.
+    /// Files without a CRS in their header are assumed to already be in this CRS.
+    /// Heights are converted from feet too, if the file's CRS is in feet and this one isn't.
+    ///
+    /// `None` uses the coordinates as they are in the files, unless the files are in different CRSs (like tiles in
+    /// two UTM zones), in which case everything is reprojected into the CRS most files are in, or the files are in a US state
+    /// plane CRS, in which case everything is reprojected into the UTM zone it's in (see `StatePlane::get_utm_zone`).
+    /// Reprojecting between UTM zones is built in, anything else needs the `proj` feature.
+    pub target_crs: Option<Crs>,
+
+    /// The CRS of files that don't have one in their header (like the tiles of an EPT point cloud).
+    /// Files that do have one always use their own.
+    pub fallback_crs: Option<Crs>,
+
+    /// Only load points inside this region (only x and y are used). The heightmap bounds are shrunk to fit it,
+    /// and files that don't overlap it at all are skipped without being read.
+    ///
+    /// If `target_crs` is set, this is in that CRS. Use `UtmBoundingBox::from_lat_lon_corners` or `UtmBoundingBox::from_polygon` to crop with lat/lon.
+    pub crop: Option<UtmBoundingBox>,
+
+    /// Only load points inside this polygon (in UTM, or in `target_crs` if set).
+    /// The heightmap bounds are shrunk to the polygon's bounding box (and `crop`, if both are set).
+    ///
+    /// Use `LasLoadOptions::clipped_to_lat_lon_polygon` for regions loaded with `kml_utils`.
+    pub clip_region: Option<Polygon>,
+
+    /// Periodically save the partially loaded grid (sums and counters, not just averages) and the list of files
+    /// already read to this file, so a multi-hour load that crashes or gets killed can resume from the last checkpoint.
+    ///
+    /// If the file already exists and was made with the same resolution, bounds and binning, loading resumes from it
+    /// and skips the files it already has. Otherwise it's ignored and overwritten.
+    /// Files that couldn't be read aren't saved as done, so they're tried again when resuming.
+    /// The checkpoint is deleted once loading is done.
+    pub checkpoint_path: Option<PathBuf>,
+
+    /// Number of files to read between checkpoints. Only used if `checkpoint_path` is set.
+    /// Files are only read in parallel within one batch, so don't make this smaller than the number of threads.
+    pub checkpoint_interval: usize,
+}
+
+impl Default for LasLoadOptions {
+    fn default() -> Self {
+        LasLoadOptions {
+            filter: PointFilter::default(),
+            binning: BinningStrategy::default(),
+            outlier_rejection: None,
+            hole_filling: None,
+            geoid_correction: None,
+            aggregate_intensity: false,
+            aggregate_color: false,
+            num_threads: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            spill_directory: None,
+            target_crs: None,
+            fallback_crs: None,
+            crop: None,
+            clip_region: None,
+            checkpoint_path: None,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+        }
+    }
+}
+
+impl LasLoadOptions {
+    /// Creates options for streaming mode, reading `chunk_size` points at a time and spilling
+    /// partial aggregates to `spill_directory`. See `LasLoadOptions::spill_directory`
+    pub fn streaming<P: AsRef<Path>>(chunk_size: usize, spill_directory: P) -> Self {
+        LasLoadOptions {
+            chunk_size,
+            spill_directory: Some(spill_directory.as_ref().to_path_buf()),
+            ..LasLoadOptions::default()
+        }
+    }
+
+    /// Creates options that only load points inside `crop`. See `LasLoadOptions::crop`
+    pub fn cropped(crop: UtmBoundingBox) -> Self {
+        LasLoadOptions {
+            crop: Some(crop),
+            ..LasLoadOptions::default()
+        }
+    }
+
+    /// Creates options that only load points inside the lat/lon rectangle with the corners `corner_a` and `corner_b`
+    /// (like north west and south east). See `UtmBoundingBox::from_lat_lon_corners`
+    pub fn cropped_to_lat_lon_corners(corner_a: &Coord, corner_b: &Coord, utm_zone: UtmZone) -> Self {
+        LasLoadOptions::cropped(UtmBoundingBox::from_lat_lon_corners(corner_a, corner_b, utm_zone))
+    }
+
+    /// Creates options that only load points inside `lat_lon_region`, like a region from `kml_utils::get_regions`.
+    /// See `LasLoadOptions::clip_region`
+    pub fn clipped_to_lat_lon_polygon(lat_lon_region: &Polygon, utm_zone: UtmZone) -> Self {
+        LasLoadOptions {
+            clip_region: Some(polygon_to_utm_polygon(lat_lon_region, utm_zone)),
+            ..LasLoadOptions::default()
+        }
+    }
+
+    /// Same as `clipped_to_lat_lon_polygon`, but for LAS files in any CRS: every file is reprojected into `target_crs`
+    /// while loading and the region is reprojected into it too. Anything but UTM zones needs the `proj` feature
+    pub fn clipped_to_lat_lon_polygon_in_crs(lat_lon_region: &Polygon, target_crs: Crs) -> Result<Self, LasToStlError> {
+        Ok(LasLoadOptions {
+            clip_region: Some(reproject_polygon(&Crs::wgs84(), &target_crs, lat_lon_region)?),
+            target_crs: Some(target_crs),
+            ..LasLoadOptions::default()
+        })
+    }
+
+    /// Gets the x/y region points must be in to be loaded, combining `crop` and the bounding box of `clip_region`.
+    /// `None` means no cropping.
+    pub fn get_crop_bounds(&self) -> Result<Option<UtmBoundingBox>, LasToStlError> {
+        let clip_bounds = match &self.clip_region{
+            Some(clip_region) => {
+                let rect = clip_region.bounding_rect().ok_or(LasToStlError::NoBoundingRectError)?;
+                let mut clip_bounds = UtmBoundingBox::default();
+                clip_bounds.add_utm(rect.min());
+                clip_bounds.add_utm(rect.max());
+                Some(clip_bounds)
+            }
+            None => {
+                None
+            }
+        };
+
+        Ok(match (self.crop, clip_bounds){
+            (Some(crop), Some(clip_bounds)) => {
+                Some(crop.intersection(&clip_bounds).ok_or(LasToStlError::CropOutsideDataError(crop))?)
+            }
+            (crop, clip_bounds) => {
+                crop.or(clip_bounds)
+            }
+        })
+    }
+
+    /// Gets the CRS of a file from its header, or `fallback_crs` if the header doesn't say
+    pub fn get_crs(&self, header: &Header) -> Option<Crs> {
+        Crs::from_las_header(header).or_else(|| self.fallback_crs.clone())
+    }
+}
+
+/// What `HeightMap::glob_get_maps` loads: the heightmap, and the other grids `LasLoadOptions` asked for,
+/// all with the same resolution and bounds
+pub struct LoadedMaps {
+    pub height_map: HeightMap,
+    /// average intensity of the points in each cell, if `LasLoadOptions::aggregate_intensity` was set
+    pub intensity_map: Option<IntensityMap>,
+    /// average color of the points in each cell, if `LasLoadOptions::aggregate_color` was set
+    pub color_map: Option<ColorMap>,
+}
 
 
 impl HeightMap{
@@ -26,101 +263,1354 @@ impl HeightMap{
     ///
     /// Only accepts UTM projected data. Make sure your LAS files are in UTM and not some
     /// abomination conjured out of the ass of you local city officials. (Like California 2 SP83 survey feet).
-    /// To convert, it is possible to call `height_map.convert_projection()`, but you must provide the boundary points converted to UTM
+    /// To convert, it is possible to call `height_map.convert_projection()`, but you must provide the boundary points converted to UTM.
+    /// Or, with the `proj` feature, set `LasLoadOptions::target_crs` and the points are reprojected while loading.
     ///
     /// This takes a long time and logs info with log::info
     /// (https://docs.rs/log/latest/log/enum.Level.html#variant.Info)
-
     pub fn glob_get_height_map(glob_pattern: &str,
                                resolution_x_in: Option<usize>,
                                resolution_y_in: Option<usize>)
         -> Result<HeightMap, LasToStlError> // , convert_from_lat_lon_to_utm: bool?
     {
+        HeightMap::glob_get_height_map_with_options(glob_pattern, resolution_x_in, resolution_y_in, &LasLoadOptions::default())
+    }
 
-        //TODO: What about different data formats? like https://epsg.io/102642
+    /// Same as `glob_get_height_map`, but only bins points accepted by `filter`.
+    ///
+    /// For example, `PointFilter::bare_earth()` skips buildings and vegetation to get a bare-earth DEM.
+    /// Points that are filtered out are ignored entirely, so cells that only had filtered points
+    /// will default to the lowest point in the dataset like any other empty cell.
+    pub fn glob_get_height_map_filtered(glob_pattern: &str,
+                                        resolution_x_in: Option<usize>,
+                                        resolution_y_in: Option<usize>,
+                                        filter: &PointFilter)
+        -> Result<HeightMap, LasToStlError>
+    {
+        let options = LasLoadOptions {
+            filter: filter.clone(),
+            ..LasLoadOptions::default()
+        };
+        HeightMap::glob_get_height_map_with_options(glob_pattern, resolution_x_in, resolution_y_in, &options)
+    }
+
+    /// Same as `glob_get_height_map`, but only bins points for which `filter` returns true.
+    /// Use this to filter on scan angle, GPS time, intensity, or anything else in `las::Point`.
+    ///
+    /// Files are read in parallel, so `filter` must be `Send + Sync`.
+    /// To combine this with classification or return filters, use `PointFilter::custom` and `glob_get_height_map_filtered`.
+    pub fn glob_get_height_map_with_filter<F>(glob_pattern: &str,
+                                              resolution_x_in: Option<usize>,
+                                              resolution_y_in: Option<usize>,
+                                              filter: F)
+        -> Result<HeightMap, LasToStlError>
+        where F: Fn(&Point) -> bool + Send + Sync + 'static
+    {
+        HeightMap::glob_get_height_map_filtered(glob_pattern, resolution_x_in, resolution_y_in, &PointFilter::with_custom(filter))
+    }
+
+    /// Same as `glob_get_height_map`, but only loads points inside `crop` (in UTM, z is ignored),
+    /// and the heightmap only covers `crop` instead of every file matched by the glob.
+    ///
+    /// Files that don't overlap `crop` are skipped entirely, so this is much faster than loading everything
+    /// and masking it afterward.
+    pub fn glob_get_height_map_cropped(glob_pattern: &str,
+                                       resolution_x_in: Option<usize>,
+                                       resolution_y_in: Option<usize>,
+                                       crop: UtmBoundingBox)
+        -> Result<HeightMap, LasToStlError>
+    {
+        HeightMap::glob_get_height_map_with_options(glob_pattern, resolution_x_in, resolution_y_in, &LasLoadOptions::cropped(crop))
+    }
+
+    /// Same as `glob_get_height_map`, but only loads points inside `lat_lon_region`
+    /// (like a region from `kml_utils::get_regions`), and the heightmap only covers the region's bounding box.
+    ///
+    /// Points outside the region are never binned, so the cells outside of it are empty
+    /// and default to the lowest point like any other empty cell. Mask the heightmap with the same region to cut them off.
+    pub fn glob_get_height_map_clipped(glob_pattern: &str,
+                                       resolution_x_in: Option<usize>,
+                                       resolution_y_in: Option<usize>,
+                                       lat_lon_region: &Polygon,
+                                       utm_zone: UtmZone)
+        -> Result<HeightMap, LasToStlError>
+    {
+        let options = LasLoadOptions::clipped_to_lat_lon_polygon(lat_lon_region, utm_zone);
+        HeightMap::glob_get_height_map_with_options(glob_pattern, resolution_x_in, resolution_y_in, &options)
+    }
+
+    /// Same as `glob_get_height_map`, but with all the settings in `LasLoadOptions`.
+    ///
+    /// Files are read in parallel (see `LasLoadOptions::num_threads`) into partial grids,
+    /// and the partial grids are merged once all files are read.
+    pub fn glob_get_height_map_with_options(glob_pattern: &str,
+                                            resolution_x_in: Option<usize>,
+                                            resolution_y_in: Option<usize>,
+                                            options: &LasLoadOptions)
+        -> Result<HeightMap, LasToStlError>
+    {
+        HeightMap::glob_get_height_map_with_progress(glob_pattern, resolution_x_in, resolution_y_in, options, &LogProgress)
+    }
+
+    /// Same as `glob_get_height_map_with_options`, but reports progress to `progress` instead of logging it.
+    ///
+    /// There is a stage for every file being read, and a `"loading LAS files"` stage for the whole operation
+    /// that progresses every time a file is done.
+    pub fn glob_get_height_map_with_progress(glob_pattern: &str,
+                                             resolution_x_in: Option<usize>,
+                                             resolution_y_in: Option<usize>,
+                                             options: &LasLoadOptions,
+                                             progress: &dyn ProgressSink)
+        -> Result<HeightMap, LasToStlError>
+    {
+        Ok(HeightMap::glob_get_maps(glob_pattern, resolution_x_in, resolution_y_in, options, progress)?.height_map)
+    }
+
+    /// Same as `glob_get_height_map_with_progress`, but also returns the intensity and color grids if `options.aggregate_intensity`
+    /// and `options.aggregate_color` are set, e.g. to save them with `save_to_image` as a texture for the model. See `LoadedMaps`
+    pub fn glob_get_maps(glob_pattern: &str,
+                         resolution_x_in: Option<usize>,
+                         resolution_y_in: Option<usize>,
+                         options: &LasLoadOptions,
+                         progress: &dyn ProgressSink)
+        -> Result<LoadedMaps, LasToStlError>
+    {
+        let height_map_intermediate = HeightMapIntermediate::glob_load(glob_pattern, resolution_x_in, resolution_y_in, options, progress)?;
+        let intensity_map = height_map_intermediate.get_intensity_map();
+        let color_map = height_map_intermediate.get_color_map();
+
+        let mut height_map = HeightMap::from(height_map_intermediate);
+        if let Some(geoid_correction) = &options.geoid_correction{
+            height_map.apply_geoid_correction(geoid_correction)?;
+        }
+        Ok(LoadedMaps { height_map, intensity_map, color_map })
+    }
+
+    /// Same as `glob_get_height_map_with_progress`, but with LAS/LAZ files that are already in memory,
+    /// without touching the filesystem or starting threads. See `HeightMapIntermediate::load_las_bytes`
+    pub fn from_las_bytes(files: &[&[u8]],
+                          resolution_x_in: Option<usize>,
+                          resolution_y_in: Option<usize>,
+                          options: &LasLoadOptions,
+                          progress: &dyn ProgressSink)
+        -> Result<HeightMap, LasToStlError>
+    {
+        let mut height_map = HeightMap::from(HeightMapIntermediate::load_las_bytes(files, resolution_x_in, resolution_y_in, options, progress)?);
+        if let Some(geoid_correction) = &options.geoid_correction{
+            height_map.apply_geoid_correction(geoid_correction)?;
+        }
+        Ok(height_map)
+    }
+
+    /// Adds more LAS/LAZ files to an existing (possibly saved and loaded) heightmap, so newly downloaded tiles
+    /// don't mean re-processing everything from scratch. The heightmap keeps its resolution and bounds,
+    /// so points outside of `self.bounds` are ignored.
+    ///
+    /// A `HeightMap` only has one height per cell, not every point that went into it, so cells that get new points
+    /// are replaced with the height of only the new points. This is perfect for adjacent tiles and fine for overlapping ones.
+    /// If you need the new points averaged with the old ones, keep the `HeightMapIntermediate` around
+    /// and use `HeightMapIntermediate::extend_from_glob` instead.
+    ///
+    /// Returns which files were read and which were skipped, see `HeightMapIntermediate::extend_from_glob`.
+    /// Returns `LasToStlError::GeoidCorrectionMismatchError` without reading anything if `options.geoid_correction`
+    /// isn't the correction the heightmap was made with (see `HeightMap::geoid_correction`), since the heights wouldn't match.
+    pub fn add_from_glob(&mut self, glob_pattern: &str, options: &LasLoadOptions) -> Result<BatchReport, LasToStlError>{
+        let new_correction = options.geoid_correction.as_ref().map(|correction| correction.to_string());
+        if new_correction != self.geoid_correction{
+            let describe = |correction: Option<String>| correction.unwrap_or_else(|| String::from("no geoid correction"));
+            return Err(LasToStlError::GeoidCorrectionMismatchError{
+                height_map: describe(self.geoid_correction.clone()),
+                new_points: describe(new_correction),
+            })
+        }
+
+        let mut new_data = HeightMapIntermediate::new_for_options(self.x_res, self.y_res, self.bounds, options)?;
+        let report = new_data.extend_from_glob(glob_pattern, options, &LogProgress)?;
+
+        let mut new_heights = new_data.get_heights();
+        if let Some(geoid_correction) = &options.geoid_correction{
+            let mut new_height_map = HeightMap::from(new_data);
+            new_height_map.utm_zone = self.utm_zone;
+            new_height_map.apply_geoid_correction(geoid_correction)?;
+            for (new_height, corrected_height) in new_heights.iter_mut().zip(new_height_map.data){
+                if let Some(new_height) = new_height{
+                    *new_height = corrected_height;
+                }
+            }
+        }
+
+        let mut heights = self.get_heights();
+        let mut bounds = self.bounds;
+        let mut num_replaced: usize = 0;
+        for (height, new_height) in heights.iter_mut().zip(new_heights){
+            if let Some(new_height) = new_height{
+                *height = Some(new_height);
+                bounds.max_z = f64_max(bounds.max_z, new_height);
+                num_replaced += 1;
+            }
+        }
+        // lowers min_z below the new heights if needed, and moves the old empty cells down with it
+        *self = self.with_heights(&heights, self.x_res, self.y_res, bounds);
+        info!("{num_replaced} / {} cells got new points", self.data.len());
+        Ok(report)
+    }
+}
+
+impl HeightMapIntermediate{
+
+    /// Creates a new `HeightMapIntermediate` with the binning strategy and extra grids requested in `options`
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn new_for_options(x_res: usize, y_res: usize, utm_bounds: UtmBoundingBox, options: &LasLoadOptions) -> Result<HeightMapIntermediate, LasToStlError>{
+        let mut height_map_intermediate = HeightMapIntermediate::new_with_outlier_rejection(x_res, y_res, utm_bounds, options.binning, options.outlier_rejection)?;
+        height_map_intermediate.hole_filling = options.hole_filling;
+        if options.aggregate_intensity{
+            height_map_intermediate.intensity = Some(vec![PointAggregate::default(); x_res*y_res]);
+        }
+        if options.aggregate_color{
+            height_map_intermediate.color = Some(vec![ColorAggregate::default(); x_res*y_res]);
+        }
+        Ok(height_map_intermediate)
+    }
+
+    /// Does the actual work of `HeightMap::glob_get_height_map_with_progress`, but stops before converting to a `HeightMap`.
+    /// Use this if you need more than the heightmap, like the point density (`get_density_map`) or the intensity or color grids
+    /// (see `LasLoadOptions::aggregate_intensity` and `LasLoadOptions::aggregate_color`).
+    ///
+    /// Files are read in parallel (see `LasLoadOptions::num_threads`) into partial grids,
+    /// and the partial grids are merged once all files are read.
+    pub fn glob_load(glob_pattern: &str,
+                     resolution_x_in: Option<usize>,
+                     resolution_y_in: Option<usize>,
+                     options: &LasLoadOptions,
+                     progress: &dyn ProgressSink)
+        -> Result<HeightMapIntermediate, LasToStlError>
+    {
+        HeightMapIntermediate::load_paths(utils::get_paths(glob_pattern)?, resolution_x_in, resolution_y_in, options, progress)
+    }
+
+    /// Same as `glob_load` but with a list of LAS/LAZ files. Files that can't be read are skipped,
+    /// see `HeightMapIntermediate::load_report` for which ones
+    pub fn load_paths(paths: Vec<PathBuf>,
+                      resolution_x_in: Option<usize>,
+                      resolution_y_in: Option<usize>,
+                      options: &LasLoadOptions,
+                      progress: &dyn ProgressSink)
+        -> Result<HeightMapIntermediate, LasToStlError>
+    {
+        // positions in the list given, for the load report, since unreadable files and cropping leave some files out
+        let mut files: Vec<NumberedFile> = paths.into_iter().enumerate().collect();
+        // files whose header can't be read are skipped before loading, and end up in the load report with the rest
+        let mut header_report = BatchReport::new();
+
+        let crs_counts = get_crs_counts(&mut files, options, &mut header_report, progress)?;
+        let (options, utm_zone) = with_default_target_crs(crs_counts, options, progress);
+        let options = options.as_ref();
 
-        let paths = utils::get_paths(glob_pattern)?;
         // get a bound on all data
-        let bounds = UtmBoundingBox::get_bounds_from_las_paths(&paths)?;
+        let (files, bounds) = get_load_bounds(files, options, &mut header_report, progress)?;
 
-        let x_range = bounds.x_range();
-        let y_range = bounds.y_range();
+        let bounds = UtmBoundingBox{ utm_zone, ..bounds };
+        let (resolution_x, resolution_y) = get_resolution(&bounds, resolution_x_in, resolution_y_in)?;
 
-        let (resolution_x, resolution_y): (usize, usize);
+        let mut height_map_intermediate = HeightMapIntermediate::new_for_options(resolution_x, resolution_y, bounds, options)?;
+        height_map_intermediate.utm_zone = utm_zone;
 
-        match (resolution_x_in, resolution_y_in){
-            (Some(x), Some(y)) => {
-                resolution_x = x;
-                resolution_y = y;
-            },
-            (Some(x), None) => {
-                resolution_x = x;
-                resolution_y = ((x as f64) * (y_range/x_range)) as usize;
-            },
-            (None, Some(y)) => {
-                resolution_x = ((y as f64) * (x_range/y_range)) as usize;
-                resolution_y = y;
-            },
-            (None, None) => {
-                return Err(LasToStlError::NoResolutionError)
+        match &options.checkpoint_path{
+            Some(checkpoint_path) => {
+                let mut checkpoint = Checkpoint{
+                    intermediate: height_map_intermediate,
+                    done_files: Vec::new(),
+                };
+
+                if checkpoint_path.exists(){
+                    match Checkpoint::load(checkpoint_path).map(|mut saved_checkpoint| {
+                        // outlier rejection and hole filling only matter when converting, so they aren't saved
+                        saved_checkpoint.intermediate.outlier_rejection = options.outlier_rejection;
+                        saved_checkpoint.intermediate.hole_filling = options.hole_filling;
+                        saved_checkpoint.intermediate.utm_zone = utm_zone;
+                        saved_checkpoint
+                    }){
+                        Ok(saved_checkpoint) if saved_checkpoint.matches(&checkpoint.intermediate) => {
+                            info!("resuming from checkpoint {:?} with {} files already read", checkpoint_path.display(), saved_checkpoint.done_files.len());
+                            checkpoint = saved_checkpoint;
+                        }
+                        Ok(_) => {
+                            progress.on_event(&PipelineEvent::Warning { message: format!("checkpoint {:?} was made with different settings, starting over", checkpoint_path.display()) });
+                        }
+                        Err(e) => {
+                            progress.on_event(&PipelineEvent::Warning { message: format!("could not load checkpoint {:?}, starting over. error:\n\t{e}", checkpoint_path.display()) });
+                        }
+                    }
+                }
+
+                let remaining_files: Vec<(usize, PathBuf)> = files.into_iter().filter(|(_, path)| !checkpoint.done_files.contains(path)).collect();
+
+                for batch in remaining_files.chunks(options.checkpoint_interval.max(1)){
+                    let report = checkpoint.intermediate.read_files(batch, options, progress)?;
+                    // files that failed aren't done, so they're tried again when resuming (and are in the report either way)
+                    let skipped: HashSet<usize> = report.skipped.iter().map(|skipped| skipped.index).collect();
+                    checkpoint.done_files.extend(batch.iter().filter(|(index, _)| !skipped.contains(index)).map(|(_, path)| path.clone()));
+                    checkpoint.intermediate.load_report.merge(report);
+                    checkpoint.save(checkpoint_path)?;
+                }
+
+                if checkpoint_path.exists(){
+                    std::fs::remove_file(checkpoint_path)?;
+                }
+                height_map_intermediate = checkpoint.intermediate;
+            }
+            None => {
+                let report = height_map_intermediate.read_files(&files, options, progress)?;
+                height_map_intermediate.load_report.merge(report);
+            }
+        }
+        height_map_intermediate.load_report.merge(header_report);
+
+        Ok(height_map_intermediate)
+    }
+
+    /// Same as `load_paths`, but with LAS/LAZ files that are already in memory (like files uploaded to a web page).
+    /// Nothing is read from or written to the filesystem, and the files are read one after another on the current thread,
+    /// so this works where there are no files or threads, like in the browser (see the `wasm` feature).
+    /// Checkpoints and streaming need files, so `checkpoint_path` and `spill_directory` are ignored.
+    ///
+    /// Files that can't be read are skipped, see `HeightMapIntermediate::load_report` for which ones.
+    /// They're called "LAS file <index>" in logs and progress events
+    pub fn load_las_bytes(files: &[&[u8]],
+                          resolution_x_in: Option<usize>,
+                          resolution_y_in: Option<usize>,
+                          options: &LasLoadOptions,
+                          progress: &dyn ProgressSink)
+        -> Result<HeightMapIntermediate, LasToStlError>
+    {
+        if files.is_empty(){
+            return Err(LasToStlError::NoLasFilesError)
+        }
+        let headers: Vec<Header> = files.iter()
+            .map(|bytes| Ok(Reader::new(Cursor::new(*bytes))?.header().clone()))
+            .collect::<Result<Vec<Header>, LasToStlError>>()?;
+
+        let mut crs_counts: Vec<(Crs, usize)> = Vec::new();
+        for header in &headers{
+            count_crs(&mut crs_counts, header, options);
+        }
+        let (options, utm_zone) = with_default_target_crs(crs_counts, options, progress);
+        let options = options.as_ref();
+
+        // get a bound on all data, leaving out files outside of the crop region
+        let crop = options.get_crop_bounds()?;
+        let mut bounds = UtmBoundingBox::default();
+        let mut files_to_read: Vec<(usize, String, &[u8])> = Vec::new();
+        for (index, (bytes, header)) in files.iter().zip(&headers).enumerate(){
+            let display_name = format!("LAS file {index}");
+            let file_bounds = match &options.target_crs{
+                Some(target_crs) => {
+                    UtmBoundingBox::from_las_header_in_crs(header, &display_name, target_crs, options.fallback_crs.as_ref())?
+                }
+                None => {
+                    UtmBoundingBox::from_las_header(header)
+                }
+            };
+            if crop.is_none_or(|crop| file_bounds.overlaps_xy(&crop)){
+                bounds = bounds.union(&file_bounds)?;
+                files_to_read.push((index, display_name, *bytes));
+            } else {
+                trace!("{display_name} is outside of the crop region, skipping it");
             }
         }
+        if let Some(crop) = crop{
+            bounds = bounds.intersection(&crop).ok_or(LasToStlError::CropOutsideDataError(crop))?;
+        }
+
+        let bounds = UtmBoundingBox{ utm_zone, ..bounds };
+        let (resolution_x, resolution_y) = get_resolution(&bounds, resolution_x_in, resolution_y_in)?;
+
+        let mut height_map_intermediate = HeightMapIntermediate::new_for_options(resolution_x, resolution_y, bounds, options)?;
+        height_map_intermediate.utm_zone = utm_zone;
+
+        progress.on_stage(LOADING_STAGE);
+        let num_files = files_to_read.len();
+        for (files_done, (index, display_name, bytes)) in (1..).zip(files_to_read){
+            progress.check_cancelled()?;
+            let stopwatch = Stopwatch::start();
+            let path = PathBuf::from(&display_name);
+            progress.on_event(&PipelineEvent::FileStarted { path: path.clone() });
 
+            let result = Reader::new(Cursor::new(bytes)).map_err(LasToStlError::from)
+                .and_then(|reader| height_map_intermediate.add_las_reader(reader, &display_name, options, progress));
+            match result{
+                Ok(()) => {
+                    height_map_intermediate.load_report.add_success();
+                }
+                Err(LasToStlError::CancelledError) => {
+                    return Err(LasToStlError::CancelledError)
+                }
+                Err(e) => {
+                    height_map_intermediate.load_report.add_skipped(index, &display_name, &e);
+                    progress.on_event(&PipelineEvent::FileSkipped { path: path.clone(), reason: e.to_string() });
+                }
+            }
 
-        // create a height map intermediate to hold the data while reading LAS files.
-        // This struct should not be used in any other context
-        let mut height_map_intermediate = HeightMapIntermediate::new(resolution_x, resolution_y, bounds);
+            progress.on_event(&PipelineEvent::FileFinished { path, files_done, num_files, seconds: stopwatch.elapsed().as_secs_f64() });
+            progress.on_progress(LOADING_STAGE, files_done as f64 / num_files as f64);
+        }
+        progress.on_finish(LOADING_STAGE);
 
-        // the 'index' of the file being processed (starting at 1)
-        let mut current_file_number: usize = 1;
+        Ok(height_map_intermediate)
+    }
 
-        let global_now = SystemTime::now();
+    /// Reads more LAS/LAZ files into self, so newly downloaded tiles can be added without re-reading everything.
+    /// The grid doesn't grow, so points outside of `self.bounds` are ignored, and files entirely outside of it are skipped.
+    ///
+    /// Every setting in `options` that affects which points are loaded applies, but self keeps its own
+    /// binning strategy and extra grids.
+    ///
+    /// Returns which files were read and which were skipped (outside of the bounds, or unreadable).
+    /// The same is added to `self.load_report`.
+    pub fn extend_from_glob(&mut self,
+                            glob_pattern: &str,
+                            options: &LasLoadOptions,
+                            progress: &dyn ProgressSink)
+        -> Result<BatchReport, LasToStlError>
+    {
+        let paths = utils::get_paths(glob_pattern)?;
+        let mut files: Vec<(usize, PathBuf)> = Vec::new();
+        let mut report = BatchReport::new();
+        for (index, path) in paths.into_iter().enumerate(){
+            match get_file_bounds(&path, options){
+                Ok(file_bounds) if !file_bounds.overlaps_xy(&self.bounds) => {
+                    let reason = format!("outside of the heightmap bounds {}", self.bounds);
+                    report.add_skipped(index, path.display(), &reason);
+                    progress.on_event(&PipelineEvent::FileSkipped { path, reason });
+                }
+                _ => {
+                    // files that can't be bounded will fail (and be logged) when they're read
+                    files.push((index, path));
+                }
+            }
+        }
+        report.merge(self.read_files(&files, options, progress)?);
+        self.load_report.merge(report.clone());
+        Ok(report)
+    }
+
+    /// Same as `extend_from_glob` but with a list of LAS/LAZ files. Files are not checked against the bounds before reading.
+    ///
+    /// Files are read in parallel (see `LasLoadOptions::num_threads`) into partial grids,
+    /// and the partial grids are merged into self once all files are read.
+    ///
+    /// Files that can't be read are skipped. Returns which ones, and the same is added to `self.load_report`.
+    pub fn extend_from_paths(&mut self,
+                             paths: &[PathBuf],
+                             options: &LasLoadOptions,
+                             progress: &dyn ProgressSink)
+        -> Result<BatchReport, LasToStlError>
+    {
+        let files: Vec<(usize, PathBuf)> = paths.iter().cloned().enumerate().collect();
+        let report = self.read_files(&files, options, progress)?;
+        self.load_report.merge(report.clone());
+        Ok(report)
+    }
 
-        let num_files = paths.len();
+    /// does the work of `extend_from_paths`, for files with their position in the list the caller gave,
+    /// without adding to `self.load_report`
+    fn read_files(&mut self,
+                  files: &[(usize, PathBuf)],
+                  options: &LasLoadOptions,
+                  progress: &dyn ProgressSink)
+        -> Result<BatchReport, LasToStlError>
+    {
+        let global_stopwatch = Stopwatch::start();
 
-        for path in paths{
-            let now = SystemTime::now();
+        let num_files = files.len();
 
-            match Reader::from_path(&path){
-                Ok(mut reader) => {
-                    let num_points = reader.header().number_of_points();
+        // number of files that have been read so far. Shared between threads, only used for logging
+        let files_done = AtomicUsize::new(0);
+        let report = Mutex::new(BatchReport::new());
 
-                    trace!("file header: {:?}", reader.header().system_identifier());
+        progress.on_stage(LOADING_STAGE);
 
-                    let display_path = path.display().to_string();
+        let log_file_done = |path: &PathBuf, stopwatch: Stopwatch| {
+            let current_file_number = files_done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress.on_event(&PipelineEvent::FileFinished {
+                path: path.clone(),
+                files_done: current_file_number,
+                num_files,
+                seconds: stopwatch.elapsed().as_secs_f64(),
+            });
+            progress.on_progress(LOADING_STAGE, current_file_number as f64 / num_files as f64);
+        };
 
-                    info!("Number of points: {num_points} in {display_path}");
+        match &options.spill_directory{
+            Some(spill_directory) => {
+                // every load gets its own folder, so loads running at the same time (in this process or another)
+                // don't overwrite each other's spill files
+                let spill_directory = spill_directory.join(format!(
+                    "las_kml_to_stl_spill_{}_{}",
+                    std::process::id(),
+                    NEXT_SPILL_LOAD.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::create_dir_all(&spill_directory)?;
 
-                    let mut counter: usize = 0;
-                    for wrapped_point_result in reader.points() {
-                        match wrapped_point_result{
-                            Ok(wrapped_point) => {
-                                height_map_intermediate.add_point_unchecked(wrapped_point); // TODO: spawn this in a new thread
-                                counter += 1;
+                // bin each file into its own spill file without ever holding a full grid per thread
+                let spill_paths: Vec<PathBuf> = install_in_pool(options.num_threads, || {
+                    files.par_iter().filter_map(|(file_index, path)| {
+                        if progress.is_cancelled(){
+                            return None
+                        }
+                        let stopwatch = Stopwatch::start();
+                        progress.on_event(&PipelineEvent::FileStarted { path: path.clone() });
 
-                                // total time with this \/ check: Ok(633.5581957s). Without: Ok(632.358371s)
+                        let spill_path = spill_directory.join(format!("las_kml_to_stl_spill_{file_index}.bin"));
+                        let result = self.spill_las_file(path, options, &spill_path, progress);
 
-                                if counter % 2097152 == 0 {
-                                    info!("{:.2}% done with {display_path}. (file {current_file_number} / {num_files})", 100f64 * counter as f64 / num_points as f64);
-                                }
+                        match result{
+                            Ok(()) => {
+                                report.lock().unwrap().add_success();
+                                log_file_done(path, stopwatch);
+                                Some(spill_path)
+                            }
+                            Err(LasToStlError::CancelledError) => {
+                                // a partial spill file is useless, the finished ones are deleted below
+                                let _ = std::fs::remove_file(&spill_path);
+                                None
                             }
                             Err(e) => {
-                                warn!("reader failed to data point in file {:?} with error:\n\t{:?}\nSkipping point.", path.display(), e)
+                                report.lock().unwrap().add_skipped(*file_index, path.display(), &e);
+                                progress.on_event(&PipelineEvent::FileSkipped { path: path.clone(), reason: e.to_string() });
+                                log_file_done(path, stopwatch);
+                                None
                             }
                         }
+                    }).collect()
+                })?;
+
+                if progress.is_cancelled(){
+                    for spill_path in spill_paths{
+                        std::fs::remove_file(&spill_path)?;
+                    }
+                    std::fs::remove_dir(&spill_directory)?;
+                    return Err(LasToStlError::CancelledError)
+                }
+
+                for spill_path in spill_paths{
+                    info!("merging spill file {:?}", spill_path.display());
+                    self.add_spill_file(&spill_path)?;
+                    std::fs::remove_file(&spill_path)?;
+                }
+                std::fs::remove_dir(&spill_directory)?;
+            }
+            None => {
+                // fold the files into height map intermediates to hold the data while reading, then merge them.
+                // rayon makes at least one per thread, and more if it splits the work further
+                // These structs should not be used in any other context
+                let merged = install_in_pool(options.num_threads, || {
+                    files.par_iter().fold(
+                        || self.new_empty_like(),
+                        |mut partial, (file_index, path)| {
+                            if progress.is_cancelled(){
+                                return partial
+                            }
+                            let stopwatch = Stopwatch::start();
+                            progress.on_event(&PipelineEvent::FileStarted { path: path.clone() });
+
+                            match partial.add_las_file(path, options, progress){
+                                Ok(()) => report.lock().unwrap().add_success(),
+                                Err(LasToStlError::CancelledError) => {}
+                                Err(e) => {
+                                    report.lock().unwrap().add_skipped(*file_index, path.display(), &e);
+                                    progress.on_event(&PipelineEvent::FileSkipped { path: path.clone(), reason: e.to_string() });
+                                }
+                            }
+
+                            log_file_done(path, stopwatch);
+                            partial
+                        }
+                    ).reduce(
+                        || self.new_empty_like(),
+                        |mut merged, partial| {
+                            merged.merge(&partial);
+                            merged
+                        }
+                    )
+                })?;
+                // a partly read file can't be taken back out, so nothing is merged
+                progress.check_cancelled()?;
+                self.merge(&merged);
+            }
+        }
+
+        progress.on_finish(LOADING_STAGE);
+        info!("loading all {num_files} files took {:?}", global_stopwatch.elapsed());
+
+        Ok(report.into_inner().unwrap())
+    }
+
+    /// Creates an empty `HeightMapIntermediate` with the same grid, binning strategy, and extra grids as self
+    pub fn new_empty_like(&self) -> HeightMapIntermediate{
+        // self was checked when it was made
+        let mut empty = HeightMapIntermediate::new_unchecked(self.x_res, self.y_res, self.bounds, self.binning, self.outlier_rejection);
+        empty.utm_zone = self.utm_zone;
+        empty.hole_filling = self.hole_filling;
+        if self.intensity.is_some(){
+            empty.intensity = Some(vec![PointAggregate::default(); self.x_res*self.y_res]);
+        }
+        if self.color.is_some(){
+            empty.color = Some(vec![ColorAggregate::default(); self.x_res*self.y_res]);
+        }
+        empty
+    }
+
+    /// adds a single point from a LAS/LAZ file to every grid that self has (height, intensity, ...).
+    /// Points outside of the grid are ignored.
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn add_las_point(&mut self, point: &Point){
+        if let Some(index) = self.get_index_checked(point){
+            self.data[index].add_sample(point.z);
+            if let Some(intensity) = &mut self.intensity{
+                intensity[index].add_sample(point.intensity as f64);
+            }
+            if let (Some(color), Some(point_color)) = (&mut self.color, point.color){
+                color[index].add_sample(point_color);
+            }
+        }
+    }
+
+
+    /// Reads every point from a single LAS/LAZ file into self, skipping points rejected by `options.filter`.
+    /// Progress through the file is reported to `progress` under the stage `"reading <path>"`.
+    ///
+    /// Returns an error if the file could not be opened. Points that could not be read are logged and skipped.
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn add_las_file<P: AsRef<Path>>(&mut self, path: P, options: &LasLoadOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        read_las_chunks(path, options, progress, |chunk| {
+            for point in chunk{
+                self.add_las_point(point);
+            }
+            Ok(())
+        })
+    }
+
+    /// Same as `add_las_file`, but for a LAS/LAZ file that's already open (with `las::Reader::new`), like one in memory.
+    /// `display_name` is only used for logging and progress.
+    ///
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn add_las_reader(&mut self, reader: Reader, display_name: &str, options: &LasLoadOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        read_las_reader_chunks(reader, display_name, options, progress, |chunk| {
+            for point in chunk{
+                self.add_las_point(point);
+            }
+            Ok(())
+        })
+    }
+
+    /// Streaming version of `add_las_file`. Instead of adding points to self, each chunk is binned into only the
+    /// cells it touches, and those partial aggregates are written to `spill_path` (see `add_spill_file`).
+    /// Self is only used for its resolution and bounds.
+    ///
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn spill_las_file<P: AsRef<Path>>(&self, path: P, options: &LasLoadOptions, spill_path: &Path, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        let mut writer = BufWriter::new(File::create(spill_path)?);
+
+        let mut chunk_cells: HashMap<usize, SpillCell> = HashMap::new();
+
+        read_las_chunks(path, options, progress, |chunk| {
+            for point in chunk{
+                if let Some(index) = self.get_index_checked(point){
+                    let cell = chunk_cells.entry(index).or_insert_with(|| SpillCell{
+                        height: PointAggregate::new(self.keeps_samples()),
+                        intensity: self.intensity.as_ref().map(|_| PointAggregate::default()),
+                        color: self.color.as_ref().map(|_| ColorAggregate::default()),
+                    });
+                    cell.height.add_sample(point.z);
+                    if let Some(intensity) = &mut cell.intensity{
+                        intensity.add_sample(point.intensity as f64);
                     }
+                    if let (Some(color), Some(point_color)) = (&mut cell.color, point.color){
+                        color.add_sample(point_color);
+                    }
+                }
+            }
+            for (index, cell) in chunk_cells.drain(){
+                cell.write_to(index, &mut writer)?;
+            }
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// adds all the records from a spill file written by `spill_las_file`.
+    /// Records for cells outside of self are logged and skipped.
+    /// This should probably not be public, but I don't believe in private fields. so just think about what you're doing if you want to use this.
+    pub fn add_spill_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LasToStlError>{
+        let mut reader = BufReader::new(File::open(path)?);
+        while let Some((index, cell)) = SpillCell::read_from(&mut reader)?{
+            if index >= self.data.len(){
+                warn!("spill record for index {index} is out of bounds, skipping it");
+                continue;
+            }
+            self.data[index].merge(&cell.height);
+            if let (Some(intensity), Some(cell_intensity)) = (&mut self.intensity, &cell.intensity){
+                intensity[index].merge(cell_intensity);
+            }
+            if let (Some(color), Some(cell_color)) = (&mut self.color, &cell.color){
+                color[index].merge(cell_color);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts how many of the LAS files are in each CRS. Files that don't say (and no `fallback_crs`) are not counted.
+///
+/// Files whose header can't be read are removed from `files` and added to `report`.
+/// Returns the error of the first file only if none of them can be read
+fn get_crs_counts(files: &mut Vec<NumberedFile>,
+                  options: &LasLoadOptions,
+                  report: &mut BatchReport,
+                  progress: &dyn ProgressSink)
+    -> Result<Vec<(Crs, usize)>, LasToStlError>
+{
+    let mut crs_counts: Vec<(Crs, usize)> = Vec::new();
+    let mut first_error: Option<LasToStlError> = None;
+    for (index, path) in std::mem::take(files){
+        match Reader::from_path(&path){
+            Ok(reader) => {
+                count_crs(&mut crs_counts, reader.header(), options);
+                files.push((index, path));
+            }
+            Err(e) => {
+                let e = LasToStlError::from(e);
+                skip_unreadable_file(report, progress, index, path, &e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (files.is_empty(), first_error){
+        (true, Some(e)) => Err(e),
+        _ => Ok(crs_counts),
+    }
+}
+
+/// adds a file that couldn't be read to `report` and tells `progress`
+fn skip_unreadable_file(report: &mut BatchReport, progress: &dyn ProgressSink, index: usize, path: PathBuf, error: &LasToStlError){
+    report.add_skipped(index, path.display(), error);
+    progress.on_event(&PipelineEvent::FileSkipped { path, reason: error.to_string() });
+}
 
-                    println!("file {current_file_number} / {num_files} took {:?} seconds", now.elapsed());
-                    current_file_number += 1;
+/// adds the CRS of `header` to `crs_counts`, see `get_crs_counts`
+fn count_crs(crs_counts: &mut Vec<(Crs, usize)>, header: &Header, options: &LasLoadOptions){
+    if let Some(crs) = options.get_crs(header){
+        match crs_counts.iter_mut().find(|(counted_crs, _)| *counted_crs == crs){
+            Some((_, count)) => {
+                *count += 1;
+            }
+            None => {
+                crs_counts.push((crs, 1));
+            }
+        }
+    }
+}
+
+/// Picks a `target_crs` for files in the CRSs in `crs_counts` if `options` doesn't have one and the files can't be binned as they are,
+/// and returns `options` with it, along with the UTM zone the data ends up in.
+///
+/// State plane coordinates (often in feet) go into the UTM zone the state plane zone is in,
+/// and files in different UTM zones (or other CRSs) are reprojected into the most common one
+fn with_default_target_crs<'a>(crs_counts: Vec<(Crs, usize)>,
+                               options: &'a LasLoadOptions,
+                               progress: &dyn ProgressSink)
+    -> (Cow<'a, LasLoadOptions>, Option<UtmZone>)
+{
+    let crs_counts_mixed = crs_counts.len() > 1;
+    if crs_counts_mixed{
+        for (crs, count) in &crs_counts{
+            info!("{count} files are in {crs}");
+        }
+    }
+    let most_common_crs = crs_counts.into_iter().max_by_key(|(_, count)| *count).map(|(crs, _)| crs);
+
+    let default_target_crs = match (&options.target_crs, most_common_crs.as_ref().and_then(StatePlane::from_crs)){
+        (Some(_), _) => {
+            None
+        }
+        (None, Some(state_plane)) => {
+            let utm_crs = state_plane.get_utm_zone().to_crs();
+            info!("LAS files are in a state plane CRS, reprojecting everything into {utm_crs}. Set LasLoadOptions::target_crs to pick a different one");
+            Some(utm_crs)
+        }
+        (None, None) => {
+            let mixed_crs = most_common_crs.clone().filter(|_| crs_counts_mixed);
+            if let Some(mixed_crs) = &mixed_crs{
+                progress.on_event(&PipelineEvent::Warning {
+                    message: format!("LAS files are in more than one CRS, reprojecting everything into {mixed_crs}. Set LasLoadOptions::target_crs to pick a different one"),
+                });
+            }
+            mixed_crs
+        }
+    };
+
+    // the data ends up in the target CRS if there is one, otherwise in the CRS of the files
+    let utm_zone = options.target_crs.as_ref().or(default_target_crs.as_ref()).or(most_common_crs.as_ref()).and_then(|crs| crs.get_utm_zone());
+
+    let options = match default_target_crs{
+        Some(target_crs) => {
+            Cow::Owned(LasLoadOptions{
+                target_crs: Some(target_crs),
+                ..options.clone()
+            })
+        }
+        None => {
+            Cow::Borrowed(options)
+        }
+    };
+    (options, utm_zone)
+}
+
+/// the resolution of a grid over `bounds`. If only one of them is given, the other keeps the aspect ratio
+fn get_resolution(bounds: &UtmBoundingBox, resolution_x_in: Option<usize>, resolution_y_in: Option<usize>) -> Result<(usize, usize), LasToStlError>{
+    let x_range = bounds.x_range();
+    let y_range = bounds.y_range();
+
+    match (resolution_x_in, resolution_y_in){
+        (Some(x), Some(y)) => {
+            Ok((x, y))
+        },
+        (Some(x), None) => {
+            Ok((x, ((x as f64) * (y_range/x_range)) as usize))
+        },
+        (None, Some(y)) => {
+            Ok((((y as f64) * (x_range/y_range)) as usize, y))
+        },
+        (None, None) => {
+            Err(LasToStlError::NoResolutionError)
+        }
+    }
+}
+
+/// Gets the bounds of a single file, reprojected into `options.target_crs` if set
+fn get_file_bounds(path: &PathBuf, options: &LasLoadOptions) -> Result<UtmBoundingBox, LasToStlError>{
+    match &options.target_crs{
+        Some(target_crs) => {
+            UtmBoundingBox::get_bounds_from_las_in_crs_with_fallback(path, target_crs, options.fallback_crs.as_ref())
+        }
+        None => {
+            UtmBoundingBox::get_bounds_from_las(path)
+        }
+    }
+}
+
+/// Finds the bounds of the heightmap for `files` with `options`
+/// (reprojected into `options.target_crs` and cropped to `options.crop` and `options.clip_region`).
+/// Also returns the files that are actually worth reading, which is all of them unless cropping.
+///
+/// Files that can't be bounded are skipped and added to `report`, like in `get_crs_counts`
+fn get_load_bounds(files: Vec<NumberedFile>,
+                   options: &LasLoadOptions,
+                   report: &mut BatchReport,
+                   progress: &dyn ProgressSink)
+    -> Result<(Vec<NumberedFile>, UtmBoundingBox), LasToStlError>
+{
+    let crop = options.get_crop_bounds()?;
+
+    let num_files = files.len();
+    match &crop{
+        Some(crop) => {
+            info!("finding bounds of {num_files} files and cropping to {crop}");
+        }
+        None => {
+            info!("finding bounds of {num_files} files");
+        }
+    }
+
+    let mut bounds = UtmBoundingBox::default();
+    let mut overlapping_files: Vec<NumberedFile> = Vec::new();
+    let mut first_error: Option<LasToStlError> = None;
+    let mut num_bounded: usize = 0;
+
+    for (count, (index, path)) in (1..).zip(files){
+        info!("bounding... {count} / {num_files}");
+        let file_bounds = match get_file_bounds(&path, options){
+            Ok(file_bounds) => {
+                file_bounds
+            }
+            Err(e) => {
+                skip_unreadable_file(report, progress, index, path, &e);
+                first_error.get_or_insert(e);
+                continue
+            }
+        };
+        num_bounded += 1;
+        if crop.is_none_or(|crop| file_bounds.overlaps_xy(&crop)){
+            bounds = bounds.union(&file_bounds)?;
+            overlapping_files.push((index, path));
+        } else {
+            trace!("{:?} is outside of the crop region, skipping it", path.display());
+        }
+    }
+
+    if let (0, Some(e)) = (num_bounded, first_error){
+        return Err(e)
+    }
+
+    let Some(crop) = crop else {
+        return Ok((overlapping_files, bounds))
+    };
+
+    if overlapping_files.is_empty(){
+        return Err(LasToStlError::CropOutsideDataError(crop))
+    }
+
+    info!("{} / {num_files} files overlap the crop region", overlapping_files.len());
+
+    let bounds = bounds.intersection(&crop).ok_or(LasToStlError::CropOutsideDataError(crop))?;
+
+    Ok((overlapping_files, bounds))
+}
+
+/// Everything aggregated for a single cell from one chunk of points. Only used for spill files in streaming mode.
+struct SpillCell {
+    height: PointAggregate,
+    intensity: Option<PointAggregate>,
+    color: Option<ColorAggregate>,
+}
+
+impl SpillCell {
+    /// writes the cell and its index as a binary record
+    fn write_to<W: Write>(&self, index: usize, writer: &mut W) -> Result<(), LasToStlError>{
+        writer.write_all(&(index as u64).to_le_bytes())?;
+        self.height.write_to(writer)?;
+        match &self.intensity{
+            Some(intensity) => {
+                writer.write_all(&[1u8])?;
+                intensity.write_to(writer)?;
+            }
+            None => {
+                writer.write_all(&[0u8])?;
+            }
+        }
+        match &self.color{
+            Some(color) => {
+                writer.write_all(&[1u8])?;
+                color.write_to(writer)?;
+            }
+            None => {
+                writer.write_all(&[0u8])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// reads a record written by `write_to`. Returns `None` at the end of the stream.
+    fn read_from<R: std::io::Read>(reader: &mut R) -> Result<Option<(usize, SpillCell)>, LasToStlError>{
+        let mut index_bytes = [0u8; 8];
+        match reader.read_exact(&mut index_bytes){
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(e) => {
+                return Err(e.into())
+            }
+        }
+
+        let height = PointAggregate::read_from(reader)?;
+
+        let mut flag = [0u8; 1];
+        reader.read_exact(&mut flag)?;
+        let intensity = if flag[0] == 1 {
+            Some(PointAggregate::read_from(reader)?)
+        } else {
+            None
+        };
+
+        reader.read_exact(&mut flag)?;
+        let color = if flag[0] == 1 {
+            Some(ColorAggregate::read_from(reader)?)
+        } else {
+            None
+        };
+
+        Ok(Some((u64::from_le_bytes(index_bytes) as usize, SpillCell{
+            height,
+            intensity,
+            color,
+        })))
+    }
+}
+
+/// reads a LAS/LAZ file `options.chunk_size` points at a time, passing every chunk
+/// (with the points rejected by `options.filter` or outside `options.crop`/`options.clip_region` removed,
+/// and reprojected into `options.target_crs`) to `process_chunk`.
+///
+/// COPC files (`.copc.laz`) are read one octree node at a time instead, skipping nodes outside the crop region.
+///
+/// Points that can't be read are skipped (a whole octree node at a time for COPC files) and logged as warnings (how many as a `PipelineEvent::Warning`),
+/// so only errors opening the file or from `process_chunk` are returned.
+fn read_las_chunks<P, F>(path: P, options: &LasLoadOptions, progress: &dyn ProgressSink, mut process_chunk: F) -> Result<(), LasToStlError>
+    where P: AsRef<Path>,
+          F: FnMut(&[Point]) -> Result<(), LasToStlError>
+{
+    let display_path = path.as_ref().display().to_string();
+
+    if is_copc_path(path.as_ref()){
+        let copc_reader = CopcReader::open(&path)?;
+
+        let mut chunk_processor = ChunkProcessor::new(copc_reader.header.number_of_points(), &copc_reader.header, options, progress, &display_path)?;
+
+        // the octree is in the file's CRS, so it can only be used to skip nodes if the crop region is in the same CRS
+        let query_bounds = if chunk_processor.reprojector.is_none(){
+            chunk_processor.crop
+        } else {
+            None
+        };
+        let entries = copc_reader.get_entries_in_bounds(query_bounds.as_ref());
+        info!("reading {} / {} octree nodes of {display_path}", entries.len(), copc_reader.entries.len());
+        chunk_processor.num_points = entries.iter().map(|entry| entry.point_count).sum();
+
+        for entry in entries{
+            match copc_reader.read_entry(&entry){
+                Ok(mut chunk) => {
+                    chunk_processor.process(&mut chunk, &mut process_chunk)?;
                 }
                 Err(e) => {
-                    warn!("reader failed to read file {:?} with error:\n\t{:?}\nSkipping file.", path.display(), e)
+                    warn!("skipping the {} points of an octree node in {display_path} that could not be read: {e}", entry.point_count);
                 }
-            };
+            }
+        }
+
+        chunk_processor.finish();
+        return Ok(())
+    }
+
+    read_las_reader_chunks(Reader::from_path(&path)?, &display_path, options, progress, process_chunk)
+}
+
+/// Same as `read_las_chunks`, but for a LAS/LAZ file that's already open, from wherever it is (like bytes in memory).
+/// `display_name` is only used for logging and progress
+fn read_las_reader_chunks<F>(mut reader: Reader, display_name: &str, options: &LasLoadOptions, progress: &dyn ProgressSink, mut process_chunk: F) -> Result<(), LasToStlError>
+    where F: FnMut(&[Point]) -> Result<(), LasToStlError>
+{
+    trace!("file header: {:?}", reader.header().system_identifier());
+
+    let mut chunk_processor = ChunkProcessor::new(reader.header().number_of_points(), reader.header(), options, progress, display_name)?;
+
+    let chunk_size = options.chunk_size.max(1);
+
+    let mut chunk: Vec<Point> = Vec::with_capacity(chunk_size);
+    let mut num_unreadable: u64 = 0;
+
+    // read point by point instead of with `read_n_into`, so one bad point doesn't lose the whole chunk (and file)
+    let mut end_of_file = false;
+    while !end_of_file {
+        chunk.clear();
+        while chunk.len() < chunk_size {
+            match reader.read(){
+                Some(Ok(point)) => {
+                    chunk.push(point);
+                }
+                Some(Err(e)) => {
+                    if num_unreadable == 0{
+                        warn!("skipping a point in {display_name} that could not be read: {e}");
+                    }
+                    num_unreadable += 1;
+                }
+                None => {
+                    end_of_file = true;
+                    break;
+                }
+            }
+        }
+        if !chunk.is_empty(){
+            chunk_processor.process(&mut chunk, &mut process_chunk)?;
+        }
+    }
+
+    if num_unreadable != 0{
+        progress.on_event(&PipelineEvent::Warning {
+            message: format!("{num_unreadable} / {} points in {display_name} could not be read and were skipped", reader.header().number_of_points())
+        });
+    }
+    chunk_processor.finish();
+
+    Ok(())
+}
+
+/// Filters, reprojects, and crops chunks of points read from one file, and keeps track of progress for `read_las_chunks`
+struct ChunkProcessor<'a> {
+    options: &'a LasLoadOptions,
+    progress: &'a dyn ProgressSink,
+    display_path: &'a str,
+    stage: String,
+    /// the reprojector and what to multiply the heights by, for files in feet
+    reprojector: Option<(Reprojector, f64)>,
+    crop: Option<UtmBoundingBox>,
+    num_points: u64,
+    counter: usize,
+    num_filtered: usize,
+    num_cropped: usize,
+    /// the next point count at which to log progress
+    next_log: usize,
+}
+
+impl<'a> ChunkProcessor<'a> {
+    fn new(num_points: u64, header: &Header, options: &'a LasLoadOptions, progress: &'a dyn ProgressSink, display_path: &'a str) -> Result<ChunkProcessor<'a>, LasToStlError>{
+        let reprojector = match &options.target_crs{
+            Some(target_crs) => {
+                match options.get_crs(header){
+                    Some(source_crs) if source_crs != *target_crs => {
+                        info!("reprojecting {display_path} from {source_crs} to {target_crs}");
+                        Some((Reprojector::new(&source_crs, target_crs)?, source_crs.meters_per_unit() / target_crs.meters_per_unit()))
+                    }
+                    Some(_) => {
+                        None
+                    }
+                    None => {
+                        progress.on_event(&PipelineEvent::Warning { message: format!("{display_path} has no CRS in its header, assuming it is already in {target_crs}") });
+                        None
+                    }
+                }
+            }
+            None => {
+                None
+            }
+        };
+
+        info!("Number of points: {num_points} in {display_path}");
+
+        let stage = format!("reading {display_path}");
+        progress.on_stage(&stage);
+
+        Ok(ChunkProcessor{
+            options,
+            progress,
+            display_path,
+            stage,
+            reprojector,
+            crop: options.get_crop_bounds()?,
+            num_points,
+            counter: 0,
+            num_filtered: 0,
+            num_cropped: 0,
+            next_log: POINTS_PER_PROGRESS_UPDATE,
+        })
+    }
+
+    /// removes the points that shouldn't be loaded from `chunk`, reprojects the rest, and passes them to `process_chunk`
+    fn process<F>(&mut self, chunk: &mut Vec<Point>, process_chunk: &mut F) -> Result<(), LasToStlError>
+        where F: FnMut(&[Point]) -> Result<(), LasToStlError>
+    {
+        self.progress.check_cancelled()?;
+        let num_read = chunk.len();
+        self.counter += num_read;
+
+        chunk.retain(|point| self.options.filter.accepts(point));
+        self.num_filtered += num_read - chunk.len();
+
+        if let Some((reprojector, z_scale)) = &self.reprojector{
+            chunk.retain_mut(|point| {
+                match reprojector.convert(point.x, point.y){
+                    Ok((x, y)) => {
+                        point.x = x;
+                        point.y = y;
+                        point.z *= z_scale;
+                        true
+                    }
+                    Err(e) => {
+                        trace!("dropping point that could not be reprojected: {e}");
+                        false
+                    }
+                }
+            });
         }
-        info!("loading all {num_files} files took {:?}", global_now.elapsed());
 
-        Ok(HeightMap::from(height_map_intermediate))
+        if let Some(crop) = &self.crop{
+            let num_before_crop = chunk.len();
+            chunk.retain(|point| {
+                crop.contains_xy(point.x, point.y) && match &self.options.clip_region{
+                    Some(clip_region) => {
+                        clip_region.contains(&Coord{x: point.x, y: point.y})
+                    }
+                    None => {
+                        true
+                    }
+                }
+            });
+            self.num_cropped += num_before_crop - chunk.len();
+        }
+
+        process_chunk(chunk)?;
+
+        if self.counter >= self.next_log {
+            self.progress.on_progress(&self.stage, self.counter as f64 / self.num_points as f64);
+            self.next_log += POINTS_PER_PROGRESS_UPDATE;
+        }
+        Ok(())
+    }
+
+    /// reports the end of the file
+    fn finish(&self){
+        self.progress.on_finish(&self.stage);
+
+        let display_path = self.display_path;
+        info!("{} / {} points in {display_path} were rejected by the point filter", self.num_filtered, self.counter);
+        if self.crop.is_some(){
+            info!("{} / {} points in {display_path} were outside of the crop region", self.num_cropped, self.counter);
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::{Builder, Write, Writer};
+    use crate::progress::{CallbackProgress, NoProgress};
+
+    /// an uncompressed LAS file with one point at (x, y, z) for every item of `points`
+    fn las_bytes(points: &[(f64, f64, f64)]) -> Vec<u8> {
+        let mut writer = Writer::new(Cursor::new(Vec::new()), Builder::default().into_header().unwrap()).unwrap();
+        for (x, y, z) in points {
+            writer.write(Point { x: *x, y: *y, z: *z, ..Default::default() }).unwrap();
+        }
+        writer.into_inner().unwrap().into_inner()
+    }
+
+    #[test]
+    fn unreadable_points_are_skipped_not_the_whole_file() {
+        let points: Vec<(f64, f64, f64)> = (0..10).map(|i| (i as f64, i as f64, 100f64 + i as f64)).collect();
+        // cut the last point in half
+        let mut bytes = las_bytes(&points);
+        bytes.truncate(bytes.len() - 10);
+
+        let bounds = UtmBoundingBox::new(0f64, 9f64, 0f64, 9f64, 0f64, 200f64);
+        let mut intermediate = HeightMapIntermediate::new(10, 10, bounds).unwrap();
+        let reader = Reader::new(Cursor::new(bytes)).unwrap();
+        intermediate.add_las_reader(reader, "truncated.las", &LasLoadOptions::default(), &NoProgress).unwrap();
+
+        let num_points: u32 = intermediate.data.iter().map(PointAggregate::get_num_points).sum();
+        assert_eq!(num_points, 9);
+    }
+
+    #[test]
+    fn the_number_of_unreadable_points_is_reported() {
+        let points: Vec<(f64, f64, f64)> = (0..10).map(|i| (i as f64, i as f64, 100f64 + i as f64)).collect();
+        let mut bytes = las_bytes(&points);
+        bytes.truncate(bytes.len() - 10);
+
+        let warnings = Mutex::new(Vec::new());
+        let progress = CallbackProgress(|event: &PipelineEvent| {
+            if let PipelineEvent::Warning { message } = event {
+                warnings.lock().unwrap().push(message.clone());
+            }
+        });
+        let bounds = UtmBoundingBox::new(0f64, 9f64, 0f64, 9f64, 0f64, 200f64);
+        let mut intermediate = HeightMapIntermediate::new(10, 10, bounds).unwrap();
+        let reader = Reader::new(Cursor::new(bytes)).unwrap();
+        intermediate.add_las_reader(reader, "truncated.las", &LasLoadOptions::default(), &progress).unwrap();
+
+        let warnings = warnings.into_inner().unwrap();
+        assert_eq!(warnings, vec![String::from("1 / 10 points in truncated.las could not be read and were skipped")]);
+    }
+
+    #[test]
+    fn add_from_glob_checks_the_geoid_correction_and_keeps_holes_below_new_points() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_add_from_glob_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("low.las"), las_bytes(&[(1f64, 1f64, 5f64)])).unwrap();
+        let glob_pattern = directory.join("*.las").display().to_string();
+
+        // everything at 20 except an empty cell in the corner
+        let mut height_map = HeightMap {
+            data: (0..16).map(|index| if index == 0 { 10f64 } else { 20f64 }).collect(),
+            x_res: 4,
+            y_res: 4,
+            bounds: UtmBoundingBox::new(0f64, 3f64, 0f64, 3f64, 10f64, 20f64),
+            utm_zone: None,
+            geoid_correction: Some(String::from("constant geoid height of 30")),
+            crs: None,
+        };
+        let result = height_map.add_from_glob(&glob_pattern, &LasLoadOptions::default());
+        assert!(matches!(result, Err(LasToStlError::GeoidCorrectionMismatchError{ .. })));
+
+        height_map.geoid_correction = None;
+        height_map.add_from_glob(&glob_pattern, &LasLoadOptions::default()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let heights = height_map.get_heights();
+        assert_eq!(heights[0], None);
+        assert_eq!(heights[5], Some(5f64));
+        assert_eq!(heights.iter().filter(|height| height.is_none()).count(), 1);
+    }
+
+    #[test]
+    fn glob_get_maps_returns_the_intensity_and_color_maps() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_glob_get_maps_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut builder = Builder::from((1, 2));
+        builder.point_format = las::point::Format::new(2).unwrap();
+        let mut writer = Writer::new(Cursor::new(Vec::new()), builder.into_header().unwrap()).unwrap();
+        for (x, y) in [(0f64, 0f64), (10f64, 0f64), (0f64, 10f64), (10f64, 10f64)] {
+            let color = las::Color::new(200, 100, if x > 0f64 { 50 } else { 0 });
+            writer.write(Point { x, y, z: 100f64 + x, intensity: 7, color: Some(color), ..Default::default() }).unwrap();
+        }
+        std::fs::write(directory.join("colored.las"), writer.into_inner().unwrap().into_inner()).unwrap();
+
+        let options = LasLoadOptions { aggregate_color: true, aggregate_intensity: true, ..LasLoadOptions::default() };
+        let glob_pattern = directory.join("*.las").display().to_string();
+        let maps = HeightMap::glob_get_maps(&glob_pattern, Some(2), Some(2), &options, &crate::progress::NoProgress).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(maps.intensity_map.unwrap().get_intensity(1, 0).unwrap(), 7f64);
+        let color_map = maps.color_map.unwrap();
+        assert_eq!((color_map.x_res, color_map.y_res), (maps.height_map.x_res, maps.height_map.y_res));
+        assert_eq!(color_map.get_color(0, 0).unwrap(), [200, 100, 0]);
+        assert_eq!(color_map.get_color(1, 1).unwrap(), [200, 100, 50]);
+        #[cfg(feature = "image")]
+        assert_eq!(color_map.to_image().unwrap().get_pixel(1, 1).0, [200, 100, 50]);
+    }
+
+    #[test]
+    fn loads_at_the_same_time_dont_share_spill_files() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_spill_loads_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("low")).unwrap();
+        std::fs::create_dir_all(directory.join("high")).unwrap();
+        let corners = |z: f64| las_bytes(&[(0f64, 0f64, z), (10f64, 0f64, z), (0f64, 10f64, z), (10f64, 10f64, z)]);
+        std::fs::write(directory.join("low").join("tile.las"), corners(5f64)).unwrap();
+        std::fs::write(directory.join("high").join("tile.las"), corners(50f64)).unwrap();
+
+        let spill_directory = directory.join("spill");
+        let options = LasLoadOptions::streaming(DEFAULT_CHUNK_SIZE, &spill_directory);
+        let load = |name: &str| {
+            let glob_pattern = directory.join(name).join("*.las").display().to_string();
+            HeightMap::glob_get_height_map_with_options(&glob_pattern, Some(2), Some(2), &options).unwrap()
+        };
+        let (low, high) = rayon::join(|| load("low"), || load("high"));
+
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0, "every load should clean up its folder");
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(low.get_heights(), vec![Some(5f64); 4]);
+        assert_eq!(high.get_heights(), vec![Some(50f64); 4]);
+    }
+
+    #[test]
+    fn files_with_unreadable_headers_are_skipped_not_fatal() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_unreadable_headers_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let good = directory.join("good.las");
+        let bad = directory.join("bad.las");
+        std::fs::write(&good, las_bytes(&[(0f64, 0f64, 5f64), (10f64, 0f64, 5f64), (0f64, 10f64, 5f64), (10f64, 10f64, 5f64)])).unwrap();
+        std::fs::write(&bad, b"not a las file").unwrap();
+
+        let loaded = HeightMapIntermediate::load_paths(vec![bad.clone(), good], Some(2), Some(2), &LasLoadOptions::default(), &NoProgress);
+        let only_bad = HeightMapIntermediate::load_paths(vec![bad], Some(2), Some(2), &LasLoadOptions::default(), &NoProgress);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.load_report.num_succeeded, 1);
+        assert_eq!(loaded.load_report.skipped.len(), 1);
+        assert_eq!(loaded.load_report.skipped[0].index, 0);
+        assert!(only_bad.is_err(), "a load with no readable files should still fail");
+    }
+}
@@ -6,4 +6,61 @@ pub mod utm_bounds;
 pub mod mask;
 pub mod kml_utils;
 pub mod utm_point;
-pub mod stl;
\ No newline at end of file
+#[cfg(feature = "stl")]
+pub mod stl;
+pub mod point_filter;
+pub mod progress;
+pub mod intensity_map;
+pub mod color_map;
+pub mod crs;
+pub mod checkpoint;
+pub mod density_map;
+pub mod copc;
+#[cfg(feature = "ept")]
+pub mod ept;
+#[cfg(feature = "shapefile")]
+pub mod shapefile_utils;
+pub mod contours;
+pub mod stats;
+pub mod geoid;
+pub mod weight_mask;
+pub mod alpha_mask;
+#[cfg(feature = "stl")]
+pub mod tiles;
+pub mod text;
+#[cfg(feature = "stl")]
+pub mod three_mf;
+#[cfg(all(feature = "stl", feature = "image"))]
+pub mod gltf;
+#[cfg(feature = "stl")]
+pub mod multi_body;
+#[cfg(feature = "geojson")]
+pub mod geojson_utils;
+#[cfg(all(feature = "stl", feature = "kml", feature = "image"))]
+pub mod ground_overlay;
+#[cfg(feature = "kml")]
+pub mod kml_export;
+#[cfg(feature = "gpx")]
+pub mod altitude_trails;
+#[cfg(feature = "geotiff")]
+pub mod geotiff;
+#[cfg(feature = "cog")]
+pub mod cog;
+#[cfg(feature = "image")]
+pub mod color_relief;
+pub mod dem_fallback;
+#[cfg(feature = "image")]
+pub mod terrain_tiles;
+pub mod state_plane;
+pub mod datum;
+#[cfg(all(feature = "stl", feature = "kml"))]
+pub mod terrain_model;
+#[cfg(feature = "project")]
+pub mod project;
+pub mod batch_report;
+#[cfg(feature = "kml")]
+pub mod kml_reader;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "wasm")]
+pub mod wasm;
\ No newline at end of file
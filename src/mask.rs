@@ -1,14 +1,33 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
 use std::ops::{BitAndAssign, BitOrAssign, BitXorAssign, SubAssign};
-use geo::{BoundingRect, Contains, Coord, EuclideanLength, LineInterpolatePoint, LineString, Point, Polygon};
-use log::{error, info, trace, warn};
+use std::path::Path;
+use geo::{BoundingRect, Coord, LineInterpolatePoint, LineString, Point, Polygon};
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use crate::batch_report::BatchReport;
+use crate::datum::DatumShift;
 use crate::errors::LasToStlError;
 use crate::kml_utils::{linestring_to_utm_linestring, polygon_to_utm_polygon};
-use crate::utils::get_point_deltas_within_radius;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::utils::{f64_max, f64_min, get_point_deltas_within_radius, par_for_each, par_zip_for_each, reorient_grid, swap_bound_ranges, GridOrientation};
 use crate::utm_bounds::UtmBoundingBox;
-use crate::utm_point::UtmCoord;
+use crate::height_map::HeightMap;
+use crate::utm_point::{UtmCoord, UtmZone};
+
+/// How `Mask::resample_to` decides the state of each new pixel
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MaskSampling {
+    /// the state of the closest old pixel. Fast, and thin lines stay connected when making the mask bigger
+    Nearest,
+    /// true if more than half of the old pixels the new pixel covers are true, which is smoother when making the mask smaller.
+    /// Falls back to nearest when the new pixels are smaller than the old ones
+    Majority,
+}
 
 /// A Boolean mask intended to span the same region as a heightmap to be able to apply certain
 /// functions selectively
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Mask{
     pub data: Vec<bool>,
     pub x_res: usize,
@@ -28,8 +47,15 @@ pub struct Mask{
     pub bounds: UtmBoundingBox,
 
     /// see [UTM on wikipedia](https://en.wikipedia.org/wiki/Universal_Transverse_Mercator_coordinate_system) to find what a UTM zone is.
-    /// This is required and must be correct (or at least constant)
-    pub utm_zone: u8
+    /// This is required and must be correct (or at least constant).
+    /// Every lat/lon function on the mask converts into this zone
+    pub utm_zone: UtmZone,
+
+    /// Shift applied to every lat/lon coordinate before it's converted into `utm_zone`, to move KML (WGS84) onto
+    /// the datum of the lidar, like `DatumShift::wgs84_to_nad83` for most US lidar. `None` treats them as the same,
+    /// which leaves trails a meter or two off
+    #[serde(default)]
+    pub datum_shift: Option<DatumShift>,
 }
 
 impl Mask{
@@ -66,6 +92,41 @@ impl Mask{
         }
     }
 
+    /// logs a warning if a lat/lon coordinate is more than one zone away from the mask's zone,
+    /// which usually means the mask has the wrong zone and geometry will be shifted by hundreds of km
+    fn warn_if_outside_zone(&self, lat_lon: Option<&Coord>){
+        if let Some(lat_lon) = lat_lon{
+            let coord_zone = UtmZone::from_lat_lon_coord(lat_lon);
+            if coord_zone.distance(&self.utm_zone) > 1{
+                warn!("geometry at {:?} is in UTM zone {coord_zone}, but the mask is in zone {}. It will probably end up in the wrong place", lat_lon, self.utm_zone);
+            }
+        }
+    }
+
+    /// converts a lat/lon coordinate into `utm_zone`, shifted by `datum_shift` first
+    fn lat_lon_to_utm_coord(&self, lat_lon: &Coord) -> UtmCoord{
+        match self.datum_shift {
+            Some(datum_shift) => UtmCoord::from_gps_coord_in_zone(&datum_shift.shift_lat_lon(lat_lon), &self.utm_zone),
+            None => UtmCoord::from_gps_coord_in_zone(lat_lon, &self.utm_zone),
+        }
+    }
+
+    /// `linestring_to_utm_linestring`, shifted by `datum_shift` first
+    fn lat_lon_to_utm_linestring(&self, lat_lon_line_string: &LineString) -> LineString{
+        match self.datum_shift {
+            Some(datum_shift) => linestring_to_utm_linestring(&datum_shift.shift(lat_lon_line_string), self.utm_zone),
+            None => linestring_to_utm_linestring(lat_lon_line_string, self.utm_zone),
+        }
+    }
+
+    /// `polygon_to_utm_polygon`, shifted by `datum_shift` first
+    fn lat_lon_to_utm_polygon(&self, lat_lon_polygon: &Polygon) -> Polygon{
+        match self.datum_shift {
+            Some(datum_shift) => polygon_to_utm_polygon(&datum_shift.shift(lat_lon_polygon), self.utm_zone),
+            None => polygon_to_utm_polygon(lat_lon_polygon, self.utm_zone),
+        }
+    }
+
     /// sets the state of the point at `(x, y)` to `state`. Returns an error if out of bounds
     pub fn set_x_y(&mut self, x: usize, y: usize, new_state: bool) -> Result<(), LasToStlError>{
 
@@ -83,12 +144,57 @@ impl Mask{
 
     }
 
+    /// Creates an empty mask with the same resolution, bounds and UTM zone as `height_map`.
+    ///
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its zone
+    /// (the LAS files didn't say). Set `height_map.utm_zone` first, e.g. with `kml_utils::infer_utm_zone`
+    pub fn new_for_height_map(height_map: &HeightMap) -> Result<Mask, LasToStlError>{
+        let utm_zone = height_map.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+        Mask::new_with_dims(height_map.x_res, height_map.y_res, height_map.bounds, utm_zone)
+    }
+
+    /// Creates a mask that is true wherever `predicate` returns true for the height of `height_map`.
+    /// e.g. `Mask::from_height_map(&hm.slope_degrees(), |slope| slope > 30.0)` for terrain steeper than 30 degrees.
+    ///
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its zone, see `new_for_height_map`
+    pub fn from_height_map<F>(height_map: &HeightMap, predicate: F) -> Result<Mask, LasToStlError>
+        where F: Fn(f64) -> bool
+    {
+        let mut mask = Mask::new_for_height_map(height_map)?;
+        mask.data = height_map.data.iter().map(|height| predicate(*height)).collect();
+        Ok(mask)
+    }
+
+    /// Rotates or flips the mask the same way as `HeightMap::reorient`, so it still matches a heightmap that was reoriented
+    pub fn reorient(&mut self, orientation: GridOrientation){
+        let (data, x_res, y_res) = reorient_grid(&self.data, self.x_res, self.y_res, orientation);
+        self.data = data;
+        self.x_res = x_res;
+        self.y_res = y_res;
+        if orientation.swaps_axes() {
+            self.bounds = swap_bound_ranges(self.bounds);
+            std::mem::swap(&mut self.x_tick, &mut self.y_tick);
+        }
+    }
+
     /// Creates a new mask from some basic info. Recommended to get this info from the heightmap it is intended to be applied to
+    /// (or just use `new_for_height_map`)
     ///
     /// see [UTM on wikipedia](https://en.wikipedia.org/wiki/Universal_Transverse_Mercator_coordinate_system) to find what a UTM zone is.
     /// This is required and must be correct (or at least constant)
-    pub fn new_with_dims(x_res: usize, y_res: usize, bounds: UtmBoundingBox, utm_zone: u8) -> Mask{
+    ///
+    /// Returns `LasToStlError::ResolutionTooSmallError` if either resolution is below 2,
+    /// and `LasToStlError::InvalidBoundsError` if the x/y bounds are inverted or not finite (see `UtmBoundingBox::validate_xy`)
+    pub fn new_with_dims(x_res: usize, y_res: usize, bounds: UtmBoundingBox, utm_zone: UtmZone) -> Result<Mask, LasToStlError>{
+        if x_res < 2 || y_res < 2 {
+            return Err(LasToStlError::ResolutionTooSmallError{ x_res, y_res })
+        }
+        bounds.validate_xy()?;
+        Ok(Mask::new_with_dims_unchecked(x_res, y_res, bounds, utm_zone))
+    }
 
+    /// `new_with_dims` without checking the resolution and bounds, for masks made from things that were already checked
+    pub(crate) fn new_with_dims_unchecked(x_res: usize, y_res: usize, bounds: UtmBoundingBox, utm_zone: UtmZone) -> Mask{
         let x_tick: f64 = bounds.x_range() / (x_res - 1) as f64;
         let y_tick: f64 = bounds.y_range() / (y_res - 1) as f64;
 
@@ -100,7 +206,81 @@ impl Mask{
             y_tick,
             bounds,
             utm_zone,
+            datum_shift: None,
+        }
+    }
+
+    /// Loads from a JSON file made with `save`. Rasterizing a big polygon can take minutes,
+    /// so save the mask once and load it next time instead.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Mask, LasToStlError> {
+        Mask::load_from_reader(&mut File::open(path)?)
+    }
+
+    /// Same as `load`, but reads the JSON from `reader` (like a `&[u8]` or a socket) instead of a file
+    pub fn load_from_reader<R: Read>(reader: &mut R) -> Result<Mask, LasToStlError> {
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        serde_json::from_slice::<Mask>(&buf[..]).map_err(|e|{LasToStlError::SerdeError(e)})
+    }
+
+    /// Saves to a JSON file, with the bounds, resolution and UTM zone. Like `HeightMap::save`, not a standard format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LasToStlError>{
+        let mut f = BufWriter::new(File::create(path)?);
+        self.save_to_writer(&mut f)?;
+        f.flush()?;
+
+        Ok(())
+    }
+
+    /// Same as `save`, but writes the JSON into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    pub fn save_to_writer<W: Write>(&self, writer: &mut W) -> Result<(), LasToStlError>{
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Creates a copy of the mask with a different resolution and bounds, like a mask for a cropped or resampled heightmap,
+    /// so it doesn't have to be built from the KML again. Anything outside of the old bounds is false.
+    pub fn resample_to(&self, x_res: usize, y_res: usize, bounds: UtmBoundingBox, sampling: MaskSampling) -> Result<Mask, LasToStlError>{
+        let mut mask = Mask::new_with_dims(x_res, y_res, bounds, self.utm_zone)?;
+        mask.datum_shift = self.datum_shift;
+
+        // how many old pixels the new pixels are from their center to their edge
+        let half_x = if mask.x_tick.is_finite() { mask.x_tick / self.x_tick / 2f64 } else { 0f64 };
+        let half_y = if mask.y_tick.is_finite() { mask.y_tick / self.y_tick / 2f64 } else { 0f64 };
+
+        for y in 0..y_res{
+            for x in 0..x_res{
+                let utm_coord = mask.get_x_y_utm_unchecked(x, y);
+                // position in old pixels
+                let old_x = (utm_coord.easting - self.bounds.min_x) / self.x_tick;
+                let old_y = (utm_coord.northing - self.bounds.min_y) / self.y_tick;
+
+                let (first_x, last_x) = ((old_x - half_x).ceil() as i64, (old_x + half_x).floor() as i64);
+                let (first_y, last_y) = ((old_y - half_y).ceil() as i64, (old_y + half_y).floor() as i64);
+
+                mask.data[y * x_res + x] = if sampling == MaskSampling::Nearest || first_x > last_x || first_y > last_y {
+                    self.get_by_xy_checked(old_x.round() as isize, old_y.round() as isize).unwrap_or_default()
+                } else {
+                    let mut num_true: usize = 0;
+                    for covered_y in first_y..=last_y{
+                        for covered_x in first_x..=last_x{
+                            if self.get_by_xy_checked(covered_x as isize, covered_y as isize).unwrap_or_default(){
+                                num_true += 1;
+                            }
+                        }
+                    }
+                    let num_covered = ((last_x - first_x + 1) * (last_y - first_y + 1)) as usize;
+                    num_true * 2 > num_covered
+                };
+            }
         }
+
+        Ok(mask)
+    }
+
+    /// Same as `resample_to`, with the resolution and bounds of `height_map`
+    pub fn resample_for_height_map(&self, height_map: &HeightMap, sampling: MaskSampling) -> Result<Mask, LasToStlError>{
+        self.resample_to(height_map.x_res, height_map.y_res, height_map.bounds, sampling)
     }
 
     /// will return an error if any of the points (with radius) have no pixels within bounds
@@ -108,7 +288,7 @@ impl Mask{
     pub fn add_trail_raw(&mut self, trail: &LineString, dot_radius: u16) -> Result<(), LasToStlError>{
         let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
         for point in trail{
-            let utm_point: UtmCoord = UtmCoord::from_gps_coord_zoned(point, self.utm_zone);
+            let utm_point: UtmCoord = self.lat_lon_to_utm_coord(point);
             let (x, y) = utm_point.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
             self.set_with_deltas(x, y, true, &deltas)?;
         }
@@ -116,33 +296,176 @@ impl Mask{
         Ok(())
     }
 
-    /// resamples and plots a LineString
+    /// plots a LineString as a continuous line `dot_radius` pixels wide on each side, no matter how far apart its points are.
+    /// Every segment is walked pixel by pixel (Bresenham) and a circle is drawn at each pixel.
+    /// Returns an error only if none of the trail is inside the mask
     pub fn add_utm_trail_auto_sample(&mut self, utm_trail: &LineString, dot_radius: u16) -> Result<(), LasToStlError>{
+        let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
+        self.add_utm_trail_line_walk(utm_trail, &deltas)
+    }
 
-        let trail_length_meters = utm_trail.euclidean_length();
-        let avg_meters_per_pixel: f64 = (self.x_tick + self.y_tick) / 2f64;
-        let trail_length_pixels = trail_length_meters / avg_meters_per_pixel;
+    /// plots a lat/lon LineString as a continuous line, see `add_utm_trail_auto_sample`
+    pub fn add_lat_lon_trail_auto_sample(&mut self, lat_lon_trail: &LineString, dot_radius: u16) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(lat_lon_trail.0.first());
 
-        let target_num_points: usize = (dot_radius as f64 / trail_length_pixels) as usize + 1;
+        let utm_trail = self.lat_lon_to_utm_linestring(lat_lon_trail);
 
-        self.add_utm_trail(&utm_trail, dot_radius, target_num_points)
+        self.add_utm_trail_auto_sample(&utm_trail, dot_radius)
     }
 
-    /// resamples and plots a LineString
-    pub fn add_lat_lon_trail_auto_sample(&mut self, lat_lon_trail: &LineString, dot_radius: u16, utm_zone: u8) -> Result<(), LasToStlError>{
+    /// plots `target_num_points` + 1 evenly spaced points along a lat/lon LineString.
+    /// Leaves gaps if they end up farther apart than the radius, `add_lat_lon_trail_auto_sample` doesn't
+    pub fn add_lat_lon_trail(&mut self, lat_lon_trail: &LineString, dot_radius: u16, target_num_points: usize) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(lat_lon_trail.0.first());
+        self.add_utm_trail(&self.lat_lon_to_utm_linestring(lat_lon_trail), dot_radius, target_num_points)
+    }
 
-        let utm_trail = linestring_to_utm_linestring(lat_lon_trail, utm_zone);
+    /// plots `target_num_points` + 1 evenly spaced points along a LineString.
+    /// Leaves gaps if they end up farther apart than the radius, `add_utm_trail_auto_sample` doesn't
+    pub fn add_utm_trail(&mut self, utm_trail: &LineString, dot_radius: u16, target_num_points: usize) -> Result<(), LasToStlError>{
+        let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
+        self.add_utm_trail_with_deltas(utm_trail, &deltas, target_num_points)
+    }
 
-        self.add_utm_trail_auto_sample(&utm_trail, dot_radius)
+    /// Same as `add_utm_trail_auto_sample`, but the trail is `dot_radius_m` meters wide on each side no matter the resolution,
+    /// even if the pixels aren't square.
+    pub fn add_utm_trail_meters(&mut self, utm_trail: &LineString, dot_radius_m: f64) -> Result<(), LasToStlError>{
+        let deltas = self.get_deltas_within_meters(dot_radius_m);
+        self.add_utm_trail_line_walk(utm_trail, &deltas)
     }
 
-    pub fn add_lat_lon_trail(&mut self, lat_lon_trail: &LineString, dot_radius: u16, target_num_points: usize, utm_zone: u8) -> Result<(), LasToStlError>{
-        self.add_utm_trail(&linestring_to_utm_linestring(&lat_lon_trail, utm_zone), dot_radius, target_num_points)
+    /// Same as `add_lat_lon_trail_auto_sample`, but with a radius in meters. See `add_utm_trail_meters`
+    pub fn add_lat_lon_trail_meters(&mut self, lat_lon_trail: &LineString, dot_radius_m: f64) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(lat_lon_trail.0.first());
+        self.add_utm_trail_meters(&self.lat_lon_to_utm_linestring(lat_lon_trail), dot_radius_m)
     }
 
-    pub fn add_utm_trail(&mut self, utm_trail: &LineString, dot_radius: u16, target_num_points: usize) -> Result<(), LasToStlError>{
+    /// Draws the outline of a polygon (the exterior and every hole) as a trail `width_m` meters wide.
+    /// Same as `add_utm_trail_meters` with half the width as the radius
+    pub fn add_utm_polygon_outline_meters(&mut self, utm_region: &Polygon, width_m: f64) -> Result<(), LasToStlError>{
+        for ring in std::iter::once(utm_region.exterior()).chain(utm_region.interiors()){
+            self.add_utm_trail_meters(ring, width_m / 2f64)?;
+        }
+        Ok(())
+    }
 
-        let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
+    /// Same as `add_utm_polygon_outline_meters`, but with a lat/lon polygon
+    pub fn add_lat_lon_polygon_outline_meters(&mut self, lat_lon_region: &Polygon, width_m: f64) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(lat_lon_region.exterior().0.first());
+        self.add_utm_polygon_outline_meters(&self.lat_lon_to_utm_polygon(lat_lon_region), width_m)
+    }
+
+    /// Same as `add_utm_point`, but with a radius in meters
+    pub fn add_utm_point_meters(&mut self, utm_coord: UtmCoord, radius_m: f64) -> Result<(), LasToStlError>{
+        let deltas = self.get_deltas_within_meters(radius_m);
+        let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
+        self.set_with_deltas(x, y, true, &deltas)
+    }
+
+    /// Same as `add_lat_lon_waypoints`, but with a radius in meters
+    pub fn add_lat_lon_waypoints_meters(&mut self, waypoints: Vec<Point>, dot_radius_m: f64) -> BatchReport{
+        let deltas = self.get_deltas_within_meters(dot_radius_m);
+        let mut report = BatchReport::new();
+        for (index, waypoint) in waypoints.into_iter().enumerate(){
+            let utm_coord = self.lat_lon_to_utm_coord(&waypoint.0);
+            let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
+            report.record(index, format_lat_lon(&waypoint), self.set_with_deltas(x, y, true, &deltas));
+        }
+        report
+    }
+
+    /// like `utils::get_point_deltas_within_radius()`, but the radius is in meters,
+    /// so it is an ellipse in pixels if `x_tick` and `y_tick` are different
+    fn get_deltas_within_meters(&self, radius_m: f64) -> Vec<(i16, i16)>{
+        let x_radius = (radius_m / self.x_tick).floor() as i16;
+        let y_radius = (radius_m / self.y_tick).floor() as i16;
+        let mut deltas: Vec<(i16, i16)> = Vec::new();
+        for x in -x_radius..=x_radius{
+            for y in -y_radius..=y_radius{
+                if (x as f64 * self.x_tick).powi(2) + (y as f64 * self.y_tick).powi(2) <= radius_m * radius_m{
+                    deltas.push((x, y))
+                }
+            }
+        }
+        // always at least the pixel the point is in
+        if deltas.is_empty(){
+            deltas.push((0, 0))
+        }
+        deltas
+    }
+
+    /// walks every segment of the trail pixel by pixel with Bresenham's line algorithm and sets `deltas` around each pixel.
+    /// Segments are clipped to the mask (grown by the size of the stamp) first, so parts far outside of it aren't walked.
+    /// Pixels outside the mask are skipped quietly, it's only an error if nothing was inside
+    fn add_utm_trail_line_walk(&mut self, utm_trail: &LineString, deltas: &[(i16, i16)]) -> Result<(), LasToStlError>{
+        let to_pixel = |coord: &Coord| -> Coord {
+            Coord{ x: (coord.x - self.bounds.min_x) / self.x_tick, y: (coord.y - self.bounds.min_y) / self.y_tick }
+        };
+        let (x_res, y_res) = (self.x_res, self.y_res);
+        let stamp = |data: &mut Vec<bool>, x: i64, y: i64| -> bool {
+            let mut any_inside = false;
+            for (delta_x, delta_y) in deltas{
+                let (new_x, new_y) = (x + *delta_x as i64, y + *delta_y as i64);
+                if new_x >= 0 && new_y >= 0 && (new_x as usize) < x_res && (new_y as usize) < y_res{
+                    data[new_y as usize * x_res + new_x as usize] = true;
+                    any_inside = true;
+                }
+            }
+            any_inside
+        };
+
+        // pixels farther out than the stamp reaches (plus one for rounding) can't set anything inside the mask
+        let reach_x = deltas.iter().map(|(delta_x, _)| delta_x.unsigned_abs()).max().unwrap_or(0) as f64 + 1f64;
+        let reach_y = deltas.iter().map(|(_, delta_y)| delta_y.unsigned_abs()).max().unwrap_or(0) as f64 + 1f64;
+        let clip_min = Coord{ x: -reach_x, y: -reach_y };
+        let clip_max = Coord{ x: (x_res - 1) as f64 + reach_x, y: (y_res - 1) as f64 + reach_y };
+
+        let pixels: Vec<Coord> = utm_trail.coords().map(to_pixel).collect();
+        // a trail with one point is a segment that goes nowhere
+        let segments: Vec<(Coord, Coord)> = match pixels.len(){
+            1 => vec![(pixels[0], pixels[0])],
+            _ => pixels.windows(2).map(|segment| (segment[0], segment[1])).collect(),
+        };
+
+        let mut any_inside = false;
+        for (start, end) in segments{
+            let Some((start, end)) = clip_segment(start, end, clip_min, clip_max) else { continue };
+            let (mut x, mut y) = (start.x.round() as i64, start.y.round() as i64);
+            let (end_x, end_y) = (end.x.round() as i64, end.y.round() as i64);
+            any_inside |= stamp(&mut self.data, x, y);
+
+            let (step_x, step_y) = (if x < end_x { 1 } else { -1 }, if y < end_y { 1 } else { -1 });
+            let (distance_x, distance_y) = ((end_x - x).abs(), -(end_y - y).abs());
+            let mut error = distance_x + distance_y;
+            while (x, y) != (end_x, end_y){
+                let doubled_error = 2 * error;
+                if doubled_error >= distance_y{
+                    error += distance_y;
+                    x += step_x;
+                }
+                if doubled_error <= distance_x{
+                    error += distance_x;
+                    y += step_y;
+                }
+                any_inside |= stamp(&mut self.data, x, y);
+            }
+        }
+
+        if any_inside || utm_trail.0.is_empty(){
+            Ok(())
+        } else {
+            let (x, y) = (pixels[0].x.round() as i64, pixels[0].y.round() as i64);
+            Err(LasToStlError::SetWithDeltaError {
+                x_res: self.x_res,
+                y_res: self.y_res,
+                x: x.max(0) as usize,
+                y: y.max(0) as usize,
+            })
+        }
+    }
+
+    /// plots `target_num_points` + 1 evenly spaced points along the trail
+    fn add_utm_trail_with_deltas(&mut self, utm_trail: &LineString, deltas: &Vec<(i16, i16)>, target_num_points: usize) -> Result<(), LasToStlError>{
         for i in 0..=target_num_points{
 
             let fraction_of_length = i as f64 / target_num_points as f64;
@@ -151,7 +474,7 @@ impl Mask{
                 Some(utm_interpolated_point) => {
                     let utm_coord = UtmCoord::new(utm_interpolated_point.x_y());
                     let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
-                    self.set_with_deltas(x, y, true, &deltas)?;
+                    self.set_with_deltas(x, y, true, deltas)?;
                 }
                 None => {
                     error!("Could not interpolate point at {:.2} of trail, Skipping point", fraction_of_length)
@@ -162,15 +485,26 @@ impl Mask{
         Ok(())
     }
 
-    /// sets all points inside the polygon to true
-    pub fn add_filled_lat_lon_polygon(&mut self, lat_lon_region: &Polygon, utm_zone: u8) -> Result<(), LasToStlError>{
+    /// sets all points inside the polygon to true, except inside its holes
+    pub fn add_filled_lat_lon_polygon(&mut self, lat_lon_region: &Polygon) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(lat_lon_region.exterior().0.first());
 
-        let utm_region = polygon_to_utm_polygon(lat_lon_region, utm_zone);
+        let utm_region = self.lat_lon_to_utm_polygon(lat_lon_region);
 
         self.add_filled_utm_polygon(&utm_region)
     }
 
+    /// sets all points inside the polygon to true, one row at a time,
+    /// so it takes about as long as the number of rows times the number of edges, not every pixel in the bounding box.
+    /// Points exactly on the west, east and south edges are set too, but not ones on a north edge (or a top corner),
+    /// since every row only counts the edges that start at or below it and end above it, so no corner is counted twice.
+    /// Points inside the polygon's holes (interior rings, like KML `innerBoundaryIs`) are left alone, by the same rule
     pub fn add_filled_utm_polygon(&mut self, utm_region: &Polygon) -> Result<(), LasToStlError>{
+        self.add_filled_utm_polygon_with_progress(utm_region, &LogProgress)
+    }
+
+    /// Same as `add_filled_utm_polygon`, but reports progress to `progress` under the stage `"rasterizing region"`
+    pub fn add_filled_utm_polygon_with_progress(&mut self, utm_region: &Polygon, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
         // get bounding rectangle to avoid checking points that arent even close
 
         let utm_bounding_rectangle = utm_region.bounding_rect().ok_or(LasToStlError::NoBoundingRectError)?;
@@ -198,83 +532,142 @@ impl Mask{
             })
         }
 
-        for x in min_x..=max_x{
-            for y in min_y..=max_y{
-                self.data[(y*self.x_res) + x] |=
-                    utm_region.contains(&Coord::from(&self.get_x_y_utm_unchecked(x, y)))
+        const STAGE: &str = "rasterizing region";
+        progress.on_stage(STAGE);
+
+        let max_y = max_y.min(self.y_res - 1);
+        let mut row: Vec<bool> = vec![false; self.x_res];
+        for y in min_y..=max_y{
+            progress.check_cancelled()?;
+            let utm_y = self.bounds.min_y + y as f64 * self.y_tick;
+
+            // fill the exterior, then clear every hole (interior ring), so holes stay false
+            // even if they overlap each other or touch the exterior
+            for (first_x, last_x) in self.get_ring_row_spans(utm_region.exterior(), utm_y){
+                row[first_x..=last_x].fill(true);
+            }
+            for hole in utm_region.interiors(){
+                for (first_x, last_x) in self.get_ring_row_spans(hole, utm_y){
+                    row[first_x..=last_x].fill(false);
+                }
+            }
+
+            for (state, row_state) in self.data[y*self.x_res..(y + 1)*self.x_res].iter_mut().zip(row.iter_mut()){
+                *state |= *row_state;
+                *row_state = false;
             }
-            if x % 512 == 0{
-                info!("region_rasterizing: {:.2}%", 100f64 * x as f64 / self.x_res as f64)
+
+            if y.is_multiple_of(512){
+                progress.on_progress(STAGE, (y - min_y) as f64 / (max_y - min_y + 1) as f64)
             }
         }
 
+        progress.on_finish(STAGE);
+
         Ok(())
     }
 
-    /// expects line_string to be in lat lon, not UTM
-    pub fn add_lat_lon_line_string_as_region(&mut self, line_string: &LineString, utm_zone: u8) -> Result<(), LasToStlError>{
-        if !line_string.is_closed(){
-            return Err(LasToStlError::OpenLineStringError)
-        }
-        let utm_line_string: LineString = linestring_to_utm_linestring(&line_string, utm_zone);
-        let utm_polygon = Polygon::new(utm_line_string, vec!());
+    /// scanline: finds where the row at `utm_y` crosses the edges of a closed ring, and returns the
+    /// (first, last) pixel of every part of the row inside it
+    fn get_ring_row_spans(&self, utm_ring: &LineString, utm_y: f64) -> Vec<(usize, usize)>{
+        let mut crossings: Vec<f64> = utm_ring.lines()
+            .filter(|line| (line.start.y <= utm_y) != (line.end.y <= utm_y))
+            .map(|line| line.start.x + (utm_y - line.start.y) / (line.end.y - line.start.y) * (line.end.x - line.start.x))
+            .collect();
+        crossings.sort_unstable_by(|a, b| a.total_cmp(b));
+
+        crossings.chunks_exact(2).filter_map(|pair| {
+            let first_x = ((pair[0] - self.bounds.min_x) / self.x_tick).ceil().max(0f64) as usize;
+            let last_x = ((pair[1] - self.bounds.min_x) / self.x_tick).floor();
+            if last_x < 0f64 || first_x >= self.x_res{
+                return None
+            }
+            let last_x = (last_x as usize).min(self.x_res - 1);
+            (first_x <= last_x).then_some((first_x, last_x))
+        }).collect()
+    }
+
+    /// Same as `add_lat_lon_line_string_as_region`, but everything inside `holes` is left out,
+    /// e.g. a property with an excluded parcel. All of them are expected to be in lat lon, and closed or within
+    /// `close_tolerance_m` meters of closed
+    pub fn add_lat_lon_line_string_as_region_with_holes(&mut self, line_string: &LineString, holes: &[LineString], close_tolerance_m: f64) -> Result<(), LasToStlError>{
+        self.warn_if_outside_zone(line_string.0.first());
+        let utm_polygon = Polygon::new(
+            close_utm_ring(self.lat_lon_to_utm_linestring(line_string), close_tolerance_m)?,
+            holes.iter()
+                .map(|hole| close_utm_ring(self.lat_lon_to_utm_linestring(hole), close_tolerance_m))
+                .collect::<Result<Vec<LineString>, LasToStlError>>()?
+        );
 
         self.add_filled_utm_polygon(&utm_polygon)
     }
 
+    /// expects line_string to be in lat lon, not UTM.
+    ///
+    /// Hand drawn boundaries often don't quite end where they start. If the last point is within `close_tolerance_m`
+    /// meters of the first one, it is moved onto the first one to close it, otherwise it returns `LasToStlError::OpenLineStringError`.
+    /// Use 0 to only accept line strings that are already closed
+    pub fn add_lat_lon_line_string_as_region(&mut self, line_string: &LineString, close_tolerance_m: f64) -> Result<(), LasToStlError>{
+        self.add_lat_lon_line_string_as_region_with_holes(line_string, &[], close_tolerance_m)
+    }
+
     /// adds a GEO point with the specified radius.
     /// If adding multiple points please use `add_waypoints` instead to avoid recalculating deltas
     /// returns an error if none of the pixels in or on the radius are within bounds of the mask.
     pub fn add_lat_lon_waypoint(&mut self, waypoint: Point, radius: u16) -> Result<(), LasToStlError>{
         let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(radius);
 
-        let utm_coord = UtmCoord::from_lat_lon_point_zoned(&waypoint, self.utm_zone);
+        let utm_coord = self.lat_lon_to_utm_coord(&waypoint.0);
 
         let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
         self.set_with_deltas(x, y, true, &deltas)
     }
 
-    /// adds a list of geo points with a specified radius
-    /// returns an error if: for any of the points, none of the pixels in or on the radius are within bounds of the mask.
-    pub fn add_lat_lon_waypoints(&mut self, waypoints: Vec<Point>, dot_radius: u16) -> Result<(), LasToStlError>{
+    /// adds a list of geo points with a specified radius.
+    /// Points where none of the pixels in or on the radius are within bounds of the mask are skipped and listed in the report.
+    pub fn add_lat_lon_waypoints(&mut self, waypoints: Vec<Point>, dot_radius: u16) -> BatchReport{
         let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
-        for waypoint in waypoints{
+        let mut report = BatchReport::new();
+        for (index, waypoint) in waypoints.into_iter().enumerate(){
 
-            let utm_coord = UtmCoord::from_lat_lon_point_zoned(&waypoint, self.utm_zone);
+            let utm_coord = self.lat_lon_to_utm_coord(&waypoint.0);
 
             let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
-            self.set_with_deltas(x, y, true, &deltas)?
+            report.record(index, format_lat_lon(&waypoint), self.set_with_deltas(x, y, true, &deltas));
         }
-        Ok(())
+        report
     }
 
 
     /// adds a UTM coordinate with the specified radius.
     /// If adding multiple points please use `add_utm_points` instead to avoid recalculating deltas
-    /// returns an error if none of the pixels in or on the radius are within bounds of the mask.
+    /// returns an error if none of the pixels in or on the radius are within bounds of the mask,
+    /// or the point knows its UTM zone and it isn't the zone of the mask.
     pub fn add_utm_point(&mut self, utm_coord: UtmCoord, radius: u16) -> Result<(), LasToStlError>{
+        utm_coord.check_zone(&self.utm_zone)?;
         let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(radius);
         let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
         self.set_with_deltas(x, y, true, &deltas)
     }
 
-    /// adds a vec of UTM points with specified radius
-    /// returns an error if: for any of the points, none of the pixels in or on the radius are within bounds of the mask.
-    pub fn add_utm_points(&mut self, utm_coords: Vec<UtmCoord>, dot_radius: u16) -> Result<(), LasToStlError>{
+    /// adds a vec of UTM points with specified radius.
+    /// Points where none of the pixels in or on the radius are within bounds of the mask,
+    /// or that know their UTM zone and it isn't the zone of the mask, are skipped and listed in the report.
+    pub fn add_utm_points(&mut self, utm_coords: Vec<UtmCoord>, dot_radius: u16) -> BatchReport{
         let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(dot_radius);
-        for utm_coord in utm_coords{
+        let mut report = BatchReport::new();
+        for (index, utm_coord) in utm_coords.into_iter().enumerate(){
             let (x, y) = utm_coord.get_x_y_coords(self.bounds.min_x, self.bounds.min_y, self.x_tick, self.y_tick);
-            self.set_with_deltas(x, y, true, &deltas)?
+            let result = utm_coord.check_zone(&self.utm_zone).and_then(|_| self.set_with_deltas(x, y, true, &deltas));
+            report.record(index, format!("{} E {} N", utm_coord.easting, utm_coord.northing), result);
         }
-        Ok(())
+        report
     }
 
-    /// Bounds and resolution must match
-    pub fn checked_bitor_assign(&mut self, other_mask: &Mask) -> Result<(), LasToStlError> {
-        if self.x_res == other_mask.x_res && self.y_res == other_mask.y_res && self.bounds == other_mask.bounds{
-            for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-                *own_state |= *other_state;
-            }
+    /// errors if `other_mask` doesn't have the same x/y bounds, resolution and UTM zone as self
+    fn check_matches(&self, other_mask: &Mask) -> Result<(), LasToStlError> {
+        self.utm_zone.check_matches(&other_mask.utm_zone)?;
+        if self.x_res == other_mask.x_res && self.y_res == other_mask.y_res && self.bounds.eq_xy(&other_mask.bounds){
             Ok(())
         } else {
             Err(LasToStlError::MaskBoundMismatchError {
@@ -282,71 +675,105 @@ impl Mask{
                 other_y_res:other_mask.y_res,
                 mask_x_res: self.x_res,
                 mask_y_res: self.y_res,
-                other_bounds: other_mask.bounds,
-                mask_bounds: self.bounds,
+                other_bounds: Box::new(other_mask.bounds),
+                mask_bounds: Box::new(self.bounds),
             })
         }
     }
 
-    /// Bounds and resolution must match
+    /// Bounds, resolution and UTM zone must match
+    pub fn checked_bitor_assign(&mut self, other_mask: &Mask) -> Result<(), LasToStlError> {
+        self.check_matches(other_mask)?;
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state |= *other_state);
+        Ok(())
+    }
+
+    /// Bounds, resolution and UTM zone must match
     pub fn checked_bitand_assign(&mut self, other_mask: &Mask) -> Result<(), LasToStlError> {
-        if self.x_res == other_mask.x_res && self.y_res == other_mask.y_res && self.bounds == other_mask.bounds{
-            for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-                *own_state &= *other_state;
-            }
-            Ok(())
-        } else {
-            Err(LasToStlError::MaskBoundMismatchError {
-                other_x_res: other_mask.x_res,
-                other_y_res:other_mask.y_res,
-                mask_x_res: self.x_res,
-                mask_y_res: self.y_res,
-                other_bounds: other_mask.bounds,
-                mask_bounds: self.bounds,
-            })
-        }
+        self.check_matches(other_mask)?;
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state &= *other_state);
+        Ok(())
     }
 
-    /// Bounds and resolution must match
+    /// Bounds, resolution and UTM zone must match
     pub fn checked_bitxor_assign(&mut self, other_mask: &Mask) -> Result<(), LasToStlError> {
-        if self.x_res == other_mask.x_res && self.y_res == other_mask.y_res && self.bounds == other_mask.bounds{
-            for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-                *own_state ^= *other_state;
-            }
-            Ok(())
-        } else {
-            Err(LasToStlError::MaskBoundMismatchError {
-                other_x_res: other_mask.x_res,
-                other_y_res:other_mask.y_res,
-                mask_x_res: self.x_res,
-                mask_y_res: self.y_res,
-                other_bounds: other_mask.bounds,
-                mask_bounds: self.bounds,
-            })
-        }
+        self.check_matches(other_mask)?;
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state ^= *other_state);
+        Ok(())
     }
 
+    /// Bounds, resolution and UTM zone must match
     pub fn checked_sub_assign(&mut self, other_mask: &Mask) -> Result<(), LasToStlError> {
-        if self.x_res == other_mask.x_res && self.y_res == other_mask.y_res && self.bounds == other_mask.bounds{
-            for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-                *own_state = *own_state && !*other_state;
-            }
-            Ok(())
-        } else {
-            Err(LasToStlError::MaskBoundMismatchError {
-                other_x_res: other_mask.x_res,
-                other_y_res:other_mask.y_res,
-                mask_x_res: self.x_res,
-                mask_y_res: self.y_res,
-                other_bounds: other_mask.bounds,
-                mask_bounds: self.bounds,
-            })
-        }
+        self.check_matches(other_mask)?;
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state = *own_state && !*other_state);
+        Ok(())
     }
 
     /// inverts the mask... Duh
     pub fn invert(&mut self){
-        self.data.iter_mut().for_each(|p| { *p = !*p })
+        par_for_each(&mut self.data, |p| { *p = !*p })
+    }
+
+    /// Widens everything in the mask by `radius` pixels, like drawing a circle of `radius` around every set pixel.
+    /// Use it to make a trail or region wider without rasterizing the KML again.
+    pub fn dilate(&mut self, radius: u16){
+        self.spread(true, radius);
+    }
+
+    /// Shrinks everything in the mask by `radius` pixels, the opposite of `dilate`.
+    /// Anything thinner than 2 * `radius` + 1 pixels disappears. Outside the mask counts as set, so a region that
+    /// touches the edge isn't eaten away from there.
+    pub fn erode(&mut self, radius: u16){
+        self.spread(false, radius);
+    }
+
+    /// `erode` then `dilate` by `radius`, which removes specks and thin spurs smaller than the radius
+    /// while leaving bigger shapes about the same size
+    pub fn open(&mut self, radius: u16){
+        self.erode(radius);
+        self.dilate(radius);
+    }
+
+    /// `dilate` then `erode` by `radius`, which fills pinholes and small gaps (like the ones polygon rasterization
+    /// can leave) while leaving bigger shapes about the same size. Do this before using a mask as an STL boundary,
+    /// single pixel holes turn into broken walls
+    pub fn close(&mut self, radius: u16){
+        self.dilate(radius);
+        self.erode(radius);
+    }
+
+    /// Creates a mask of only the edge of everything in this mask, the true pixels within `thickness` pixels of a false one.
+    /// e.g. to engrave a property line instead of offsetting the whole property.
+    /// Like `erode`, the edge of the mask itself doesn't count as an edge
+    pub fn outline(&self, thickness: u16) -> Mask{
+        let mut outline = Mask::new_with_dims_unchecked(self.x_res, self.y_res, self.bounds, self.utm_zone);
+        outline.datum_shift = self.datum_shift;
+        outline.data.clone_from(&self.data);
+        outline.erode(thickness);
+        for (outline_state, state) in outline.data.iter_mut().zip(self.data.iter()){
+            *outline_state = *state && !*outline_state;
+        }
+        outline
+    }
+
+    /// sets every pixel within `radius` of a pixel that is `state` to `state`
+    fn spread(&mut self, state: bool, radius: u16){
+        let deltas: Vec<(i16, i16)> = get_point_deltas_within_radius(radius);
+        let mut new_data = self.data.clone();
+        for y in 0..self.y_res{
+            for x in 0..self.x_res{
+                if self.data[y * self.x_res + x] != state{
+                    continue
+                }
+                for (delta_x, delta_y) in &deltas{
+                    let (new_x, new_y) = (x as isize + *delta_x as isize, y as isize + *delta_y as isize);
+                    if new_x >= 0 && new_y >= 0 && (new_x as usize) < self.x_res && (new_y as usize) < self.y_res{
+                        new_data[new_y as usize * self.x_res + new_x as usize] = state;
+                    }
+                }
+            }
+        }
+        self.data = new_data;
     }
 
     /// neighbors are in the order of the following relative coordinates:
@@ -361,14 +788,7 @@ impl Mask{
         ];
 
         MAP.iter().map(|coord| {
-            match self.get_by_xy_checked(x as isize + coord.0, y as isize + coord.1){
-                Ok(s) => {
-                    s
-                }
-                Err(_) => {
-                    false
-                }
-            }
+            self.get_by_xy_checked(x as isize + coord.0, y as isize + coord.1).unwrap_or_default()
         }).collect::<Vec<bool>>().try_into().unwrap() // I don't think this can produce an error because the length must be 9.
     }
 
@@ -377,7 +797,7 @@ impl Mask{
     /// The points outside will still be marginally valid,
     /// but for most applications this indicates an error in the parameters.
     pub fn get_x_y_utm_unchecked(&self, x: usize, y: usize) -> UtmCoord{
-        UtmCoord::from(((x as f64 * self.x_tick) + self.bounds.min_x, (y as f64 * self.y_tick) + self.bounds.min_y))
+        UtmCoord::new_in_zone(((x as f64 * self.x_tick) + self.bounds.min_x, (y as f64 * self.y_tick) + self.bounds.min_y), self.utm_zone)
     }
 
     /// gets the UTM coordinates of the specified point in pixel space
@@ -415,6 +835,49 @@ impl Mask{
         }
     }
 
+    /// Gets the smallest rectangle of pixels with every true pixel in it, as (min_x, min_y, max_x, max_y), all inclusive.
+    /// `None` if nothing is true
+    pub fn true_bounds(&self) -> Option<(usize, usize, usize, usize)>{
+        let mut true_bounds: Option<(usize, usize, usize, usize)> = None;
+        for (index, state) in self.data.iter().enumerate(){
+            if *state{
+                let (x, y) = (index % self.x_res, index / self.x_res);
+                true_bounds = Some(match true_bounds{
+                    Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        true_bounds
+    }
+
+    /// Same as `true_bounds`, but in UTM, with the z range of the mask.
+    /// e.g. `hm.crop(&mask.true_utm_bounds()?)` to crop a heightmap down to a region
+    pub fn true_utm_bounds(&self) -> Option<UtmBoundingBox>{
+        let (min_x, min_y, max_x, max_y) = self.true_bounds()?;
+        let min_utm = self.get_x_y_utm_unchecked(min_x, min_y);
+        let max_utm = self.get_x_y_utm_unchecked(max_x, max_y);
+        Some(UtmBoundingBox::new_in_zone(min_utm.easting, max_utm.easting, min_utm.northing, max_utm.northing, self.bounds.min_z, self.bounds.max_z, self.utm_zone))
+    }
+
+    /// Crops the mask down to `true_bounds`, moving `bounds` with it. The pixels stay the same size.
+    /// Returns false (and doesn't change anything) if nothing is true
+    pub fn trim(&mut self) -> bool{
+        let Some((min_x, min_y, max_x, max_y)) = self.true_bounds() else {
+            return false
+        };
+        let bounds = self.true_utm_bounds().unwrap_or(self.bounds);
+
+        let new_x_res = max_x - min_x + 1;
+        self.data = (min_y..=max_y)
+            .flat_map(|y| self.data[y*self.x_res + min_x..y*self.x_res + min_x + new_x_res].to_vec())
+            .collect();
+        self.x_res = new_x_res;
+        self.y_res = max_y - min_y + 1;
+        self.bounds = bounds;
+        true
+    }
+
     pub fn get_percent_coverage(&self) -> f64{
         let mut num_true: u64 = 0;
         for state in &self.data{
@@ -435,9 +898,7 @@ impl BitOrAssign for Mask{
     /// Resolutions MUST match and while mismatched bounds technically aren't a problem,
     /// please think deeply about what it means to takes a mask from one region and apply a binary operation on a mask somewhere else.
     fn bitor_assign(&mut self, other_mask: Mask) {
-        for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-            *own_state |= *other_state;
-        }
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state |= *other_state);
     }
 }
 
@@ -448,9 +909,7 @@ impl BitAndAssign for Mask{
     /// Resolutions MUST match and while mismatched bounds technically aren't a problem,
     /// please think deeply about what it means to takes a mask from one region and apply a binary operation on a mask somewhere else.
     fn bitand_assign(&mut self, other_mask: Self) {
-        for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-            *own_state &= *other_state;
-        }
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state &= *other_state);
     }
 }
 
@@ -461,16 +920,172 @@ impl BitXorAssign for Mask{
     /// Resolutions MUST match and while mismatched bounds technically aren't a problem,
     /// please think deeply about what it means to takes a mask from one region and apply a binary operation on a mask somewhere else.
     fn bitxor_assign(&mut self, other_mask: Self) {
-        for (own_state, other_state) in self.data.iter_mut().zip(other_mask.data.iter()){
-            *own_state ^= *other_state;
-        }
+        par_zip_for_each(&mut self.data, &other_mask.data, |own_state, other_state| *own_state ^= *other_state);
     }
 }
 
 impl SubAssign for Mask{
     fn sub_assign(&mut self, rhs: Self) {
-        for (own_state, other_state) in self.data.iter_mut().zip(rhs.data.iter()){
-            *own_state = *own_state && !*other_state;
+        par_zip_for_each(&mut self.data, &rhs.data, |own_state, other_state| *own_state = *own_state && !*other_state);
+    }
+}
+
+/// closes a UTM ring by moving its last point onto the first if they are within `close_tolerance_m` meters of each other
+fn close_utm_ring(mut utm_ring: LineString, close_tolerance_m: f64) -> Result<LineString, LasToStlError>{
+    if utm_ring.is_closed(){
+        return Ok(utm_ring)
+    }
+    let (first, last) = (utm_ring.0[0], utm_ring.0[utm_ring.0.len() - 1]);
+    let gap = ((last.x - first.x).powi(2) + (last.y - first.y).powi(2)).sqrt();
+    if gap <= close_tolerance_m{
+        trace!("closing a ring that was open by {gap:.2} m");
+        let last_index = utm_ring.0.len() - 1;
+        utm_ring.0[last_index] = first;
+        Ok(utm_ring)
+    } else {
+        Err(LasToStlError::OpenLineStringError)
+    }
+}
+
+/// clips the segment from `start` to `end` to the rectangle from `min` to `max` (Liang-Barsky).
+/// Returns the part inside, or `None` if none of it is
+fn clip_segment(start: Coord, end: Coord, min: Coord, max: Coord) -> Option<(Coord, Coord)>{
+    let delta = end - start;
+    let (mut t_start, mut t_end) = (0f64, 1f64);
+    for (direction, distance) in [(-delta.x, start.x - min.x), (delta.x, max.x - start.x), (-delta.y, start.y - min.y), (delta.y, max.y - start.y)]{
+        if direction == 0f64 {
+            // parallel to this edge, so it's all inside or all outside of it
+            if distance < 0f64 {
+                return None
+            }
+        } else if direction < 0f64 {
+            t_start = f64_max(t_start, distance / direction);
+        } else {
+            t_end = f64_min(t_end, distance / direction);
+        }
+    }
+    (t_start <= t_end).then(|| (start + delta * t_start, start + delta * t_end))
+}
+
+/// a lat/lon point as "lat, lon", for skipped waypoints in a `BatchReport`
+fn format_lat_lon(point: &Point) -> String{
+    format!("{}, {}", point.y(), point.x())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::Contains;
+
+    /// 10x10 cells a meter apart, flat on the west half and a steep ramp (2 meters up per meter) on the east half
+    fn ramp_height_map() -> HeightMap {
+        let data = (0..100).map(|index| 10f64 + ((index % 10) as f64 - 4f64).max(0f64) * 2f64).collect();
+        HeightMap {
+            data,
+            x_res: 10,
+            y_res: 10,
+            bounds: UtmBoundingBox::new_in_zone(500000f64, 500009f64, 4000000f64, 4000009f64, 0f64, 20f64, UtmZone::new(10, true)),
+            utm_zone: Some(UtmZone::new(10, true)),
+            geoid_correction: None,
+            crs: None,
+        }
+    }
+
+    #[test]
+    fn slope_mask_applies_to_its_source_map() {
+        let mut height_map = ramp_height_map();
+        let steep = Mask::from_height_map(&height_map.slope_degrees(), |slope| slope > 30f64).unwrap();
+        for (index, is_steep) in steep.data.iter().enumerate() {
+            assert_eq!(*is_steep, index % 10 >= 4, "cell {index}");
+        }
+
+        let mut combined = Mask::new_for_height_map(&height_map).unwrap();
+        combined.checked_bitor_assign(&steep).unwrap();
+        height_map.set_by_mask(&combined, 5f64).unwrap();
+        assert_eq!(height_map.data[9], 5f64);
+        assert_eq!(height_map.data[0], 10f64);
+    }
+
+    #[test]
+    fn masks_with_different_x_y_bounds_dont_match() {
+        let mut height_map = ramp_height_map();
+        let mut mask = Mask::new_for_height_map(&height_map).unwrap();
+        mask.bounds.max_x += 1f64;
+        assert!(matches!(height_map.offset_by_mask(&mask, 1f64), Err(LasToStlError::MaskBoundMismatchError{ .. })));
+    }
+
+    #[test]
+    fn filled_polygons_include_every_edge_but_the_north_one() {
+        let bounds = UtmBoundingBox::new(0f64, 9f64, 0f64, 9f64, 0f64, 0f64);
+        let mut mask = Mask::new_with_dims(10, 10, bounds, UtmZone::new(10, true)).unwrap();
+        let ring = |min: f64, max: f64, max_y: f64| LineString::from(vec![(min, min), (max, min), (max, max_y), (min, max_y), (min, min)]);
+        let polygon = Polygon::new(ring(2f64, 6f64, 5f64), vec![ring(3f64, 4f64, 4f64)]);
+        mask.add_filled_utm_polygon(&polygon).unwrap();
+
+        for y in 0..10 {
+            for x in 0..10 {
+                let in_exterior = (2..=6).contains(&x) && (2..5).contains(&y);
+                let in_hole = (3..=4).contains(&x) && y == 3;
+                assert_eq!(mask.data[y * 10 + x], in_exterior && !in_hole, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn grids_need_at_least_two_cells_each_way() {
+        let bounds = UtmBoundingBox::new(0f64, 10f64, 0f64, 10f64, 0f64, 0f64);
+        let zone = UtmZone::new(10, true);
+        assert!(matches!(Mask::new_with_dims(1, 5, bounds, zone), Err(LasToStlError::ResolutionTooSmallError{ x_res: 1, y_res: 5 })));
+        assert!(Mask::new_with_dims(2, 2, bounds, zone).is_ok());
+        assert!(matches!(crate::height_map::HeightMapIntermediate::new(5, 1, bounds), Err(LasToStlError::ResolutionTooSmallError{ .. })));
+    }
+
+    #[test]
+    fn trails_walked_across_a_diagonal_have_no_gaps() {
+        let bounds = UtmBoundingBox::new(0f64, 9f64, 0f64, 9f64, 0f64, 0f64);
+        let mut mask = Mask::new_with_dims(10, 10, bounds, UtmZone::new(10, true)).unwrap();
+        mask.add_utm_trail_auto_sample(&LineString::from(vec![(0f64, 0f64), (9f64, 3f64), (9f64, 9f64)]), 0).unwrap();
+
+        for x in 0..10 {
+            assert!((0..10).any(|y| mask.data[y * 10 + x]), "column {x} was skipped");
+        }
+        for y in 3..10 {
+            assert!(mask.data[y * 10 + 9], "pixel (9, {y}) on the north leg was skipped");
+        }
+        assert_eq!(mask.data.iter().filter(|is_set| **is_set).count(), 16, "only one pixel per step");
+    }
+
+    #[test]
+    fn trails_far_outside_the_mask_are_clipped_not_walked() {
+        let bounds = UtmBoundingBox::new(0f64, 9f64, 0f64, 9f64, 0f64, 0f64);
+        let mut mask = Mask::new_with_dims(10, 10, bounds, UtmZone::new(10, true)).unwrap();
+        // walking this pixel by pixel would take forever
+        mask.add_utm_trail_auto_sample(&LineString::from(vec![(-1e12, 5f64), (1e12, 5f64)]), 1).unwrap();
+
+        for y in 0..10 {
+            for x in 0..10 {
+                assert_eq!(mask.data[y * 10 + x], (4..=6).contains(&y), "pixel ({x}, {y})");
+            }
         }
+        let far_away = LineString::from(vec![(-1e12, -1e12), (-1e12, 1e12)]);
+        assert!(mask.add_utm_trail_auto_sample(&far_away, 1).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn scanline_fill_matches_point_in_polygon() {
+        let bounds = UtmBoundingBox::new(0f64, 19f64, 0f64, 19f64, 0f64, 0f64);
+        let mut mask = Mask::new_with_dims(20, 20, bounds, UtmZone::new(10, true)).unwrap();
+        // concave, with a hole, and no pixel right on an edge so there's no question which side it's on
+        let exterior = LineString::from(vec![(1.3, 1.1), (18.2, 2.7), (9.6, 9.4), (17.7, 18.3), (2.1, 16.9), (1.3, 1.1)]);
+        let hole = LineString::from(vec![(3.4, 10.2), (6.6, 10.3), (5.1, 14.6), (3.4, 10.2)]);
+        let polygon = Polygon::new(exterior, vec![hole]);
+        mask.add_filled_utm_polygon(&polygon).unwrap();
+
+        for y in 0..20 {
+            for x in 0..20 {
+                let inside = polygon.contains(&Coord{ x: x as f64, y: y as f64 });
+                assert_eq!(mask.data[y * 20 + x], inside, "pixel ({x}, {y})");
+            }
+        }
+    }
+}
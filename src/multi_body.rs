@@ -0,0 +1,88 @@
+use log::debug;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::mask::Mask;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::{BaseOptions, IndexedMesh, StlOptions};
+use crate::three_mf::{write_3mf, ThreeMfObject};
+
+/// Something printed as its own body (in its own color) on top of the terrain, like a trail, a lake or the waypoints
+pub struct BodyLayer<'a> {
+    /// used for the file name or the 3MF object name
+    pub name: String,
+    /// where the body is. Must have the same resolution and x/y bounds as the heightmap
+    pub mask: &'a Mask,
+    /// how thick the body is, in model units. The terrain is carved out this much under it so they fit together
+    pub thickness: f32,
+}
+
+impl HeightMap {
+    /// Splits the model into the terrain and one body for every layer, so multi material printers can print them in different colors.
+    /// Every layer is a shell `thickness` thick that follows the surface where its mask is true, and the terrain is carved out
+    /// under it so the bodies fit together without overlapping. Layers are done in order, so if two overlap,
+    /// the later one ends up under the earlier one.
+    ///
+    /// The first mesh is the terrain, then one per layer, each with its name.
+    /// Returns `LasToStlError::MaskBoundMismatchError` if a mask doesn't match the heightmap
+    pub fn get_multi_body_meshes(&self, layers: &[BodyLayer], options: &StlOptions) -> Result<Vec<(String, IndexedMesh)>, LasToStlError>{
+        self.get_multi_body_meshes_with_progress(layers, options, &LogProgress)
+    }
+
+    /// Same as `get_multi_body_meshes`, but reports progress to `progress`
+    pub fn get_multi_body_meshes_with_progress(&self,
+                                               layers: &[BodyLayer],
+                                               options: &StlOptions,
+                                               progress: &dyn ProgressSink) -> Result<Vec<(String, IndexedMesh)>, LasToStlError>{
+        // clipped before carving, or the carved cells would be clamped back up
+        if let Some((clipped, options)) = self.clip_for_stl(options)? {
+            return clipped.get_multi_body_meshes_with_progress(layers, &options, progress)
+        }
+        self.check_meshable(options)?;
+        for layer in layers {
+            self.check_mask_matches(layer.mask)?;
+        }
+
+        let (_, _, z_unit) = self.get_stl_units(options);
+        let mut carved = self.with_grid(self.data.clone(), self.x_res, self.y_res, self.bounds);
+
+        let mut meshes: Vec<(String, IndexedMesh)> = Vec::with_capacity(layers.len() + 1);
+        for layer in layers {
+            debug!("building body {}", layer.name);
+            let layer_options = StlOptions {
+                shell_thickness: Some(layer.thickness),
+                max_error: None,
+                base: BaseOptions {
+                    frame: None,
+                    ..options.base.clone()
+                },
+                ..options.clone()
+            };
+            let mesh = carved.get_mesh_masked_with_progress(layer.mask, &layer_options, progress)?;
+            meshes.push((layer.name.clone(), mesh));
+            carved.offset_by_mask(layer.mask, -(layer.thickness as f64) / z_unit)?;
+        }
+
+        meshes.insert(0, (String::from("terrain"), carved.get_mesh_with_progress(options, progress)?));
+        Ok(meshes)
+    }
+
+    /// Saves every body of `get_multi_body_meshes` as its own STL, `{path_prefix}_terrain.stl` and `{path_prefix}_{layer name}.stl`.
+    /// Returns the paths of every file that was saved
+    pub fn save_as_stl_multi_body(&self, path_prefix: &str, layers: &[BodyLayer], options: &StlOptions) -> Result<Vec<String>, LasToStlError>{
+        self.get_multi_body_meshes(layers, options)?.into_iter().map(|(name, mesh)| {
+            let path = format!("{path_prefix}_{name}.stl");
+            options.overwrite.save(&path, |file| mesh.write_stl(file, options.format))?;
+            Ok(path)
+        }).collect()
+    }
+
+    /// Saves every body of `get_multi_body_meshes` as its own object in one 3MF file. See `save_as_3mf`
+    pub fn save_as_3mf_multi_body(&self, path: &str, layers: &[BodyLayer], options: &StlOptions) -> Result<(), LasToStlError>{
+        let meshes = self.get_multi_body_meshes(layers, options)?;
+        let objects: Vec<ThreeMfObject> = meshes.iter().map(|(name, mesh)| ThreeMfObject {
+            name: name.clone(),
+            mesh,
+        }).collect();
+        options.overwrite.save(path, |file| write_3mf(file, &objects, &self.get_3mf_metadata(options)))
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use las::Point;
+
+/// ASPRS classification code for ground points
+pub const CLASSIFICATION_GROUND: u8 = 2;
+
+/// ASPRS classification code for low points (noise)
+pub const CLASSIFICATION_LOW_NOISE: u8 = 7;
+
+/// ASPRS classification code for water points
+pub const CLASSIFICATION_WATER: u8 = 9;
+
+/// ASPRS classification code for high noise (birds, clouds, ...). Only in LAS 1.4
+pub const CLASSIFICATION_HIGH_NOISE: u8 = 18;
+
+/// Which returns of each laser pulse to keep.
+///
+/// A single pulse can produce several returns (e.g. one off a treetop and one off the ground).
+/// The first return is usually the highest surface (canopy, roofs) and the last return is usually the ground.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReturnFilter {
+    /// keep every return
+    #[default]
+    All,
+    /// keep only the first return of each pulse, for canopy / surface models
+    FirstOnly,
+    /// keep only the last return of each pulse, which is usually the ground
+    LastOnly,
+}
+
+impl ReturnFilter {
+    /// returns true if the point's return number passes the filter
+    pub fn accepts(&self, point: &Point) -> bool {
+        match self {
+            ReturnFilter::All => {
+                true
+            }
+            ReturnFilter::FirstOnly => {
+                point.return_number <= 1
+            }
+            ReturnFilter::LastOnly => {
+                point.return_number >= point.number_of_returns
+            }
+        }
+    }
+}
+
+/// A user supplied function that returns true for points that should be kept.
+/// Files are read in parallel, so it must be `Send + Sync`.
+pub type CustomPointFilter = Arc<dyn Fn(&Point) -> bool + Send + Sync>;
+
+/// Decides which points from LAS/LAZ files get binned into a heightmap.
+///
+/// The default filter keeps every point, which is the same as not filtering at all.
+/// Buildings and vegetation will show up in the heightmap with the default filter,
+/// so use `PointFilter::bare_earth()` if you only want the terrain.
+#[derive(Clone, Default)]
+pub struct PointFilter {
+    /// LAS classification codes to keep (ground = 2, water = 9, etc.).
+    /// `None` keeps points regardless of classification.
+    ///
+    /// see [the LAS spec](https://www.asprs.org/wp-content/uploads/2019/07/LAS_1_4_r15.pdf) for a list of codes
+    pub classifications: Option<Vec<u8>>,
+
+    /// LAS classification codes to drop, even if they are in `classifications`
+    pub excluded_classifications: Vec<u8>,
+
+    /// which returns of each pulse to keep. See `ReturnFilter`
+    pub returns: ReturnFilter,
+
+    /// any other check you want to do on each point (scan angle, GPS time, intensity, ...).
+    /// Only called on points that passed the rest of the filter
+    pub custom: Option<CustomPointFilter>,
+}
+
+impl PointFilter {
+    /// Creates a filter that only keeps points with one of the given classification codes
+    pub fn with_classifications(classifications: Vec<u8>) -> Self {
+        PointFilter {
+            classifications: Some(classifications),
+            ..PointFilter::default()
+        }
+    }
+
+    /// Creates a filter that keeps ground and water points to get a bare-earth DEM.
+    ///
+    /// Note that not all LAS files are classified, and unclassified files will produce an empty heightmap with this filter
+    pub fn bare_earth() -> Self {
+        PointFilter::with_classifications(vec![CLASSIFICATION_GROUND, CLASSIFICATION_WATER])
+    }
+
+    /// Creates a filter that drops points classified as low or high noise, which are usually birds or sensor glitches.
+    /// For noise that wasn't classified, see `OutlierRejection`
+    pub fn without_noise() -> Self {
+        PointFilter {
+            excluded_classifications: vec![CLASSIFICATION_LOW_NOISE, CLASSIFICATION_HIGH_NOISE],
+            ..PointFilter::default()
+        }
+    }
+
+    /// Creates a filter that only keeps the given returns of each pulse, regardless of classification
+    pub fn with_returns(returns: ReturnFilter) -> Self {
+        PointFilter {
+            returns,
+            ..PointFilter::default()
+        }
+    }
+
+    /// Creates a filter that only keeps points for which `custom` returns true
+    pub fn with_custom<F>(custom: F) -> Self
+        where F: Fn(&Point) -> bool + Send + Sync + 'static
+    {
+        PointFilter {
+            custom: Some(Arc::new(custom)),
+            ..PointFilter::default()
+        }
+    }
+
+    /// returns true if the point passes every part of the filter and should be binned
+    pub fn accepts(&self, point: &Point) -> bool {
+        let classification = u8::from(point.classification);
+        let classification_ok = !self.excluded_classifications.contains(&classification) && match &self.classifications {
+            Some(classifications) => {
+                classifications.contains(&classification)
+            }
+            None => {
+                true
+            }
+        };
+
+        classification_ok && self.returns.accepts(point) && match &self.custom {
+            Some(custom) => {
+                custom(point)
+            }
+            None => {
+                true
+            }
+        }
+    }
+}
+
+impl Debug for PointFilter {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("PointFilter")
+            .field("classifications", &self.classifications)
+            .field("excluded_classifications", &self.excluded_classifications)
+            .field("returns", &self.returns)
+            .field("custom", &self.custom.as_ref().map(|_| "<closure>"))
+            .finish()
+    }
+}
@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+
+/// Receives progress updates from long operations (loading LAS files, rasterizing regions, saving STLs)
+/// so callers can drive a GUI progress bar, indicatif, etc.
+///
+/// Loading LAS files reads several files at once, so these can be called from multiple threads at the same time.
+/// Use interior mutability if your implementation needs to change state.
+pub trait ProgressSink: Sync {
+    /// called when a stage of the operation starts (e.g. reading a file)
+    fn on_stage(&self, stage: &str);
+
+    /// called periodically during a stage with the fraction (0 to 1) of the stage that is done
+    fn on_progress(&self, stage: &str, fraction: f64);
+
+    /// called when a stage of the operation is done
+    fn on_finish(&self, stage: &str);
+
+    /// called for everything that isn't a stage starting, progressing or finishing: files being read or skipped, and warnings.
+    /// The default logs them like `LogProgress`, so implementing it is optional
+    fn on_event(&self, event: &PipelineEvent) {
+        log_event(event);
+    }
+
+    /// Checked every so often by long operations (every chunk of LAS points, every row of a polygon, every column of an STL),
+    /// which stop with `LasToStlError::CancelledError` once it's true. Never cancelled by default, see `CancellableProgress`
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    /// `Err(LasToStlError::CancelledError)` if `is_cancelled`
+    fn check_cancelled(&self) -> Result<(), LasToStlError> {
+        match self.is_cancelled() {
+            true => Err(LasToStlError::CancelledError),
+            false => Ok(()),
+        }
+    }
+}
+
+/// A flag to stop a long operation from another thread (like a cancel button), see `CancellableProgress`.
+/// Clones share the same flag
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// asks everything using this token to stop as soon as it can
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Passes every update on to `progress`, and cancels the operation once `token` is cancelled.
+///
+/// Cancelled operations return `LasToStlError::CancelledError` without saving anything. A LAS load with a checkpoint
+/// keeps the checkpoint, so it can be resumed later
+#[derive(Clone, Debug)]
+pub struct CancellableProgress<S: ProgressSink> {
+    pub progress: S,
+    pub token: CancellationToken,
+}
+
+impl<S: ProgressSink> ProgressSink for CancellableProgress<S> {
+    fn on_stage(&self, stage: &str) {
+        self.progress.on_stage(stage);
+    }
+
+    fn on_progress(&self, stage: &str, fraction: f64) {
+        self.progress.on_progress(stage, fraction);
+    }
+
+    fn on_finish(&self, stage: &str) {
+        self.progress.on_finish(stage);
+    }
+
+    fn on_event(&self, event: &PipelineEvent) {
+        self.progress.on_event(event);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled() || self.progress.is_cancelled()
+    }
+}
+
+/// Everything a long operation reports, as data, so it can be sent to a TUI or a web UI (it's serializable) instead of only logged.
+/// Use `ChannelProgress` or `CallbackProgress` to get every update as one of these
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PipelineEvent {
+    /// see `ProgressSink::on_stage`
+    StageStarted { stage: String },
+    /// see `ProgressSink::on_progress`
+    StageProgress { stage: String, fraction: f64 },
+    /// see `ProgressSink::on_finish`
+    StageFinished { stage: String },
+    /// a LAS/LAZ file started being read
+    FileStarted { path: PathBuf },
+    /// a LAS/LAZ file was read (or failed to be, see `FileSkipped`). `files_done` counts up to `num_files`
+    FileFinished { path: PathBuf, files_done: usize, num_files: usize, seconds: f64 },
+    /// a LAS/LAZ file was left out, because it couldn't be read or is outside of the bounds
+    FileSkipped { path: PathBuf, reason: String },
+    /// something the data should probably be checked for, but that didn't stop the operation
+    Warning { message: String },
+}
+
+/// how `LogProgress` (and the default `ProgressSink::on_event`) logs events
+fn log_event(event: &PipelineEvent) {
+    match event {
+        PipelineEvent::StageStarted { stage } => info!("{stage}"),
+        PipelineEvent::StageProgress { stage, fraction } => info!("{stage}: {:.2}%", 100f64 * fraction),
+        PipelineEvent::StageFinished { stage } => info!("done {stage}"),
+        PipelineEvent::FileStarted { path } => debug!("reading {:?}", path.display()),
+        PipelineEvent::FileFinished { files_done, num_files, seconds, .. } => info!("file {files_done} / {num_files} took {seconds:.2} seconds"),
+        PipelineEvent::FileSkipped { path, reason } => warn!("skipping {:?}: {reason}", path.display()),
+        PipelineEvent::Warning { message } => warn!("{message}"),
+    }
+}
+
+/// Logs progress with log::info (https://docs.rs/log/latest/log/enum.Level.html#variant.Info).
+/// This is what the functions without a `progress` parameter use.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogProgress;
+
+impl ProgressSink for LogProgress {
+    fn on_stage(&self, stage: &str) {
+        info!("{stage}");
+    }
+
+    fn on_progress(&self, stage: &str, fraction: f64) {
+        info!("{stage}: {:.2}%", 100f64 * fraction);
+    }
+
+    fn on_finish(&self, stage: &str) {
+        info!("done {stage}");
+    }
+}
+
+/// Ignores all progress updates
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn on_stage(&self, _stage: &str) {}
+
+    fn on_progress(&self, _stage: &str, _fraction: f64) {}
+
+    fn on_finish(&self, _stage: &str) {}
+
+    fn on_event(&self, _event: &PipelineEvent) {}
+}
+
+/// Sends every update as a `PipelineEvent` down a channel, so another thread (like a UI's) can receive them.
+/// Updates are dropped once the receiver is gone
+#[derive(Clone, Debug)]
+pub struct ChannelProgress(pub Sender<PipelineEvent>);
+
+impl ProgressSink for ChannelProgress {
+    fn on_stage(&self, stage: &str) {
+        self.on_event(&PipelineEvent::StageStarted { stage: stage.to_string() });
+    }
+
+    fn on_progress(&self, stage: &str, fraction: f64) {
+        self.on_event(&PipelineEvent::StageProgress { stage: stage.to_string(), fraction });
+    }
+
+    fn on_finish(&self, stage: &str) {
+        self.on_event(&PipelineEvent::StageFinished { stage: stage.to_string() });
+    }
+
+    fn on_event(&self, event: &PipelineEvent) {
+        // nobody is listening anymore, which isn't a reason to stop loading
+        let _ = self.0.send(event.clone());
+    }
+}
+
+/// Calls a function with every update as a `PipelineEvent`. Like the rest of the sinks,
+/// it can be called from several threads at once
+#[derive(Clone, Copy, Debug)]
+pub struct CallbackProgress<F: Fn(&PipelineEvent) + Sync>(pub F);
+
+impl<F: Fn(&PipelineEvent) + Sync> ProgressSink for CallbackProgress<F> {
+    fn on_stage(&self, stage: &str) {
+        (self.0)(&PipelineEvent::StageStarted { stage: stage.to_string() });
+    }
+
+    fn on_progress(&self, stage: &str, fraction: f64) {
+        (self.0)(&PipelineEvent::StageProgress { stage: stage.to_string(), fraction });
+    }
+
+    fn on_finish(&self, stage: &str) {
+        (self.0)(&PipelineEvent::StageFinished { stage: stage.to_string() });
+    }
+
+    fn on_event(&self, event: &PipelineEvent) {
+        (self.0)(event);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::color_relief::Colormap;
+use crate::datum::DatumShift;
+use crate::errors::LasToStlError;
+use crate::height_map::{HeightMap, HeightMapFormat};
+use crate::kml_utils::{get_regions, get_trails, get_waypoints, load_kml_file};
+use crate::las_resampler::LasLoadOptions;
+use crate::mask::Mask;
+use crate::point_filter::PointFilter;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::StlOptions;
+use crate::terrain_model::TerrainModelBuilder;
+use crate::utm_point::UtmZone;
+
+/// default width of trails and waypoints drawn onto masks, in meters
+const DEFAULT_MASK_WIDTH_M: f64 = 4f64;
+
+/// A whole build described in a TOML (or JSON) file: what to load, which masks to draw from which KML files,
+/// what to do to the heights, and what to save. Running the same file again makes the same models,
+/// so a build with a dozen masks doesn't live in someone's shell history.
+///
+/// ```toml
+/// [input]
+/// las = "lidar/*.laz"
+/// resolution = 1000
+/// clip_kml = "property_line.kml"
+///
+/// [[masks]]
+/// name = "trails"
+/// kml = "trails.kml"
+/// draw = "trails"
+/// width_m = 4.0
+///
+/// [[operations]]
+/// type = "offset"
+/// mask = "trails"
+/// meters = -2.0
+///
+/// [[outputs]]
+/// type = "stl"
+/// path = "property.stl"
+/// z_scale = 2.0
+/// ```
+///
+/// Relative paths (and LAS globs) are relative to the folder the project file is in.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Project {
+    pub input: ProjectInput,
+    /// masks, drawn in order, so later masks can use earlier ones
+    #[serde(default)]
+    pub masks: Vec<ProjectMask>,
+    /// changes to the heights, done in order after the masks are drawn
+    #[serde(default)]
+    pub operations: Vec<ProjectOperation>,
+    #[serde(default)]
+    pub outputs: Vec<ProjectOutput>,
+}
+
+/// where the heightmap comes from
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ProjectInput {
+    /// glob pattern of the LAS/LAZ files
+    pub las: Option<String>,
+    /// a heightmap saved with `HeightMap::save`, instead of `las`
+    pub height_map: Option<PathBuf>,
+    /// number of cells across the x axis. 1000 if not set
+    pub resolution: Option<usize>,
+    /// only load ground points
+    #[serde(default)]
+    pub bare_earth: bool,
+    /// only load the LAS points inside the polygons of this KML file, and leave everything outside them out of the STLs
+    pub clip_kml: Option<PathBuf>,
+    /// for LAS files that don't say what zone they're in
+    pub utm_zone: Option<UtmZone>,
+    /// see `Mask::datum_shift`
+    pub datum_shift: Option<DatumShift>,
+}
+
+/// what a `ProjectMask` draws from its KML file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskShape {
+    /// every polygon, filled
+    #[default]
+    Regions,
+    /// every line string, `width_m` wide
+    Trails,
+    /// every point, as a circle `width_m` across
+    Waypoints,
+}
+
+/// a mask drawn from a KML file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProjectMask {
+    /// what operations and outputs call it
+    pub name: String,
+    /// KML file to draw. Can be left out to start from an empty mask (and `add` others to it)
+    pub kml: Option<PathBuf>,
+    #[serde(default)]
+    pub draw: MaskShape,
+    /// width of trails and waypoints in meters
+    #[serde(default = "default_mask_width_m")]
+    pub width_m: f64,
+    /// names of earlier masks to add to this one
+    #[serde(default)]
+    pub add: Vec<String>,
+    /// names of earlier masks to cut out of this one
+    #[serde(default)]
+    pub subtract: Vec<String>,
+    /// make everything in the mask this many cells wider, see `Mask::dilate`
+    pub dilate: Option<u16>,
+    /// flip the mask (after adding and subtracting), see `Mask::invert`
+    #[serde(default)]
+    pub invert: bool,
+}
+
+fn default_mask_width_m() -> f64 {
+    DEFAULT_MASK_WIDTH_M
+}
+
+/// a change to the heights
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectOperation {
+    /// see `HeightMap::offset_by_mask`
+    Offset { mask: String, meters: f64 },
+    /// see `HeightMap::set_by_mask`
+    Set { mask: String, height: f64 },
+    /// see `HeightMap::clamp`
+    Clamp { min: f64, max: f64 },
+    /// see `HeightMap::terrace`
+    Terrace { step: f64 },
+    /// see `HeightMap::median_filter`
+    MedianFilter { radius: usize },
+    /// see `HeightMap::despike`
+    Despike { radius: usize, max_difference: f64 },
+}
+
+/// something to save once all the operations are done
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectOutput {
+    /// an STL, of only the cells in `mask` if it's set (and only inside `input.clip_kml`, if that's set)
+    Stl {
+        path: PathBuf,
+        mask: Option<String>,
+        #[serde(default = "default_z_scale")]
+        z_scale: f64,
+        #[serde(default)]
+        base_thickness: f32,
+    },
+    /// a black and white PNG, see `HeightMap::save_to_image`
+    Image { path: PathBuf },
+    /// a colored and hillshaded PNG, see `HeightMap::save_color_relief`
+    ColorRelief {
+        path: PathBuf,
+        #[serde(default)]
+        colormap: Colormap,
+    },
+    /// the heightmap, in the compressed binary format unless `format = "json"`, see `HeightMap::save_with_format`
+    HeightMap {
+        path: PathBuf,
+        #[serde(default)]
+        format: HeightMapFormat,
+    },
+    /// a mask as JSON, see `Mask::save`
+    Mask { name: String, path: PathBuf },
+}
+
+fn default_z_scale() -> f64 {
+    1f64
+}
+
+impl Project {
+    /// Reads a project file, as JSON if it ends in `.json` and as TOML otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Project, LasToStlError> {
+        let text = fs::read_to_string(&path)?;
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&text)?),
+            _ => Ok(toml::from_str(&text)?),
+        }
+    }
+
+    /// Runs everything in the project, with paths relative to `base_directory`
+    pub fn run<P: AsRef<Path>>(&self, base_directory: P) -> Result<(), LasToStlError> {
+        self.run_with_progress(base_directory, &LogProgress)
+    }
+
+    /// Same as `run`, but reports progress to `progress` instead of logging it
+    pub fn run_with_progress<P: AsRef<Path>>(&self, base_directory: P, progress: &dyn ProgressSink) -> Result<(), LasToStlError> {
+        let base_directory = base_directory.as_ref();
+        let resolve = |path: &Path| base_directory.join(path);
+
+        let mut builder = TerrainModelBuilder::new();
+        builder.x_resolution = self.input.resolution;
+        builder.utm_zone = self.input.utm_zone;
+        builder.datum_shift = self.input.datum_shift;
+        builder.clip_kml_path = self.input.clip_kml.as_deref().map(resolve);
+        builder.load_options = LasLoadOptions {
+            filter: if self.input.bare_earth { PointFilter::bare_earth() } else { PointFilter::default() },
+            ..LasLoadOptions::default()
+        };
+        builder.height_map = self.input.height_map.as_deref().map(|path| HeightMap::load(resolve(path))).transpose()?;
+        builder.las_glob = self.input.las.as_ref().map(|las| resolve(Path::new(las)).to_string_lossy().into_owned());
+        let mut model = builder.build_with_progress(progress)?;
+
+        let mut masks: HashMap<&str, Mask> = HashMap::new();
+        for project_mask in &self.masks {
+            let mask = project_mask.draw(&model.height_map, self.input.datum_shift, &masks, base_directory)?;
+            masks.insert(&project_mask.name, mask);
+        }
+        let get_mask = |name: &str| masks.get(name).ok_or_else(|| LasToStlError::UnknownProjectMaskError(name.to_string()));
+
+        for operation in &self.operations {
+            info!("{operation:?}");
+            let height_map = &mut model.height_map;
+            match operation {
+                ProjectOperation::Offset { mask, meters } => height_map.offset_by_mask(get_mask(mask)?, *meters)?,
+                ProjectOperation::Set { mask, height } => height_map.set_by_mask(get_mask(mask)?, *height)?,
+                ProjectOperation::Clamp { min, max } => height_map.clamp(*min, *max)?,
+                ProjectOperation::Terrace { step } => height_map.terrace(*step),
+                ProjectOperation::MedianFilter { radius } => height_map.median_filter(*radius),
+                ProjectOperation::Despike { radius, max_difference } => height_map.despike(*radius, *max_difference),
+            }
+        }
+
+        for output in &self.outputs {
+            match output {
+                ProjectOutput::Stl { path, mask, z_scale, base_thickness } => {
+                    let stl_options = StlOptions { z_scaling: *z_scale, base_thickness: *base_thickness, ..StlOptions::default() };
+                    let path = resolve(path).to_string_lossy().into_owned();
+                    match (mask, &model.clip_mask) {
+                        (Some(mask), Some(clip_mask)) => {
+                            let mut mask = get_mask(mask)?.clone();
+                            mask.checked_bitand_assign(clip_mask)?;
+                            model.height_map.save_as_stl_masked_with_progress(&path, &mask, &stl_options, progress)?
+                        }
+                        (Some(mask), None) => model.height_map.save_as_stl_masked_with_progress(&path, get_mask(mask)?, &stl_options, progress)?,
+                        (None, _) => model.save_stl_with_progress(&path, &stl_options, progress)?,
+                    }
+                }
+                ProjectOutput::Image { path } => model.height_map.save_to_image(resolve(path))?,
+                ProjectOutput::ColorRelief { path, colormap } => model.height_map.save_color_relief(resolve(path), colormap, 0.5)?,
+                ProjectOutput::HeightMap { path, format } => model.height_map.save_with_format(resolve(path), *format)?,
+                ProjectOutput::Mask { name, path } => get_mask(name)?.save(resolve(path))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProjectMask {
+    /// draws the mask for `height_map`, using the `masks` drawn before it
+    fn draw(&self,
+            height_map: &HeightMap,
+            datum_shift: Option<DatumShift>,
+            masks: &HashMap<&str, Mask>,
+            base_directory: &Path)
+        -> Result<Mask, LasToStlError>
+    {
+        let mut mask = Mask::new_for_height_map(height_map)?;
+        mask.datum_shift = datum_shift;
+        if let Some(kml) = &self.kml {
+            let geometry_collection = load_kml_file(base_directory.join(kml))?;
+            match self.draw {
+                MaskShape::Regions => {
+                    for region in get_regions(geometry_collection) {
+                        mask.add_filled_lat_lon_polygon(&region)?;
+                    }
+                }
+                MaskShape::Trails => {
+                    for trail in get_trails(geometry_collection) {
+                        mask.add_lat_lon_trail_meters(&trail, self.width_m / 2f64)?;
+                    }
+                }
+                MaskShape::Waypoints => mask.add_lat_lon_waypoints_meters(get_waypoints(geometry_collection), self.width_m / 2f64).log_skipped("waypoint"),
+            }
+        }
+
+        let get_mask = |name: &String| masks.get(name.as_str()).ok_or_else(|| LasToStlError::UnknownProjectMaskError(name.clone()));
+        for name in &self.add {
+            mask.checked_bitor_assign(get_mask(name)?)?;
+        }
+        for name in &self.subtract {
+            mask.checked_sub_assign(get_mask(name)?)?;
+        }
+        if let Some(dilate) = self.dilate {
+            mask.dilate(dilate);
+        }
+        if self.invert {
+            mask.invert();
+        }
+        Ok(mask)
+    }
+}
+
+/// Loads a project file and runs it, with paths relative to the folder it's in. See `Project`
+pub fn run_project_file<P: AsRef<Path>>(path: P) -> Result<(), LasToStlError> {
+    let project = Project::load(&path)?;
+    let base_directory = path.as_ref().parent().unwrap_or(Path::new("."));
+    project.run(base_directory)
+}
@@ -0,0 +1,283 @@
+// the pyo3 macros convert every returned error into a `PyErr`, even ones that already are
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+use geo::{Coord, LineString, Point, Polygon};
+use pyo3::exceptions::{PyIOError, PyRuntimeError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use crate::altitude_trails::{load_gpx_trails, load_kml_altitude_trails, AltitudeTrail};
+use crate::errors::LasToStlError;
+use crate::height_map::{HeightMap, HeightMapFormat};
+use crate::kml_utils::{get_regions, get_trails, get_waypoints};
+use crate::las_resampler::LasLoadOptions;
+use crate::mask::Mask;
+use crate::point_filter::PointFilter;
+
+/// a line or ring the way it goes in and out of Python: a list of (lon, lat) tuples
+type LonLatPoints = Vec<(f64, f64)>;
+
+/// a trail with altitudes the way it goes out to Python: a list of (lon, lat, altitude) tuples
+type LonLatAltPoints = Vec<(f64, f64, f64)>;
+
+impl From<LasToStlError> for PyErr {
+    fn from(error: LasToStlError) -> PyErr {
+        match error {
+            LasToStlError::IoError(io_error) => PyIOError::new_err(io_error.to_string()),
+            error => PyRuntimeError::new_err(error.to_string()),
+        }
+    }
+}
+
+fn to_line_string(points: LonLatPoints) -> LineString {
+    LineString::new(points.into_iter().map(|(x, y)| Coord { x, y }).collect())
+}
+
+fn from_line_string(line_string: &LineString) -> LonLatPoints {
+    line_string.coords().map(|coord| (coord.x, coord.y)).collect()
+}
+
+fn from_altitude_trails(trails: Vec<AltitudeTrail>) -> Vec<LonLatAltPoints> {
+    trails.into_iter()
+        .map(|trail| trail.points.iter().zip(trail.altitudes).map(|(coord, altitude)| (coord.x, coord.y, altitude)).collect())
+        .collect()
+}
+
+/// `HeightMap` for Python. Heights are in meters and the cells are row by row, starting at the north edge
+#[pyclass(name = "HeightMap", module = "las_kml_to_stl")]
+pub struct PyHeightMap {
+    pub inner: HeightMap,
+}
+
+#[pymethods]
+impl PyHeightMap {
+    /// loads every LAS/LAZ file matching `glob_pattern`, `resolution` cells across the x axis
+    #[staticmethod]
+    #[pyo3(signature = (glob_pattern, resolution = 1000, bare_earth = false))]
+    fn from_las(py: Python<'_>, glob_pattern: &str, resolution: usize, bare_earth: bool) -> PyResult<Self> {
+        let load_options = LasLoadOptions {
+            filter: if bare_earth { PointFilter::bare_earth() } else { PointFilter::default() },
+            ..LasLoadOptions::default()
+        };
+        let inner = py.allow_threads(|| HeightMap::glob_get_height_map_with_options(glob_pattern, Some(resolution), None, &load_options))?;
+        Ok(PyHeightMap { inner })
+    }
+
+    /// see `HeightMap::load`
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        Ok(PyHeightMap { inner: HeightMap::load(path)? })
+    }
+
+    /// see `HeightMap::save`. `json = True` saves it as JSON instead of the compressed binary format
+    #[pyo3(signature = (path, json = false))]
+    fn save(&self, path: PathBuf, json: bool) -> PyResult<()> {
+        Ok(self.inner.save_with_format(path, if json { HeightMapFormat::Json } else { HeightMapFormat::Binary })?)
+    }
+
+    /// saves as an STL, only the cells that are true in `mask` if it's given
+    #[pyo3(signature = (path, z_scaling = 1.0, base_thickness = 5.0, mask = None))]
+    fn save_as_stl(&self, py: Python<'_>, path: &str, z_scaling: f64, base_thickness: f32, mask: Option<PyRef<PyMask>>) -> PyResult<()> {
+        let mask = mask.as_ref().map(|mask| &mask.inner);
+        py.allow_threads(|| match mask {
+            Some(mask) => self.inner.save_as_stl_masked(path, mask, z_scaling, base_thickness),
+            None => self.inner.save_as_stl(path, z_scaling, base_thickness),
+        })?;
+        Ok(())
+    }
+
+    /// see `HeightMap::save_to_image`
+    fn save_to_image(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.inner.save_to_image(path)?)
+    }
+
+    /// see `HeightMap::offset_by_mask`
+    fn offset_by_mask(&mut self, mask: PyRef<PyMask>, offset: f64) -> PyResult<()> {
+        Ok(self.inner.offset_by_mask(&mask.inner, offset)?)
+    }
+
+    /// see `HeightMap::set_by_mask`
+    fn set_by_mask(&mut self, mask: PyRef<PyMask>, height: f64) -> PyResult<()> {
+        Ok(self.inner.set_by_mask(&mask.inner, height)?)
+    }
+
+    /// see `HeightMap::clamp`
+    fn clamp(&mut self, min: f64, max: f64) -> PyResult<()> {
+        Ok(self.inner.clamp(min, max)?)
+    }
+
+    /// the height of one cell
+    fn get_height(&self, x: usize, y: usize) -> PyResult<f64> {
+        Ok(self.inner.get_height(x, y)?)
+    }
+
+    #[getter]
+    fn x_res(&self) -> usize {
+        self.inner.x_res
+    }
+
+    #[getter]
+    fn y_res(&self) -> usize {
+        self.inner.y_res
+    }
+
+    /// (min_x, max_x, min_y, max_y, min_z, max_z) in UTM meters
+    #[getter]
+    fn bounds(&self) -> (f64, f64, f64, f64, f64, f64) {
+        let bounds = &self.inner.bounds;
+        (bounds.min_x, bounds.max_x, bounds.min_y, bounds.max_y, bounds.min_z, bounds.max_z)
+    }
+
+    /// like "10N", or None if the LAS files didn't say
+    #[getter]
+    fn utm_zone(&self) -> Option<String> {
+        self.inner.utm_zone.map(|utm_zone| utm_zone.to_string())
+    }
+
+    /// every height as a flat list, `x_res` per row. Copies the whole heightmap
+    #[getter]
+    fn data(&self) -> Vec<f64> {
+        self.inner.data.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("HeightMap({}x{}, {})", self.inner.x_res, self.inner.y_res, self.inner.bounds)
+    }
+}
+
+/// `Mask` for Python. Coordinates given to it are lat/lon, as (lon, lat) tuples like in KML
+#[pyclass(name = "Mask", module = "las_kml_to_stl")]
+#[derive(Clone)]
+pub struct PyMask {
+    pub inner: Mask,
+}
+
+#[pymethods]
+impl PyMask {
+    /// an empty mask the same size as `height_map`
+    #[staticmethod]
+    fn for_height_map(height_map: PyRef<PyHeightMap>) -> PyResult<Self> {
+        Ok(PyMask { inner: Mask::new_for_height_map(&height_map.inner)? })
+    }
+
+    /// see `Mask::load`
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        Ok(PyMask { inner: Mask::load(path)? })
+    }
+
+    /// see `Mask::save`
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.inner.save(path)?)
+    }
+
+    /// draws a trail `width_m` meters wide
+    fn add_trail(&mut self, points: LonLatPoints, width_m: f64) -> PyResult<()> {
+        Ok(self.inner.add_lat_lon_trail_meters(&to_line_string(points), width_m / 2f64)?)
+    }
+
+    /// fills a polygon, leaving its holes empty
+    #[pyo3(signature = (exterior, holes = Vec::new()))]
+    fn add_region(&mut self, exterior: LonLatPoints, holes: Vec<LonLatPoints>) -> PyResult<()> {
+        let region = Polygon::new(to_line_string(exterior), holes.into_iter().map(to_line_string).collect());
+        Ok(self.inner.add_filled_lat_lon_polygon(&region)?)
+    }
+
+    /// draws every waypoint as a circle `width_m` meters across.
+    /// Returns the (index, reason) of every waypoint that was skipped, see `BatchReport`
+    fn add_waypoints(&mut self, points: LonLatPoints, width_m: f64) -> Vec<(usize, String)> {
+        let waypoints: Vec<Point> = points.into_iter().map(Point::from).collect();
+        let report = self.inner.add_lat_lon_waypoints_meters(waypoints, width_m / 2f64);
+        report.skipped.into_iter().map(|skipped| (skipped.index, skipped.reason)).collect()
+    }
+
+    /// turns on every cell that is on in `other`
+    fn add_mask(&mut self, other: PyRef<PyMask>) -> PyResult<()> {
+        Ok(self.inner.checked_bitor_assign(&other.inner)?)
+    }
+
+    /// turns off every cell that is off in `other`
+    fn intersect_mask(&mut self, other: PyRef<PyMask>) -> PyResult<()> {
+        Ok(self.inner.checked_bitand_assign(&other.inner)?)
+    }
+
+    /// turns off every cell that is on in `other`
+    fn subtract_mask(&mut self, other: PyRef<PyMask>) -> PyResult<()> {
+        Ok(self.inner.checked_sub_assign(&other.inner)?)
+    }
+
+    /// see `Mask::invert`
+    fn invert(&mut self) {
+        self.inner.invert();
+    }
+
+    /// see `Mask::dilate`
+    fn dilate(&mut self, radius: u16) {
+        self.inner.dilate(radius);
+    }
+
+    /// see `Mask::erode`
+    fn erode(&mut self, radius: u16) {
+        self.inner.erode(radius);
+    }
+
+    #[getter]
+    fn x_res(&self) -> usize {
+        self.inner.x_res
+    }
+
+    #[getter]
+    fn y_res(&self) -> usize {
+        self.inner.y_res
+    }
+
+    /// every cell as a flat list, `x_res` per row. Copies the whole mask
+    #[getter]
+    fn data(&self) -> Vec<bool> {
+        self.inner.data.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Mask({}x{}, zone {})", self.inner.x_res, self.inner.y_res, self.inner.utm_zone)
+    }
+}
+
+/// Loads a KML file into a dict of "regions" (a list of (exterior, holes)), "trails" and "waypoints", all (lon, lat)
+#[pyfunction]
+fn load_kml_file(py: Python<'_>, path: PathBuf) -> PyResult<Bound<'_, PyDict>> {
+    let geometry_collection = crate::kml_utils::load_kml_file(path)?;
+    let regions: Vec<(LonLatPoints, Vec<LonLatPoints>)> = get_regions(geometry_collection.clone()).iter()
+        .map(|region| (from_line_string(region.exterior()), region.interiors().iter().map(from_line_string).collect()))
+        .collect();
+    let trails: Vec<LonLatPoints> = get_trails(geometry_collection.clone()).iter().map(from_line_string).collect();
+    let waypoints: LonLatPoints = get_waypoints(geometry_collection).iter().map(|waypoint| (waypoint.x(), waypoint.y())).collect();
+
+    let dict = PyDict::new_bound(py);
+    dict.set_item("regions", regions)?;
+    dict.set_item("trails", trails)?;
+    dict.set_item("waypoints", waypoints)?;
+    Ok(dict)
+}
+
+/// Loads the tracks (and routes) of a GPX file, as lists of (lon, lat, altitude)
+#[pyfunction(name = "load_gpx_trails")]
+fn py_load_gpx_trails(path: PathBuf) -> PyResult<Vec<LonLatAltPoints>> {
+    Ok(from_altitude_trails(load_gpx_trails(path)?))
+}
+
+/// Loads the line strings of a KML file with their altitudes, as lists of (lon, lat, altitude)
+#[pyfunction(name = "load_kml_altitude_trails")]
+fn py_load_kml_altitude_trails(path: PathBuf) -> PyResult<Vec<LonLatAltPoints>> {
+    Ok(from_altitude_trails(load_kml_altitude_trails(path)?))
+}
+
+/// the `las_kml_to_stl` Python module
+#[pymodule]
+fn las_kml_to_stl(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyHeightMap>()?;
+    module.add_class::<PyMask>()?;
+    module.add_function(wrap_pyfunction!(load_kml_file, module)?)?;
+    module.add_function(wrap_pyfunction!(py_load_gpx_trails, module)?)?;
+    module.add_function(wrap_pyfunction!(py_load_kml_altitude_trails, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::Path;
+use geo::{Contains, Coord, Geometry, GeometryCollection, LineString, Point, Polygon};
+use log::warn;
+use crate::errors::LasToStlError;
+
+/// file code at the start of every .shp file
+const SHAPEFILE_CODE: i32 = 9994;
+/// the .shp header is always this long
+const HEADER_LENGTH: usize = 100;
+
+/// Loads every shape of an ESRI shapefile (the .shp file, the .shx and .dbf next to it aren't needed)
+/// as the same geo types `kml_utils::load_kml_file` returns, so `get_regions`, `get_trails` and `get_waypoints` work on it.
+/// Polygons are split into their outer rings with their holes, multi part polylines into one line string per part.
+/// Z and M values are ignored, and multipatches are skipped.
+///
+/// The coordinates are in whatever CRS the shapefile is in (see `load_shapefile_prj`), not necessarily lon/lat.
+/// If it's in UTM, use the `add_utm_*` functions of `Mask`, if it's lon/lat, the `add_lat_lon_*` ones.
+///
+/// Returns `LasToStlError::BadShapefileError` if the file isn't a shapefile or is cut off
+pub fn load_shapefile<P: AsRef<Path>>(path: P) -> Result<GeometryCollection<f64>, LasToStlError>{
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    let bad_shapefile = || LasToStlError::BadShapefileError(path.to_path_buf());
+
+    if bytes.len() < HEADER_LENGTH || read_i32_be(&bytes, 0) != Some(SHAPEFILE_CODE) {
+        return Err(bad_shapefile())
+    }
+
+    let mut out_vec: Vec<Geometry<f64>> = Vec::new();
+    let mut offset = HEADER_LENGTH;
+    while offset < bytes.len() {
+        // record number, then the length of the content in 16 bit words
+        let content_length = read_count(read_i32_be(&bytes, offset + 4)).ok_or_else(bad_shapefile)? * 2;
+        let content = bytes.get(offset + 8..offset + 8 + content_length).ok_or_else(bad_shapefile)?;
+        offset += 8 + content_length;
+
+        match read_shape(content) {
+            Some(Some(geometry)) => out_vec.push(geometry),
+            Some(None) => {}
+            None => return Err(bad_shapefile()),
+        }
+    }
+
+    Ok(GeometryCollection::new_from(out_vec))
+}
+
+/// The CRS of a shapefile as WKT, from the .prj file next to it. `None` if there isn't one.
+/// If it starts with `GEOGCS` the coordinates are lon/lat, if it starts with `PROJCS` they're projected (like UTM)
+pub fn load_shapefile_prj<P: AsRef<Path>>(path: P) -> Result<Option<String>, LasToStlError>{
+    let prj_path = path.as_ref().with_extension("prj");
+    if !prj_path.exists() {
+        return Ok(None)
+    }
+    Ok(Some(fs::read_to_string(prj_path)?.trim().to_string()))
+}
+
+/// reads one record. `None` if it's cut off, `Some(None)` for null and unsupported shapes
+fn read_shape(content: &[u8]) -> Option<Option<Geometry<f64>>>{
+    let shape_type = read_i32_le(content, 0)?;
+    Some(match shape_type {
+        0 => None,
+        // point, with z, with m
+        1 | 11 | 21 => Some(Geometry::Point(Point::from(read_coord(content, 4)?))),
+        // multipoint
+        8 | 18 | 28 => {
+            let num_points = read_count(read_i32_le(content, 36))?;
+            let points = (0..num_points)
+                .map(|index| read_coord(content, 40 + index * 16).map(|coord| Geometry::Point(Point::from(coord))))
+                .collect::<Option<Vec<Geometry<f64>>>>()?;
+            Some(Geometry::GeometryCollection(GeometryCollection::new_from(points)))
+        }
+        // polyline
+        3 | 13 | 23 => {
+            let line_strings = read_parts(content)?.into_iter().map(Geometry::LineString).collect();
+            Some(Geometry::GeometryCollection(GeometryCollection::new_from(line_strings)))
+        }
+        // polygon
+        5 | 15 | 25 => {
+            let polygons = rings_to_polygons(read_parts(content)?).into_iter().map(Geometry::Polygon).collect();
+            Some(Geometry::GeometryCollection(GeometryCollection::new_from(polygons)))
+        }
+        _ => {
+            warn!("skipping shape of unsupported type {}", shape_type);
+            None
+        }
+    })
+}
+
+/// the parts of a polyline or polygon record
+fn read_parts(content: &[u8]) -> Option<Vec<LineString>>{
+    // after the shape type and the bounding box
+    let num_parts = read_count(read_i32_le(content, 36))?;
+    let num_points = read_count(read_i32_le(content, 40))?;
+    let points_start = 44 + num_parts * 4;
+
+    let part_starts = (0..num_parts)
+        .map(|part| read_count(read_i32_le(content, 44 + part * 4)))
+        .collect::<Option<Vec<usize>>>()?;
+
+    (0..num_parts).map(|part| {
+        let end = part_starts.get(part + 1).copied().unwrap_or(num_points);
+        (part_starts[part]..end)
+            .map(|index| read_coord(content, points_start + index * 16))
+            .collect::<Option<Vec<Coord>>>()
+            .map(LineString::from)
+    }).collect()
+}
+
+/// Shapefile polygons are a list of rings: outer rings are clockwise, holes are counter clockwise.
+/// Every hole goes with the outer ring that contains it
+fn rings_to_polygons(rings: Vec<LineString>) -> Vec<Polygon>{
+    let (outer_rings, holes): (Vec<LineString>, Vec<LineString>) = rings.into_iter()
+        .filter(|ring| ring.0.len() >= 4)
+        .partition(|ring| get_signed_area(ring) <= 0f64);
+
+    let mut polygons: Vec<Polygon> = outer_rings.into_iter().map(|ring| Polygon::new(ring, vec![])).collect();
+    let mut interiors: Vec<Vec<LineString>> = vec![Vec::new(); polygons.len()];
+    let mut holes_outside: Vec<Polygon> = Vec::new();
+    for hole in holes {
+        match polygons.iter().position(|polygon| polygon.contains(&hole.0[0])) {
+            Some(index) => interiors[index].push(hole),
+            // some files get the winding wrong, so a hole that isn't in anything is probably an outer ring
+            None => holes_outside.push(Polygon::new(hole, vec![])),
+        }
+    }
+    for (polygon, holes) in polygons.iter_mut().zip(interiors) {
+        for hole in holes {
+            polygon.interiors_push(hole);
+        }
+    }
+    polygons.extend(holes_outside);
+    polygons
+}
+
+/// shoelace formula, positive if the ring is counter clockwise
+fn get_signed_area(ring: &LineString) -> f64{
+    ring.lines().map(|line| line.start.x * line.end.y - line.end.x * line.start.y).sum::<f64>() / 2f64
+}
+
+/// counts and lengths can't be negative
+fn read_count(value: Option<i32>) -> Option<usize>{
+    usize::try_from(value?).ok()
+}
+
+fn read_coord(bytes: &[u8], offset: usize) -> Option<Coord>{
+    Some(Coord { x: read_f64_le(bytes, offset)?, y: read_f64_le(bytes, offset + 8)? })
+}
+
+fn read_i32_be(bytes: &[u8], offset: usize) -> Option<i32>{
+    Some(i32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_i32_le(bytes: &[u8], offset: usize) -> Option<i32>{
+    Some(i32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_f64_le(bytes: &[u8], offset: usize) -> Option<f64>{
+    Some(f64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the content of a polyline or polygon record with the bounding box left at 0
+    fn parts_content(shape_type: i32, parts: &[&[(f64, f64)]]) -> Vec<u8> {
+        let mut content = shape_type.to_le_bytes().to_vec();
+        content.extend([0u8; 32]);
+        content.extend((parts.len() as i32).to_le_bytes());
+        content.extend((parts.iter().map(|part| part.len()).sum::<usize>() as i32).to_le_bytes());
+        let mut part_start = 0i32;
+        for part in parts {
+            content.extend(part_start.to_le_bytes());
+            part_start += part.len() as i32;
+        }
+        for (x, y) in parts.iter().flat_map(|part| part.iter()) {
+            content.extend(x.to_le_bytes());
+            content.extend(y.to_le_bytes());
+        }
+        content
+    }
+
+    /// a .shp file with a header and every record content in `records`
+    fn shapefile_bytes(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_LENGTH];
+        bytes[..4].copy_from_slice(&SHAPEFILE_CODE.to_be_bytes());
+        for (record_number, content) in (1i32..).zip(records) {
+            bytes.extend(record_number.to_be_bytes());
+            bytes.extend((content.len() as i32 / 2).to_be_bytes());
+            bytes.extend(content);
+        }
+        bytes
+    }
+
+    #[test]
+    fn shapes_come_out_as_geo_types() {
+        let outer: &[(f64, f64)] = &[(0f64, 0f64), (0f64, 10f64), (10f64, 10f64), (10f64, 0f64), (0f64, 0f64)];
+        let hole: &[(f64, f64)] = &[(2f64, 2f64), (4f64, 2f64), (4f64, 4f64), (2f64, 4f64), (2f64, 2f64)];
+        let mut point = 1i32.to_le_bytes().to_vec();
+        point.extend(5f64.to_le_bytes());
+        point.extend(6f64.to_le_bytes());
+        let records = [
+            parts_content(5, &[outer, hole]),
+            parts_content(3, &[&[(0f64, 0f64), (1f64, 1f64)], &[(2f64, 2f64), (3f64, 3f64), (4f64, 3f64)]]),
+            point,
+            0i32.to_le_bytes().to_vec(),
+        ];
+
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_shapefile_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("shapes.shp");
+        let cut_off_path = directory.join("cut_off.shp");
+        let bytes = shapefile_bytes(&records);
+        std::fs::write(&path, &bytes).unwrap();
+        std::fs::write(&cut_off_path, &bytes[..bytes.len() - 20]).unwrap();
+
+        let shapes = load_shapefile(&path);
+        let cut_off = load_shapefile(&cut_off_path);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let shapes = shapes.unwrap();
+        let regions = crate::kml_utils::get_regions(shapes.clone());
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].interiors().len(), 1, "the counter clockwise ring should be a hole in the other one");
+        let trails = crate::kml_utils::get_trails(shapes.clone());
+        assert_eq!(trails.iter().map(|trail| trail.0.len()).collect::<Vec<usize>>(), vec![2, 3]);
+        assert_eq!(crate::kml_utils::get_waypoints(shapes), vec![Point::new(5f64, 6f64)]);
+        assert!(matches!(cut_off, Err(LasToStlError::BadShapefileError(_))));
+    }
+}
@@ -0,0 +1,304 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use geo::Coord;
+use serde::{Deserialize, Serialize};
+use crate::crs::Crs;
+use crate::utm_point::UtmZone;
+
+/// meters in a US survey foot, what most state plane lidar is in
+pub const US_SURVEY_FOOT: f64 = 1200f64 / 3937f64;
+
+/// meters in an international foot
+pub const INTERNATIONAL_FOOT: f64 = 0.3048;
+
+/// semi-major axis of the GRS80 ellipsoid NAD83 is on (WGS84 is the same to a tenth of a millimeter)
+pub(crate) const GRS80_A: f64 = 6378137f64;
+
+/// flattening of the GRS80 ellipsoid
+const GRS80_F: f64 = 1f64 / 298.257222101;
+
+/// how a state plane zone is projected. Long east-west states use Lambert, long north-south ones transverse mercator
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatePlaneProjection {
+    /// Lambert conformal conic with two standard parallels (in degrees)
+    LambertConformalConic {
+        standard_parallel_1: f64,
+        standard_parallel_2: f64,
+    },
+    /// transverse mercator, like UTM but with its own scale factor on the central meridian
+    TransverseMercator {
+        scale_factor: f64,
+    },
+}
+
+/// A US state plane coordinate system on NAD83 (like "California zone 2 (ftUS)"),
+/// which county and state lidar downloads are usually in. Converts to and from lat/lon without PROJ.
+///
+/// NAD83 and WGS84 are treated as the same (they're about a meter apart), like the rest of the built in conversions.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatePlane {
+    pub projection: StatePlaneProjection,
+    /// in degrees
+    pub latitude_of_origin: f64,
+    /// in degrees
+    pub central_meridian: f64,
+    /// in meters, even if the coordinates are in feet
+    pub false_easting: f64,
+    /// in meters, even if the coordinates are in feet
+    pub false_northing: f64,
+    /// meters per unit of the coordinates, `US_SURVEY_FOOT` for most lidar
+    pub meters_per_unit: f64,
+}
+
+impl StatePlane {
+    /// Gets one of the state plane zones that are built in, by EPSG code (NAD83, in meters or US survey feet).
+    /// Only California, Oregon and Washington are built in, for the rest use the WKT in the LAS header (see `from_wkt`)
+    pub fn from_epsg(code: u32) -> Option<StatePlane> {
+        let lcc = |parallel_1: f64, parallel_2: f64, origin: f64, meridian: f64, false_easting: f64, false_northing: f64| StatePlane {
+            projection: StatePlaneProjection::LambertConformalConic {
+                standard_parallel_1: parallel_1,
+                standard_parallel_2: parallel_2,
+            },
+            latitude_of_origin: origin,
+            central_meridian: meridian,
+            false_easting,
+            false_northing,
+            meters_per_unit: 1f64,
+        };
+        // minutes are written out so they can be checked against the EPSG registry
+        let state_plane = match code {
+            // California zones 1 to 6
+            26941 | 2225 => lcc(41f64 + 40f64 / 60f64, 40f64, 39f64 + 20f64 / 60f64, -122f64, 2000000f64, 500000f64),
+            26942 | 2226 => lcc(39f64 + 50f64 / 60f64, 38f64 + 20f64 / 60f64, 37f64 + 40f64 / 60f64, -122f64, 2000000f64, 500000f64),
+            26943 | 2227 => lcc(38f64 + 26f64 / 60f64, 37f64 + 4f64 / 60f64, 36f64 + 30f64 / 60f64, -120f64 - 30f64 / 60f64, 2000000f64, 500000f64),
+            26944 | 2228 => lcc(37f64 + 15f64 / 60f64, 36f64, 35f64 + 20f64 / 60f64, -119f64, 2000000f64, 500000f64),
+            26945 | 2229 => lcc(35f64 + 28f64 / 60f64, 34f64 + 2f64 / 60f64, 33f64 + 30f64 / 60f64, -118f64, 2000000f64, 500000f64),
+            26946 | 2230 => lcc(33f64 + 53f64 / 60f64, 32f64 + 47f64 / 60f64, 32f64 + 10f64 / 60f64, -116f64 - 15f64 / 60f64, 2000000f64, 500000f64),
+            // Oregon north and south (the foot versions are in international feet)
+            32126 | 2269 => lcc(46f64, 44f64 + 20f64 / 60f64, 43f64 + 40f64 / 60f64, -120f64 - 30f64 / 60f64, 2500000f64, 0f64),
+            32127 | 2270 => lcc(44f64, 42f64 + 20f64 / 60f64, 41f64 + 40f64 / 60f64, -120f64 - 30f64 / 60f64, 1500000f64, 0f64),
+            // Washington north and south
+            32148 | 2285 => lcc(48f64 + 44f64 / 60f64, 47f64 + 30f64 / 60f64, 47f64, -120f64 - 50f64 / 60f64, 500000f64, 0f64),
+            32149 | 2286 => lcc(47f64 + 20f64 / 60f64, 45f64 + 50f64 / 60f64, 45f64 + 20f64 / 60f64, -120f64 - 30f64 / 60f64, 500000f64, 0f64),
+            _ => return None,
+        };
+        let meters_per_unit = match code {
+            2225..=2230 | 2285 | 2286 => US_SURVEY_FOOT,
+            2269 | 2270 => INTERNATIONAL_FOOT,
+            _ => 1f64,
+        };
+        Some(StatePlane { meters_per_unit, ..state_plane })
+    }
+
+    /// Reads a Lambert conformal conic or transverse mercator projection out of OGC or ESRI WKT (version 1,
+    /// what LAS headers have), so every state plane zone works, not just the built in ones.
+    /// Returns `None` for anything else, for UTM (which is handled by `UtmZone`), and for NAD27, which is ~100m off
+    pub fn from_wkt(wkt: &str) -> Option<StatePlane> {
+        let lowercase = wkt.to_lowercase();
+        if lowercase.contains("nad27") || lowercase.contains("1927") || Crs::Wkt(wkt.to_string()).get_utm_zone().is_some() {
+            return None
+        }
+        let projection_start = lowercase.find("projection[\"")? + "projection[\"".len();
+        let projection_name = &lowercase[projection_start..projection_start + lowercase[projection_start..].find('"')?];
+
+        let parameter = |name: &str| get_wkt_number(&lowercase, &format!("parameter[\"{name}\","));
+        let meters_per_unit = get_wkt_number(&lowercase[projection_start..], "unit[\"").unwrap_or(1f64);
+
+        let projection = if projection_name.starts_with("lambert_conformal_conic") {
+            let standard_parallel_1 = parameter("standard_parallel_1")?;
+            StatePlaneProjection::LambertConformalConic {
+                standard_parallel_1,
+                standard_parallel_2: parameter("standard_parallel_2").unwrap_or(standard_parallel_1),
+            }
+        } else if projection_name == "transverse_mercator" {
+            StatePlaneProjection::TransverseMercator {
+                scale_factor: parameter("scale_factor").unwrap_or(1f64),
+            }
+        } else {
+            return None
+        };
+
+        Some(StatePlane {
+            projection,
+            latitude_of_origin: parameter("latitude_of_origin").unwrap_or(0f64),
+            central_meridian: parameter("central_meridian")?,
+            false_easting: parameter("false_easting").unwrap_or(0f64) * meters_per_unit,
+            false_northing: parameter("false_northing").unwrap_or(0f64) * meters_per_unit,
+            meters_per_unit,
+        })
+    }
+
+    /// Gets the state plane zone of a CRS, if it is one (see `from_epsg` and `from_wkt`)
+    pub fn from_crs(crs: &Crs) -> Option<StatePlane> {
+        match crs {
+            Crs::Epsg(code) => StatePlane::from_epsg(*code),
+            Crs::Wkt(wkt) => StatePlane::from_wkt(wkt),
+        }
+    }
+
+    /// The UTM zone the origin of this zone is in, which state plane data is reprojected into by default
+    pub fn get_utm_zone(&self) -> UtmZone {
+        UtmZone::from_lat_lon_coord(&Coord { x: self.central_meridian, y: self.latitude_of_origin })
+    }
+
+    /// converts x, y (in `meters_per_unit`) to lat/lon (x = lon, y = lat)
+    pub fn to_lat_lon(&self, x: f64, y: f64) -> Coord<f64> {
+        let easting = x * self.meters_per_unit - self.false_easting;
+        let northing = y * self.meters_per_unit - self.false_northing;
+        let (lat, lon_offset) = match self.projection {
+            StatePlaneProjection::LambertConformalConic { standard_parallel_1, standard_parallel_2 } => {
+                let cone = LambertCone::new(standard_parallel_1, standard_parallel_2, self.latitude_of_origin);
+                cone.inverse(easting, northing)
+            }
+            StatePlaneProjection::TransverseMercator { scale_factor } => {
+                transverse_mercator_inverse(easting, northing, scale_factor, self.latitude_of_origin.to_radians())
+            }
+        };
+        Coord { x: self.central_meridian + lon_offset.to_degrees(), y: lat.to_degrees() }
+    }
+
+    /// converts lat/lon (x = lon, y = lat) to x, y (in `meters_per_unit`)
+    pub fn from_lat_lon(&self, lat_lon: &Coord<f64>) -> (f64, f64) {
+        let lat = lat_lon.y.to_radians();
+        let lon_offset = (lat_lon.x - self.central_meridian).to_radians();
+        let (easting, northing) = match self.projection {
+            StatePlaneProjection::LambertConformalConic { standard_parallel_1, standard_parallel_2 } => {
+                let cone = LambertCone::new(standard_parallel_1, standard_parallel_2, self.latitude_of_origin);
+                cone.forward(lat, lon_offset)
+            }
+            StatePlaneProjection::TransverseMercator { scale_factor } => {
+                transverse_mercator_forward(lat, lon_offset, scale_factor, self.latitude_of_origin.to_radians())
+            }
+        };
+        ((easting + self.false_easting) / self.meters_per_unit, (northing + self.false_northing) / self.meters_per_unit)
+    }
+}
+
+/// the number after `key` in lowercase WKT, like the 0.3048006 in `unit["us survey foot",0.3048006]` for the key `unit["`
+fn get_wkt_number(wkt: &str, key: &str) -> Option<f64> {
+    let after_key = &wkt[wkt.find(key)? + key.len()..];
+    // skip the name of the unit
+    let after_name = match after_key.find("\",") {
+        Some(end_of_name) if key.ends_with('"') => &after_key[end_of_name + 2..],
+        _ => after_key,
+    };
+    let end = after_name.find([',', ']'])?;
+    after_name[..end].trim().parse().ok()
+}
+
+/// eccentricity squared of GRS80
+pub(crate) fn eccentricity_squared() -> f64 {
+    GRS80_F * (2f64 - GRS80_F)
+}
+
+/// the constants of a Lambert conformal conic projection, see Snyder's "Map Projections: A Working Manual" page 107
+struct LambertCone {
+    /// cone constant
+    n: f64,
+    /// a * F
+    scale: f64,
+    /// radius of the latitude of origin
+    rho_0: f64,
+}
+
+impl LambertCone {
+    fn new(standard_parallel_1: f64, standard_parallel_2: f64, latitude_of_origin: f64) -> LambertCone {
+        let e = eccentricity_squared().sqrt();
+        let m = |lat: f64| lat.cos() / (1f64 - e * e * lat.sin().powi(2)).sqrt();
+        let t = |lat: f64| (FRAC_PI_4 - lat / 2f64).tan() / ((1f64 - e * lat.sin()) / (1f64 + e * lat.sin())).powf(e / 2f64);
+        let (lat_1, lat_2) = (standard_parallel_1.to_radians(), standard_parallel_2.to_radians());
+
+        let n = if (lat_1 - lat_2).abs() < 1e-12 {
+            lat_1.sin()
+        } else {
+            (m(lat_1).ln() - m(lat_2).ln()) / (t(lat_1).ln() - t(lat_2).ln())
+        };
+        let scale = GRS80_A * m(lat_1) / (n * t(lat_1).powf(n));
+        LambertCone { n, scale, rho_0: scale * t(latitude_of_origin.to_radians()).powf(n) }
+    }
+
+    /// (lat, lon - central meridian) in radians to (easting, northing) in meters from the false origin
+    fn forward(&self, lat: f64, lon_offset: f64) -> (f64, f64) {
+        let e = eccentricity_squared().sqrt();
+        let t = (FRAC_PI_4 - lat / 2f64).tan() / ((1f64 - e * lat.sin()) / (1f64 + e * lat.sin())).powf(e / 2f64);
+        let rho = self.scale * t.powf(self.n);
+        let theta = self.n * lon_offset;
+        (rho * theta.sin(), self.rho_0 - rho * theta.cos())
+    }
+
+    /// the opposite of `forward`
+    fn inverse(&self, easting: f64, northing: f64) -> (f64, f64) {
+        let e = eccentricity_squared().sqrt();
+        let sign = self.n.signum();
+        let y = self.rho_0 - northing;
+        let rho = sign * (easting * easting + y * y).sqrt();
+        let theta = (sign * easting).atan2(sign * y);
+        let t = (rho / self.scale).powf(1f64 / self.n);
+
+        // latitude has no closed form, but this converges to well under a millimeter in a few steps
+        let mut lat = FRAC_PI_2 - 2f64 * t.atan();
+        for _ in 0..10 {
+            lat = FRAC_PI_2 - 2f64 * (t * ((1f64 - e * lat.sin()) / (1f64 + e * lat.sin())).powf(e / 2f64)).atan();
+        }
+        (lat, theta / self.n)
+    }
+}
+
+/// distance along the central meridian from the equator to `lat` (radians), in meters
+fn meridian_arc(lat: f64) -> f64 {
+    let e2 = eccentricity_squared();
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    GRS80_A * ((1f64 - e2 / 4f64 - 3f64 * e4 / 64f64 - 5f64 * e6 / 256f64) * lat
+        - (3f64 * e2 / 8f64 + 3f64 * e4 / 32f64 + 45f64 * e6 / 1024f64) * (2f64 * lat).sin()
+        + (15f64 * e4 / 256f64 + 45f64 * e6 / 1024f64) * (4f64 * lat).sin()
+        - (35f64 * e6 / 3072f64) * (6f64 * lat).sin())
+}
+
+/// (lat, lon - central meridian) in radians to (easting, northing) in meters from the false origin.
+/// Snyder's series, which is millimeter accurate within a state plane zone
+fn transverse_mercator_forward(lat: f64, lon_offset: f64, scale_factor: f64, latitude_of_origin: f64) -> (f64, f64) {
+    let e2 = eccentricity_squared();
+    let e_prime2 = e2 / (1f64 - e2);
+    let n = GRS80_A / (1f64 - e2 * lat.sin().powi(2)).sqrt();
+    let t = lat.tan().powi(2);
+    let c = e_prime2 * lat.cos().powi(2);
+    let a = lon_offset * lat.cos();
+
+    let easting = scale_factor * n * (a
+        + (1f64 - t + c) * a.powi(3) / 6f64
+        + (5f64 - 18f64 * t + t * t + 72f64 * c - 58f64 * e_prime2) * a.powi(5) / 120f64);
+    let northing = scale_factor * (meridian_arc(lat) - meridian_arc(latitude_of_origin) + n * lat.tan() * (a * a / 2f64
+        + (5f64 - t + 9f64 * c + 4f64 * c * c) * a.powi(4) / 24f64
+        + (61f64 - 58f64 * t + t * t + 600f64 * c - 330f64 * e_prime2) * a.powi(6) / 720f64));
+    (easting, northing)
+}
+
+/// the opposite of `transverse_mercator_forward`
+fn transverse_mercator_inverse(easting: f64, northing: f64, scale_factor: f64, latitude_of_origin: f64) -> (f64, f64) {
+    let e2 = eccentricity_squared();
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    let e_prime2 = e2 / (1f64 - e2);
+    let arc = meridian_arc(latitude_of_origin) + northing / scale_factor;
+    let mu = arc / (GRS80_A * (1f64 - e2 / 4f64 - 3f64 * e4 / 64f64 - 5f64 * e6 / 256f64));
+    let e1 = (1f64 - (1f64 - e2).sqrt()) / (1f64 + (1f64 - e2).sqrt());
+
+    // latitude of the point on the central meridian with the same northing
+    let lat_1 = mu
+        + (3f64 * e1 / 2f64 - 27f64 * e1.powi(3) / 32f64) * (2f64 * mu).sin()
+        + (21f64 * e1 * e1 / 16f64 - 55f64 * e1.powi(4) / 32f64) * (4f64 * mu).sin()
+        + (151f64 * e1.powi(3) / 96f64) * (6f64 * mu).sin()
+        + (1097f64 * e1.powi(4) / 512f64) * (8f64 * mu).sin();
+
+    let c = e_prime2 * lat_1.cos().powi(2);
+    let t = lat_1.tan().powi(2);
+    let n = GRS80_A / (1f64 - e2 * lat_1.sin().powi(2)).sqrt();
+    let r = GRS80_A * (1f64 - e2) / (1f64 - e2 * lat_1.sin().powi(2)).powf(1.5);
+    let d = easting / (n * scale_factor);
+
+    let lat = lat_1 - (n * lat_1.tan() / r) * (d * d / 2f64
+        - (5f64 + 3f64 * t + 10f64 * c - 4f64 * c * c - 9f64 * e_prime2) * d.powi(4) / 24f64
+        + (61f64 + 90f64 * t + 298f64 * c + 45f64 * t * t - 252f64 * e_prime2 - 3f64 * c * c) * d.powi(6) / 720f64);
+    let lon_offset = (d
+        - (1f64 + 2f64 * t + c) * d.powi(3) / 6f64
+        + (5f64 - 2f64 * c + 28f64 * t - 3f64 * c * c + 8f64 * e_prime2 + 24f64 * t * t) * d.powi(5) / 120f64) / lat_1.cos();
+    (lat, lon_offset)
+}
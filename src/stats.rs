@@ -0,0 +1,180 @@
+use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::height_map::{check_z_range, HeightMap};
+
+/// Summary of the heights in a `HeightMap`, to pick clamping, terracing and z scale values without eyeballing the image.
+/// Empty cells (at or below `bounds.min_z`) aren't counted, except in `num_empty`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeightMapStats {
+    /// number of cells with data
+    pub num_cells: usize,
+    /// number of empty cells
+    pub num_empty: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub p1: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Display for HeightMapStats {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{} cells ({} empty), min: {:.2}, max: {:.2}, mean: {:.2}, std dev: {:.2}, \
+            percentiles: [1%: {:.2}, 5%: {:.2}, 25%: {:.2}, 50%: {:.2}, 75%: {:.2}, 95%: {:.2}, 99%: {:.2}]",
+               self.num_cells, self.num_empty, self.min, self.max, self.mean, self.std_dev,
+               self.p1, self.p5, self.p25, self.median, self.p75, self.p95, self.p99)
+    }
+}
+
+/// Which heights the image brightness and STL height are stretched over
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ZRange {
+    /// from `bounds.min_z` to `bounds.max_z`
+    Full,
+    /// between two percentiles (0 to 100) of the cells with data, like 1 and 99,
+    /// so a few outlier cells (a quarry pit, a radio tower) don't flatten everything else
+    Percentile{
+        low: f64,
+        high: f64,
+    },
+    /// between two heights
+    Fixed{
+        min: f64,
+        max: f64,
+    },
+}
+
+/// Number of cells in equally sized height ranges between `min` and `max`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub min: f64,
+    pub max: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// the height range each bin covers
+    pub fn get_bin_width(&self) -> f64 {
+        (self.max - self.min) / self.counts.len() as f64
+    }
+
+    /// gets the lowest and highest height that goes into bin `index`
+    pub fn get_bin_range(&self, index: usize) -> (f64, f64) {
+        let bin_width = self.get_bin_width();
+        (self.min + index as f64 * bin_width, self.min + (index + 1) as f64 * bin_width)
+    }
+}
+
+impl HeightMap {
+    /// gets the heights of every cell with data, sorted
+    fn get_sorted_heights(&self) -> Vec<f64> {
+        let mut heights: Vec<f64> = self.data.iter()
+            .copied()
+            .filter(|height| !self.is_empty_height(*height))
+            .collect();
+        heights.sort_unstable_by(|a, b| a.total_cmp(b));
+        heights
+    }
+
+    /// Gets the min, max, mean, standard deviation and percentiles of the heights.
+    /// `None` if every cell is empty.
+    pub fn stats(&self) -> Option<HeightMapStats> {
+        let heights = self.get_sorted_heights();
+        if heights.is_empty() {
+            return None
+        }
+
+        let num_cells = heights.len();
+        let mean = heights.iter().sum::<f64>() / num_cells as f64;
+        let variance = heights.iter().map(|height| (height - mean).powi(2)).sum::<f64>() / num_cells as f64;
+        let percentile = |percentile: f64| heights[((percentile / 100f64) * (num_cells - 1) as f64).round() as usize];
+
+        Some(HeightMapStats {
+            num_cells,
+            num_empty: self.data.len() - num_cells,
+            min: heights[0],
+            max: heights[num_cells - 1],
+            mean,
+            std_dev: variance.sqrt(),
+            p1: percentile(1f64),
+            p5: percentile(5f64),
+            p25: percentile(25f64),
+            median: percentile(50f64),
+            p75: percentile(75f64),
+            p95: percentile(95f64),
+            p99: percentile(99f64),
+        })
+    }
+
+    /// Gets the height that `percentile` percent (0 to 100) of the cells with data are below.
+    /// `None` if every cell is empty.
+    ///
+    /// e.g. `hm.clamp(hm.percentile(1.0)?, hm.percentile(99.0)?)` to cut off the most extreme 1% on both ends.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        let heights = self.get_sorted_heights();
+        if heights.is_empty() {
+            return None
+        }
+        let index = ((percentile.clamp(0f64, 100f64) / 100f64) * (heights.len() - 1) as f64).round() as usize;
+        Some(heights[index])
+    }
+
+    /// Gets the (min, max) heights of `z_range`. Percentiles of a heightmap with no data fall back to the bounds
+    ///
+    /// Returns `LasToStlError::InvalidZRangeError` if the min is above the max (like `ZRange::Fixed{min: 10.0, max: 0.0}`,
+    /// or a low percentile above the high one) or either isn't a finite number
+    pub fn get_z_range(&self, z_range: ZRange) -> Result<(f64, f64), LasToStlError> {
+        let (min, max) = match z_range {
+            ZRange::Full => {
+                (self.bounds.min_z, self.bounds.max_z)
+            }
+            ZRange::Percentile { low, high } => {
+                (self.percentile(low).unwrap_or(self.bounds.min_z), self.percentile(high).unwrap_or(self.bounds.max_z))
+            }
+            ZRange::Fixed { min, max } => {
+                (min, max)
+            }
+        };
+        check_z_range(min, max)?;
+        Ok((min, max))
+    }
+
+    /// Clamps every height to `z_range` (see `clamp`). Do this before `save_as_stl` to stop a few outliers
+    /// from making the model a flat plate with a spike, e.g. `hm.clip_z_range(ZRange::Percentile{low: 1.0, high: 99.0})`
+    pub fn clip_z_range(&mut self, z_range: ZRange) -> Result<(), LasToStlError> {
+        let (min, max) = self.get_z_range(z_range)?;
+        self.clamp(min, max)
+    }
+
+    /// Counts the cells with data in `bins` equally sized height ranges between the lowest and highest cell with data.
+    /// `None` if every cell is empty or `bins` is 0.
+    pub fn histogram(&self, bins: usize) -> Option<Histogram> {
+        let heights = self.get_sorted_heights();
+        if heights.is_empty() || bins == 0 {
+            return None
+        }
+
+        let mut histogram = Histogram {
+            min: heights[0],
+            max: heights[heights.len() - 1],
+            counts: vec![0; bins],
+        };
+        let bin_width = histogram.get_bin_width();
+        for height in heights {
+            let bin = if bin_width > 0f64 {
+                (((height - histogram.min) / bin_width) as usize).min(bins - 1)
+            } else {
+                0
+            };
+            histogram.counts[bin] += 1;
+        }
+        Some(histogram)
+    }
+}
@@ -1,95 +1,384 @@
-use std::fs::OpenOptions;
-use std::time::SystemTime;
-use log::{debug, error, info};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use log::{debug, error, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use stl_io::{Normal, Triangle, Vector, Vertex};
 use crate::errors::LasToStlError;
 use crate::height_map::HeightMap;
 use crate::mask::Mask;
+use crate::progress::{LogProgress, NoProgress, ProgressSink};
+use crate::stats::ZRange;
+
+use crate::utils::{install_in_pool, normal_pos_or_default, x_y_to_index, Stopwatch};
+
+/// How big the model comes out. STL files don't have units, but slicers assume millimeters
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StlScale {
+    /// one unit per cell in x and y, and `z_scaling` times the height in meters converted to x units.
+    /// The size depends on the resolution, and the model is stretched if the cells aren't square
+    Pixels,
+    /// the model is this many mm wide (in x), with y and z in real proportion
+    ModelWidthMm(f64),
+    /// every meter of terrain is this many mm, e.g. 1:25000 scale is `MmPerMeter(0.04)`
+    MmPerMeter(f64),
+}
+
+/// Binary or ASCII STL
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum StlFormat {
+    /// small and fast, what everything expects
+    #[default]
+    Binary,
+    /// human readable text, several times bigger. Useful for debugging
+    Ascii,
+}
+
+/// What to do when saving to a file that already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OverwritePolicy {
+    /// return an IO error instead of touching it
+    #[default]
+    Error,
+    /// replace it
+    Overwrite,
+}
+
+impl OverwritePolicy {
+    /// opens `path` for writing, following the policy
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File, LasToStlError>{
+        let file = match self {
+            OverwritePolicy::Error => OpenOptions::new().write(true).create_new(true).open(path)?,
+            OverwritePolicy::Overwrite => File::create(path)?,
+        };
+        Ok(file)
+    }
+
+    /// saves a file at `path` with `write`, following the policy. It's written to `{path}.partial` and only moved
+    /// onto `path` once `write` is done, so a save that fails or is cancelled doesn't leave half a file behind,
+    /// or (with `Overwrite`) delete the file that was there
+    pub fn save<P: AsRef<Path>, F: FnOnce(&mut File) -> Result<(), LasToStlError>>(&self, path: P, write: F) -> Result<(), LasToStlError>{
+        let path = path.as_ref();
+        // with `Error`, the name is taken right away (empty), so it fails before doing any work if the file exists
+        let took_name = match self {
+            OverwritePolicy::Error => { OpenOptions::new().write(true).create_new(true).open(path)?; true }
+            OverwritePolicy::Overwrite => false,
+        };
+        let mut partial_path = path.as_os_str().to_os_string();
+        partial_path.push(".partial");
+
+        let result = File::create(&partial_path).map_err(LasToStlError::from).and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()?;
+            Ok(())
+        }).and_then(|_| Ok(std::fs::rename(&partial_path, path)?));
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&partial_path);
+            if took_name {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        result
+    }
+}
+
+/// A rectangular wall around the model, like a picture frame. It's its own closed body touching the sides of the model
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StlFrame {
+    /// how far it sticks out from the sides of the model, in model units
+    pub width: f32,
+    /// how tall it is from the bottom of the model, in model units
+    pub height: f32,
+}
+
+/// Where the bottom of the model is and what goes around it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BaseOptions {
+    /// The elevation (in meters, same as the heightmap) that ends up at the bottom of the model, instead of `bounds.min_z`.
+    /// Handy for making several tiles with the same floor. Anything lower is flattened to it
+    pub floor_elevation: Option<f64>,
+    /// Adds a frame (or a skirt if it's not very wide) around the model. See `StlFrame`
+    pub frame: Option<StlFrame>,
+}
+
+/// How much `get_mesh` checks the mesh (with `validate_mesh`) before it's used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MeshValidation {
+    /// don't check. Checking takes about as much memory as the faces, which adds up on huge meshes
+    Off,
+    /// log whatever is wrong, but use the mesh anyway
+    Warn,
+    /// holes are errors. Non-manifold edges (where two parts of a mask only touch at a corner) and triangles
+    /// with no area (like the sides where the terrain is on the floor and there's no base) are only logged,
+    /// because slicers handle them fine
+    #[default]
+    RejectLeaks,
+    /// anything wrong is an error
+    Strict,
+}
+
+/// What `validate_mesh` found wrong with a mesh
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeshReport {
+    /// edges with a triangle on only one side, so there's a hole there
+    pub boundary_edges: usize,
+    /// edges with more than two triangles, or two that face opposite ways
+    pub non_manifold_edges: usize,
+    /// triangles with no area
+    pub degenerate_triangles: usize,
+}
+
+impl MeshReport {
+    /// whether the mesh is closed with every edge between exactly two triangles, like slicers want
+    pub fn is_watertight(&self) -> bool{
+        self.boundary_edges == 0 && self.non_manifold_edges == 0
+    }
+
+    /// logs or returns the problems, depending on `validation`
+    pub fn check(&self, validation: MeshValidation) -> Result<(), LasToStlError>{
+        let (leaks_are_errors, everything_is_an_error) = match validation {
+            MeshValidation::Off => return Ok(()),
+            MeshValidation::Warn => (false, false),
+            MeshValidation::RejectLeaks => (true, false),
+            MeshValidation::Strict => (true, true),
+        };
+        if self.boundary_edges > 0 {
+            if leaks_are_errors {
+                return Err(LasToStlError::LeakyMeshError{ boundary_edges: self.boundary_edges })
+            }
+            warn!("the mesh has {} edges with only one triangle, so it has holes", self.boundary_edges);
+        }
+        if self.non_manifold_edges > 0 {
+            if everything_is_an_error {
+                return Err(LasToStlError::NonManifoldMeshError{ non_manifold_edges: self.non_manifold_edges })
+            }
+            warn!("the mesh has {} non-manifold edges", self.non_manifold_edges);
+        }
+        if self.degenerate_triangles > 0 {
+            if everything_is_an_error {
+                return Err(LasToStlError::DegenerateTrianglesError{ degenerate_triangles: self.degenerate_triangles })
+            }
+            debug!("the mesh has {} triangles with no area", self.degenerate_triangles);
+        }
+        Ok(())
+    }
+}
+
+/// Settings for saving a heightmap as an STL
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StlOptions {
+    /// How big the model comes out. See `StlScale`
+    pub scale: StlScale,
+    /// Exaggerates the heights, 1 is real proportions (except with `StlScale::Pixels`, see its docs)
+    pub z_scaling: f64,
+    /// Added to every height so the printed part is a little stronger, in the same units as the model
+    pub base_thickness: f32,
+    /// Binary or ASCII. See `StlFormat`
+    pub format: StlFormat,
+    /// What to do if the file already exists. Not used when writing to a writer. See `OverwritePolicy`
+    pub overwrite: OverwritePolicy,
+    /// Simplify the top surface: square blocks of cells that are within this many meters (before `z_scaling`)
+    /// of flat are merged into a few big triangles, so flat farmland isn't millions of tiny ones while ridges keep their detail.
+    /// `None` keeps every cell. Masked exports ignore it
+    pub max_error: Option<f64>,
+    /// Number of threads assembling the faces. `None` uses one thread per core
+    pub num_threads: Option<usize>,
+    /// Make a shell instead of a solid block: the bottom follows the top this far below it (in model units) instead of being flat at 0.
+    /// Mountains come out a lot lighter. The bottom never goes below 0, so low areas are still solid. `None` is a solid block
+    pub shell_thickness: Option<f32>,
+    /// Where the bottom of the model is and what goes around it. See `BaseOptions`
+    pub base: BaseOptions,
+    /// How much the mesh is checked before it's saved. See `MeshValidation`
+    pub validation: MeshValidation,
+    /// Heights outside of this range are clamped to it before meshing (see `clip_z_range`), so the bottom of the model
+    /// is the bottom of the range. e.g. `ZRange::Percentile{low: 1.0, high: 99.0}` so a few outliers don't squash the relief
+    pub z_range: ZRange,
+}
+
+impl StlOptions {
+    /// runs `op` on `num_threads` threads, see `utils::install_in_pool`
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> Result<R, LasToStlError>{
+        install_in_pool(self.num_threads, op)
+    }
+}
 
-use crate::utils::{normal_pos_or_default, x_y_to_index};
+impl Default for StlOptions {
+    fn default() -> Self {
+        StlOptions {
+            scale: StlScale::Pixels,
+            z_scaling: 1f64,
+            base_thickness: 0f32,
+            format: StlFormat::default(),
+            overwrite: OverwritePolicy::default(),
+            max_error: None,
+            num_threads: None,
+            shell_thickness: None,
+            base: BaseOptions::default(),
+            validation: MeshValidation::default(),
+            z_range: ZRange::Full,
+        }
+    }
+}
 
 impl HeightMap {
+    /// The bottom of the model is `bounds.min_z` (see `BaseOptions::floor_elevation` to change it).
+    /// If a few outlier cells squash the rest of the relief, set `StlOptions::z_range` to a `ZRange::Percentile`
     pub fn save_as_stl(&self, path: &str, z_scaling: f64, base_thickness: f32) -> Result<(), LasToStlError>{
+        self.save_as_stl_with_options(path, &StlOptions {
+            z_scaling,
+            base_thickness,
+            ..StlOptions::default()
+        })
+    }
 
-        println!("saving as stl");
+    /// Same as `save_as_stl`, but with more settings, like the size of the model. See `StlOptions`
+    pub fn save_as_stl_with_options(&self, path: &str, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.save_as_stl_with_progress(path, options, &LogProgress)
+    }
 
-        let now = SystemTime::now();
+    /// Same as `save_as_stl_with_options`, but reports progress to `progress`.
+    /// Every group of faces (top and bottom, each side) is its own stage.
+    pub fn save_as_stl_with_progress(&self, path: &str, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        options.overwrite.save(path, |file| self.write_stl_with_progress(file, options, progress))
+    }
 
-        let z_scale_factor = z_scaling * self.x_res as f64 / self.bounds.x_range();
+    /// Same as `save_as_stl_with_options`, but writes the STL into `writer` (like a `Vec<u8>` or a socket) instead of a file
+    pub fn write_stl<W: Write>(&self, writer: &mut W, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.write_stl_with_progress(writer, options, &LogProgress)
+    }
 
-        let data_length = self.x_res * self.y_res;
+    /// Same as `write_stl`, but reports progress to `progress`
+    pub fn write_stl_with_progress<W: Write>(&self, writer: &mut W, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
 
-        info!("assembling vertex lists");
+        debug!("saving as stl");
 
-        let top_vertex_list: Vec<Vertex> = self.data.iter().enumerate().map(|(index, height)| {
-            let x = index % self.x_res;
-            let y = index / self.x_res;
-            Vertex::new([x as f32, y as f32, (normal_pos_or_default(height - self.bounds.min_z, 0f64) * z_scale_factor) as f32 + base_thickness])
-        }).collect();
+        let stopwatch = Stopwatch::start();
 
-        let bottom_vertex_list: Vec<Vertex> = self.data.iter().enumerate().map(|(index, _height)| {
-            let x = index % self.x_res;
-            let y = index / self.x_res;
-            Vertex::new([x as f32, y as f32, 0f32])
-        }).collect();
+        let mesh = self.get_mesh_with_progress(options, progress)?;
+        mesh.write_stl(writer, options.format)?;
 
-        info!("assembled vertex lists");
+        debug!("saved as stl. took {:?}", stopwatch.elapsed());
 
-        let total_triangles = (4 * data_length) + (4 * self.x_res) + (4 * self.y_res);
+        Ok(())
+    }
 
-        let mut triangle_list: Vec<Triangle> = Vec::with_capacity(total_triangles);
+    /// Assembles the closed mesh `save_as_stl` saves, with every vertex stored once.
+    /// Useful for writing other formats
+    pub fn get_mesh(&self, options: &StlOptions) -> Result<IndexedMesh, LasToStlError>{
+        self.get_mesh_with_progress(options, &LogProgress)
+    }
 
-        for x in 0..self.x_res-1{
-            for y in 0..self.y_res-1{
-                triangle_list.extend(vertex_rec_to_triangles_diagonal(
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
-                    Normal::from(Vector::new([0f32, 0f32, 1f32]))
-                ));
-
-                triangle_list.extend(vertex_rec_to_triangles_diagonal(
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
-                    Normal::from(Vector::new([0f32, 0f32, -1f32]))
-                ));
-            }
+    /// Same as `get_mesh`, but reports progress to `progress`
+    pub fn get_mesh_with_progress(&self, options: &StlOptions, progress: &dyn ProgressSink) -> Result<IndexedMesh, LasToStlError>{
+        if let Some((clipped, options)) = self.clip_for_stl(options)? {
+            return clipped.get_mesh_with_progress(&options, progress)
+        }
+        self.check_meshable(options)?;
+        let mut mesh = match options.max_error {
+            Some(max_error) => self.get_decimated_mesh(max_error, options, progress)?,
+            None => self.get_full_mesh(options, progress)?,
+        };
+        if let Some(frame) = options.base.frame {
+            self.add_frame(&mut mesh, &frame, (0, 0, self.x_res - 1, self.y_res - 1), options);
         }
+        mesh.recompute_normals();
+        mesh.check(options.validation, progress)?;
+        Ok(mesh)
+    }
+
+    /// the mesh with every cell
+    pub(crate) fn get_full_mesh(&self, options: &StlOptions, progress: &dyn ProgressSink) -> Result<IndexedMesh, LasToStlError>{
+
+        let data_length = self.x_res * self.y_res;
+
+        progress.on_stage("assembling vertex lists");
+
+        let total_triangles = (4 * data_length) + (4 * self.x_res) + (4 * self.y_res);
+
+        let mut mesh = IndexedMesh::with_capacity(2 * data_length, total_triangles);
+        let get_stl_vertex = self.get_stl_vertex_fn(options);
+
+        let top_vertex_list: Vec<u32> = self.data.iter().enumerate().map(|(index, height)| {
+            mesh.push_vertex(get_stl_vertex(index, Some(*height)))
+        }).collect();
+
+        let bottom_vertex_list: Vec<u32> = (0..data_length).map(|index| {
+            mesh.push_vertex(get_stl_vertex(index, None))
+        }).collect();
+
+        progress.on_finish("assembling vertex lists");
+
+        progress.on_stage("assembling top and bottom faces");
+
+        // every column of cells is done on its own thread, then they're put back together in order
+        let columns_done = AtomicUsize::new(0);
+        let columns = options.install(|| {
+            (0..self.x_res-1).into_par_iter().map(|x| {
+                progress.check_cancelled()?;
+                let mut faces: Vec<MeshFace> = Vec::with_capacity(4 * (self.y_res - 1));
+                for y in 0..self.y_res-1{
+                    faces.extend(index_rec_to_faces_diagonal(
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
+                        Normal::from(Vector::new([0f32, 0f32, 1f32]))
+                    ));
+
+                    faces.extend(index_rec_to_faces_diagonal(
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
+                        Normal::from(Vector::new([0f32, 0f32, -1f32]))
+                    ));
+                }
+                report_column_done(&columns_done, self.x_res - 1, progress);
+                Ok(faces)
+            }).collect::<Result<Vec<Vec<MeshFace>>, LasToStlError>>()
+        })??;
+        mesh.faces.extend(columns.into_iter().flatten());
 
-        info!("assembled top and bottom faces");
+        progress.on_finish("assembling top and bottom faces");
+
+        progress.on_stage("assembling north faces");
 
         // north?
         for x in 0..self.x_res-1{
-            triangle_list.extend(vertex_rec_to_triangles_diagonal(
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, self.y_res-1)?],
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, self.y_res-1)?],
+            mesh.faces.extend(index_rec_to_faces_diagonal(
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, self.y_res-1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, self.y_res-1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, self.y_res-1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, self.y_res-1)?],
                 Normal::from(Vector::new([0f32, 1f32, 0f32]))
             ))
         }
 
-        info!("assembled north faces");
+        progress.on_finish("assembling north faces");
+
+        progress.on_stage("assembling south faces");
 
         // south?
         for x in 0..self.x_res-1{
-            triangle_list.extend(vertex_rec_to_triangles_diagonal(
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, 0)?],
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, 0)?],
+            mesh.faces.extend(index_rec_to_faces_diagonal(
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, 0)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, 0)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, 0)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, 0)?],
                 Normal::from(Vector::new([0f32, -1f32, 0f32]))
             ))
         }
 
-        info!("assembled south faces");
+        progress.on_finish("assembling south faces");
+
+        progress.on_stage("assembling east faces");
 
         // east?
         for y in 0..self.y_res-1{
-            triangle_list.extend(vertex_rec_to_triangles_diagonal(
+            mesh.faces.extend(index_rec_to_faces_diagonal(
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, self.x_res-1, y+1)?],
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, self.x_res-1, y)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, self.x_res-1, y)?],
@@ -98,11 +387,13 @@ impl HeightMap {
             ))
         }
 
-        info!("assembled east faces");
+        progress.on_finish("assembling east faces");
+
+        progress.on_stage("assembling west faces");
 
         // west?
         for y in 0..self.y_res-1{
-            triangle_list.extend(vertex_rec_to_triangles_diagonal(
+            mesh.faces.extend(index_rec_to_faces_diagonal(
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, 0, y)?],
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, 0, y+1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, 0, y+1)?],
@@ -111,101 +402,176 @@ impl HeightMap {
             ))
         }
 
-        info!("assembled west faces");
+        progress.on_finish("assembling west faces");
+
+        Ok(mesh)
+    }
 
-        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?; // .create_new(true)
-        stl_io::write_stl(&mut file, triangle_list.iter())?;
+    pub fn save_as_stl_masked(&self, path: &str, mask: &Mask, z_scaling: f64, base_thickness: f32) -> Result<(), LasToStlError>{
+        self.save_as_stl_masked_with_options(path, mask, &StlOptions {
+            z_scaling,
+            base_thickness,
+            ..StlOptions::default()
+        })
+    }
 
-        debug!("saved as stl. took {:?}", now.elapsed());
+    /// Same as `save_as_stl_masked`, but with more settings, like the size of the model. See `StlOptions`
+    pub fn save_as_stl_masked_with_options(&self, path: &str, mask: &Mask, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.save_as_stl_masked_with_progress(path, mask, options, &LogProgress)
+    }
 
-        Ok(())
+    /// Same as `save_as_stl_masked_with_options`, but reports progress to `progress`.
+    /// Every group of faces (top and bottom, each edge) is its own stage.
+    pub fn save_as_stl_masked_with_progress(&self, path: &str, mask: &Mask, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        options.overwrite.save(path, |file| self.write_stl_masked_with_progress(file, mask, options, progress))
     }
 
-    pub fn save_as_stl_masked(&self, path: &str, mask: &Mask, z_scaling: f64, base_thickness: f32) -> Result<(), LasToStlError>{
+    /// Same as `save_as_stl_masked_with_options`, but writes the STL into `writer` instead of a file
+    pub fn write_stl_masked<W: Write>(&self, writer: &mut W, mask: &Mask, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.write_stl_masked_with_progress(writer, mask, options, &LogProgress)
+    }
+
+    /// Same as `write_stl_masked`, but reports progress to `progress`
+    pub fn write_stl_masked_with_progress<W: Write>(&self, writer: &mut W, mask: &Mask, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
 
         debug!("save as stl masked");
 
-        let now = SystemTime::now();
+        let stopwatch = Stopwatch::start();
+
+        let mesh = self.get_mesh_masked_with_progress(mask, options, progress)?;
+        mesh.write_stl(writer, options.format)?;
+
+        debug!("save as stl masked top and bottom done in {:?}", stopwatch.elapsed());
+
+        Ok(())
+    }
+
+    /// Same as `get_mesh`, but only where `mask` is true, like `save_as_stl_masked`
+    pub fn get_mesh_masked(&self, mask: &Mask, options: &StlOptions) -> Result<IndexedMesh, LasToStlError>{
+        self.get_mesh_masked_with_progress(mask, options, &LogProgress)
+    }
+
+    /// Same as `get_mesh_masked`, but reports progress to `progress`
+    pub fn get_mesh_masked_with_progress(&self, mask: &Mask, options: &StlOptions, progress: &dyn ProgressSink) -> Result<IndexedMesh, LasToStlError>{
+        if let Some((clipped, options)) = self.clip_for_stl(options)? {
+            return clipped.get_mesh_masked_with_progress(mask, &options, progress)
+        }
+        self.check_meshable(options)?;
+        self.check_mask_matches(mask)?;
+        if options.max_error.is_some() {
+            warn!("`max_error` is ignored for masked STLs, every cell is kept");
+        }
+
+        progress.on_stage("calculating edges");
+
+        let stl_helper_mask = StlHelperMask::from(mask);
+
+        // only pixels that are the corner of a cell with all four corners in the mask end up in the mesh
+        let mut used = vec![false; mask.data.len()];
+        for x in 0..stl_helper_mask.x_res {
+            for y in 0..stl_helper_mask.y_res {
+                if stl_helper_mask.get_by_xy_unchecked(x, y) {
+                    for (corner_x, corner_y) in [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)] {
+                        used[corner_y * self.x_res + corner_x] = true;
+                    }
+                }
+            }
+        }
+        let num_used = used.iter().filter(|used| **used).count();
+        if num_used == 0 {
+            return Err(LasToStlError::EmptyMaskMeshError)
+        }
+        let num_left_out = mask.data.iter().filter(|set| **set).count() - num_used;
+        if num_left_out > 0 {
+            warn!("{} mask pixels are in parts less than 2 pixels wide, so they're left out of the mesh. \
+                `Mask::dilate` the mask to keep them", num_left_out);
+        }
+
+        let x_pos_edges = stl_helper_mask.get_cardinal_edge(true, true);
+        let x_neg_edges = stl_helper_mask.get_cardinal_edge(true, false);
+        let y_pos_edges = stl_helper_mask.get_cardinal_edge(false, true);
+        let y_neg_edges = stl_helper_mask.get_cardinal_edge(false, false);
+
+        progress.on_finish("calculating edges");
 
-        let z_scale_factor = z_scaling * self.x_res as f64 / self.bounds.x_range();
+        progress.on_stage("assembling vertex lists");
 
-        let top_vertex_list: Vec<Option<Vertex>> = self.data.iter().enumerate().map(|(index, height)| {
-            match mask.data[index]{
+        let mut mesh = IndexedMesh::default();
+        let get_stl_vertex = self.get_stl_vertex_fn(options);
+
+        let top_vertex_list: Vec<Option<u32>> = self.data.iter().enumerate().map(|(index, height)| {
+            match used[index]{
                 false => {
                     None
                 }
                 true => {
-                    let x = index % self.x_res;
-                    let y = index / self.x_res;
-                    Some(Vertex::new([x as f32, y as f32, (normal_pos_or_default(height - self.bounds.min_z, 0f64) * z_scale_factor) as f32 + base_thickness]))
+                    Some(mesh.push_vertex(get_stl_vertex(index, Some(*height))))
                 }
             }
 
-        }).collect::<Vec<Option<Vertex>>>();
+        }).collect::<Vec<Option<u32>>>();
 
-        let bottom_vertex_list: Vec<Option<Vertex>> = self.data.iter().enumerate().map(|(index, _height)| {
-            match mask.data[index]{
+        let bottom_vertex_list: Vec<Option<u32>> = (0..self.data.len()).map(|index| {
+            match used[index]{
                 false => {
                     None
                 }
                 true => {
-                    let x = index % self.x_res;
-                    let y = index / self.x_res;
-                    Some(Vertex::new([x as f32, y as f32, 0f32]))
+                    Some(mesh.push_vertex(get_stl_vertex(index, None)))
                 }
             }
 
-        }).collect::<Vec<Option<Vertex>>>();
+        }).collect::<Vec<Option<u32>>>();
 
-        info!("assembled vertex lists");
+        progress.on_finish("assembling vertex lists");
 
-        let mut triangle_list: Vec<Triangle> = Vec::new();
+        progress.on_stage("assembling top and bottom faces");
 
-        for x in 0..self.x_res-1{
-            for y in 0..self.y_res-1{
-
-                let top_vertices = option_vertex_rec_to_triangles_diagonal(
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
-                    top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
-                    Normal::from(Vector::new([0f32, 0f32, 1f32]))
-                );
+        // every column of cells is done on its own thread, then they're put back together in order
+        let columns_done = AtomicUsize::new(0);
+        let columns = options.install(|| {
+            (0..self.x_res-1).into_par_iter().map(|x| {
+                progress.check_cancelled()?;
+                let mut faces: Vec<MeshFace> = Vec::new();
+                for y in 0..self.y_res-1{
 
-                if top_vertices.is_some(){
-                    triangle_list.extend(top_vertices.unwrap() /*safe unwrap*/);
-                }
+                    let top_vertices = option_index_rec_to_faces_diagonal(
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
+                        top_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
+                        Normal::from(Vector::new([0f32, 0f32, 1f32]))
+                    );
 
-                let bottom_vertices = option_vertex_rec_to_triangles_diagonal(
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
-                    bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
-                    Normal::from(Vector::new([0f32, 0f32, -1f32]))
-                );
+                    if let Some(top_faces) = top_vertices{
+                        faces.extend(top_faces);
+                    }
 
-                if bottom_vertices.is_some(){
-                    triangle_list.extend(bottom_vertices.unwrap() /*safe unwrap*/);
-                }
-            }
-        }
+                    let bottom_vertices = option_index_rec_to_faces_diagonal(
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x, y+1)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y+1)?],
+                        bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, x+1, y)?],
+                        Normal::from(Vector::new([0f32, 0f32, -1f32]))
+                    );
 
-        info!("assembled top and bottom faces");
+                    if let Some(bottom_faces) = bottom_vertices{
+                        faces.extend(bottom_faces);
+                    }
+                }
+                report_column_done(&columns_done, self.x_res - 1, progress);
+                Ok(faces)
+            }).collect::<Result<Vec<Vec<MeshFace>>, LasToStlError>>()
+        })??;
+        mesh.faces.extend(columns.into_iter().flatten());
 
-        let stl_helper_mask = StlHelperMask::from(mask);
+        progress.on_finish("assembling top and bottom faces");
 
-        let x_pos_edges = stl_helper_mask.get_cardinal_edge(true, true);
-        info!("calculated east edge faces");
-        let x_neg_edges = stl_helper_mask.get_cardinal_edge(true, false);
-        info!("calculated west edge faces");
-        let y_pos_edges = stl_helper_mask.get_cardinal_edge(false, true);
-        info!("calculated north edge faces");
-        let y_neg_edges = stl_helper_mask.get_cardinal_edge(false, false);
-        info!("calculated south edge faces");
+        progress.on_stage("assembling east edge faces");
 
         for edge_coord in x_pos_edges{
 
-            match option_vertex_rec_to_triangles_diagonal(
+            match option_index_rec_to_faces_diagonal(
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, (edge_coord.1)+1)?],
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1)?],
@@ -213,19 +579,22 @@ impl HeightMap {
                 Normal::from(Vector::new([1f32, 0f32, 0f32]))
             ){
                 Some(faces) => {
-                    triangle_list.extend(faces);
+                    mesh.faces.extend(faces);
                 }
                 None => {
-                    error!("Attempted to build a face from a vertex that doesn't exist. Skipping");
+                    error!("Attempted to build a face from a vertex that doesn't exist");
                     debug!("edge coord: {:?}", edge_coord);
+                    return Err(LasToStlError::StlSideFaceGenerationError)
                 }
             }
         }
 
-        info!("assembled east edge faces");
+        progress.on_finish("assembling east edge faces");
+
+        progress.on_stage("assembling west edge faces");
 
         for edge_coord in x_neg_edges{
-            match option_vertex_rec_to_triangles_diagonal(
+            match option_index_rec_to_faces_diagonal(
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1)?],
                 top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1 + 1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1 + 1)?],
@@ -233,68 +602,576 @@ impl HeightMap {
                 Normal::from(Vector::new([-1f32, 0f32, 0f32]))
             ){
                 Some(faces) => {
-                    triangle_list.extend(faces);
+                    mesh.faces.extend(faces);
                 }
                 None => {
-                    error!("Attempted to build a face from a vertex that doesn't exist. Skipping");
+                    error!("Attempted to build a face from a vertex that doesn't exist");
                     debug!("edge coord: {:?}", edge_coord);
+                    return Err(LasToStlError::StlSideFaceGenerationError)
                 }
             }
         }
 
-        info!("assembled west edge faces");
+        progress.on_finish("assembling west edge faces");
+
+        progress.on_stage("assembling north edge faces");
 
         for edge_coord in y_pos_edges{
-            match option_vertex_rec_to_triangles_diagonal(
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1 + 1)?],
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1 + 1)?],
+            match option_index_rec_to_faces_diagonal(
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1 + 1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1 + 1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1 + 1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1 + 1)?],
                 Normal::from(Vector::new([0f32, 1f32, 0f32]))
             ){
                 Some(faces) => {
-                    triangle_list.extend(faces);
+                    mesh.faces.extend(faces);
                 }
                 None => {
-                    error!("Attempted to build a face from a vertex that doesn't exist. Skipping");
+                    error!("Attempted to build a face from a vertex that doesn't exist");
                     debug!("edge coord: {:?}", edge_coord);
+                    return Err(LasToStlError::StlSideFaceGenerationError)
                 }
             }
         }
 
-        info!("assembled north edge faces");
+        progress.on_finish("assembling north edge faces");
+
+        progress.on_stage("assembling south edge faces");
 
         for edge_coord in y_neg_edges{
 
-            match option_vertex_rec_to_triangles_diagonal(
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1)?],
-                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1)?],
+            match option_index_rec_to_faces_diagonal(
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0 + 1, edge_coord.1)?],
+                top_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1)?],
                 bottom_vertex_list[x_y_to_index(self.x_res, self.y_res, edge_coord.0, edge_coord.1)?],
                 Normal::from(Vector::new([0f32, -1f32, 0f32]))
             ){
                 Some(faces) => {
-                    triangle_list.extend(faces);
+                    mesh.faces.extend(faces);
                 }
                 None => {
-                    error!("Attempted to build a face from a vertex that doesn't exist. Skipping");
+                    error!("Attempted to build a face from a vertex that doesn't exist");
                     debug!("edge coord: {:?}", edge_coord);
+                    return Err(LasToStlError::StlSideFaceGenerationError)
                 }
             }
         }
 
-        info!("assembled south edge faces");
+        progress.on_finish("assembling south edge faces");
 
-        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
-        stl_io::write_stl(&mut file, triangle_list.iter())?;
+        if let (Some(frame), Some(true_bounds)) = (options.base.frame, mask.true_bounds()) {
+            self.add_frame(&mut mesh, &frame, true_bounds, options);
+        }
+        mesh.recompute_normals();
+        mesh.check(options.validation, progress)?;
 
-        debug!("save as stl masked top and bottom done in {:?}", now.elapsed());
+        Ok(mesh)
+    }
 
+    /// Checks that the heightmap can be made into a mesh: at least 2x2 cells (every cell of the mesh is between
+    /// four of them) and bounds with some width and height, so the cells aren't all on top of each other.
+    /// Also warns if there's no base, because then cells on the floor make walls with no area.
+    ///
+    /// Returns `LasToStlError::TooSmallToMeshError`, `LasToStlError::ZeroAreaBoundsError`,
+    /// or `LasToStlError::InvalidBoundsError` (see `UtmBoundingBox::validate`)
+    pub fn check_meshable(&self, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.bounds.validate()?;
+        if self.x_res < 2 || self.y_res < 2 {
+            return Err(LasToStlError::TooSmallToMeshError{ x_res: self.x_res, y_res: self.y_res })
+        }
+        if !(self.bounds.x_range() > 0f64 && self.bounds.y_range() > 0f64) {
+            return Err(LasToStlError::ZeroAreaBoundsError(self.bounds))
+        }
+        if options.base_thickness <= 0f32 && options.shell_thickness.is_none() {
+            debug!("no base, so the walls and bottom have triangles with no area wherever the terrain is on the floor");
+        }
+        Ok(())
+    }
+
+    /// A copy of self clipped to `options.z_range`, and options that don't clip it again.
+    /// `None` if the range is `ZRange::Full`, so there's nothing to clip
+    pub(crate) fn clip_for_stl(&self, options: &StlOptions) -> Result<Option<(HeightMap, StlOptions)>, LasToStlError>{
+        if options.z_range == ZRange::Full {
+            return Ok(None)
+        }
+        let mut clipped = self.with_grid(self.data.clone(), self.x_res, self.y_res, self.bounds);
+        clipped.clip_z_range(options.z_range)?;
+        Ok(Some((clipped, StlOptions { z_range: ZRange::Full, ..options.clone() })))
+    }
+
+    /// Gets how many model units one cell is in x and y, and one meter of height is in z
+    pub fn get_stl_units(&self, options: &StlOptions) -> (f64, f64, f64){
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+        let units_per_meter = match options.scale {
+            StlScale::Pixels => {
+                return (1f64, 1f64, options.z_scaling * self.x_res as f64 / self.bounds.x_range())
+            }
+            StlScale::ModelWidthMm(width) => width / self.bounds.x_range(),
+            StlScale::MmPerMeter(mm_per_meter) => mm_per_meter,
+        };
+        (x_tick * units_per_meter, y_tick * units_per_meter, options.z_scaling * units_per_meter)
+    }
+
+    /// Same as `get_mesh`, but the top surface is a quadtree of square blocks that are each within `max_error` of flat.
+    /// Blocks next to smaller blocks are triangulated as a fan from their center through every vertex on their edges,
+    /// so there are no cracks between them. The sides and bottom only use the vertices on the edge of the top.
+    fn get_decimated_mesh(&self, max_error: f64, options: &StlOptions, progress: &dyn ProgressSink) -> Result<IndexedMesh, LasToStlError>{
+        let (cells_x, cells_y) = (self.x_res - 1, self.y_res - 1);
+
+        progress.on_stage("simplifying");
+
+        let mut block_size: usize = 1;
+        while block_size < cells_x.max(cells_y) {
+            block_size *= 2;
+        }
+        // (x, y, size) of every block, in cells
+        let mut blocks: Vec<(usize, usize, usize)> = Vec::new();
+        self.collect_flat_blocks((0, 0, block_size), max_error, self.get_stl_floor(options), &mut blocks, progress)?;
+
+        // every vertex that is the corner of a block
+        let mut used = vec![false; self.data.len()];
+        for (x, y, size) in &blocks {
+            for (corner_x, corner_y) in [(*x, *y), (x + size, *y), (*x, y + size), (x + size, y + size)] {
+                used[corner_y * self.x_res + corner_x] = true;
+            }
+        }
+
+        progress.on_finish("simplifying");
+        debug!("simplified {} cells into {} blocks", cells_x * cells_y, blocks.len());
+
+        progress.on_stage("assembling faces");
+
+        let mut mesh = IndexedMesh::default();
+        let get_stl_vertex = self.get_stl_vertex_fn(options);
+        let mut top_indices: Vec<Option<u32>> = vec![None; self.data.len()];
+        let mut get_top = |mesh: &mut IndexedMesh, x: usize, y: usize| {
+            let index = y * self.x_res + x;
+            *top_indices[index].get_or_insert_with(|| mesh.push_vertex(get_stl_vertex(index, Some(self.data[index]))))
+        };
+        let up = Normal::from(Vector::new([0f32, 0f32, 1f32]));
+
+        for (block_index, (x, y, size)) in blocks.into_iter().enumerate() {
+            if block_index.is_multiple_of(1024) {
+                progress.check_cancelled()?;
+            }
+            // the used vertices on the edge of the block, counter clockwise from the bottom left corner
+            let edge: Vec<(usize, usize)> = (0..size).map(|offset| (x + offset, y))
+                .chain((0..size).map(|offset| (x + size, y + offset)))
+                .chain((0..size).map(|offset| (x + size - offset, y + size)))
+                .chain((0..size).map(|offset| (x, y + size - offset)))
+                .filter(|(edge_x, edge_y)| used[edge_y * self.x_res + edge_x])
+                .collect();
+
+            if edge.len() == 4 {
+                let faces = index_rec_to_faces_diagonal(
+                    get_top(&mut mesh, x + size, y + size),
+                    get_top(&mut mesh, x, y + size),
+                    get_top(&mut mesh, x, y),
+                    get_top(&mut mesh, x + size, y),
+                    up
+                );
+                mesh.faces.extend(faces);
+            } else {
+                // same winding as the full resolution top faces
+                let center = get_top(&mut mesh, x + size / 2, y + size / 2);
+                let edge_indices: Vec<u32> = edge.iter().map(|(edge_x, edge_y)| get_top(&mut mesh, *edge_x, *edge_y)).collect();
+                for (i, first) in edge_indices.iter().enumerate() {
+                    let second = edge_indices[(i + 1) % edge_indices.len()];
+                    mesh.faces.push(MeshFace { normal: up, vertices: [center, *first, second] });
+                }
+            }
+        }
+
+        let num_top_faces = mesh.faces.len();
+
+        // the used vertices around the edge of the model, counter clockwise from (0, 0)
+        let outline: Vec<(usize, usize)> = (0..cells_x).map(|x| (x, 0))
+            .chain((0..cells_y).map(|y| (cells_x, y)))
+            .chain((0..cells_x).map(|x| (cells_x - x, cells_y)))
+            .chain((0..cells_y).map(|y| (0, cells_y - y)))
+            .filter(|(x, y)| used[y * self.x_res + x])
+            .collect();
+        let outline_top: Vec<u32> = outline.iter().map(|(x, y)| get_top(&mut mesh, *x, *y)).collect();
+
+        // every top vertex gets one below it for a shell, otherwise only the outline does
+        let mut bottom_indices: Vec<Option<u32>> = vec![None; self.data.len()];
+        let mut get_bottom = |mesh: &mut IndexedMesh, index: usize| {
+            *bottom_indices[index].get_or_insert_with(|| mesh.push_vertex(get_stl_vertex(index, None)))
+        };
+        let outline_bottom: Vec<u32> = outline.iter().map(|(x, y)| get_bottom(&mut mesh, y * self.x_res + x)).collect();
+
+        // the sides, with the same winding and normals as the full resolution ones
+        for i in 0..outline.len() {
+            let next = (i + 1) % outline.len();
+            let (x, y) = outline[i];
+            let normal = if y == 0 && x < cells_x {
+                Vector::new([0f32, -1f32, 0f32])
+            } else if x == cells_x && y < cells_y {
+                Vector::new([1f32, 0f32, 0f32])
+            } else if y == cells_y && x > 0 {
+                Vector::new([0f32, 1f32, 0f32])
+            } else {
+                Vector::new([-1f32, 0f32, 0f32])
+            };
+            mesh.faces.extend(index_rec_to_faces_diagonal(
+                outline_bottom[next],
+                outline_top[next],
+                outline_top[i],
+                outline_bottom[i],
+                Normal::from(normal)
+            ));
+        }
+
+        let down = Normal::from(Vector::new([0f32, 0f32, -1f32]));
+        if options.shell_thickness.is_some() {
+            // the bottom follows the top, so it's the top faces flipped over
+            // the cell every top vertex is at
+            let mut cell_indices = vec![0usize; mesh.vertices.len()];
+            for (index, top) in top_indices.iter().enumerate() {
+                if let Some(top) = top {
+                    cell_indices[*top as usize] = index;
+                }
+            }
+            for face_index in 0..num_top_faces {
+                let [first, second, third] = mesh.faces[face_index].vertices.map(|top| get_bottom(&mut mesh, cell_indices[top as usize]));
+                mesh.faces.push(MeshFace { normal: down, vertices: [first, third, second] });
+            }
+        } else {
+            // the bottom is flat, so a fan from its center is enough
+            let (x_unit, y_unit, _) = self.get_stl_units(options);
+            let bottom_center = mesh.push_vertex(Vertex::new([
+                (cells_x as f64 * x_unit / 2f64) as f32,
+                (cells_y as f64 * y_unit / 2f64) as f32,
+                0f32
+            ]));
+            for i in 0..outline.len() {
+                let next = (i + 1) % outline.len();
+                mesh.faces.push(MeshFace { normal: down, vertices: [bottom_center, outline_bottom[next], outline_bottom[i]] });
+            }
+        }
+
+        progress.on_finish("assembling faces");
+
+        Ok(mesh)
+    }
+
+    /// splits the square block (x, y, size) (in cells) into quarters until every block is within `max_error` of its
+    /// two triangles, or one cell. Parts of blocks outside of the grid are dropped
+    fn collect_flat_blocks(&self, block: (usize, usize, usize), max_error: f64, floor: f64, blocks: &mut Vec<(usize, usize, usize)>, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        let (x, y, size) = block;
+        let (cells_x, cells_y) = (self.x_res - 1, self.y_res - 1);
+        if x >= cells_x || y >= cells_y {
+            return Ok(())
+        }
+        let inside = x + size <= cells_x && y + size <= cells_y;
+        if size == 1 {
+            blocks.push((x, y, size));
+            return Ok(())
+        }
+        // checking a block reads every cell in it, so the big ones can take a while
+        progress.check_cancelled()?;
+        if inside && self.is_block_flat(x, y, size, max_error, floor) {
+            blocks.push((x, y, size));
+            return Ok(())
+        }
+        let half = size / 2;
+        for (block_x, block_y) in [(x, y), (x + half, y), (x, y + half), (x + half, y + half)] {
+            self.collect_flat_blocks((block_x, block_y, half), max_error, floor, blocks, progress)?;
+        }
         Ok(())
     }
+
+    /// whether every cell in the block is within `max_error` of the two triangles between its corners
+    fn is_block_flat(&self, x: usize, y: usize, size: usize, max_error: f64, floor: f64) -> bool{
+        // empty cells and anything below the floor end up on the floor in the STL
+        let get = |x: usize, y: usize| self.data[y * self.x_res + x].max(floor);
+        let (bottom_left, bottom_right) = (get(x, y), get(x + size, y));
+        let (top_left, top_right) = (get(x, y + size), get(x + size, y + size));
+
+        for offset_y in 0..=size {
+            for offset_x in 0..=size {
+                let (u, v) = (offset_x as f64 / size as f64, offset_y as f64 / size as f64);
+                // split along the diagonal from the top left to the bottom right corner, like the full resolution faces
+                let expected = if u + v <= 1f64 {
+                    bottom_left + u * (bottom_right - bottom_left) + v * (top_left - bottom_left)
+                } else {
+                    top_right + (1f64 - u) * (top_left - top_right) + (1f64 - v) * (bottom_right - top_right)
+                };
+                if (get(x + offset_x, y + offset_y) - expected).abs() > max_error {
+                    return false
+                }
+            }
+        }
+        true
+    }
+
+    /// the elevation at the bottom of the model, `BaseOptions::floor_elevation` or `bounds.min_z`
+    fn get_stl_floor(&self, options: &StlOptions) -> f64{
+        options.base.floor_elevation.unwrap_or(self.bounds.min_z)
+    }
+
+    /// adds `frame` around the cells from (min_x, min_y) to (max_x, max_y) as a closed rectangular ring
+    fn add_frame(&self, mesh: &mut IndexedMesh, frame: &StlFrame, cell_bounds: (usize, usize, usize, usize), options: &StlOptions){
+        let (x_unit, y_unit, _) = self.get_stl_units(options);
+        let (min_x, min_y, max_x, max_y) = cell_bounds;
+        let (min_x, max_x) = ((min_x as f64 * x_unit) as f32, (max_x as f64 * x_unit) as f32);
+        let (min_y, max_y) = ((min_y as f64 * y_unit) as f32, (max_y as f64 * y_unit) as f32);
+
+        // corners counter clockwise from the bottom left, on the bottom and top of the inside and outside of the frame
+        let mut get_corners = |offset: f32, z: f32| {
+            [(min_x - offset, min_y - offset), (max_x + offset, min_y - offset), (max_x + offset, max_y + offset), (min_x - offset, max_y + offset)]
+                .map(|(x, y)| mesh.push_vertex(Vertex::new([x, y, z])))
+        };
+        let (inner_bottom, inner_top) = (get_corners(0f32, 0f32), get_corners(0f32, frame.height));
+        let (outer_bottom, outer_top) = (get_corners(frame.width, 0f32), get_corners(frame.width, frame.height));
+
+        let placeholder = Normal::from(Vector::new([0f32, 0f32, 1f32]));
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            for quad in [
+                [outer_top[i], outer_top[next], inner_top[next], inner_top[i]],
+                [inner_bottom[i], inner_bottom[next], outer_bottom[next], outer_bottom[i]],
+                [outer_bottom[i], outer_bottom[next], outer_top[next], outer_top[i]],
+                [inner_top[i], inner_top[next], inner_bottom[next], inner_bottom[i]],
+            ] {
+                mesh.faces.extend(index_rec_to_faces_diagonal(quad[0], quad[1], quad[2], quad[3], placeholder));
+            }
+        }
+    }
+
+    /// gets a function that makes the vertex at the cell at `index`, on top if `height` is given, otherwise on the bottom.
+    /// The units and floor are worked out once here instead of for every vertex
+    fn get_stl_vertex_fn<'a>(&'a self, options: &'a StlOptions) -> impl Fn(usize, Option<f64>) -> Vertex + 'a{
+        let (x_unit, y_unit, z_unit) = self.get_stl_units(options);
+        let floor = self.get_stl_floor(options);
+        move |index, height| {
+            let x = (index % self.x_res) as f64 * x_unit;
+            let y = (index / self.x_res) as f64 * y_unit;
+            let get_top_z = |height: f64| (normal_pos_or_default(height - floor, 0f64) * z_unit) as f32 + options.base_thickness;
+            let z = match (height, options.shell_thickness) {
+                (Some(height), _) => get_top_z(height),
+                (None, Some(shell_thickness)) => (get_top_z(self.data[index]) - shell_thickness).max(0f32),
+                (None, None) => 0f32,
+            };
+            Vertex::new([x as f32, y as f32, z])
+        }
+    }
+}
+
+/// counts a column of faces as done and reports progress every 64 columns
+fn report_column_done(columns_done: &AtomicUsize, num_columns: usize, progress: &dyn ProgressSink){
+    let done = columns_done.fetch_add(1, Ordering::Relaxed) + 1;
+    if done.is_multiple_of(64){
+        progress.on_progress("assembling top and bottom faces", done as f64 / num_columns as f64);
+    }
+}
+
+/// A triangle of an `IndexedMesh`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshFace {
+    /// points out of the model. See `IndexedMesh::recompute_normals`
+    pub normal: Normal,
+    /// indices into `IndexedMesh::vertices`, counter clockwise when looking at the face from outside the model
+    pub vertices: [u32; 3],
+}
+
+/// A mesh where every vertex is stored once and the triangles point to them,
+/// instead of every triangle having its own copy of its corners like in an STL file.
+/// Takes a lot less memory, and is what most other 3D formats use
+#[derive(Clone, Debug, Default)]
+pub struct IndexedMesh {
+    pub vertices: Vec<Vertex>,
+    pub faces: Vec<MeshFace>,
 }
 
-/// will preserve order, so if you want them to be clockwise, pass them clockwise and vice versa
+impl IndexedMesh {
+    pub fn with_capacity(num_vertices: usize, num_faces: usize) -> IndexedMesh{
+        IndexedMesh {
+            vertices: Vec::with_capacity(num_vertices),
+            faces: Vec::with_capacity(num_faces),
+        }
+    }
+
+    /// adds a vertex and returns its index
+    pub fn push_vertex(&mut self, vertex: Vertex) -> u32{
+        self.vertices.push(vertex);
+        (self.vertices.len() - 1) as u32
+    }
+
+    /// gets a face with its vertices copied in, like STL wants them
+    pub fn get_triangle(&self, face: &MeshFace) -> Triangle{
+        Triangle {
+            normal: face.normal,
+            vertices: face.vertices.map(|index| self.vertices[index as usize]),
+        }
+    }
+
+    /// Sets the normal of every face to the real direction it faces, from its vertices.
+    /// Faces with no area (like the sides where the terrain is at the bottom and there is no base) keep the normal they had
+    pub fn recompute_normals(&mut self){
+        for face in self.faces.iter_mut() {
+            if let Some(normal) = get_triangle_normal(face.vertices.map(|index| self.vertices[index as usize])) {
+                face.normal = normal;
+            }
+        }
+    }
+
+    /// runs `validate_mesh` (unless `validation` is `Off`) and logs or returns the problems. See `MeshValidation`
+    pub fn check(&self, validation: MeshValidation, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        if validation == MeshValidation::Off {
+            return Ok(())
+        }
+        progress.on_stage("validating mesh");
+        let report = validate_mesh_with_progress(self, progress)?;
+        progress.on_finish("validating mesh");
+        report.check(validation)
+    }
+
+    /// every face with its vertices copied in
+    pub fn triangles(&self) -> impl ExactSizeIterator<Item = Triangle> + '_{
+        self.faces.iter().map(|face| self.get_triangle(face))
+    }
+
+    /// writes the mesh as a binary or ASCII STL
+    pub fn write_stl<W: Write>(&self, writer: &mut W, format: StlFormat) -> Result<(), LasToStlError>{
+        match format {
+            StlFormat::Binary => {
+                stl_io::write_stl(writer, self.triangles())?;
+            }
+            StlFormat::Ascii => {
+                let mut writer = BufWriter::new(writer);
+                writeln!(writer, "solid terrain")?;
+                for triangle in self.triangles() {
+                    writeln!(writer, "  facet normal {:e} {:e} {:e}", triangle.normal[0], triangle.normal[1], triangle.normal[2])?;
+                    writeln!(writer, "    outer loop")?;
+                    for vertex in &triangle.vertices {
+                        writeln!(writer, "      vertex {:e} {:e} {:e}", vertex[0], vertex[1], vertex[2])?;
+                    }
+                    writeln!(writer, "    endloop")?;
+                    writeln!(writer, "  endfacet")?;
+                }
+                writeln!(writer, "endsolid terrain")?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every edge of `mesh` is between exactly two triangles that face the same way (so it's closed
+/// and has an inside and outside), and counts triangles with no area. Vertices are compared by index, not position,
+/// so separate bodies that touch (like a frame) are fine
+pub fn validate_mesh(mesh: &IndexedMesh) -> MeshReport{
+    validate_mesh_with_progress(mesh, &NoProgress).unwrap_or_else(|_| unreachable!("NoProgress is never cancelled"))
+}
+
+/// Same as `validate_mesh`, but stops with `LasToStlError::CancelledError` once `progress` is cancelled
+pub fn validate_mesh_with_progress(mesh: &IndexedMesh, progress: &dyn ProgressSink) -> Result<MeshReport, LasToStlError>{
+    // every vertex's outgoing edges, one after another (compressed sparse rows)
+    let mut offsets = vec![0usize; mesh.vertices.len() + 1];
+    for face in &mesh.faces {
+        for vertex in face.vertices {
+            offsets[vertex as usize + 1] += 1;
+        }
+    }
+    for index in 1..offsets.len() {
+        offsets[index] += offsets[index - 1];
+    }
+    let mut next_free = offsets.clone();
+    let mut targets = vec![0u32; mesh.faces.len() * 3];
+    for face in &mesh.faces {
+        for corner in 0..3 {
+            let (from, to) = (face.vertices[corner] as usize, face.vertices[(corner + 1) % 3]);
+            targets[next_free[from]] = to;
+            next_free[from] += 1;
+        }
+    }
+    let get_targets = |vertex: usize| &targets[offsets[vertex]..offsets[vertex + 1]];
+
+    progress.check_cancelled()?;
+
+    let (boundary_edges, non_manifold_edges) = (0..mesh.vertices.len()).into_par_iter().map(|from| -> Result<(usize, usize), LasToStlError> {
+        if from.is_multiple_of(4096) {
+            progress.check_cancelled()?;
+        }
+        let (mut boundary_edges, mut non_manifold_edges) = (0usize, 0usize);
+        for (index, to) in get_targets(from).iter().enumerate() {
+            // only count every edge once
+            if get_targets(from)[..index].contains(to) {
+                continue
+            }
+            let times_used = get_targets(from).iter().filter(|other| *other == to).count();
+            let times_reversed = get_targets(*to as usize).iter().filter(|other| **other as usize == from).count();
+            if times_used > 1 || times_reversed > 1 {
+                // both ends see it, so only one of them counts it
+                if from < *to as usize || times_reversed == 0 {
+                    non_manifold_edges += 1;
+                }
+            } else if times_reversed == 0 {
+                boundary_edges += 1;
+            }
+        }
+        Ok((boundary_edges, non_manifold_edges))
+    }).try_reduce(|| (0, 0), |a, b| Ok((a.0 + b.0, a.1 + b.1)))?;
+
+    progress.check_cancelled()?;
+
+    let degenerate_triangles = mesh.faces.par_iter()
+        .filter(|face| get_triangle_normal(face.vertices.map(|index| mesh.vertices[index as usize])).is_none())
+        .count();
+
+    Ok(MeshReport {
+        boundary_edges,
+        non_manifold_edges,
+        degenerate_triangles,
+    })
+}
+
+/// Gets the unit normal of a triangle with counter clockwise vertices, or `None` if it has no area
+pub fn get_triangle_normal(vertices: [Vertex; 3]) -> Option<Normal>{
+    let [a, b, c] = vertices.map(|vertex| [vertex[0] as f64, vertex[1] as f64, vertex[2] as f64]);
+    let (ab, ac) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+    let cross = [
+        ab[1] * ac[2] - ab[2] * ac[1],
+        ab[2] * ac[0] - ab[0] * ac[2],
+        ab[0] * ac[1] - ab[1] * ac[0],
+    ];
+    let length = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+    if length > 0f64 && length.is_finite() {
+        Some(Normal::from(Vector::new(cross.map(|component| (component / length) as f32))))
+    } else {
+        None
+    }
+}
+
+/// Same as `vertex_rec_to_triangles_diagonal`, but with indices into an `IndexedMesh`.
+/// `normal` is only used if the faces have no area, `IndexedMesh::recompute_normals` replaces it otherwise
+pub fn index_rec_to_faces_diagonal(vertex_1: u32, vertex_2: u32, vertex_3: u32, vertex_4: u32, normal: Normal) -> [MeshFace; 2]{
+    [MeshFace{
+        normal,
+        vertices: [vertex_1, vertex_2, vertex_4]
+    }, MeshFace{
+        normal,
+        vertices: [vertex_2, vertex_3, vertex_4]
+    }]
+}
+
+/// Same as `option_vertex_rec_to_triangles_diagonal`, but with indices into an `IndexedMesh`
+pub fn option_index_rec_to_faces_diagonal(
+    vertex_1: Option<u32>,
+    vertex_2: Option<u32>,
+    vertex_3: Option<u32>,
+    vertex_4: Option<u32>,
+    normal: Normal) -> Option<[MeshFace; 2]>
+{
+    Some(index_rec_to_faces_diagonal(vertex_1?, vertex_2?, vertex_3?, vertex_4?, normal))
+}
+
+/// will preserve order, so if you want them to be clockwise, pass them clockwise and vice versa.
+/// The diagonal is between `vertex_2` and `vertex_4`. Use `get_triangle_normal` for the real normal of each triangle
 pub fn vertex_rec_to_triangles_diagonal(vertex_1: Vertex, vertex_2: Vertex, vertex_3: Vertex, vertex_4: Vertex, normal: Normal) -> [Triangle; 2]{
     [Triangle{
         normal,
@@ -406,3 +1283,129 @@ impl StlHelperMask{
         self.data[(y*self.x_res) + x]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::{CancellableProgress, CancellationToken};
+    use crate::utm_bounds::UtmBoundingBox;
+
+    /// 9x9 cells a meter apart, a bump in the middle of a flat square
+    fn bump_height_map() -> HeightMap {
+        HeightMap {
+            data: (0..81).map(|index| if index == 40 { 5f64 } else { 1f64 }).collect(),
+            x_res: 9,
+            y_res: 9,
+            bounds: UtmBoundingBox::new(0f64, 8f64, 0f64, 8f64, 0f64, 5f64),
+            utm_zone: None,
+            geoid_correction: None,
+            crs: None,
+        }
+    }
+
+    fn cancelled() -> CancellableProgress<NoProgress> {
+        let token = CancellationToken::new();
+        token.cancel();
+        CancellableProgress { progress: NoProgress, token }
+    }
+
+    /// a closed tetrahedron, faces counter clockwise from outside
+    fn tetrahedron() -> IndexedMesh {
+        let mut mesh = IndexedMesh::default();
+        for vertex in [[0f32, 0f32, 0f32], [1f32, 0f32, 0f32], [0f32, 1f32, 0f32], [0f32, 0f32, 1f32]] {
+            mesh.push_vertex(Vertex::new(vertex));
+        }
+        for vertices in [[0, 2, 1], [0, 1, 3], [1, 2, 3], [0, 3, 2]] {
+            mesh.faces.push(MeshFace { normal: Normal::new([0f32, 0f32, 0f32]), vertices });
+        }
+        mesh.recompute_normals();
+        mesh
+    }
+
+    #[test]
+    fn decimating_and_validating_stop_when_cancelled() {
+        let height_map = bump_height_map();
+        let options = StlOptions { max_error: Some(0.1), ..StlOptions::default() };
+        assert!(matches!(height_map.get_mesh_with_progress(&options, &cancelled()), Err(LasToStlError::CancelledError)));
+
+        let mesh = height_map.get_mesh_with_progress(&options, &NoProgress).unwrap();
+        assert!(matches!(validate_mesh_with_progress(&mesh, &cancelled()), Err(LasToStlError::CancelledError)));
+        let report = validate_mesh(&mesh);
+        assert_eq!((report.boundary_edges, report.non_manifold_edges), (0, 0));
+    }
+
+    #[test]
+    fn cancelled_saves_leave_the_old_file_alone() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_cancelled_save_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("terrain.stl");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"the old model").unwrap();
+
+        let height_map = bump_height_map();
+        let options = StlOptions { overwrite: OverwritePolicy::Overwrite, ..StlOptions::default() };
+        let result = height_map.save_as_stl_with_progress(path, &options, &cancelled());
+        assert!(matches!(result, Err(LasToStlError::CancelledError)));
+        assert_eq!(std::fs::read(path).unwrap(), b"the old model");
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1, "the partial file should be gone");
+
+        height_map.save_as_stl_with_progress(path, &options, &NoProgress).unwrap();
+        assert_ne!(std::fs::read(path).unwrap(), b"the old model");
+
+        // `Error` doesn't touch a file that's there, and leaves nothing behind when it fails on a new one
+        let new_path = directory.join("new.stl");
+        let new_path = new_path.to_str().unwrap();
+        assert!(matches!(height_map.save_as_stl_with_progress(path, &StlOptions::default(), &NoProgress), Err(LasToStlError::IoError(_))));
+        assert!(height_map.save_as_stl_with_progress(new_path, &StlOptions::default(), &cancelled()).is_err());
+        assert!(!Path::new(new_path).exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn flat_areas_are_decimated_and_the_bump_is_kept() {
+        let height_map = bump_height_map();
+        let full = height_map.get_mesh(&StlOptions::default()).unwrap();
+        let decimated = height_map.get_mesh(&StlOptions { max_error: Some(0.1), ..StlOptions::default() }).unwrap();
+        assert!(decimated.faces.len() < full.faces.len(), "{} faces decimated, {} without", decimated.faces.len(), full.faces.len());
+        assert!(validate_mesh(&decimated).is_watertight());
+
+        let top = |mesh: &IndexedMesh| mesh.vertices.iter().map(|vertex| vertex[2]).fold(f32::MIN, f32::max);
+        assert_eq!(top(&decimated), top(&full), "the top of the bump should still be there");
+    }
+
+    #[test]
+    fn validation_finds_holes_and_flat_triangles() {
+        let mut mesh = tetrahedron();
+        assert_eq!(validate_mesh(&mesh), MeshReport::default());
+
+        mesh.faces.pop();
+        let report = validate_mesh(&mesh);
+        assert_eq!(report.boundary_edges, 3);
+        assert!(matches!(report.check(MeshValidation::RejectLeaks), Err(LasToStlError::LeakyMeshError{ boundary_edges: 3 })));
+        assert!(report.check(MeshValidation::Warn).is_ok());
+
+        let mut mesh = tetrahedron();
+        let middle = mesh.push_vertex(Vertex::new([0.5f32, 0f32, 0f32]));
+        mesh.faces.push(MeshFace { normal: Normal::new([0f32, 0f32, 1f32]), vertices: [0, middle, 1] });
+        let report = validate_mesh(&mesh);
+        assert_eq!(report.degenerate_triangles, 1);
+        assert!(report.check(MeshValidation::RejectLeaks).is_err(), "the flat triangle's edges only have it on one side");
+        assert!(matches!(MeshReport { degenerate_triangles: 1, ..MeshReport::default() }.check(MeshValidation::Strict),
+                         Err(LasToStlError::DegenerateTrianglesError{ degenerate_triangles: 1 })));
+    }
+
+    #[test]
+    fn masked_meshes_are_watertight() {
+        let height_map = bump_height_map();
+        let mut mask = Mask::new_with_dims(9, 9, height_map.bounds, crate::utm_point::UtmZone::new(10, true)).unwrap();
+        // an L, so the walls have an inside corner
+        for y in 1..8 {
+            for x in 1..8 {
+                mask.data[y * 9 + x] = x < 4 || y < 4;
+            }
+        }
+        let mesh = height_map.get_mesh_masked(&mask, &StlOptions::default()).unwrap();
+        assert_eq!(validate_mesh(&mesh), MeshReport::default());
+    }
+}
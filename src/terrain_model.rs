@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use geo::Polygon;
+use log::info;
+use crate::datum::DatumShift;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::kml_utils::{get_regions, get_trails, infer_utm_zone, load_kml_file};
+use crate::las_resampler::LasLoadOptions;
+use crate::mask::Mask;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::StlOptions;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// default number of cells across the x axis, if `TerrainModelBuilder::resolution` isn't called
+const DEFAULT_X_RESOLUTION: usize = 1000;
+
+/// Trails to carve into the terrain, see `TerrainModelBuilder::carve_trails`
+#[derive(Clone, Debug)]
+pub struct TrailCarving {
+    /// KML file with the trails (every `LineString` in it is carved)
+    pub kml_path: PathBuf,
+    /// width of the trails in meters
+    pub width_m: f64,
+    /// how far down the trails are carved, in meters (before `StlOptions::z_scaling`)
+    pub depth_m: f64,
+}
+
+/// The common workflow in one chain: load LAS files, clip to a KML region, carve trails, and save an STL.
+/// Everything it does can be done by hand with `HeightMap`, `Mask` and `kml_utils` for more control.
+///
+/// ```no_run
+/// use las_kml_to_stl::terrain_model::TerrainModelBuilder;
+///
+/// TerrainModelBuilder::new()
+///     .las_glob("test_laz/*.laz")
+///     .resolution(1000)
+///     .clip_to_kml("test_perimeters/property_line.kml")
+///     .carve_trails("test_perimeters/trails.kml", 4.0, 2.0)
+///     .z_scale(2.0)
+///     .save_stl("my property.stl")
+///     .unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct TerrainModelBuilder {
+    /// glob pattern of the LAS/LAZ files to load. Not needed if `height_map` is set
+    pub las_glob: Option<String>,
+    /// a heightmap that was already loaded (e.g. with `HeightMap::load`) to use instead of `las_glob`
+    pub height_map: Option<HeightMap>,
+    /// number of cells across the x axis. The y axis keeps the aspect ratio
+    pub x_resolution: Option<usize>,
+    /// settings for loading the LAS files. The crop is set from the clip region, if there is one
+    pub load_options: LasLoadOptions,
+    /// KML file with the region(s) to keep. Everything outside all of its polygons is left out of the model
+    pub clip_kml_path: Option<PathBuf>,
+    /// trails to carve, in order
+    pub trails: Vec<TrailCarving>,
+    /// zone of the model. `None` uses the zone of the LAS files, or the zone the KML is in if they don't say
+    pub utm_zone: Option<UtmZone>,
+    /// shift applied to the KML before it's drawn, see `Mask::datum_shift`
+    pub datum_shift: Option<DatumShift>,
+    /// settings for the STL
+    pub stl_options: StlOptions,
+}
+
+/// What `TerrainModelBuilder::build` made, ready to be saved (or changed more by hand first)
+#[derive(Debug)]
+pub struct TerrainModel {
+    /// the heightmap, with the trails already carved
+    pub height_map: HeightMap,
+    /// the clip region drawn onto a mask, if there was one
+    pub clip_mask: Option<Mask>,
+}
+
+impl TerrainModelBuilder {
+    pub fn new() -> Self {
+        TerrainModelBuilder::default()
+    }
+
+    /// glob pattern of the LAS/LAZ files to load, like `"test_laz/*.laz"`
+    pub fn las_glob(mut self, glob_pattern: &str) -> Self {
+        self.las_glob = Some(glob_pattern.to_string());
+        self
+    }
+
+    /// use a heightmap that was already loaded instead of loading LAS files
+    pub fn height_map(mut self, height_map: HeightMap) -> Self {
+        self.height_map = Some(height_map);
+        self
+    }
+
+    /// number of cells across the x axis, 1000 by default. The y axis keeps the aspect ratio
+    pub fn resolution(mut self, x_resolution: usize) -> Self {
+        self.x_resolution = Some(x_resolution);
+        self
+    }
+
+    /// all the settings for loading the LAS files, see `LasLoadOptions`
+    pub fn load_options(mut self, load_options: LasLoadOptions) -> Self {
+        self.load_options = load_options;
+        self
+    }
+
+    /// only keep what's inside the polygons in this KML file. Only the LAS points around them are loaded
+    pub fn clip_to_kml<P: AsRef<Path>>(mut self, kml_path: P) -> Self {
+        self.clip_kml_path = Some(kml_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// carve every trail in this KML file `depth_m` meters into the terrain, `width_m` meters wide.
+    /// Can be called more than once for different widths and depths
+    pub fn carve_trails<P: AsRef<Path>>(mut self, kml_path: P, width_m: f64, depth_m: f64) -> Self {
+        self.trails.push(TrailCarving { kml_path: kml_path.as_ref().to_path_buf(), width_m, depth_m });
+        self
+    }
+
+    /// exaggerates the heights, see `StlOptions::z_scaling`
+    pub fn z_scale(mut self, z_scaling: f64) -> Self {
+        self.stl_options.z_scaling = z_scaling;
+        self
+    }
+
+    /// see `StlOptions::base_thickness`
+    pub fn base_thickness(mut self, base_thickness: f32) -> Self {
+        self.stl_options.base_thickness = base_thickness;
+        self
+    }
+
+    /// all the settings for the STL, see `StlOptions`. Overwrites `z_scale` and `base_thickness` if they were called first
+    pub fn stl_options(mut self, stl_options: StlOptions) -> Self {
+        self.stl_options = stl_options;
+        self
+    }
+
+    /// the UTM zone of the model, for LAS files that don't say
+    pub fn utm_zone(mut self, utm_zone: UtmZone) -> Self {
+        self.utm_zone = Some(utm_zone);
+        self
+    }
+
+    /// see `Mask::datum_shift`
+    pub fn datum_shift(mut self, datum_shift: DatumShift) -> Self {
+        self.datum_shift = Some(datum_shift);
+        self
+    }
+
+    /// Builds the model and saves it as an STL at `path`, only the clip region if there is one
+    pub fn save_stl(self, path: &str) -> Result<TerrainModel, LasToStlError> {
+        self.save_stl_with_progress(path, &LogProgress)
+    }
+
+    /// Same as `save_stl`, but reports progress to `progress` instead of logging it
+    pub fn save_stl_with_progress(self, path: &str, progress: &dyn ProgressSink) -> Result<TerrainModel, LasToStlError> {
+        let stl_options = self.stl_options.clone();
+        let model = self.build_with_progress(progress)?;
+        model.save_stl_with_progress(path, &stl_options, progress)?;
+        Ok(model)
+    }
+
+    /// Loads the heightmap, draws the clip region and carves the trails, without saving anything
+    pub fn build(self) -> Result<TerrainModel, LasToStlError> {
+        self.build_with_progress(&LogProgress)
+    }
+
+    /// Same as `build`, but reports progress to `progress` instead of logging it
+    pub fn build_with_progress(mut self, progress: &dyn ProgressSink) -> Result<TerrainModel, LasToStlError> {
+        let clip = match &self.clip_kml_path {
+            Some(path) => Some(load_kml_file(path)?),
+            None => None,
+        };
+        let utm_zone = self.utm_zone.or(clip.as_ref().and_then(infer_utm_zone));
+        let clip_regions: Option<Vec<Polygon>> = clip.map(|clip| {
+            let regions = get_regions(clip);
+            match regions.is_empty() {
+                true => Err(LasToStlError::NoValidGeometriesError),
+                false => Ok(regions),
+            }
+        }).transpose()?;
+
+        let mut height_map = match (self.height_map.take(), &self.las_glob) {
+            (Some(height_map), _) => height_map,
+            (None, Some(glob_pattern)) => {
+                let mut load_options = self.load_options.clone();
+                if let (Some(regions), Some(utm_zone)) = (&clip_regions, utm_zone) {
+                    let region_bounds = self.get_region_bounds(regions, utm_zone)?;
+                    load_options.crop = Some(match load_options.crop {
+                        Some(crop) => crop.intersection(&region_bounds).ok_or(LasToStlError::CropOutsideDataError(crop))?,
+                        None => region_bounds,
+                    });
+                }
+                let x_resolution = self.x_resolution.unwrap_or(DEFAULT_X_RESOLUTION);
+                HeightMap::glob_get_height_map_with_progress(glob_pattern, Some(x_resolution), None, &load_options, progress)?
+            }
+            (None, None) => return Err(LasToStlError::NoHeightMapSourceError),
+        };
+        if height_map.utm_zone.is_none() {
+            height_map.set_utm_zone(utm_zone);
+        }
+
+        let clip_mask = match clip_regions {
+            Some(regions) => {
+                let mut clip_mask = self.new_mask(&height_map)?;
+                for region in &regions {
+                    clip_mask.add_filled_lat_lon_polygon(region)?;
+                }
+                Some(clip_mask)
+            }
+            None => None,
+        };
+
+        for trail_carving in &self.trails {
+            let trails = get_trails(load_kml_file(&trail_carving.kml_path)?);
+            info!("carving {} trails from {:?}", trails.len(), trail_carving.kml_path);
+            let mut trail_mask = self.new_mask(&height_map)?;
+            for trail in &trails {
+                trail_mask.add_lat_lon_trail_meters(trail, trail_carving.width_m / 2f64)?;
+            }
+            height_map.offset_by_mask(&trail_mask, -trail_carving.depth_m)?;
+        }
+
+        Ok(TerrainModel { height_map, clip_mask })
+    }
+
+    /// the bounds of every region together, with a cell of margin so the edges aren't cut off
+    fn get_region_bounds(&self, regions: &[Polygon], utm_zone: UtmZone) -> Result<UtmBoundingBox, LasToStlError> {
+        let shifted_regions: Vec<Polygon> = regions.iter()
+            .map(|region| self.datum_shift.map_or(region.clone(), |datum_shift| datum_shift.shift(region)))
+            .collect();
+        let mut bounds = UtmBoundingBox::from_polygon(&shifted_regions[0], utm_zone)?;
+        for region in &shifted_regions[1..] {
+            bounds = bounds.union(&UtmBoundingBox::from_polygon(region, utm_zone)?)?;
+        }
+        let x_resolution = self.x_resolution.unwrap_or(DEFAULT_X_RESOLUTION);
+        Ok(bounds.expand_by(bounds.x_range() / x_resolution as f64))
+    }
+
+    /// an empty mask for `height_map`, with the datum shift
+    fn new_mask(&self, height_map: &HeightMap) -> Result<Mask, LasToStlError> {
+        let mut mask = Mask::new_for_height_map(height_map)?;
+        mask.datum_shift = self.datum_shift;
+        Ok(mask)
+    }
+}
+
+impl TerrainModel {
+    /// Saves as an STL, only the clip region if there is one
+    pub fn save_stl(&self, path: &str, options: &StlOptions) -> Result<(), LasToStlError> {
+        self.save_stl_with_progress(path, options, &LogProgress)
+    }
+
+    /// Same as `save_stl`, but reports progress to `progress` instead of logging it
+    pub fn save_stl_with_progress(&self, path: &str, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError> {
+        match &self.clip_mask {
+            Some(clip_mask) => self.height_map.save_as_stl_masked_with_progress(path, clip_mask, options, progress),
+            None => self.height_map.save_as_stl_with_progress(path, options, progress),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use geo::{BoundingRect, Coord};
+use image::{ImageBuffer, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::progress::{LogProgress, ProgressSink};
+
+/// stage reported to the `ProgressSink` for every zoom level
+const TERRAIN_TILES_STAGE: &str = "saving terrain tiles";
+
+/// How heights are packed into the red, green and blue of a terrain tile
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TerrainEncoding {
+    /// height = (red * 256 + green + blue / 256) - 32768, used by Tangram, MapLibre and the AWS terrain tiles
+    #[default]
+    Terrarium,
+    /// height = -10000 + (red * 256 * 256 + green * 256 + blue) * 0.1, used by Mapbox
+    MapboxTerrainRgb,
+}
+
+impl TerrainEncoding {
+    /// packs a height (in meters) into a color
+    pub fn encode(&self, height: f64) -> [u8; 3]{
+        match self {
+            TerrainEncoding::Terrarium => {
+                let value = (height + 32768f64).clamp(0f64, 65535.996);
+                [(value / 256f64).floor() as u8, (value.floor() % 256f64) as u8, (value.fract() * 256f64).floor() as u8]
+            }
+            TerrainEncoding::MapboxTerrainRgb => {
+                let value = ((height + 10000f64) * 10f64).round().clamp(0f64, 16_777_215f64) as u32;
+                [(value >> 16) as u8, (value >> 8) as u8, value as u8]
+            }
+        }
+    }
+
+    /// gets the height back out of a color
+    pub fn decode(&self, [red, green, blue]: [u8; 3]) -> f64{
+        let (red, green, blue) = (red as f64, green as f64, blue as f64);
+        match self {
+            TerrainEncoding::Terrarium => red * 256f64 + green + blue / 256f64 - 32768f64,
+            TerrainEncoding::MapboxTerrainRgb => -10000f64 + (red * 65536f64 + green * 256f64 + blue) * 0.1,
+        }
+    }
+}
+
+/// Settings for `HeightMap::save_terrain_tiles`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainTileOptions {
+    pub encoding: TerrainEncoding,
+    /// the first zoom level to save (0 is the whole world in one tile)
+    pub min_zoom: u8,
+    /// the last zoom level to save. Every level has 4 times as many tiles as the one before
+    pub max_zoom: u8,
+    /// width and height of the tiles in pixels, usually 256 or 512
+    pub tile_size: u32,
+}
+
+impl Default for TerrainTileOptions {
+    fn default() -> Self {
+        TerrainTileOptions {
+            encoding: TerrainEncoding::default(),
+            min_zoom: 8,
+            max_zoom: 14,
+            tile_size: 256,
+        }
+    }
+}
+
+/// the tile (x, y) a lat/lon point (x = lon, y = lat) is in at `zoom`, as fractions
+fn lat_lon_to_tile(lat_lon: &Coord, zoom: u8) -> (f64, f64){
+    let num_tiles = 2f64.powi(zoom as i32);
+    let lat = lat_lon.y.to_radians();
+    let x = (lat_lon.x + 180f64) / 360f64 * num_tiles;
+    let y = (1f64 - (lat.tan() + 1f64 / lat.cos()).ln() / PI) / 2f64 * num_tiles;
+    (x, y)
+}
+
+/// the lat/lon point (x = lon, y = lat) at a fractional tile position at `zoom`
+fn tile_to_lat_lon(x: f64, y: f64, zoom: u8) -> Coord{
+    let num_tiles = 2f64.powi(zoom as i32);
+    Coord {
+        x: x / num_tiles * 360f64 - 180f64,
+        y: (PI * (1f64 - 2f64 * y / num_tiles)).sinh().atan().to_degrees(),
+    }
+}
+
+impl HeightMap {
+    /// Slices the heightmap into web mercator XYZ tiles with heights encoded as colors (see `TerrainEncoding`),
+    /// saved as `directory/{z}/{x}/{y}.png`, so web maps (MapLibre, Cesium, deck.gl, ...) can show the terrain straight
+    /// from a static file server. Pixels outside the heightmap or over empty cells (at or below `bounds.min_z`)
+    /// are transparent, and tiles without any data aren't saved.
+    ///
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn save_terrain_tiles<P: AsRef<Path>>(&self, directory: P, options: &TerrainTileOptions) -> Result<(), LasToStlError>{
+        self.save_terrain_tiles_with_progress(directory, options, &LogProgress)
+    }
+
+    /// Same as `save_terrain_tiles`, but reports progress to `progress` instead of logging it
+    pub fn save_terrain_tiles_with_progress<P: AsRef<Path>>(&self,
+                                                           directory: P,
+                                                           options: &TerrainTileOptions,
+                                                           progress: &dyn ProgressSink)
+        -> Result<(), LasToStlError>
+    {
+        let utm_zone = self.utm_zone.ok_or(LasToStlError::NoUtmZoneError)?;
+        let footprint = self.bounds.to_lat_lon_polygon(utm_zone)?.bounding_rect().ok_or(LasToStlError::ZeroAreaBoundsError(self.bounds))?;
+        let tile_size = options.tile_size as usize;
+        let directory = directory.as_ref();
+
+        progress.on_stage(TERRAIN_TILES_STAGE);
+        let num_levels = (options.max_zoom.saturating_sub(options.min_zoom) + 1) as f64;
+        for zoom in options.min_zoom..=options.max_zoom {
+            // north west and south east corners, tile y goes down
+            let (first_x, first_y) = lat_lon_to_tile(&Coord { x: footprint.min().x, y: footprint.max().y }, zoom);
+            let (last_x, last_y) = lat_lon_to_tile(&Coord { x: footprint.max().x, y: footprint.min().y }, zoom);
+            let tiles: Vec<(u32, u32)> = (first_y.floor() as u32..=last_y.floor() as u32)
+                .flat_map(|y| (first_x.floor() as u32..=last_x.floor() as u32).map(move |x| (x, y)))
+                .collect();
+
+            let num_done = AtomicUsize::new(0);
+            tiles.par_iter().try_for_each(|(tile_x, tile_y)| {
+                let mut has_data = false;
+                let mut pixels: Vec<u8> = vec![0u8; tile_size * tile_size * 4];
+                for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                    let lat_lon = tile_to_lat_lon(
+                        *tile_x as f64 + ((index % tile_size) as f64 + 0.5) / tile_size as f64,
+                        *tile_y as f64 + ((index / tile_size) as f64 + 0.5) / tile_size as f64,
+                        zoom,
+                    );
+                    let (easting, northing) = utm_zone.from_lat_lon(&lat_lon);
+                    if let Some(height) = self.sample_utm(easting, northing) {
+                        let [red, green, blue] = options.encoding.encode(height);
+                        pixel.copy_from_slice(&[red, green, blue, 255]);
+                        has_data = true;
+                    }
+                }
+
+                if has_data {
+                    let tile_directory = directory.join(zoom.to_string()).join(tile_x.to_string());
+                    fs::create_dir_all(&tile_directory)?;
+                    let image: RgbaImage = ImageBuffer::from_vec(options.tile_size, options.tile_size, pixels)
+                        .ok_or(LasToStlError::ImageNoneError)?;
+                    image.save(tile_directory.join(format!("{tile_y}.png")))?;
+                }
+                let done = num_done.fetch_add(1, Ordering::Relaxed) + 1;
+                let fraction_of_level = done as f64 / tiles.len() as f64;
+                progress.on_progress(TERRAIN_TILES_STAGE, ((zoom - options.min_zoom) as f64 + fraction_of_level) / num_levels);
+                Ok::<(), LasToStlError>(())
+            })?;
+        }
+        progress.on_finish(TERRAIN_TILES_STAGE);
+        Ok(())
+    }
+}
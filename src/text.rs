@@ -0,0 +1,160 @@
+use geo::Point;
+use log::warn;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::mask::Mask;
+use crate::utm_point::UtmCoord;
+
+/// width of a character in font pixels, plus one between characters
+const CHARACTER_WIDTH: usize = 6;
+/// height of a line in font pixels, plus two between lines
+const LINE_HEIGHT: usize = 9;
+/// height of a character in font pixels
+const GLYPH_HEIGHT: usize = 7;
+
+impl Mask {
+    /// Same as `add_utm_text`, but the center of the text is at a lat/lon point (x is lon, y is lat)
+    pub fn add_lat_lon_text(&mut self, text: &str, lat_lon_center: &Point, character_height_m: f64) -> Result<(), LasToStlError>{
+        let utm_coord = UtmCoord::from_gps_coord_in_zone(&lat_lon_center.0, &self.utm_zone);
+        self.add_utm_text(text, utm_coord, character_height_m)
+    }
+
+    /// Sets every pixel covered by `text`, written in a simple 5x7 pixel font centered on `utm_center`
+    /// with capital letters `character_height_m` meters tall. Lowercase letters come out as capitals,
+    /// and characters the font doesn't have come out as `?`. `\n` starts a new line.
+    ///
+    /// Every font pixel should be at least a couple of mask pixels (so characters at least ~15 pixels tall) to be readable.
+    /// Parts of the text outside of the mask are ignored
+    pub fn add_utm_text(&mut self, text: &str, utm_center: UtmCoord, character_height_m: f64) -> Result<(), LasToStlError>{
+        let glyph_lines: Vec<Vec<[u8; GLYPH_HEIGHT]>> = text.lines().map(|line| {
+            line.chars().map(|character| get_glyph(character).unwrap_or_else(|| {
+                warn!("the font doesn't have {:?}, using '?' instead", character);
+                QUESTION_MARK
+            })).collect()
+        }).collect();
+
+        let font_pixel = character_height_m / GLYPH_HEIGHT as f64;
+        if font_pixel < self.x_tick.max(self.y_tick) {
+            warn!("text is {} meters tall, which is less than {} pixels, so it won't be readable",
+                character_height_m, GLYPH_HEIGHT);
+        }
+
+        // the text block in font pixels, without the spacing after the last character and line
+        let width = glyph_lines.iter().map(|line| line.len()).max().unwrap_or(0) * CHARACTER_WIDTH;
+        let height = glyph_lines.len() * LINE_HEIGHT;
+        if width == 0 {
+            return Ok(())
+        }
+        let left = utm_center.easting - (width - 1) as f64 * font_pixel / 2f64;
+        let top = utm_center.northing + (height - 2) as f64 * font_pixel / 2f64;
+        let right = left + width as f64 * font_pixel;
+        let bottom = top - height as f64 * font_pixel;
+
+        // mask pixels the text block covers
+        let first_x = ((left - self.bounds.min_x) / self.x_tick).floor().max(0f64) as usize;
+        let last_x = ((right - self.bounds.min_x) / self.x_tick).ceil().min((self.x_res - 1) as f64);
+        let first_y = ((bottom - self.bounds.min_y) / self.y_tick).floor().max(0f64) as usize;
+        let last_y = ((top - self.bounds.min_y) / self.y_tick).ceil().min((self.y_res - 1) as f64);
+        if last_x < 0f64 || last_y < 0f64 {
+            return Ok(())
+        }
+
+        for y in first_y..=(last_y as usize) {
+            let row = (top - (self.bounds.min_y + y as f64 * self.y_tick)) / font_pixel;
+            if row < 0f64 {
+                continue
+            }
+            let (line, glyph_row) = (row as usize / LINE_HEIGHT, row as usize % LINE_HEIGHT);
+            let Some(glyphs) = glyph_lines.get(line) else { continue };
+            if glyph_row >= GLYPH_HEIGHT {
+                continue
+            }
+
+            for x in first_x..=(last_x as usize) {
+                let column = (self.bounds.min_x + x as f64 * self.x_tick - left) / font_pixel;
+                if column < 0f64 {
+                    continue
+                }
+                let (character, glyph_column) = (column as usize / CHARACTER_WIDTH, column as usize % CHARACTER_WIDTH);
+                if let Some(glyph) = glyphs.get(character) {
+                    // the leftmost column is the fifth bit
+                    if glyph_column < 5 && glyph[glyph_row] & (0b10000 >> glyph_column) != 0 {
+                        self.data[y * self.x_res + x] = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HeightMap {
+    /// Raises (or recesses, if `relief_m` is negative) the heights under `text` by `relief_m`, so printed maps can be labeled.
+    /// See `Mask::add_utm_text`.
+    /// Returns `LasToStlError::NoUtmZoneError` if the heightmap doesn't know its UTM zone
+    pub fn emboss_lat_lon_text(&mut self, text: &str, lat_lon_center: &Point, character_height_m: f64, relief_m: f64) -> Result<(), LasToStlError>{
+        let mut mask = Mask::new_for_height_map(self)?;
+        mask.add_lat_lon_text(text, lat_lon_center, character_height_m)?;
+        self.offset_by_mask(&mask, relief_m)
+    }
+}
+
+const QUESTION_MARK: [u8; GLYPH_HEIGHT] = [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04];
+
+/// the rows of a character from the top, with the leftmost pixel in the fifth bit
+fn get_glyph(character: char) -> Option<[u8; GLYPH_HEIGHT]>{
+    Some(match character.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '&' => [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '?' => QUESTION_MARK,
+        '°' => [0x0C, 0x12, 0x12, 0x0C, 0x00, 0x00, 0x00],
+        _ => return None,
+    })
+}
@@ -0,0 +1,141 @@
+use std::io::{BufWriter, Seek, Write};
+use std::time::SystemTime;
+use log::{debug, warn};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::{IndexedMesh, StlOptions, StlScale};
+
+/// namespace of the metadata this library adds, 3MF wants anything that isn't in the spec to have one
+const METADATA_NAMESPACE: &str = "https://github.com/paulwrath1223/las-kml-to-stl";
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+/// One object in a 3MF file. Slicers show every object separately, so they can get their own settings or filament
+pub struct ThreeMfObject<'a> {
+    pub name: String,
+    pub mesh: &'a IndexedMesh,
+}
+
+/// Writes `objects` as a 3MF package, in millimeters. `metadata` is (name, value) pairs;
+/// names the 3MF spec doesn't have (like "Title" or "Description") get put in this library's namespace as `lkts:{name}`
+pub fn write_3mf<W: Write + Seek>(writer: W, objects: &[ThreeMfObject], metadata: &[(String, String)]) -> Result<(), LasToStlError>{
+    let mut zip = ZipWriter::new(writer);
+    let file_options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("[Content_Types].xml", file_options)?;
+    zip.write_all(CONTENT_TYPES.as_bytes())?;
+    zip.start_file("_rels/.rels", file_options)?;
+    zip.write_all(RELATIONSHIPS.as_bytes())?;
+
+    zip.start_file("3D/3dmodel.model", file_options)?;
+    {
+        let mut model = BufWriter::new(&mut zip);
+        writeln!(model, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(model, r#"<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02" xmlns:lkts="{}">"#, METADATA_NAMESPACE)?;
+        for (name, value) in metadata {
+            let name = match name.as_str() {
+                "Title" | "Designer" | "Description" | "Copyright" | "LicenseTerms" | "Rating" |
+                "CreationDate" | "ModificationDate" | "Application" => name.clone(),
+                _ => format!("lkts:{}", name),
+            };
+            writeln!(model, r#"  <metadata name="{}">{}</metadata>"#, escape_xml(&name), escape_xml(value))?;
+        }
+        writeln!(model, "  <resources>")?;
+        for (index, object) in objects.iter().enumerate() {
+            writeln!(model, r#"    <object id="{}" type="model" name="{}">"#, index + 1, escape_xml(&object.name))?;
+            writeln!(model, "      <mesh>")?;
+            writeln!(model, "        <vertices>")?;
+            for vertex in &object.mesh.vertices {
+                writeln!(model, r#"          <vertex x="{}" y="{}" z="{}"/>"#, vertex[0], vertex[1], vertex[2])?;
+            }
+            writeln!(model, "        </vertices>")?;
+            writeln!(model, "        <triangles>")?;
+            for face in &object.mesh.faces {
+                writeln!(model, r#"          <triangle v1="{}" v2="{}" v3="{}"/>"#, face.vertices[0], face.vertices[1], face.vertices[2])?;
+            }
+            writeln!(model, "        </triangles>")?;
+            writeln!(model, "      </mesh>")?;
+            writeln!(model, "    </object>")?;
+        }
+        writeln!(model, "  </resources>")?;
+        writeln!(model, "  <build>")?;
+        for index in 0..objects.len() {
+            writeln!(model, r#"    <item objectid="{}"/>"#, index + 1)?;
+        }
+        writeln!(model, "  </build>")?;
+        writeln!(model, "</model>")?;
+        model.flush()?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+impl HeightMap {
+    /// Same as `save_as_stl_with_options`, but saves a 3MF file. Unlike STL, 3MF says its units are millimeters,
+    /// so slicers don't have to guess, and it keeps where the model came from (bounds, UTM zone, scale) as metadata.
+    /// `options.format` is ignored. With `StlScale::Pixels`, every cell is 1mm
+    pub fn save_as_3mf(&self, path: &str, options: &StlOptions) -> Result<(), LasToStlError>{
+        self.save_as_3mf_with_progress(path, options, &LogProgress)
+    }
+
+    /// Same as `save_as_3mf`, but reports progress to `progress`
+    pub fn save_as_3mf_with_progress(&self, path: &str, options: &StlOptions, progress: &dyn ProgressSink) -> Result<(), LasToStlError>{
+        let now = SystemTime::now();
+
+        let mesh = self.get_mesh_with_progress(options, progress)?;
+        options.overwrite.save(path, |file| {
+            write_3mf(file, &[ThreeMfObject { name: String::from("terrain"), mesh: &mesh }], &self.get_3mf_metadata(options))
+        })?;
+
+        debug!("saved as 3mf. took {:?}", now.elapsed());
+        Ok(())
+    }
+
+    /// The metadata `save_as_3mf` adds: where the heightmap is, its UTM zone and CRS (see `get_crs`) and how it was scaled
+    pub fn get_3mf_metadata(&self, options: &StlOptions) -> Vec<(String, String)>{
+        if options.scale == StlScale::Pixels {
+            warn!("3MF files are in millimeters, so with `StlScale::Pixels` every cell will be 1mm");
+        }
+        let mut metadata = vec![
+            (String::from("Title"), String::from("Terrain")),
+            (String::from("Application"), String::from(concat!("las-kml-to-stl ", env!("CARGO_PKG_VERSION")))),
+            (String::from("SourceBounds"), self.bounds.to_string()),
+            (String::from("Scale"), format!("{:?}", options.scale)),
+            (String::from("ZScaling"), options.z_scaling.to_string()),
+            (String::from("BaseThickness"), options.base_thickness.to_string()),
+        ];
+        if let Some(utm_zone) = self.utm_zone {
+            metadata.push((String::from("UtmZone"), utm_zone.to_string()));
+        }
+        if let Some(crs) = self.get_crs() {
+            metadata.push((String::from("Crs"), crs.to_string()));
+        }
+        if let Some(geoid_correction) = &self.geoid_correction {
+            metadata.push((String::from("GeoidCorrection"), geoid_correction.clone()));
+        }
+        metadata
+    }
+}
+
+/// escapes the characters that can't be in XML text or attributes
+fn escape_xml(text: &str) -> String{
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
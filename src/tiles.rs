@@ -0,0 +1,343 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use stl_io::{Normal, Vector, Vertex};
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::progress::{LogProgress, ProgressSink};
+use crate::stl::{index_rec_to_faces_diagonal, IndexedMesh, StlOptions, StlScale};
+use crate::utm_bounds::UtmBoundingBox;
+
+/// The outline of the keys that hold neighboring tiles together
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TileKeyShape {
+    /// a plain rectangle, only lines the tiles up
+    #[default]
+    Rectangle,
+    /// a bowtie (a dovetail on both ends) that is narrowest on the seam, so it also pulls the tiles together
+    Bowtie,
+}
+
+/// Keys that get glued into pockets in the bottom of the tiles, across every seam.
+/// The pockets are cut into the tiles, and one key is saved as its own STL to print as many times as needed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileKeys {
+    pub shape: TileKeyShape,
+    /// across the seam, in model units. Half of it goes into each tile
+    pub length: f32,
+    /// along the seam (at the ends for a bowtie), in model units
+    pub width: f32,
+    /// how far the pockets go up into the bottom, in model units. Should be less than `StlOptions::base_thickness`
+    pub depth: f32,
+    /// how much smaller the key is than its pocket on every side, in model units
+    pub clearance: f32,
+}
+
+/// Settings for `save_as_stl_tiled`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TileOptions {
+    /// used for every tile. Frames are left out, and `max_error` is ignored if there are keys
+    pub stl: StlOptions,
+    /// `None` leaves the seams flat
+    pub keys: Option<TileKeys>,
+}
+
+/// where a key goes, in model units of the whole (untiled) model
+struct KeyPlacement {
+    center_x: f32,
+    center_y: f32,
+    /// whether the key crosses a seam between tiles next to each other in x (so the seam is along y)
+    across_x: bool,
+}
+
+impl TileKeys {
+    /// half the length, half the width at the ends and half the width on the seam, all made `shrink` smaller
+    fn get_half_extents(&self, shrink: f32) -> (f32, f32, f32){
+        let (half_length, half_width) = (self.length / 2f32, self.width / 2f32);
+        let half_seam_width = match self.shape {
+            TileKeyShape::Rectangle => half_width,
+            TileKeyShape::Bowtie => half_width / 2f32,
+        };
+        (half_length - shrink, half_width - shrink, half_seam_width - shrink)
+    }
+
+    /// whether a point `across` the seam and `along` it from the center of a key is inside its pocket
+    fn pocket_contains(&self, across: f32, along: f32) -> bool{
+        let (half_length, half_width, half_seam_width) = self.get_half_extents(0f32);
+        across.abs() <= half_length &&
+            along.abs() <= half_seam_width + (half_width - half_seam_width) * across.abs() / half_length
+    }
+
+    /// The key itself, with the seam along y and its corner at (0, 0, 0)
+    pub fn get_key_mesh(&self) -> IndexedMesh{
+        let (half_length, half_width, half_seam_width) = self.get_half_extents(self.clearance);
+        let height = self.depth - self.clearance;
+
+        let mut mesh = IndexedMesh::default();
+        // the outline counter clockwise from the bottom left, on the bottom and the top
+        let outline = [
+            (-half_length, -half_width), (0f32, -half_seam_width), (half_length, -half_width),
+            (half_length, half_width), (0f32, half_seam_width), (-half_length, half_width),
+        ];
+        let mut get_outline = |z: f32| {
+            outline.map(|(x, y)| mesh.push_vertex(Vertex::new([x + half_length, y + half_width, z])))
+        };
+        let (bottom, top) = (get_outline(0f32), get_outline(height));
+
+        // normals are recomputed at the end
+        let placeholder = Normal::from(Vector::new([0f32, 0f32, 1f32]));
+        // the outline is two convex halves that meet on the seam
+        for quad in [
+            [top[0], top[1], top[4], top[5]],
+            [top[1], top[2], top[3], top[4]],
+            [bottom[5], bottom[4], bottom[1], bottom[0]],
+            [bottom[4], bottom[3], bottom[2], bottom[1]],
+        ] {
+            mesh.faces.extend(index_rec_to_faces_diagonal(quad[0], quad[1], quad[2], quad[3], placeholder));
+        }
+        for i in 0..outline.len() {
+            let next = (i + 1) % outline.len();
+            mesh.faces.extend(index_rec_to_faces_diagonal(bottom[i], bottom[next], top[next], top[i], placeholder));
+        }
+        mesh.recompute_normals();
+        mesh
+    }
+}
+
+impl HeightMap {
+    /// Cuts the model into `tiles_x` by `tiles_y` pieces so big regions can be printed on small beds, and saves every one
+    /// as `{path_prefix}_{x}_{y}.stl` (0, 0 is the south west corner). Every tile gets closed walls where it was cut,
+    /// and all of them share the same scale and floor so they line up again.
+    /// With `TileOptions::keys`, the key is saved as `{path_prefix}_key.stl` and the number to print is logged.
+    ///
+    /// Returns the paths of every file that was saved.
+    /// Returns `LasToStlError::TileCountError` if there are more tiles than cells in either direction.
+    pub fn save_as_stl_tiled(&self, path_prefix: &str, tiles_x: usize, tiles_y: usize, options: &TileOptions) -> Result<Vec<String>, LasToStlError>{
+        self.save_as_stl_tiled_with_progress(path_prefix, tiles_x, tiles_y, options, &LogProgress)
+    }
+
+    /// Same as `save_as_stl_tiled`, but reports progress to `progress`
+    pub fn save_as_stl_tiled_with_progress(&self,
+                                           path_prefix: &str,
+                                           tiles_x: usize,
+                                           tiles_y: usize,
+                                           options: &TileOptions,
+                                           progress: &dyn ProgressSink) -> Result<Vec<String>, LasToStlError>{
+        // clipped once for the whole model, so every tile has the same range
+        if let Some((clipped, stl)) = self.clip_for_stl(&options.stl)? {
+            return clipped.save_as_stl_tiled_with_progress(path_prefix, tiles_x, tiles_y, &TileOptions { stl, ..options.clone() }, progress)
+        }
+        self.check_meshable(&options.stl)?;
+        let (cells_x, cells_y) = (self.x_res - 1, self.y_res - 1);
+        if tiles_x == 0 || tiles_y == 0 || tiles_x > cells_x || tiles_y > cells_y {
+            return Err(LasToStlError::TileCountError{ tiles_x, tiles_y, x_res: self.x_res, y_res: self.y_res })
+        }
+
+        // tile i is from cell starts[i] to starts[i + 1], so neighbors share the row of cells on the seam
+        let x_starts: Vec<usize> = (0..=tiles_x).map(|tile| tile * cells_x / tiles_x).collect();
+        let y_starts: Vec<usize> = (0..=tiles_y).map(|tile| tile * cells_y / tiles_y).collect();
+
+        let mut stl_options = options.stl.clone();
+        if stl_options.base.frame.take().is_some() {
+            warn!("frames are left out of tiled STLs");
+        }
+        if options.keys.is_some() && stl_options.max_error.take().is_some() {
+            warn!("`max_error` is ignored for tiles with keys, the pockets need every cell of the bottom");
+        }
+
+        let (x_unit, y_unit, _) = self.get_stl_units(&stl_options);
+        let (x_unit, y_unit) = (x_unit as f32, y_unit as f32);
+
+        let mut placements: Vec<KeyPlacement> = Vec::new();
+        if let Some(keys) = &options.keys {
+            for tile_y in 0..tiles_y {
+                for seam_x in &x_starts[1..tiles_x] {
+                    let (start, end) = (y_starts[tile_y] as f32 * y_unit, y_starts[tile_y + 1] as f32 * y_unit);
+                    placements.extend(get_key_positions(start, end, keys.width).into_iter().map(|center_y| KeyPlacement {
+                        center_x: *seam_x as f32 * x_unit,
+                        center_y,
+                        across_x: true,
+                    }));
+                }
+            }
+            for tile_x in 0..tiles_x {
+                for seam_y in &y_starts[1..tiles_y] {
+                    let (start, end) = (x_starts[tile_x] as f32 * x_unit, x_starts[tile_x + 1] as f32 * x_unit);
+                    placements.extend(get_key_positions(start, end, keys.width).into_iter().map(|center_x| KeyPlacement {
+                        center_x,
+                        center_y: *seam_y as f32 * y_unit,
+                        across_x: false,
+                    }));
+                }
+            }
+        }
+
+        let mut paths: Vec<String> = Vec::with_capacity(tiles_x * tiles_y + 1);
+        for tile_y in 0..tiles_y {
+            for tile_x in 0..tiles_x {
+                let tile = self.get_tile(x_starts[tile_x], x_starts[tile_x + 1], y_starts[tile_y], y_starts[tile_y + 1]);
+                let tile_options = self.get_tile_stl_options(&tile, &stl_options);
+
+                let mesh = match &options.keys {
+                    Some(keys) if !placements.is_empty() => {
+                        let mut mesh = tile.get_full_mesh(&tile_options, progress)?;
+                        let origin = (x_starts[tile_x] as f32 * x_unit, y_starts[tile_y] as f32 * y_unit);
+                        cut_key_pockets(&mut mesh, tile.data.len(), keys, &placements, origin);
+                        mesh.recompute_normals();
+                        mesh.check(tile_options.validation, progress)?;
+                        mesh
+                    }
+                    _ => tile.get_mesh_with_progress(&tile_options, progress)?,
+                };
+
+                let path = format!("{path_prefix}_{tile_x}_{tile_y}.stl");
+                stl_options.overwrite.save(&path, |file| mesh.write_stl(file, stl_options.format))?;
+                paths.push(path);
+            }
+        }
+
+        if let Some(keys) = &options.keys {
+            if !placements.is_empty() {
+                let path = format!("{path_prefix}_key.stl");
+                stl_options.overwrite.save(&path, |file| keys.get_key_mesh().write_stl(file, stl_options.format))?;
+                info!("print {} keys from {}", placements.len(), path);
+                paths.push(path);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// the part of self from cell (first_x, first_y) to (last_x, last_y). Unlike `crop`, the z bounds stay the same,
+    /// so holes and the floor are the same as in the whole model
+    fn get_tile(&self, first_x: usize, last_x: usize, first_y: usize, last_y: usize) -> HeightMap{
+        let x_tick = self.bounds.x_range() / (self.x_res - 1) as f64;
+        let y_tick = self.bounds.y_range() / (self.y_res - 1) as f64;
+
+        let mut data: Vec<f64> = Vec::with_capacity((last_x - first_x + 1) * (last_y - first_y + 1));
+        for row in self.data.chunks(self.x_res).skip(first_y).take(last_y - first_y + 1) {
+            data.extend_from_slice(&row[first_x..=last_x]);
+        }
+
+        let bounds = UtmBoundingBox{
+            min_x: self.bounds.min_x + first_x as f64 * x_tick,
+            max_x: self.bounds.min_x + last_x as f64 * x_tick,
+            min_y: self.bounds.min_y + first_y as f64 * y_tick,
+            max_y: self.bounds.min_y + last_y as f64 * y_tick,
+            ..self.bounds
+        };
+
+        self.with_grid(data, last_x - first_x + 1, last_y - first_y + 1, bounds)
+    }
+
+    /// `options` changed so `tile` comes out at the same scale as self would
+    fn get_tile_stl_options(&self, tile: &HeightMap, options: &StlOptions) -> StlOptions{
+        let mut tile_options = options.clone();
+        match options.scale {
+            // the z units depend on the size of the whole grid
+            StlScale::Pixels => {
+                tile_options.z_scaling *= (self.x_res as f64 / self.bounds.x_range()) / (tile.x_res as f64 / tile.bounds.x_range());
+            }
+            StlScale::ModelWidthMm(width) => {
+                tile_options.scale = StlScale::MmPerMeter(width / self.bounds.x_range());
+            }
+            StlScale::MmPerMeter(_) => {}
+        }
+        tile_options
+    }
+}
+
+/// where along a seam from `start` to `end` the keys go. Two if there's room, otherwise one in the middle
+fn get_key_positions(start: f32, end: f32, key_width: f32) -> Vec<f32>{
+    let length = end - start;
+    if length >= 4f32 * key_width {
+        vec![start + length / 4f32, start + 3f32 * length / 4f32]
+    } else {
+        vec![start + length / 2f32]
+    }
+}
+
+/// Raises the bottom of `mesh` (a full resolution mesh of `num_cells` cells, so the bottom vertices come after the top ones)
+/// to `keys.depth` inside every key pocket. `origin` is where the tile is in the whole model
+fn cut_key_pockets(mesh: &mut IndexedMesh, num_cells: usize, keys: &TileKeys, placements: &[KeyPlacement], origin: (f32, f32)){
+    let mut too_thin = false;
+    for index in 0..num_cells {
+        let bottom = mesh.vertices[num_cells + index];
+        let (x, y) = (bottom[0] + origin.0, bottom[1] + origin.1);
+        let in_pocket = placements.iter().any(|placement| {
+            let (across, along) = match placement.across_x {
+                true => (x - placement.center_x, y - placement.center_y),
+                false => (y - placement.center_y, x - placement.center_x),
+            };
+            keys.pocket_contains(across, along)
+        });
+        if in_pocket {
+            too_thin |= mesh.vertices[index][2] <= keys.depth;
+            mesh.vertices[num_cells + index] = Vertex::new([bottom[0], bottom[1], bottom[2].max(keys.depth)]);
+        }
+    }
+    if too_thin {
+        warn!("the terrain is thinner than the key pockets are deep in some places, add more base or make the keys shallower");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stl::OverwritePolicy;
+
+    /// 9x5 cells a meter apart, sloping up to the north east so every cell on a seam has its own height
+    fn slope_height_map() -> HeightMap {
+        HeightMap {
+            data: (0..45).map(|index| 1f64 + (index % 9) as f64 * 0.5 + (index / 9) as f64).collect(),
+            x_res: 9,
+            y_res: 5,
+            bounds: UtmBoundingBox::new(0f64, 8f64, 0f64, 4f64, 0f64, 9f64),
+            utm_zone: None,
+            geoid_correction: None,
+            crs: None,
+        }
+    }
+
+    /// the (y, z) of every vertex of an STL file at `x`, sorted
+    fn read_seam(path: &str, x: f32) -> Vec<(f32, f32)> {
+        let mesh = stl_io::read_stl(&mut std::fs::File::open(path).unwrap()).unwrap();
+        let mut seam: Vec<(f32, f32)> = mesh.vertices.iter()
+            .filter(|vertex| (vertex[0] - x).abs() < 1e-4)
+            .map(|vertex| (vertex[1], vertex[2]))
+            .collect();
+        seam.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        seam.dedup();
+        seam
+    }
+
+    #[test]
+    fn tiles_match_up_on_the_seam() {
+        let directory = std::env::temp_dir().join(format!("las_kml_to_stl_tiles_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path_prefix = directory.join("terrain").display().to_string();
+
+        let options = TileOptions {
+            stl: StlOptions { base_thickness: 2f32, overwrite: OverwritePolicy::Overwrite, ..StlOptions::default() },
+            keys: None,
+        };
+        let paths = slope_height_map().save_as_stl_tiled(&path_prefix, 2, 1, &options).unwrap();
+        assert_eq!(paths, vec![format!("{path_prefix}_0_0.stl"), format!("{path_prefix}_1_0.stl")]);
+
+        // the west tile is cells 0 to 4 and the east one 4 to 8, so the seam is the east edge of one and the west edge of the other
+        let west = read_seam(&paths[0], 4f32);
+        let east = read_seam(&paths[1], 0f32);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(west.len(), 10, "a top and bottom vertex for every cell on the seam");
+        assert_eq!(west.len(), east.len());
+        for ((west_y, west_z), (east_y, east_z)) in west.iter().zip(&east) {
+            assert!((west_y - east_y).abs() < 1e-4 && (west_z - east_z).abs() < 1e-4, "({west_y}, {west_z}) and ({east_y}, {east_z})");
+        }
+    }
+
+    #[test]
+    fn more_tiles_than_cells_is_an_error() {
+        let result = slope_height_map().save_as_stl_tiled("unused", 9, 1, &TileOptions::default());
+        assert!(matches!(result, Err(LasToStlError::TileCountError{ tiles_x: 9, .. })));
+    }
+}
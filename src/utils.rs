@@ -1,7 +1,13 @@
 use std::num::FpCategory;
 use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::SystemTime;
 use glob::glob;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use crate::errors::LasToStlError;
+use crate::utm_bounds::UtmBoundingBox;
 use log::warn;
 
 /// maps `value_float` from a range between `min_float` and `max_float` to a u64 in range `0` to `max_val`
@@ -133,6 +139,120 @@ pub fn get_point_deltas_within_radius(radius: u16) -> Vec<(i16, i16)>{
     point_deltas
 }
 
+/// How to reorient a grid with `reorient_grid`. Rotations are as seen from above with y pointing up, like in the STL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridOrientation {
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+    /// mirror left to right
+    FlipX,
+    /// mirror top to bottom
+    FlipY,
+}
+
+impl GridOrientation {
+    /// returns true if x and y (resolution and ranges) switch places
+    pub fn swaps_axes(&self) -> bool {
+        matches!(self, GridOrientation::RotateClockwise | GridOrientation::RotateCounterClockwise)
+    }
+}
+
+/// Rotates or flips a grid stored the same way as everything else (see `x_y_to_index`).
+/// Returns the new grid, its x resolution and y resolution.
+pub fn reorient_grid<T: Copy>(data: &[T], x_res: usize, y_res: usize, orientation: GridOrientation) -> (Vec<T>, usize, usize){
+    let (new_x_res, new_y_res) = if orientation.swaps_axes() { (y_res, x_res) } else { (x_res, y_res) };
+
+    let new_data: Vec<T> = (0..new_x_res * new_y_res).map(|new_index| {
+        let (new_x, new_y) = (new_index % new_x_res, new_index / new_x_res);
+        // where the new cell comes from in the old grid
+        let (x, y) = match orientation {
+            GridOrientation::RotateClockwise => (x_res - 1 - new_y, new_x),
+            GridOrientation::RotateCounterClockwise => (new_y, y_res - 1 - new_x),
+            GridOrientation::Rotate180 => (x_res - 1 - new_x, y_res - 1 - new_y),
+            GridOrientation::FlipX => (x_res - 1 - new_x, new_y),
+            GridOrientation::FlipY => (new_x, y_res - 1 - new_y),
+        };
+        data[y * x_res + x]
+    }).collect();
+
+    (new_data, new_x_res, new_y_res)
+}
+
+/// swaps the x and y ranges of `bounds`, keeping `min_x` and `min_y` where they are. For grids rotated by `reorient_grid`
+pub fn swap_bound_ranges(bounds: UtmBoundingBox) -> UtmBoundingBox{
+    UtmBoundingBox{
+        max_x: bounds.min_x + bounds.y_range(),
+        max_y: bounds.min_y + bounds.x_range(),
+        ..bounds
+    }
+}
+
 pub fn utm_point_to_pixel_space(x: f64, y: f64, x_offset: f64, y_offset: f64, x_tick: f64, y_tick: f64) -> (usize, usize){
     (((x - x_offset) / x_tick) as usize, ((y - y_offset) / y_tick) as usize)
-}
\ No newline at end of file
+}
+
+/// Times how long something takes, for logging. There is no clock in the browser (`SystemTime::now` panics on
+/// wasm32-unknown-unknown), so there it doesn't time anything and always says zero
+#[derive(Clone, Copy, Debug)]
+pub struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: SystemTime,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: SystemTime::now(),
+        }
+    }
+
+    /// time since `start`, zero if the clock went backwards
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        return self.start.elapsed().unwrap_or_default();
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        return Duration::ZERO;
+    }
+}
+
+/// Runs `op` on a new thread pool with `num_threads` threads, or on rayon's global pool if it's `None`.
+/// Where threads can't be spawned (like in the browser) the global pool runs everything on the current thread,
+/// but building a new pool fails, so `None` is the only thing that works there
+pub fn install_in_pool<R, F>(num_threads: Option<usize>, op: F) -> Result<R, LasToStlError>
+    where R: Send,
+          F: FnOnce() -> R + Send
+{
+    match num_threads {
+        Some(num_threads) => Ok(ThreadPoolBuilder::new().num_threads(num_threads).build()?.install(op)),
+        None => Ok(op()),
+    }
+}
+
+/// Number of cells each task gets in `par_zip_for_each` and `par_for_each`. One task per cell is slower than
+/// a plain loop for operations this cheap, so the grid is split into chunks that are each worth sending to a thread
+pub const PARALLEL_CHUNK_SIZE: usize = 1 << 16;
+
+/// Calls `op` on every cell of `a` with the cell at the same index in `b`, in parallel on rayon's global pool,
+/// `PARALLEL_CHUNK_SIZE` cells at a time. Like zip, it stops at the end of the shorter one.
+/// For applying masks to heightmaps and masks to each other, which are tens of millions of cells on big grids
+pub fn par_zip_for_each<A, B, F>(a: &mut [A], b: &[B], op: F)
+    where A: Send,
+          B: Sync,
+          F: Fn(&mut A, &B) + Sync
+{
+    a.par_chunks_mut(PARALLEL_CHUNK_SIZE).zip(b.par_chunks(PARALLEL_CHUNK_SIZE)).for_each(|(a_chunk, b_chunk)| {
+        for (a_cell, b_cell) in a_chunk.iter_mut().zip(b_chunk){
+            op(a_cell, b_cell);
+        }
+    });
+}
+
+/// Same as `par_zip_for_each`, but for a single grid
+pub fn par_for_each<A, F>(a: &mut [A], op: F)
+    where A: Send,
+          F: Fn(&mut A) + Sync
+{
+    a.par_chunks_mut(PARALLEL_CHUNK_SIZE).for_each(|a_chunk| a_chunk.iter_mut().for_each(&op));
+}
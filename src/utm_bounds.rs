@@ -1,11 +1,18 @@
 use std::fmt::Display;
 use std::path::PathBuf;
-use las::{Bounds, Read, Reader};
+use las::{Bounds, Header, Read, Reader};
 use serde::{Deserialize, Serialize};
-use geo::Coord;
-use log::info;
+use geo::{Coord, LineString, Polygon, Rect};
+use log::{info, warn};
+use crate::batch_report::BatchReport;
+use crate::crs::{Crs, Reprojector};
 use crate::errors::LasToStlError;
+use crate::kml_utils::utm_polygon_to_lat_lon_polygon;
 use crate::utils::{f64_max, f64_min};
+use crate::utm_point::{UtmCoord, UtmZone};
+
+/// how many points every edge gets when converting between UTM and lat/lon (or another CRS), since a straight line in one is a little curved in the other
+const POINTS_PER_EDGE: usize = 16;
 
 /// Bounds for 3d space in UTM form. This is used to convert between UTM objects and unit-less discrete grids
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -15,7 +22,11 @@ pub struct UtmBoundingBox {
     pub min_y: f64,
     pub max_y: f64,
     pub min_z: f64,
-    pub max_z: f64
+    pub max_z: f64,
+    /// the zone and hemisphere x and y are in, if it's known. Boxes in different zones can't be combined,
+    /// see `union` and `eq_xy`. Boxes that don't know their zone are assumed to be in the same one as anything else
+    #[serde(default)]
+    pub utm_zone: Option<UtmZone>,
 }
 
 impl UtmBoundingBox {
@@ -26,32 +37,194 @@ impl UtmBoundingBox {
             min_y,
             max_y,
             min_z,
-            max_z
+            max_z,
+            utm_zone: None,
+        }
+    }
+
+    /// Same as `new`, but remembers which zone the box is in
+    pub fn new_in_zone(min_x: f64, max_x: f64, min_y: f64, max_y: f64, min_z: f64, max_z: f64, utm_zone: UtmZone) -> Self{
+        UtmBoundingBox{
+            utm_zone: Some(utm_zone),
+            ..UtmBoundingBox::new(min_x, max_x, min_y, max_y, min_z, max_z)
+        }
+    }
+
+    /// returns true if self and other are in the same zone, or at least one of them doesn't know its zone
+    pub fn same_zone(&self, other: &UtmBoundingBox) -> bool {
+        match (self.utm_zone, other.utm_zone) {
+            (Some(zone), Some(other_zone)) => zone == other_zone,
+            _ => true,
+        }
+    }
+
+    /// The x/y of the bounds as a lat/lon polygon (x = lon, y = lat). Every edge has a few points along it,
+    /// since straight lines in UTM aren't quite straight in lat/lon
+    pub fn to_lat_lon_polygon(&self, utm_zone: UtmZone) -> Result<Polygon, LasToStlError>{
+        let corners = [
+            (self.min_x, self.min_y),
+            (self.max_x, self.min_y),
+            (self.max_x, self.max_y),
+            (self.min_x, self.max_y),
+        ];
+        let mut exterior: Vec<Coord> = Vec::with_capacity(4 * POINTS_PER_EDGE + 1);
+        for (index, (start_x, start_y)) in corners.iter().enumerate() {
+            let (end_x, end_y) = corners[(index + 1) % 4];
+            for step in 0..POINTS_PER_EDGE {
+                let t = step as f64 / POINTS_PER_EDGE as f64;
+                exterior.push(Coord { x: start_x + (end_x - start_x) * t, y: start_y + (end_y - start_y) * t });
+            }
+        }
+        utm_polygon_to_lat_lon_polygon(&Polygon::new(LineString::from(exterior), vec![]), utm_zone)
+    }
+
+    /// Creates a `UtmBoundingBox` with the x/y of the two opposite lat/lon corners of a rectangle (in any order),
+    /// like the north west and south east corners. The whole lat/lon rectangle is inside it, even though its edges
+    /// aren't straight in UTM.
+    /// The z range is left at the impossible default, so this is only useful for x/y things like `LasLoadOptions::crop`.
+    ///
+    /// see [UTM on wikipedia](https://en.wikipedia.org/wiki/Universal_Transverse_Mercator_coordinate_system) to find what a UTM zone is.
+    pub fn from_lat_lon_corners(corner_a: &Coord, corner_b: &Coord, utm_zone: UtmZone) -> UtmBoundingBox {
+        let mut bounds = UtmBoundingBox{ utm_zone: Some(utm_zone), ..UtmBoundingBox::default() };
+        bounds.add_lat_lon_ring(&Rect::new(*corner_a, *corner_b).to_polygon().exterior().0, utm_zone);
+        bounds
+    }
+
+    /// Creates the smallest `UtmBoundingBox` around a lat/lon polygon (like a KML region from `kml_utils::get_regions`)
+    /// converted to UTM, to crop or size a heightmap to it. The z range is left at the impossible default, like `from_lat_lon_corners`.
+    ///
+    /// Returns `LasToStlError::EmptyPolygonError` if the polygon has no points
+    pub fn from_polygon(lat_lon_polygon: &Polygon, utm_zone: UtmZone) -> Result<UtmBoundingBox, LasToStlError> {
+        if lat_lon_polygon.exterior().0.is_empty() {
+            return Err(LasToStlError::EmptyPolygonError)
+        }
+        // holes are inside the exterior, so they can't make it bigger
+        let mut bounds = UtmBoundingBox{ utm_zone: Some(utm_zone), ..UtmBoundingBox::default() };
+        bounds.add_lat_lon_ring(&lat_lon_polygon.exterior().0, utm_zone);
+        Ok(bounds)
+    }
+
+    /// adds points along every edge of a lat/lon ring, since straight lat/lon edges are curved in UTM
+    fn add_lat_lon_ring(&mut self, lat_lon_ring: &[Coord], utm_zone: UtmZone){
+        for (start, end) in lat_lon_ring.iter().zip(lat_lon_ring.iter().cycle().skip(1)){
+            for i in 0..POINTS_PER_EDGE{
+                let t = i as f64 / POINTS_PER_EDGE as f64;
+                let lat_lon = Coord{ x: start.x + t * (end.x - start.x), y: start.y + t * (end.y - start.y) };
+                self.add_utm(Coord::from(&UtmCoord::from_gps_coord_in_zone(&lat_lon, &utm_zone)));
+            }
         }
     }
 
     /// this function creates a new `UtmBoundingBox` from a LAS or LAZ file.
     pub fn get_bounds_from_las(path_buf: &PathBuf) -> Result<UtmBoundingBox, LasToStlError> {
         let reader = Reader::from_path(path_buf)?;
-        let b: Bounds = reader.header().bounds();
-        Ok(UtmBoundingBox {
+        Ok(UtmBoundingBox::from_las_header(reader.header()))
+    }
+
+    /// the bounds written in the header of a LAS or LAZ file, in whatever CRS the file is in
+    pub fn from_las_header(header: &Header) -> UtmBoundingBox {
+        let b: Bounds = header.bounds();
+        UtmBoundingBox {
             min_x: b.min.x,
             max_x: b.max.x,
             min_y: b.min.y,
             max_y: b.max.y,
             min_z: b.min.z,
             max_z: b.max.z,
+            utm_zone: Crs::from_las_header(header).and_then(|crs| crs.get_utm_zone()),
+        }
+    }
+
+    /// Same as `get_bounds_from_las`, but the bounds are reprojected into `target` using the CRS from the LAS header.
+    /// If the header doesn't have a CRS, the file is assumed to already be in `target`.
+    ///
+    /// The edges of the header's bounds are sampled so the box still contains all points
+    /// when the edges are curved in the new CRS.
+    pub fn get_bounds_from_las_in_crs(path_buf: &PathBuf, target: &Crs) -> Result<UtmBoundingBox, LasToStlError> {
+        UtmBoundingBox::get_bounds_from_las_in_crs_with_fallback(path_buf, target, None)
+    }
+
+    /// Same as `get_bounds_from_las_in_crs`, but files without a CRS in their header are assumed to be in `fallback`
+    /// (if it is `Some`) instead of `target`
+    pub fn get_bounds_from_las_in_crs_with_fallback(path_buf: &PathBuf, target: &Crs, fallback: Option<&Crs>) -> Result<UtmBoundingBox, LasToStlError> {
+        let reader = Reader::from_path(path_buf)?;
+        UtmBoundingBox::from_las_header_in_crs(reader.header(), &path_buf.display().to_string(), target, fallback)
+    }
+
+    /// Same as `get_bounds_from_las_in_crs_with_fallback`, but with the header of a file that's already open.
+    /// `display_name` is only used in the warning if the header has no CRS
+    pub fn from_las_header_in_crs(header: &Header, display_name: &str, target: &Crs, fallback: Option<&Crs>) -> Result<UtmBoundingBox, LasToStlError> {
+        let bounds = UtmBoundingBox::from_las_header(header);
+        match Crs::from_las_header(header).or_else(|| fallback.cloned()){
+            Some(source) if source != *target => {
+                bounds.reproject(&source, target)
+            }
+            Some(_) => {
+                Ok(UtmBoundingBox{ utm_zone: target.get_utm_zone(), ..bounds })
+            }
+            None => {
+                warn!("{display_name:?} has no CRS in its header, assuming it is already in {target}");
+                Ok(UtmBoundingBox{ utm_zone: target.get_utm_zone(), ..bounds })
+            }
+        }
+    }
+
+    /// Reprojects the bounds from `from` into `to`. The edges are sampled so the box still contains everything
+    /// that was inside it when the edges are curved in the new CRS. z is only converted from feet if one of them is in feet
+    pub fn reproject(&self, from: &Crs, to: &Crs) -> Result<UtmBoundingBox, LasToStlError> {
+        let reprojector = Reprojector::new(from, to)?;
+
+        // heights are usually in the same unit as x and y
+        let z_scale = from.meters_per_unit() / to.meters_per_unit();
+        let mut bounds = UtmBoundingBox{
+            min_z: self.min_z * z_scale,
+            max_z: self.max_z * z_scale,
+            utm_zone: to.get_utm_zone(),
+            ..UtmBoundingBox::default()
+        };
+        for i in 0..=POINTS_PER_EDGE{
+            let t = i as f64 / POINTS_PER_EDGE as f64;
+            let x = self.min_x + t * self.x_range();
+            let y = self.min_y + t * self.y_range();
+            for (sample_x, sample_y) in [(x, self.min_y), (x, self.max_y), (self.min_x, y), (self.max_x, y)]{
+                let (new_x, new_y) = reprojector.convert(sample_x, sample_y)?;
+                bounds.add_utm(Coord{x: new_x, y: new_y});
+            }
+        }
+        Ok(bounds)
+    }
+
+    /// Gets the smallest box that includes all points in both regions (z included).
+    ///
+    /// Returns `LasToStlError::UtmZoneMismatchError` if they are in different zones (see `same_zone`)
+    pub fn union(&self, other: &UtmBoundingBox) -> Result<UtmBoundingBox, LasToStlError>{
+        if let (Some(zone), Some(other_zone)) = (self.utm_zone, other.utm_zone) {
+            zone.check_matches(&other_zone)?;
+        }
+        Ok(UtmBoundingBox{
+            min_x: f64_min(self.min_x, other.min_x),
+            max_x: f64_max(self.max_x, other.max_x),
+            min_y: f64_min(self.min_y, other.min_y),
+            max_y: f64_max(self.max_y, other.max_y),
+            min_z: f64_min(self.min_z, other.min_z),
+            max_z: f64_max(self.max_z, other.max_z),
+            utm_zone: self.utm_zone.or(other.utm_zone),
         })
     }
 
-    /// adds another bounding box to self, making self include all points in both regions
-    pub fn add(&mut self, other: UtmBoundingBox){
-        self.min_x = f64_min(self.min_x, other.min_x);
-        self.max_x = f64_max(self.max_x, other.max_x);
-        self.min_y = f64_min(self.min_y, other.min_y);
-        self.max_y = f64_max(self.max_y, other.max_y);
-        self.min_z = f64_min(self.min_z, other.min_z);
-        self.max_z = f64_max(self.max_z, other.max_z);
+    /// Gets the region inside both boxes when looking from above, with the z range of self.
+    /// Returns `None` if they don't overlap (see `overlaps_xy`)
+    pub fn intersection(&self, other: &UtmBoundingBox) -> Option<UtmBoundingBox>{
+        if !self.overlaps_xy(other) {
+            return None
+        }
+        Some(UtmBoundingBox{
+            min_x: f64_max(self.min_x, other.min_x),
+            max_x: f64_min(self.max_x, other.max_x),
+            min_y: f64_max(self.min_y, other.min_y),
+            max_y: f64_min(self.max_y, other.max_y),
+            ..*self
+        })
     }
 
     /// adds a UTM coordinate, changes self to include the UTM coordinate
@@ -64,25 +237,131 @@ impl UtmBoundingBox {
 
     /// Creates a new `UtmBoundingBox` to include all LAS/LAZ data from the provided paths.
     /// Paths should be to individual LAS files, if you want to do a folder use `utils::get_paths`.
+    /// The bounds are taken as they are in each file, so files in different UTM zones are a `LasToStlError::UtmZoneMismatchError`.
+    /// Use `get_bounds_from_las_paths_in_crs` for that.
     /// Logs info about the process using because it can take around 10 seconds for large data sets.
     ///
+    /// Files whose header can't be read are left out and listed in the returned report.
+    /// Returns the error of the first file only if none of them can be read.
+    ///
     /// logging done with log::info (https://docs.rs/log/latest/log/enum.Level.html#variant.Info)
-    pub fn get_bounds_from_las_paths(las_paths: &Vec<PathBuf>) -> Result<UtmBoundingBox, LasToStlError> {
+    pub fn get_bounds_from_las_paths(las_paths: &[PathBuf]) -> Result<(UtmBoundingBox, BatchReport), LasToStlError> {
+        info!("finding bounds of {} files", las_paths.len());
+        UtmBoundingBox::union_of_las_bounds(las_paths, UtmBoundingBox::get_bounds_from_las)
+    }
+
+    /// Same as `get_bounds_from_las_paths`, but every file's bounds are reprojected into `target`.
+    /// See `get_bounds_from_las_in_crs`
+    pub fn get_bounds_from_las_paths_in_crs(las_paths: &[PathBuf], target: &Crs) -> Result<(UtmBoundingBox, BatchReport), LasToStlError> {
+        info!("finding bounds of {} files in {target}", las_paths.len());
+        UtmBoundingBox::union_of_las_bounds(las_paths, |path| UtmBoundingBox::get_bounds_from_las_in_crs(path, target))
+    }
 
+    /// does the work of `get_bounds_from_las_paths`, with `get_bounds` getting the bounds of each file
+    fn union_of_las_bounds<F>(las_paths: &[PathBuf], get_bounds: F) -> Result<(UtmBoundingBox, BatchReport), LasToStlError>
+        where F: Fn(&PathBuf) -> Result<UtmBoundingBox, LasToStlError>
+    {
         let mut global_bounds = UtmBoundingBox::default();
+        let mut report = BatchReport::new();
+        let mut first_error: Option<LasToStlError> = None;
 
         let num_files = las_paths.len();
 
-        let mut count: usize = 1;
+        for (index, path) in las_paths.iter().enumerate(){
+            info!("bounding... {} / {num_files}", index + 1);
+            match get_bounds(path){
+                Ok(bounds) => {
+                    global_bounds = global_bounds.union(&bounds)?;
+                    report.add_success();
+                }
+                Err(e) => {
+                    warn!("skipping {:?}, its bounds could not be read: {e}", path.display());
+                    report.add_skipped(index, path.display(), &e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match (report.num_succeeded, first_error){
+            (0, Some(e)) => Err(e),
+            _ => Ok((global_bounds, report)),
+        }
+    }
+
+    /// returns true if the x, y coordinate is inside self (edges included). Ignores z
+    pub fn contains_xy(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    /// Same as `contains_xy`, for a `Coord` in the same units as self
+    pub fn contains_point(&self, coord: &Coord) -> bool {
+        self.contains_xy(coord.x, coord.y)
+    }
 
-        info!("finding bounds of {num_files} files");
+    /// returns true if all of other is inside self (edges included). Ignores z
+    pub fn contains_box(&self, other: &UtmBoundingBox) -> bool {
+        other.min_x >= self.min_x && other.max_x <= self.max_x && other.min_y >= self.min_y && other.max_y <= self.max_y
+    }
 
-        for path in las_paths{
-            info!("bounding... {count} / {num_files}");
-            global_bounds.add(UtmBoundingBox::get_bounds_from_las(path)?);
-            count += 1;
+    /// Gets self grown by `margin_m` on every side (or shrunk, if it's negative). Ignores z
+    pub fn expand_by(&self, margin_m: f64) -> UtmBoundingBox {
+        UtmBoundingBox{
+            min_x: self.min_x - margin_m,
+            max_x: self.max_x + margin_m,
+            min_y: self.min_y - margin_m,
+            max_y: self.max_y + margin_m,
+            ..*self
         }
-        Ok(global_bounds)
+    }
+
+    /// Gets the point in the middle, when looking from above
+    pub fn center(&self) -> Coord {
+        Coord{ x: (self.min_x + self.max_x) / 2f64, y: (self.min_y + self.max_y) / 2f64 }
+    }
+
+    /// returns true if self and other share any area when looking from above. Ignores z
+    pub fn overlaps_xy(&self, other: &UtmBoundingBox) -> bool {
+        self.min_x <= other.max_x && self.max_x >= other.min_x &&
+            self.min_y <= other.max_y && self.max_y >= other.min_y
+    }
+
+    /// returns true if self and other cover exactly the same area when looking from above. Ignores z,
+    /// so masks still match a heightmap after its heights (and z bounds) change, or one made from it like `HeightMap::slope_degrees`
+    /// Boxes in different zones are never equal (see `same_zone`)
+    pub fn eq_xy(&self, other: &UtmBoundingBox) -> bool {
+        self.same_zone(other) &&
+            self.min_x == other.min_x && self.max_x == other.max_x &&
+            self.min_y == other.min_y && self.max_y == other.max_y
+    }
+
+    /// returns true if every bound is a finite number and no min is bigger than its max (see `validate`)
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Returns `LasToStlError::InvalidBoundsError` saying what's wrong if any bound isn't a finite number,
+    /// or a min is bigger than its max, like a `UtmBoundingBox::default()` nothing was added to.
+    /// Bounds like that make NaN or infinite cell sizes that silently turn everything into garbage
+    pub fn validate(&self) -> Result<(), LasToStlError> {
+        self.validate_xy()?;
+        self.validate_axis("z", self.min_z, self.max_z)
+    }
+
+    /// Same as `validate`, but ignores z, for things that don't use it like masks and crop regions
+    pub fn validate_xy(&self) -> Result<(), LasToStlError> {
+        self.validate_axis("x", self.min_x, self.max_x)?;
+        self.validate_axis("y", self.min_y, self.max_y)
+    }
+
+    fn validate_axis(&self, axis: &str, min: f64, max: f64) -> Result<(), LasToStlError> {
+        let reason = if !min.is_finite() || !max.is_finite() {
+            format!("the {axis} range ({min} to {max}) isn't finite")
+        } else if min > max {
+            format!("min_{axis} is bigger than max_{axis}, like a default UtmBoundingBox nothing was added to")
+        } else {
+            return Ok(())
+        };
+        Err(LasToStlError::InvalidBoundsError{ bounds: *self, reason })
     }
 
     /// Gets the difference of the largest and smallest x values
@@ -108,7 +387,8 @@ impl PartialEq for UtmBoundingBox {
             self.min_y == other_bounds.min_y &&
             self.max_y == other_bounds.max_y &&
             self.min_z == other_bounds.min_z &&
-            self.max_z == other_bounds.max_z
+            self.max_z == other_bounds.max_z &&
+            self.same_zone(other_bounds)
     }
 }
 
@@ -125,7 +405,8 @@ impl Default for UtmBoundingBox {
             min_y: f64::MAX,
             max_y: f64::MIN,
             min_z: f64::MAX,
-            max_z: f64::MIN
+            max_z: f64::MIN,
+            utm_zone: None,
         }
     }
 }
@@ -138,4 +419,31 @@ impl Display for UtmBoundingBox{
                 z: ({}, {})]",
                self.min_x, self.max_x, self.min_y, self.max_y, self.min_z, self.max_z)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_in_different_zones_dont_combine() {
+        let north_10 = UtmBoundingBox::new_in_zone(0f64, 10f64, 0f64, 10f64, 0f64, 1f64, UtmZone::new(10, true));
+        let north_11 = UtmBoundingBox::new_in_zone(0f64, 10f64, 0f64, 10f64, 0f64, 1f64, UtmZone::new(11, true));
+        let unknown = UtmBoundingBox::new(5f64, 20f64, 0f64, 10f64, 0f64, 1f64);
+
+        assert!(matches!(north_10.union(&north_11), Err(LasToStlError::UtmZoneMismatchError{ .. })));
+        assert!(!north_10.eq_xy(&north_11));
+
+        let union = unknown.union(&north_10).unwrap();
+        assert_eq!(union.utm_zone, Some(UtmZone::new(10, true)), "the zone of the box that knows it should be kept");
+        assert_eq!((union.min_x, union.max_x), (0f64, 20f64));
+        assert!(north_10.eq_xy(&UtmBoundingBox::new(0f64, 10f64, 0f64, 10f64, 5f64, 6f64)));
+    }
+
+    #[test]
+    fn lat_lon_boxes_know_their_zone() {
+        let utm_zone = UtmZone::new(10, true);
+        let bounds = UtmBoundingBox::from_lat_lon_corners(&Coord{ x: -123.1, y: 45.1 }, &Coord{ x: -123f64, y: 45f64 }, utm_zone);
+        assert_eq!(bounds.utm_zone, Some(utm_zone));
+    }
 }
\ No newline at end of file
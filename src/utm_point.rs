@@ -1,5 +1,87 @@
+use std::fmt::Display;
 use geo::{Coord, Point};
-use utm::to_utm_wgs84;
+use serde::{Deserialize, Serialize};
+use utm::{lat_lon_to_zone_number, to_utm_wgs84, wsg84_utm_to_lat_lon};
+use crate::crs::Crs;
+use crate::errors::LasToStlError;
+
+/// A UTM zone number (1 to 60) and hemisphere.
+///
+/// see [UTM on wikipedia](https://en.wikipedia.org/wiki/Universal_Transverse_Mercator_coordinate_system) to find what a UTM zone is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UtmZone {
+    pub zone: u8,
+    pub north: bool,
+}
+
+impl UtmZone {
+    pub fn new(zone: u8, north: bool) -> Self {
+        UtmZone {
+            zone,
+            north,
+        }
+    }
+
+    /// Gets the zone a lat/lon coordinate (x = lon, y = lat) is in
+    pub fn from_lat_lon_coord(lat_lon: &Coord<f64>) -> Self {
+        UtmZone {
+            zone: lat_lon_to_zone_number(lat_lon.y, lat_lon.x),
+            north: lat_lon.y >= 0f64,
+        }
+    }
+
+    /// Gets the WGS84 CRS for this zone (EPSG 326xx in the north, 327xx in the south)
+    pub fn to_crs(&self) -> Crs {
+        if self.north {
+            Crs::Epsg(32600 + self.zone as u32)
+        } else {
+            Crs::Epsg(32700 + self.zone as u32)
+        }
+    }
+
+    /// number of zones between self and other, ignoring the hemisphere and wrapping around at 60
+    pub fn distance(&self, other: &UtmZone) -> u8 {
+        let difference = self.zone.abs_diff(other.zone);
+        difference.min(60 - difference)
+    }
+
+    /// converts an easting and northing in this zone to lat/lon (x = lon, y = lat)
+    pub fn to_lat_lon(&self, easting: f64, northing: f64) -> Result<Coord<f64>, LasToStlError> {
+        // the utm crate only uses the letter to tell the hemisphere
+        let zone_letter = if self.north { 'N' } else { 'M' };
+        let (lat, lon) = wsg84_utm_to_lat_lon(easting, northing, self.zone, zone_letter)
+            .map_err(|e| LasToStlError::ReprojectionError(format!("could not convert ({easting}, {northing}) in zone {self} to lat/lon: {e:?}")))?;
+        Ok(Coord{x: lon, y: lat})
+    }
+
+    /// converts lat/lon (x = lon, y = lat) to (easting, northing) in this zone, even if the coordinate is outside of the zone
+    pub fn from_lat_lon(&self, lat_lon: &Coord<f64>) -> (f64, f64) {
+        let (northing, easting, _) = to_utm_wgs84(lat_lon.y, lat_lon.x, self.zone);
+        // the utm crate picks the false northing from the latitude, not the hemisphere of the zone
+        let northing = match (self.north, lat_lon.y >= 0f64) {
+            (true, false) => northing - 10000000f64,
+            (false, true) => northing + 10000000f64,
+            _ => northing,
+        };
+        (easting, northing)
+    }
+
+    /// Returns `LasToStlError::UtmZoneMismatchError` if `other` is a different zone or hemisphere,
+    /// for things that are combined cell by cell, where the same easting and northing must be the same place
+    pub fn check_matches(&self, other: &UtmZone) -> Result<(), LasToStlError> {
+        if self == other {
+            Ok(())
+        } else {
+            Err(LasToStlError::UtmZoneMismatchError{ first: *self, other: *other })
+        }
+    }
+}
+
+impl Display for UtmZone {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}{}", self.zone, if self.north { 'N' } else { 'S' })
+    }
+}
 
 
 
@@ -8,6 +90,8 @@ use utm::to_utm_wgs84;
 pub struct UtmCoord {
     pub northing: f64,
     pub easting: f64,
+    /// the zone and hemisphere the easting and northing are in, if it is known
+    pub zone: Option<UtmZone>,
 }
 
 
@@ -20,6 +104,15 @@ impl UtmCoord {
         UtmCoord{
             northing: coords.1,
             easting: coords.0,
+            zone: None,
+        }
+    }
+
+    /// Same as `new`, but remembers which zone the coordinates are in, so it can be converted back to lat/lon
+    pub fn new_in_zone(coords: (f64, f64), utm_zone: UtmZone) -> Self{
+        UtmCoord{
+            zone: Some(utm_zone),
+            ..UtmCoord::new(coords)
         }
     }
 
@@ -30,6 +123,7 @@ impl UtmCoord {
         UtmCoord {
             northing,
             easting,
+            zone: Some(UtmZone::new(utm_zone, gps_point.y >= 0f64)),
         }
     }
 
@@ -37,6 +131,42 @@ impl UtmCoord {
     pub fn from_lat_lon_point_zoned(gps_point: &Point<f64>, utm_zone: u8) -> Self {
         UtmCoord::from_gps_coord_zoned(&gps_point.0, utm_zone)
     }
+
+    /// Same as `from_gps_coord_zoned`, but uses the hemisphere of `utm_zone` instead of the hemisphere of the point,
+    /// so points just across the equator don't end up 10,000 km away
+    pub fn from_gps_coord_in_zone(gps_point: &Coord<f64>, utm_zone: &UtmZone) -> Self {
+        UtmCoord::new_in_zone(utm_zone.from_lat_lon(gps_point), *utm_zone)
+    }
+
+    /// converts back to lat/lon (x = lon, y = lat), with the easting and northing in UTM zone `zone` (1 to 60)
+    /// in the northern hemisphere if `north`, otherwise the southern.
+    ///
+    /// Returns `LasToStlError::UtmZoneMismatchError` if this coordinate knows its zone and it's a different one
+    pub fn to_lat_lon(&self, zone: u8, north: bool) -> Result<Coord<f64>, LasToStlError> {
+        let utm_zone = UtmZone::new(zone, north);
+        self.check_zone(&utm_zone)?;
+        utm_zone.to_lat_lon(self.easting, self.northing)
+    }
+
+    /// Same as `to_lat_lon`, with the zone and hemisphere as a `UtmZone`
+    pub fn to_lat_lon_in_zone(&self, utm_zone: &UtmZone) -> Result<Coord<f64>, LasToStlError> {
+        self.to_lat_lon(utm_zone.zone, utm_zone.north)
+    }
+
+    /// Same as `to_lat_lon`, in the zone this coordinate is in.
+    ///
+    /// Returns `LasToStlError::NoUtmZoneError` if the zone isn't known (made with `new` or from a tuple)
+    pub fn to_lat_lon_in_own_zone(&self) -> Result<Coord<f64>, LasToStlError> {
+        self.to_lat_lon_in_zone(&self.zone.ok_or(LasToStlError::NoUtmZoneError)?)
+    }
+
+    /// Returns `LasToStlError::UtmZoneMismatchError` if this coordinate knows its zone and it isn't `utm_zone`
+    pub fn check_zone(&self, utm_zone: &UtmZone) -> Result<(), LasToStlError> {
+        match self.zone {
+            Some(zone) => zone.check_matches(utm_zone),
+            None => Ok(()),
+        }
+    }
 }
 
 
@@ -58,9 +188,28 @@ impl From<(f64, f64)> for UtmCoord{
 
     /// (x, y)
     fn from(value: (f64, f64)) -> Self {
-        UtmCoord{
-            northing: value.1,
-            easting: value.0,
+        UtmCoord::new(value)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lat_lon_survives_the_round_trip() {
+        for (lat_lon, zone, north) in [(Coord{ x: -122.5, y: 45.5 }, 10, true), (Coord{ x: 151.2, y: -33.9 }, 56, false)] {
+            let utm_coord = UtmCoord::from_gps_coord_in_zone(&lat_lon, &UtmZone::new(zone, north));
+            let back = utm_coord.to_lat_lon(zone, north).unwrap();
+            assert!((back.x - lat_lon.x).abs() < 1e-5 && (back.y - lat_lon.y).abs() < 1e-5, "{lat_lon:?} came back as {back:?}");
+            assert_eq!(utm_coord.to_lat_lon_in_own_zone().unwrap(), back);
         }
     }
+
+    #[test]
+    fn converting_in_the_wrong_zone_is_an_error() {
+        let utm_coord = UtmCoord::from_gps_coord_in_zone(&Coord{ x: -122.5, y: 45.5 }, &UtmZone::new(10, true));
+        assert!(matches!(utm_coord.to_lat_lon(11, true), Err(LasToStlError::UtmZoneMismatchError{ .. })));
+        assert!(matches!(UtmCoord::new((500000f64, 5000000f64)).to_lat_lon_in_own_zone(), Err(LasToStlError::NoUtmZoneError)));
+        assert!(UtmCoord::new((500000f64, 5000000f64)).to_lat_lon(11, true).is_ok());
+    }
 }
\ No newline at end of file
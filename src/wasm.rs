@@ -0,0 +1,110 @@
+use geo::GeometryCollection;
+use wasm_bindgen::prelude::*;
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::kml_utils::{get_regions, get_trails, infer_utm_zone, load_kml_str};
+use crate::las_resampler::LasLoadOptions;
+use crate::mask::Mask;
+use crate::point_filter::PointFilter;
+use crate::progress::LogProgress;
+use crate::stl::StlOptions;
+
+/// A terrain model for a web page, built from the bytes of an uploaded LAS/LAZ file and the text of KML files,
+/// and saved as STL bytes, without a server. Same idea as `TerrainModelBuilder`, but with file contents instead of paths.
+///
+/// ```js
+/// import init, { Terrain } from "./pkg/las_kml_to_stl.js";
+///
+/// await init();
+/// const terrain = new Terrain(new Uint8Array(await lazFile.arrayBuffer()), 500, true);
+/// terrain.clip_to_kml(await kmlFile.text());
+/// const stl = terrain.to_stl(2.0, 5.0);
+/// ```
+#[wasm_bindgen]
+pub struct Terrain {
+    #[wasm_bindgen(skip)]
+    pub height_map: HeightMap,
+    /// the clip regions drawn onto a mask, if `clip_to_kml` was called
+    #[wasm_bindgen(skip)]
+    pub clip_mask: Option<Mask>,
+}
+
+#[wasm_bindgen]
+impl Terrain {
+    /// Loads a LAS/LAZ file, `resolution` cells across the x axis. `bare_earth` only keeps ground points
+    #[wasm_bindgen(constructor)]
+    pub fn new(las: &[u8], resolution: usize, bare_earth: bool) -> Result<Terrain, JsError> {
+        let options = LasLoadOptions {
+            filter: if bare_earth { PointFilter::bare_earth() } else { PointFilter::default() },
+            ..LasLoadOptions::default()
+        };
+        let height_map = HeightMap::from_las_bytes(&[las], Some(resolution), None, &options, &LogProgress)?;
+        Ok(Terrain { height_map, clip_mask: None })
+    }
+
+    /// Only keeps what's inside the polygons of a KML file (its text). Can be called more than once to keep only what's inside all of them
+    pub fn clip_to_kml(&mut self, kml: &str) -> Result<(), JsError> {
+        let geometry_collection = load_kml_str(kml)?;
+        self.infer_utm_zone(&geometry_collection);
+        let regions = get_regions(geometry_collection);
+        if regions.is_empty() {
+            return Err(LasToStlError::NoValidGeometriesError.into())
+        }
+        let mut clip_mask = Mask::new_for_height_map(&self.height_map)?;
+        for region in &regions {
+            clip_mask.add_filled_lat_lon_polygon(region)?;
+        }
+        match &mut self.clip_mask {
+            Some(existing_clip_mask) => existing_clip_mask.checked_bitand_assign(&clip_mask)?,
+            None => self.clip_mask = Some(clip_mask),
+        }
+        Ok(())
+    }
+
+    /// Carves every trail in a KML file (its text) `depth_m` meters into the terrain, `width_m` meters wide
+    pub fn carve_trails(&mut self, kml: &str, width_m: f64, depth_m: f64) -> Result<(), JsError> {
+        let geometry_collection = load_kml_str(kml)?;
+        self.infer_utm_zone(&geometry_collection);
+        let mut trail_mask = Mask::new_for_height_map(&self.height_map)?;
+        for trail in &get_trails(geometry_collection) {
+            trail_mask.add_lat_lon_trail_meters(trail, width_m / 2f64)?;
+        }
+        self.height_map.offset_by_mask(&trail_mask, -depth_m)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn x_res(&self) -> usize {
+        self.height_map.x_res
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn y_res(&self) -> usize {
+        self.height_map.y_res
+    }
+
+    /// Every height in meters, `x_res` per row, for drawing a preview (a `Float64Array` in JavaScript)
+    pub fn heights(&self) -> Vec<f64> {
+        self.height_map.data.clone()
+    }
+
+    /// Makes a binary STL, only of the clip region if there is one (a `Uint8Array` in JavaScript)
+    pub fn to_stl(&self, z_scaling: f64, base_thickness: f32) -> Result<Vec<u8>, JsError> {
+        let options = StlOptions { z_scaling, base_thickness, ..StlOptions::default() };
+        let mut stl: Vec<u8> = Vec::new();
+        match &self.clip_mask {
+            Some(clip_mask) => self.height_map.write_stl_masked(&mut stl, clip_mask, &options)?,
+            None => self.height_map.write_stl(&mut stl, &options)?,
+        }
+        Ok(stl)
+    }
+}
+
+impl Terrain {
+    /// uses the zone the KML is in if the LAS file didn't say
+    fn infer_utm_zone(&mut self, geometry_collection: &GeometryCollection<f64>) {
+        if self.height_map.utm_zone.is_none() {
+            self.height_map.set_utm_zone(infer_utm_zone(geometry_collection));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::errors::LasToStlError;
+use crate::height_map::HeightMap;
+use crate::mask::Mask;
+use crate::utils::par_zip_for_each;
+use crate::utm_bounds::UtmBoundingBox;
+use crate::utm_point::UtmZone;
+
+/// Used instead of infinity for cells that are not part of the mask in the distance transform, so there are no `inf - inf`
+const FAR: f64 = 1e20;
+
+/// Like a `Mask`, but every cell has a weight (usually 0 to 1) instead of just on or off.
+/// Used to feather trail carving and region offsets so they blend into the terrain instead of ending in a vertical cliff.
+pub struct WeightMask{
+    pub data: Vec<f64>,
+    pub x_res: usize,
+    pub y_res: usize,
+    pub bounds: UtmBoundingBox,
+    pub utm_zone: UtmZone,
+}
+
+impl WeightMask{
+    /// Creates a weight mask where every cell is its distance (in pixels) to the closest set cell of `mask`,
+    /// so 0 on the mask itself. If nothing in `mask` is set, every cell is a huge number.
+    pub fn distance_to_mask(mask: &Mask) -> WeightMask{
+        let mut squared: Vec<f64> = mask.data.iter().map(|state| if *state { 0f64 } else { FAR }).collect();
+
+        // exact euclidean distance transform (Felzenszwalb & Huttenlocher), one dimension at a time
+        for x in 0..mask.x_res{
+            let column: Vec<f64> = (0..mask.y_res).map(|y| squared[y * mask.x_res + x]).collect();
+            for (y, distance) in squared_distance_1d(&column).into_iter().enumerate(){
+                squared[y * mask.x_res + x] = distance;
+            }
+        }
+        for row in squared.chunks_mut(mask.x_res){
+            let distances = squared_distance_1d(row);
+            row.copy_from_slice(&distances);
+        }
+
+        WeightMask{
+            data: squared.into_iter().map(f64::sqrt).collect(),
+            x_res: mask.x_res,
+            y_res: mask.y_res,
+            bounds: mask.bounds,
+            utm_zone: mask.utm_zone,
+        }
+    }
+
+    /// Creates a weight mask that is 1 on `mask` and falls off smoothly to 0 at `feather_radius` pixels away from it.
+    /// A `feather_radius` of 0 or less gives the same hard edge as the mask.
+    pub fn feathered(mask: &Mask, feather_radius: f64) -> WeightMask{
+        let mut weight_mask = WeightMask::distance_to_mask(mask);
+        weight_mask.data.iter_mut().for_each(|weight| {
+            *weight = if feather_radius <= 0f64 {
+                if *weight == 0f64 { 1f64 } else { 0f64 }
+            } else {
+                // smoothstep, so the slope is 0 where it meets both the mask and the untouched terrain
+                let t = 1f64 - (*weight / feather_radius).min(1f64);
+                t * t * (3f64 - 2f64 * t)
+            }
+        });
+        weight_mask
+    }
+}
+
+impl HeightMap{
+    /// adds `weight * max_offset` to every height, e.g. a `WeightMask::feathered` trail with a negative `max_offset`
+    /// carves a trail with sloped sides. Weight mask must have the same resolution and x/y bounds as self.
+    ///
+    /// Like `offset_by_mask`, the z bounds are not changed
+    pub fn offset_by_weight_mask(&mut self, weight_mask: &WeightMask, max_offset: f64) -> Result<(), LasToStlError>{
+        if let Some(utm_zone) = self.utm_zone {
+            utm_zone.check_matches(&weight_mask.utm_zone)?;
+        }
+        if self.x_res == weight_mask.x_res && self.y_res == weight_mask.y_res && self.bounds.eq_xy(&weight_mask.bounds){
+            par_zip_for_each(&mut self.data, &weight_mask.data, |height, weight| *height += weight * max_offset);
+            Ok(())
+        } else {
+            Err(LasToStlError::MaskBoundMismatchError{
+                other_x_res: self.x_res,
+                other_y_res: self.y_res,
+                mask_x_res: weight_mask.x_res,
+                mask_y_res: weight_mask.y_res,
+                other_bounds: Box::new(self.bounds),
+                mask_bounds: Box::new(weight_mask.bounds),
+            })
+        }
+    }
+}
+
+/// squared distance transform of a single row, where `values` is 0 on the mask and `FAR` everywhere else
+fn squared_distance_1d(values: &[f64]) -> Vec<f64>{
+    let length = values.len();
+    let mut distances = vec![0f64; length];
+    // the parabolas that make up the lower envelope, and where each one starts
+    let mut parabolas = vec![0usize; length];
+    let mut starts = vec![0f64; length + 1];
+    let mut k: usize = 0;
+    starts[0] = f64::NEG_INFINITY;
+    starts[1] = f64::INFINITY;
+
+    let intersection = |q: usize, p: usize| {
+        ((values[q] + (q * q) as f64) - (values[p] + (p * p) as f64)) / (2 * q - 2 * p) as f64
+    };
+
+    for q in 1..length{
+        let mut start = intersection(q, parabolas[k]);
+        while start <= starts[k]{
+            k -= 1;
+            start = intersection(q, parabolas[k]);
+        }
+        k += 1;
+        parabolas[k] = q;
+        starts[k] = start;
+        starts[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, distance) in distances.iter_mut().enumerate(){
+        while starts[k + 1] < q as f64{
+            k += 1;
+        }
+        let offset = q as f64 - parabolas[k] as f64;
+        *distance = offset * offset + values[parabolas[k]];
+    }
+    distances
+}